@@ -0,0 +1,2309 @@
+//! The command line read into [`Args`], the config file and the environment variables filling
+//! in what it leaves out, and checked for flags that don't go together
+
+use std::path::Path;
+use std::time::Duration;
+
+use chunk_uploader::filter::{Filter, Glob};
+use chunk_uploader::output::{print, ColorChoice};
+use chunk_uploader::{
+    parse_content_type, parse_duration, parse_rate, parse_size, ByteRange, ChainFrom, Checksum,
+    Compression, Encryption, HttpVersion, NotifyOn, OnFailure, Protocol, Redirects, ResumeMode,
+    RetryJitter, SkipExisting, StreamCompression, UploadUrlFrom, Verbosity, Verify,
+    DEFAULT_MAX_RETRY_WAIT, DEFAULT_MIN_CHUNK_SIZE, DEFAULT_POOL_IDLE_TIMEOUT,
+    DEFAULT_STALL_THRESHOLD,
+};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Method, Proxy, StatusCode};
+
+use crate::config::{self, Config};
+use crate::exit::CliError;
+use crate::flags::Kind;
+use crate::{completions, flags, logging};
+use crate::{config_duration, config_value, parse_header, parse_statuses, print_version_info};
+
+/// Everything the command line asks for, not yet checked against the files or the network
+pub struct Args {
+    pub config: Config,
+    pub paths: Vec<String>,
+    pub use_stdin: bool,
+    pub dir: Option<String>,
+    pub files_from: Option<String>,
+    pub null_separated: bool,
+    pub continue_on_error: bool,
+    pub error_log: Option<String>,
+    pub filter: Filter,
+    pub include_hidden: bool,
+    pub follow_symlinks: bool,
+    pub total_size: Option<u64>,
+    pub file_range: Option<ByteRange>,
+    pub clamp_range: bool,
+    pub chunk_size: u64,
+    pub adaptive_chunk: bool,
+    pub min_chunk_size: u64,
+    pub mmap: bool,
+    pub tar: Option<String>,
+    pub tar_presize: bool,
+    pub tar_clamp_mtime: Option<u64>,
+    pub url: Option<String>,
+    pub prefix: Option<String>,
+    pub url_given: bool,
+    pub method: Method,
+    pub print_file_bytes: bool,
+    pub print_config: bool,
+    pub stats: bool,
+    pub save_responses: Option<String>,
+    pub save_final_response: Option<String>,
+    pub manifest: Option<String>,
+    pub delta_from: Option<String>,
+    pub metrics_csv: Option<String>,
+    pub metrics_textfile: Option<String>,
+    pub log_level: Option<String>,
+    pub log_file: Option<String>,
+    pub dry_run: bool,
+    pub yes: bool,
+    pub benchmark: bool,
+    pub watch: bool,
+    pub watch_poll_interval: Option<Duration>,
+    pub watch_settle: Option<Duration>,
+    pub watch_fail_fast: bool,
+    pub benchmark_sweep: Option<Vec<u64>>,
+    pub quiet: bool,
+    pub json: bool,
+    pub progress_jsonl: bool,
+    pub progress_file: Option<String>,
+    pub progress_fd: Option<i32>,
+    pub verbose: bool,
+    pub show_progress: bool,
+    pub color: ColorChoice,
+    pub resume: ResumeMode,
+    pub probe_offset: bool,
+    pub check_chunk_url: Option<String>,
+    pub check_chunk_soft: bool,
+    pub exec_before_chunk: Option<String>,
+    pub exec_after_chunk: Option<String>,
+    pub exec_on_success: Option<String>,
+    pub exec_on_failure: Option<String>,
+    pub hook_strict: bool,
+    pub hook_timeout: Duration,
+    pub if_match: Option<String>,
+    pub if_match_file: Option<String>,
+    pub if_none_match: Option<String>,
+    pub skip_existing: bool,
+    pub skip_existing_by: SkipExisting,
+    pub chunk_md5: bool,
+    pub legacy_range: bool,
+    pub sha256: bool,
+    pub checksum: Option<Checksum>,
+    pub checksum_header: Option<HeaderName>,
+    pub verify: Option<Verify>,
+    pub verify_block_size: Option<u64>,
+    pub final_digest_header: Option<HeaderName>,
+    pub chain: Option<(ChainFrom, HeaderName)>,
+    pub idempotency_key_header: Option<HeaderName>,
+    pub init_url: Option<String>,
+    pub init_method: Method,
+    pub init_body: Option<String>,
+    pub upload_url_from: Option<UploadUrlFrom>,
+    pub finalize_url: Option<String>,
+    pub on_failure: Option<OnFailure>,
+    pub abort_url: Option<String>,
+    pub notify_url: Option<String>,
+    pub notify_on: Option<NotifyOn>,
+    pub notify_token: Option<String>,
+    pub abort_method: Method,
+    pub finalize_method: Method,
+    pub finalize_body_template: Option<String>,
+    pub chunk_headers: bool,
+    pub index_header: HeaderName,
+    pub count_header: HeaderName,
+    pub offset_header: String,
+    pub expect_status: Option<Vec<StatusCode>>,
+    pub headers: HeaderMap,
+    pub content_type: Option<HeaderValue>,
+    pub detect_content_type: bool,
+    pub protocol: Protocol,
+    pub tus_metadata: Vec<(String, String)>,
+    pub form_field: Option<String>,
+    pub compress: Compression,
+    pub compress_stream: Option<StreamCompression>,
+    pub form: Vec<(String, String)>,
+    pub token: Option<String>,
+    pub user: Option<String>,
+    pub aws_sigv4: bool,
+    pub aws_region: Option<String>,
+    pub aws_service: Option<String>,
+    pub aws_access_key: Option<String>,
+    pub aws_secret_key: Option<String>,
+    pub aws_session_token: Option<String>,
+    pub aws_sign_payload: bool,
+    pub hmac_secret: Option<String>,
+    pub hmac_secret_file: Option<String>,
+    pub hmac_payload: Option<String>,
+    pub hmac_header: Option<HeaderName>,
+    pub encrypt: Option<Encryption>,
+    pub encrypt_key_file: Option<String>,
+    pub encrypt_passphrase: Option<String>,
+    pub parallel: usize,
+    pub jobs: usize,
+    pub limit_rate: Option<u64>,
+    pub connect_timeout: Duration,
+    pub timeout: Duration,
+    pub chunk_delay: Duration,
+    pub max_retry_wait: Duration,
+    pub stall_threshold: Duration,
+    pub http_version: HttpVersion,
+    pub pool_idle_timeout: Duration,
+    pub pool_max_idle_per_host: Option<usize>,
+    pub tcp_keepalive: Duration,
+    pub tcp_nodelay: bool,
+    pub redirects: Redirects,
+    pub no_proxy: bool,
+    pub proxy: Option<String>,
+    pub cacert: Option<String>,
+    pub insecure: bool,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    pub identity: Option<String>,
+    pub identity_password: Option<String>,
+    pub cookie_store: bool,
+    pub cookies: Vec<(String, String)>,
+    pub cookie_jar: Option<String>,
+    pub retries: u32,
+    pub retry_delay: Duration,
+    pub retry_jitter: RetryJitter,
+    pub verbosity: Verbosity,
+    pub events_to_stdout: bool,
+}
+
+impl Args {
+    /// Whether stdout only gets the JSON document or the progress events
+    pub fn stdout_taken(&self) -> bool {
+        self.json || self.events_to_stdout
+    }
+}
+
+/// Reads the command line, none when it only asked for the help, the version or completions,
+/// which are printed
+pub fn parse(cli: &[String]) -> std::result::Result<Option<Args>, CliError> {
+    let env_args = match flags::env_args(&cli[1..]) {
+        Ok(env_args) => env_args,
+        Err(err) => {
+            return Err(CliError::Usage(err.to_string()));
+        }
+    };
+    // Flags standing in for environment variables go first so the command line overrides them
+    let mut args = vec![cli[0].clone()];
+    let mut sources = vec![None];
+    for (arg, var) in env_args {
+        args.push(arg);
+        sources.push(Some(var));
+    }
+    for arg in cli[1..].iter() {
+        args.push(arg.clone());
+        sources.push(None);
+    }
+    // Appended to invalid values to name the flag they were given to, or the variable
+    let from = |i: usize| match sources[i] {
+        Some(var) => format!(" in {var}"),
+        None => format!(" for '{}'", args[i - 1]),
+    };
+
+    let config = match config_flag(&args) {
+        Some(path) => config::load(Path::new(path), true),
+        None => config::default_path().map_or(Ok(None), |path| config::load(&path, false)),
+    };
+    // Everything from the config file is only a default, the flags parsed below replace it
+    let config = match config {
+        Ok(config) => config.unwrap_or_default(),
+        Err(err) => {
+            return Err(CliError::Usage(err.to_string()));
+        }
+    };
+
+    let mut paths: Vec<String> = Vec::new();
+    let mut use_stdin = false;
+    let mut dir: Option<String> = None;
+    let mut files_from: Option<String> = None;
+    let mut null_separated = false;
+    let mut continue_on_error = false;
+    let mut error_log: Option<String> = None;
+    let mut filter = Filter::default();
+    let mut include_hidden = config.hidden.unwrap_or(false);
+    let mut follow_symlinks = config.follow_symlinks.unwrap_or(false);
+    let mut total_size: Option<u64> = None;
+    let mut file_range: Option<ByteRange> = None;
+    let mut clamp_range = config.clamp_range.unwrap_or(false);
+    let mut chunk_size: u64 = config.chunk_size.unwrap_or(5000000);
+    let mut adaptive_chunk = config.adaptive_chunk.unwrap_or(false);
+    let mut min_chunk_size = config.min_chunk_size.unwrap_or(DEFAULT_MIN_CHUNK_SIZE);
+    let mut mmap = config.mmap.unwrap_or(false);
+    let mut tar: Option<String> = None;
+    let mut tar_presize = config.tar_presize.unwrap_or(false);
+    let mut tar_clamp_mtime = config.tar_clamp_mtime;
+    let mut url: Option<String> = config.url.clone();
+    let mut prefix: Option<String> = config.prefix.clone();
+    // Only a URL given as a flag or variable stops a benchmark, the config file's is a default
+    let mut url_given = false;
+    let mut method: Method =
+        config_value("method", config.method.as_deref())?.unwrap_or(Method::PUT);
+    let mut print_file_bytes = false;
+    let mut print_version = false;
+    let mut print_config = false;
+    let mut stats = config.stats.unwrap_or(false);
+    let mut save_responses = config.save_responses.clone();
+    let mut save_final_response = config.save_final_response.clone();
+    let mut manifest = config.manifest.clone();
+    let mut delta_from = config.delta_from.clone();
+    let mut metrics_csv = config.metrics_csv.clone();
+    let mut metrics_textfile = config.metrics_textfile.clone();
+    config_value::<logging::Filter>("log_level", config.log_level.as_deref())?;
+    let mut log_level = config.log_level.clone();
+    let mut log_file = config.log_file.clone();
+    let mut dry_run = false;
+    let mut yes = false;
+    let mut benchmark = false;
+    let mut watch = false;
+    let mut watch_poll_interval: Option<Duration> = None;
+    let mut watch_settle: Option<Duration> = None;
+    let mut watch_fail_fast = false;
+    let mut benchmark_sweep: Option<Vec<u64>> = None;
+    let mut quiet = false;
+    let mut json = false;
+    let mut progress_jsonl = false;
+    let mut progress_file: Option<String> = None;
+    let mut progress_fd: Option<i32> = None;
+    let mut verbose = false;
+    let mut show_progress = config.progress.unwrap_or(true);
+    let mut color = config_value("color", config.color.as_deref())?.unwrap_or(ColorChoice::Auto);
+    let mut resume = config_value("resume", config.resume.as_deref())?.unwrap_or(ResumeMode::Auto);
+    let mut probe_offset = config.probe_offset.unwrap_or(false);
+    let mut check_chunk_url = config.check_chunk_url.clone();
+    let mut check_chunk_soft = config.check_chunk_soft.unwrap_or(false);
+    let mut exec_before_chunk = config.exec_before_chunk.clone();
+    let mut exec_after_chunk = config.exec_after_chunk.clone();
+    let mut exec_on_success = config.exec_on_success.clone();
+    let mut exec_on_failure = config.exec_on_failure.clone();
+    let mut hook_strict = config.hook_strict.unwrap_or(false);
+    let mut hook_timeout = config_duration(config.hook_timeout.as_deref())?;
+    let mut if_match = config.if_match.clone();
+    let mut if_match_file = config.if_match_file.clone();
+    let mut if_none_match = config.if_none_match.clone();
+    let mut skip_existing = config.skip_existing.unwrap_or(false);
+    let mut skip_existing_by =
+        config_value("skip_existing_by", config.skip_existing_by.as_deref())?
+            .unwrap_or(SkipExisting::Size);
+    let mut chunk_md5 = config.chunk_md5.unwrap_or(false);
+    let mut legacy_range = config.legacy_range.unwrap_or(false);
+    let mut sha256 = config.sha256.unwrap_or(false);
+    let mut checksum = config_value::<Checksum>("checksum", config.checksum.as_deref())?;
+    let mut checksum_header: Option<HeaderName> =
+        config_value("checksum_header", config.checksum_header.as_deref())?;
+    let mut verify = config_value::<Verify>("verify", config.verify.as_deref())?;
+    let mut verify_block_size = config.verify_block_size;
+    let mut no_verify = false;
+    let mut final_digest_header: Option<HeaderName> =
+        config_value("final_digest_header", config.final_digest_header.as_deref())?;
+    let mut chain: Option<ChainFrom> = config_value("chain", config.chain.as_deref())?;
+    let mut chain_request_header: Option<HeaderName> = config_value(
+        "chain_request_header",
+        config.chain_request_header.as_deref(),
+    )?;
+    let mut idempotency_key_header: Option<HeaderName> = config_value(
+        "idempotency_key_header",
+        config.idempotency_key_header.as_deref(),
+    )?;
+    let mut init_url: Option<String> = config.init_url.clone();
+    let mut init_method: Method =
+        config_value("init_method", config.init_method.as_deref())?.unwrap_or(Method::POST);
+    let mut init_body = config.init_body.clone();
+    let mut upload_url_from: Option<UploadUrlFrom> =
+        config_value("upload_url_from", config.upload_url_from.as_deref())?;
+    let mut finalize_url: Option<String> = config.finalize_url.clone();
+    let mut on_failure: Option<OnFailure> =
+        config_value("on_failure", config.on_failure.as_deref())?;
+    let mut abort_url: Option<String> = config.abort_url.clone();
+    let mut notify_url: Option<String> = config.notify_url.clone();
+    let mut notify_on: Option<NotifyOn> = config_value("notify_on", config.notify_on.as_deref())?;
+    let mut notify_token: Option<String> = config.notify_token.clone();
+    let mut abort_method: Method =
+        config_value("abort_method", config.abort_method.as_deref())?.unwrap_or(Method::DELETE);
+    let mut finalize_method: Method =
+        config_value("finalize_method", config.finalize_method.as_deref())?.unwrap_or(Method::POST);
+    let mut finalize_body_template = config.finalize_body_template.clone();
+    let mut chunk_headers = config.chunk_headers.unwrap_or(false);
+    let mut index_header = config_value("index_header", config.index_header.as_deref())?
+        .unwrap_or(HeaderName::from_static("x-chunk-index"));
+    let mut count_header = config_value("count_header", config.count_header.as_deref())?
+        .unwrap_or(HeaderName::from_static("x-chunk-count"));
+    let mut offset_header = config
+        .offset_header
+        .clone()
+        .unwrap_or_else(|| String::from("Upload-Offset"));
+    let mut expect_status = match config.expect_status.as_deref().map(parse_statuses) {
+        Some(Err(err)) => {
+            return Err(CliError::Usage(format!("{} in the config file", err)));
+        }
+        statuses => statuses.and_then(|s| s.ok()),
+    };
+    let mut headers = HeaderMap::new();
+    let mut content_type = match config.content_type.as_deref().map(parse_content_type) {
+        Some(Err(err)) => {
+            return Err(CliError::Usage(format!("{} in the config file", err)));
+        }
+        content_type => content_type.and_then(|c| c.ok()),
+    };
+    let mut detect_content_type = config.detect_content_type.unwrap_or(false);
+    let mut protocol =
+        config_value("protocol", config.protocol.as_deref())?.unwrap_or(Protocol::Raw);
+    let mut tus_metadata = Vec::new();
+    let mut form_field: Option<String> = config.form_field.clone();
+    let mut compress =
+        config_value("compress", config.compress.as_deref())?.unwrap_or(Compression::None);
+    let mut compress_stream =
+        config_value::<StreamCompression>("compress_stream", config.compress_stream.as_deref())?;
+    let mut form = Vec::new();
+    let mut token: Option<String> = None;
+    let mut user: Option<String> = None;
+    let mut aws_sigv4 = config.aws_sigv4.unwrap_or(false);
+    let mut aws_region: Option<String> = config.aws_region.clone();
+    let mut aws_service: Option<String> = config.aws_service.clone();
+    let mut aws_access_key: Option<String> = config.aws_access_key.clone();
+    let mut aws_secret_key: Option<String> = config.aws_secret_key.clone();
+    let mut aws_session_token: Option<String> = config.aws_session_token.clone();
+    let mut aws_sign_payload = config.aws_sign_payload.unwrap_or(false);
+    let mut hmac_secret: Option<String> = config.hmac_secret.clone();
+    let mut hmac_secret_file: Option<String> = config.hmac_secret_file.clone();
+    let mut hmac_payload: Option<String> = config.hmac_payload.clone();
+    let mut hmac_header: Option<HeaderName> =
+        config_value("hmac_header", config.hmac_header.as_deref())?;
+    let mut encrypt = config_value::<Encryption>("encrypt", config.encrypt.as_deref())?;
+    let mut encrypt_key_file: Option<String> = config.encrypt_key_file.clone();
+    let mut encrypt_passphrase: Option<String> = config.encrypt_passphrase.clone();
+    let mut parallel: usize = match config.parallel {
+        Some(0) => {
+            return Err(CliError::Usage(
+                "Invalid parallel '0' in the config file".to_string(),
+            ));
+        }
+        p => p.unwrap_or(1),
+    };
+    let mut jobs: usize = match config.jobs {
+        Some(0) => {
+            return Err(CliError::Usage(
+                "Invalid jobs '0' in the config file".to_string(),
+            ));
+        }
+        j => j.unwrap_or(1),
+    };
+    let mut limit_rate = match config.limit_rate.as_deref().map(parse_rate) {
+        Some(Err(err)) => {
+            return Err(CliError::Usage(format!("{} in the config file", err)));
+        }
+        rate => rate.and_then(|r| r.ok()),
+    };
+    let mut connect_timeout = config_duration(config.connect_timeout.as_deref())?;
+    let mut timeout = config_duration(config.timeout.as_deref())?;
+    let mut chunk_delay = config_duration(config.chunk_delay.as_deref())?;
+    let mut max_retry_wait = match config.max_retry_wait.as_deref() {
+        Some(value) => config_duration(Some(value))?,
+        None => DEFAULT_MAX_RETRY_WAIT,
+    };
+    let mut stall_threshold = match config.stall_threshold.as_deref() {
+        Some(value) => config_duration(Some(value))?,
+        None => DEFAULT_STALL_THRESHOLD,
+    };
+    // Whichever of --http1 and --http2 comes last wins
+    let mut http_version =
+        config_value("http_version", config.http_version.as_deref())?.unwrap_or(HttpVersion::Auto);
+    let mut pool_idle_timeout = match config.pool_idle_timeout.as_deref() {
+        Some(value) => config_duration(Some(value))?,
+        None => DEFAULT_POOL_IDLE_TIMEOUT,
+    };
+    let mut pool_max_idle_per_host = config.pool_max_idle_per_host;
+    let mut tcp_keepalive = config_duration(config.tcp_keepalive.as_deref())?;
+    let mut tcp_nodelay = config.tcp_nodelay.unwrap_or(true);
+    let mut redirects =
+        config_value("redirects", config.redirects.as_deref())?.unwrap_or(Redirects::Follow);
+    // Whichever of --proxy and --no-proxy comes last wins, so a flag can undo the config file
+    let mut no_proxy = config.no_proxy.unwrap_or(false);
+    let mut proxy = config.proxy.clone().filter(|_| !no_proxy);
+    let mut cacert = config.cacert.clone();
+    let mut insecure = config.insecure.unwrap_or(false);
+    let mut cert = config.cert.clone();
+    let mut key = config.key.clone();
+    let mut identity = config.identity.clone();
+    let mut identity_password = config.identity_password.clone();
+    let mut cookie_store = config.cookies.unwrap_or(false);
+    let mut cookies = Vec::new();
+    let mut cookie_jar = config.cookie_jar.clone();
+    let mut retries: u32 = config.retries.unwrap_or(0);
+    let mut retry_delay = Duration::from_millis(config.retry_delay.unwrap_or(1000));
+    let mut retry_jitter =
+        config_value("retry_jitter", config.retry_jitter.as_deref())?.unwrap_or(RetryJitter::Full);
+
+    let mut i = 1;
+    while i < args.len() {
+        let arg = flags::find(&args[i]).map_or(args[i].as_str(), |f| f.long);
+        match arg {
+            "--file" => {
+                if i + 1 < args.len() {
+                    paths.push(args[i + 1].clone());
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing file path after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--prefix" => {
+                if i + 1 < args.len() {
+                    prefix = Some(args[i + 1].clone());
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing remote directory after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--dir" => {
+                if i + 1 < args.len() {
+                    dir = Some(args[i + 1].clone());
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing directory after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--files-from" => {
+                if i + 1 < args.len() {
+                    files_from = Some(args[i + 1].clone());
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing list of files after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--null" => {
+                null_separated = true;
+            }
+            "--continue-on-error" => {
+                continue_on_error = true;
+            }
+            "--error-log" => {
+                if i + 1 < args.len() {
+                    error_log = Some(args[i + 1].to_string());
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing path after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--include" | "--exclude" | "--iinclude" | "--iexclude" => {
+                if i + 1 < args.len() {
+                    let ignore_case = matches!(args[i].as_str(), "--iinclude" | "--iexclude");
+                    let glob = match Glob::new(&args[i + 1], ignore_case) {
+                        Ok(glob) => glob,
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    match args[i].ends_with("include") {
+                        true => filter.include(glob),
+                        false => filter.exclude(glob),
+                    }
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing pattern after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--hidden" => {
+                include_hidden = true;
+            }
+            "--follow-symlinks" => {
+                follow_symlinks = true;
+            }
+            "--stdin" => {
+                use_stdin = true;
+            }
+            "--total-size" => {
+                if i + 1 < args.len() {
+                    total_size = match parse_size(&args[i + 1]) {
+                        Ok(t) => Some(t),
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing total size after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--file-range" => {
+                if i + 1 < args.len() {
+                    file_range = match args[i + 1].parse::<ByteRange>() {
+                        Ok(range) => Some(range),
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing byte range after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--clamp-range" => {
+                clamp_range = true;
+            }
+            "--chunk" => {
+                if i + 1 < args.len() {
+                    chunk_size = match parse_size(&args[i + 1]) {
+                        Ok(c) => c,
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing chunk size after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--adaptive-chunk" => {
+                adaptive_chunk = true;
+            }
+            "--min-chunk-size" => {
+                if i + 1 < args.len() {
+                    min_chunk_size = match parse_size(&args[i + 1]) {
+                        Ok(size) => size,
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing size after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--mmap" => {
+                mmap = true;
+            }
+            "--tar" => {
+                if i + 1 < args.len() {
+                    tar = Some(args[i + 1].clone());
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing directory after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--tar-presize" => {
+                tar_presize = true;
+            }
+            "--tar-clamp-mtime" => {
+                if i + 1 < args.len() {
+                    tar_clamp_mtime = match args[i + 1].parse() {
+                        Ok(seconds) => Some(seconds),
+                        Err(_) => {
+                            return Err(CliError::Usage(format!(
+                                "Invalid time '{}' for '{}', expected seconds since the epoch",
+                                args[i + 1],
+                                args[i]
+                            )))
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing time after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--url" => {
+                if i + 1 < args.len() {
+                    url = Some(args[i + 1].to_string());
+                    url_given = true;
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!("Missing URL with '{}'", args[i])));
+                }
+            }
+            "--method" | "--init-method" | "--finalize-method" | "--abort-method" => {
+                if i + 1 < args.len() {
+                    let parsed = if let Ok(m) = args[i + 1].parse::<Method>() {
+                        m
+                    } else {
+                        return Err(CliError::Usage(format!(
+                            "Invalid HTTP method '{}'{}",
+                            args[i + 1],
+                            from(i + 1)
+                        )));
+                    };
+                    match args[i].as_str() {
+                        "--method" => method = parsed,
+                        "--init-method" => init_method = parsed,
+                        "--abort-method" => abort_method = parsed,
+                        _ => finalize_method = parsed,
+                    }
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing HTTP method after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--init-url" | "--finalize-url" | "--abort-url" => {
+                if i + 1 < args.len() {
+                    match args[i].as_str() {
+                        "--init-url" => init_url = Some(args[i + 1].to_string()),
+                        "--abort-url" => abort_url = Some(args[i + 1].to_string()),
+                        _ => finalize_url = Some(args[i + 1].to_string()),
+                    }
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!("Missing URL with '{}'", args[i])));
+                }
+            }
+            "--init-body" | "--finalize-body-template" => {
+                if i + 1 < args.len() {
+                    match args[i].as_str() {
+                        "--init-body" => init_body = Some(args[i + 1].to_string()),
+                        _ => finalize_body_template = Some(args[i + 1].to_string()),
+                    }
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing body template after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--expect-status" => {
+                if i + 1 < args.len() {
+                    expect_status = match parse_statuses(&args[i + 1]) {
+                        Ok(statuses) => Some(statuses),
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing statuses after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--header" => {
+                if i + 1 < args.len() {
+                    match parse_header(&args[i + 1]) {
+                        Ok((name, value)) => {
+                            headers.append(name, value);
+                        }
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    }
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing header after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--protocol" => {
+                if i + 1 < args.len() {
+                    protocol = match args[i + 1].parse::<Protocol>() {
+                        Ok(p) => p,
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing protocol after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--tus-metadata" => {
+                if i + 1 < args.len() {
+                    match args[i + 1].split_once('=') {
+                        Some((k, v)) if !k.is_empty() => {
+                            tus_metadata.push((k.to_string(), v.to_string()));
+                        }
+                        _ => {
+                            return Err(CliError::Usage(format!(
+                                "Invalid tus metadata '{}'{}, expected 'key=value'",
+                                args[i + 1],
+                                from(i + 1)
+                            )));
+                        }
+                    }
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing metadata after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--compress" => {
+                if i + 1 < args.len() {
+                    compress = match args[i + 1].parse::<Compression>() {
+                        Ok(c) => c,
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing compression after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--compress-stream" => {
+                if i + 1 < args.len() {
+                    compress_stream = match args[i + 1].parse::<StreamCompression>() {
+                        Ok(c) => Some(c),
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing compression after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--form-field" => {
+                if i + 1 < args.len() {
+                    form_field = Some(args[i + 1].clone());
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing field name after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--form" => {
+                if i + 1 < args.len() {
+                    match args[i + 1].split_once('=') {
+                        Some((k, v)) if !k.is_empty() => {
+                            form.push((k.to_string(), v.to_string()));
+                        }
+                        _ => {
+                            return Err(CliError::Usage(format!(
+                                "Invalid form field '{}'{}, expected 'key=value'",
+                                args[i + 1],
+                                from(i + 1)
+                            )));
+                        }
+                    }
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing form field after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--token" => {
+                if i + 1 < args.len() {
+                    token = Some(args[i + 1].clone());
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing token after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--user" => {
+                if i + 1 < args.len() {
+                    user = Some(args[i + 1].clone());
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing user after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--aws-sigv4" => {
+                aws_sigv4 = true;
+            }
+            "--aws-sign-payload" => {
+                aws_sign_payload = true;
+            }
+            "--aws-region" | "--aws-service" => {
+                if i + 1 < args.len() {
+                    let value = Some(args[i + 1].clone());
+                    match args[i].as_str() {
+                        "--aws-region" => aws_region = value,
+                        _ => aws_service = value,
+                    }
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing name after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--aws-access-key" | "--aws-secret-key" | "--aws-session-token" => {
+                if i + 1 < args.len() {
+                    let value = Some(args[i + 1].clone());
+                    match args[i].as_str() {
+                        "--aws-access-key" => aws_access_key = value,
+                        "--aws-secret-key" => aws_secret_key = value,
+                        _ => aws_session_token = value,
+                    }
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing key after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--hmac-secret" | "--hmac-secret-file" | "--hmac-payload" => {
+                if i + 1 < args.len() {
+                    let value = Some(args[i + 1].clone());
+                    match arg {
+                        "--hmac-secret" => hmac_secret = value,
+                        "--hmac-secret-file" => hmac_secret_file = value,
+                        _ => hmac_payload = value,
+                    }
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing value after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--encrypt" => {
+                if i + 1 < args.len() {
+                    encrypt = match args[i + 1].parse::<Encryption>() {
+                        Ok(e) => Some(e),
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing encryption after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--encrypt-key-file" | "--encrypt-passphrase" => {
+                if i + 1 < args.len() {
+                    let value = Some(args[i + 1].clone());
+                    match arg {
+                        "--encrypt-key-file" => encrypt_key_file = value,
+                        _ => encrypt_passphrase = value,
+                    }
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing value after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--retries" => {
+                if i + 1 < args.len() {
+                    retries = if let Ok(r) = args[i + 1].parse::<u32>() {
+                        r
+                    } else {
+                        return Err(CliError::Usage(format!(
+                            "Invalid retry count '{}'{}",
+                            args[i + 1],
+                            from(i + 1)
+                        )));
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing retry count after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--retry-delay" => {
+                if i + 1 < args.len() {
+                    retry_delay = if let Ok(d) = args[i + 1].parse::<u64>() {
+                        Duration::from_millis(d)
+                    } else {
+                        return Err(CliError::Usage(format!(
+                            "Invalid retry delay '{}'{}",
+                            args[i + 1],
+                            from(i + 1)
+                        )));
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing retry delay after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--retry-jitter" => {
+                if i + 1 < args.len() {
+                    retry_jitter = match args[i + 1].parse::<RetryJitter>() {
+                        Ok(jitter) => jitter,
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing retry jitter after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--parallel" => {
+                if i + 1 < args.len() {
+                    parallel = match args[i + 1].parse::<usize>() {
+                        Ok(p) if p > 0 => p,
+                        _ => {
+                            return Err(CliError::Usage(format!(
+                                "Invalid parallel request count '{}'{}",
+                                args[i + 1],
+                                from(i + 1)
+                            )));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing parallel request count after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "-j" | "--jobs" => {
+                if i + 1 < args.len() {
+                    jobs = match args[i + 1].parse::<usize>() {
+                        Ok(j) if j > 0 => j,
+                        _ => {
+                            return Err(CliError::Usage(format!(
+                                "Invalid file count '{}'{}",
+                                args[i + 1],
+                                from(i + 1)
+                            )));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing file count after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--limit-rate" => {
+                if i + 1 < args.len() {
+                    limit_rate = match parse_rate(&args[i + 1]) {
+                        Ok(rate) => Some(rate),
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing rate after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--redirects" => {
+                if i + 1 < args.len() {
+                    redirects = match args[i + 1].parse::<Redirects>() {
+                        Ok(r) => r,
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing redirect policy after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--proxy" => {
+                if i + 1 < args.len() {
+                    if Proxy::all(&args[i + 1]).is_err() {
+                        return Err(CliError::Usage(format!(
+                            "Invalid proxy URL '{}'{}",
+                            args[i + 1],
+                            from(i + 1)
+                        )));
+                    }
+                    proxy = Some(args[i + 1].clone());
+                    no_proxy = false;
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing proxy URL after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--no-proxy" => {
+                no_proxy = true;
+                proxy = None;
+            }
+            "--cacert" => {
+                if i + 1 < args.len() {
+                    cacert = Some(args[i + 1].clone());
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing certificate file after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--insecure" => {
+                insecure = true;
+            }
+            "--http1" => {
+                http_version = HttpVersion::Http1;
+            }
+            "--http2" => {
+                http_version = HttpVersion::Http2;
+            }
+            "--cookies" => {
+                cookie_store = true;
+            }
+            "--cookie" => {
+                if i + 1 < args.len() {
+                    match args[i + 1].split_once('=') {
+                        Some((name, value)) if !name.trim().is_empty() => {
+                            cookies.push((name.trim().to_string(), value.trim().to_string()));
+                        }
+                        _ => {
+                            return Err(CliError::Usage(format!(
+                                "Invalid cookie '{}'{}, expected 'name=value'",
+                                args[i + 1],
+                                from(i + 1)
+                            )));
+                        }
+                    }
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing cookie after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--cookie-jar" => {
+                if i + 1 < args.len() {
+                    cookie_jar = Some(args[i + 1].to_string());
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing cookie jar file after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--cert" | "--key" | "--identity" | "--identity-password" => {
+                if i + 1 < args.len() {
+                    let value = Some(args[i + 1].clone());
+                    match arg {
+                        "--cert" => cert = value,
+                        "--key" => key = value,
+                        "--identity" => identity = value,
+                        _ => identity_password = value,
+                    }
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing value after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--connect-timeout"
+            | "--timeout"
+            | "--stall-threshold"
+            | "--chunk-delay"
+            | "--max-retry-wait"
+            | "--pool-idle-timeout"
+            | "--tcp-keepalive"
+            | "--watch-poll-interval"
+            | "--watch-settle"
+            | "--hook-timeout" => {
+                if i + 1 < args.len() {
+                    let value = match parse_duration(&args[i + 1]) {
+                        Ok(duration) => duration,
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    match arg {
+                        "--connect-timeout" => connect_timeout = value,
+                        "--timeout" => timeout = value,
+                        "--chunk-delay" => chunk_delay = value,
+                        "--max-retry-wait" => max_retry_wait = value,
+                        "--pool-idle-timeout" => pool_idle_timeout = value,
+                        "--tcp-keepalive" => tcp_keepalive = value,
+                        "--watch-poll-interval" => watch_poll_interval = Some(value),
+                        "--watch-settle" => watch_settle = Some(value),
+                        "--hook-timeout" => hook_timeout = value,
+                        _ => stall_threshold = value,
+                    }
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing duration after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--pool-max-idle-per-host" => {
+                if i + 1 < args.len() {
+                    pool_max_idle_per_host = match args[i + 1].parse::<usize>() {
+                        Ok(max) => Some(max),
+                        Err(_) => {
+                            return Err(CliError::Usage(format!(
+                                "Invalid idle connection count '{}'{}",
+                                args[i + 1],
+                                from(i + 1)
+                            )));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing idle connection count after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--tcp-nodelay" => {
+                if i + 1 < args.len() {
+                    tcp_nodelay = match args[i + 1].trim().to_ascii_lowercase().as_str() {
+                        "true" => true,
+                        "false" => false,
+                        _ => {
+                            return Err(CliError::Usage(format!(
+                                "Invalid value '{}'{} for '--tcp-nodelay', expected true or false",
+                                args[i + 1],
+                                from(i + 1)
+                            )));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing true or false after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--resume" => {
+                resume = ResumeMode::Require;
+            }
+            "--on-failure" => {
+                if i + 1 < args.len() {
+                    on_failure = match args[i + 1].parse::<OnFailure>() {
+                        Ok(on_failure) => Some(on_failure),
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing policy after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--abort-on-failure" => {
+                on_failure = Some(OnFailure::Abort);
+            }
+            "--notify-url" => {
+                if i + 1 < args.len() {
+                    notify_url = Some(args[i + 1].to_string());
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!("Missing URL with '{}'", args[i])));
+                }
+            }
+            "--notify-on" => {
+                if i + 1 < args.len() {
+                    notify_on = match args[i + 1].parse::<NotifyOn>() {
+                        Ok(on) => Some(on),
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing policy after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--notify-token" => {
+                if i + 1 < args.len() {
+                    notify_token = Some(args[i + 1].clone());
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing token after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--config" => {
+                // Already read before the other flags, so they can override it
+                if i + 1 < args.len() {
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing config path after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--print-config" => {
+                print_config = true;
+            }
+            "--quiet" => {
+                quiet = true;
+            }
+            "--verbose" => {
+                verbose = true;
+            }
+            "--output" => {
+                if i + 1 < args.len() {
+                    json = match args[i + 1].as_str() {
+                        "text" => false,
+                        "json" => true,
+                        format => {
+                            return Err(CliError::Usage(format!(
+                                "Unknown output format '{}'{}, expected text or json",
+                                format,
+                                from(i + 1)
+                            )));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing output format after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--dry-run" => {
+                dry_run = true;
+            }
+            "--yes" => {
+                yes = true;
+            }
+            "--benchmark" => {
+                benchmark = true;
+            }
+            "--watch" => {
+                watch = true;
+            }
+            "--watch-fail-fast" => {
+                watch_fail_fast = true;
+            }
+            "--benchmark-sweep" => {
+                if i + 1 < args.len() {
+                    let sizes: std::result::Result<Vec<_>, _> = args[i + 1]
+                        .split(',')
+                        .map(|s| parse_size(s.trim()))
+                        .collect();
+                    benchmark_sweep = match sizes {
+                        Ok(sizes) => Some(sizes),
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing chunk sizes after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--no-resume" => {
+                resume = ResumeMode::Off;
+            }
+            "--chunk-md5" => {
+                chunk_md5 = true;
+            }
+            "--sha256" => {
+                sha256 = true;
+            }
+            "--checksum" => {
+                if i + 1 < args.len() {
+                    checksum = match args[i + 1].parse::<Checksum>() {
+                        Ok(checksum) => Some(checksum),
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing checksum algorithm after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--verify" => {
+                if i + 1 < args.len() {
+                    verify = match args[i + 1].parse::<Verify>() {
+                        Ok(verify) => Some(verify),
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing way to verify after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--verify-block-size" => {
+                if i + 1 < args.len() {
+                    verify_block_size = match parse_size(&args[i + 1]) {
+                        Ok(size) => Some(size),
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing size after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--no-verify" => {
+                no_verify = true;
+            }
+            "--final-digest-header"
+            | "--checksum-header"
+            | "--hmac-header"
+            | "--idempotency-key-header"
+            | "--chain-request-header"
+            | "--index-header"
+            | "--count-header" => {
+                if i + 1 < args.len() {
+                    let name = match HeaderName::from_bytes(args[i + 1].as_bytes()) {
+                        Ok(name) => name,
+                        Err(_) => {
+                            return Err(CliError::Usage(format!(
+                                "Invalid header name '{}'{}",
+                                args[i + 1],
+                                from(i + 1)
+                            )));
+                        }
+                    };
+                    match args[i].as_str() {
+                        "--final-digest-header" => final_digest_header = Some(name),
+                        "--checksum-header" => checksum_header = Some(name),
+                        "--hmac-header" => hmac_header = Some(name),
+                        "--idempotency-key-header" => idempotency_key_header = Some(name),
+                        "--chain-request-header" => chain_request_header = Some(name),
+                        "--index-header" => {
+                            index_header = name;
+                            chunk_headers = true;
+                        }
+                        _ => {
+                            count_header = name;
+                            chunk_headers = true;
+                        }
+                    }
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing header name after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--chain" => {
+                if i + 1 < args.len() {
+                    chain = match args[i + 1].parse::<ChainFrom>() {
+                        Ok(from) => Some(from),
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing value source after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--upload-url-from" => {
+                if i + 1 < args.len() {
+                    upload_url_from = match args[i + 1].parse::<UploadUrlFrom>() {
+                        Ok(from) => Some(from),
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing URL source after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--chunk-headers" => {
+                chunk_headers = true;
+            }
+            "--content-type" => {
+                if i + 1 < args.len() {
+                    content_type = match parse_content_type(&args[i + 1]) {
+                        Ok(content_type) => Some(content_type),
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing content type after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--detect-content-type" => {
+                detect_content_type = true;
+            }
+            "--probe-offset" => {
+                probe_offset = true;
+            }
+            "--check-chunk-url" => {
+                if i + 1 < args.len() {
+                    check_chunk_url = Some(args[i + 1].clone());
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing URL after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--check-chunk-soft" => {
+                check_chunk_soft = true;
+            }
+            "--exec-before-chunk"
+            | "--exec-after-chunk"
+            | "--exec-on-success"
+            | "--exec-on-failure" => {
+                if i + 1 < args.len() {
+                    let command = Some(args[i + 1].clone());
+                    match args[i].as_str() {
+                        "--exec-before-chunk" => exec_before_chunk = command,
+                        "--exec-after-chunk" => exec_after_chunk = command,
+                        "--exec-on-success" => exec_on_success = command,
+                        _ => exec_on_failure = command,
+                    }
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing command after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--hook-strict" => {
+                hook_strict = true;
+            }
+            "--if-match" | "--if-match-file" | "--if-none-match" => {
+                if i + 1 < args.len() {
+                    let value = Some(args[i + 1].clone());
+                    match args[i].as_str() {
+                        "--if-match" => (if_match, if_match_file) = (value, None),
+                        "--if-match-file" => (if_match, if_match_file) = (None, value),
+                        _ => if_none_match = value,
+                    }
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing ETag after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--skip-existing" => {
+                skip_existing = true;
+            }
+            "--skip-existing-by" => {
+                if i + 1 < args.len() {
+                    skip_existing_by = match args[i + 1].parse::<SkipExisting>() {
+                        Ok(by) => by,
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    skip_existing = true;
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing comparison after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--legacy-range" => {
+                legacy_range = true;
+            }
+            "--offset-header" => {
+                if i + 1 < args.len() {
+                    offset_header = args[i + 1].clone();
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing header name after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--no-progress" => {
+                show_progress = false;
+            }
+            "--color" => {
+                if i + 1 < args.len() {
+                    color = match args[i + 1].parse::<ColorChoice>() {
+                        Ok(color) => color,
+                        Err(err) => {
+                            return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing color after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--progress-format" => {
+                if i + 1 < args.len() {
+                    progress_jsonl = match args[i + 1].as_str() {
+                        "text" => false,
+                        "jsonl" => true,
+                        format => {
+                            return Err(CliError::Usage(format!(
+                                "Unknown progress format '{}'{}, expected text or jsonl",
+                                format,
+                                from(i + 1)
+                            )));
+                        }
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing progress format after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--progress-file" => {
+                if i + 1 < args.len() {
+                    progress_file = Some(args[i + 1].to_string());
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing path after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--progress-fd" => {
+                if i + 1 < args.len() {
+                    progress_fd = if let Ok(fd) = args[i + 1].parse::<i32>() {
+                        Some(fd)
+                    } else {
+                        return Err(CliError::Usage(format!(
+                            "Invalid file descriptor '{}'{}",
+                            args[i + 1],
+                            from(i + 1)
+                        )));
+                    };
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing file descriptor after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--stats" => {
+                stats = true;
+            }
+            "--save-responses"
+            | "--save-final-response"
+            | "--manifest"
+            | "--delta-from"
+            | "--metrics-csv"
+            | "--metrics-textfile"
+            | "--log-file" => {
+                if i + 1 < args.len() {
+                    match args[i].as_str() {
+                        "--save-responses" => save_responses = Some(args[i + 1].to_string()),
+                        "--manifest" => manifest = Some(args[i + 1].to_string()),
+                        "--delta-from" => delta_from = Some(args[i + 1].to_string()),
+                        "--metrics-csv" => metrics_csv = Some(args[i + 1].to_string()),
+                        "--metrics-textfile" => metrics_textfile = Some(args[i + 1].to_string()),
+                        "--log-file" => log_file = Some(args[i + 1].to_string()),
+                        _ => save_final_response = Some(args[i + 1].to_string()),
+                    }
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing path after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--log-level" => {
+                if i + 1 < args.len() {
+                    if let Err(err) = args[i + 1].parse::<logging::Filter>() {
+                        return Err(CliError::Usage(format!("{}{}", err, from(i + 1))));
+                    }
+                    log_level = Some(args[i + 1].clone());
+                    i += 1;
+                } else {
+                    return Err(CliError::Usage(format!(
+                        "Missing log level after argument '{}'",
+                        args[i]
+                    )));
+                }
+            }
+            "--file-bytes" => {
+                print_file_bytes = true;
+            }
+            "--help" => {
+                print(&flags::help());
+                return Ok(None);
+            }
+            // Printed once every flag is read, so '--output' after it still counts
+            "--version" => {
+                print_version = true;
+            }
+            // Left out of the flag table so it stays out of --help
+            "--completions" => {
+                let Some(shell) = args.get(i + 1) else {
+                    return Err(CliError::Usage(format!(
+                        "Missing shell after argument '{}', expected bash, zsh, fish or powershell",
+                        args[i]
+                    )));
+                };
+                let shell = shell
+                    .parse::<completions::Shell>()
+                    .map_err(CliError::Usage)?;
+                print(completions::script(shell).trim_end());
+                return Ok(None);
+            }
+            a if !a.starts_with('-') || a == "-" => {
+                paths.push(a.to_string());
+            }
+            a => {
+                return Err(CliError::Usage(format!(
+                    "Unknown argument '{a}', use '-h' or '--help' for help"
+                )));
+            }
+        }
+        i += 1;
+    }
+
+    if print_version {
+        print_version_info(json);
+        return Ok(None);
+    }
+
+    // A header echoes the value under its own name unless told otherwise
+    let chain = match (chain, chain_request_header) {
+        (Some(ChainFrom::Header(name)), None) => Some((ChainFrom::Header(name.clone()), name)),
+        (Some(from), Some(header)) => Some((from, header)),
+        (Some(from), None) => {
+            return Err(CliError::Usage(format!(
+                "'--chain {}' needs '--chain-request-header' to send the value in",
+                from
+            )));
+        }
+        (None, Some(_)) => {
+            return Err(CliError::Usage(
+                "'--chain-request-header' needs '--chain' to take the value from".to_string(),
+            ));
+        }
+        (None, None) => None,
+    };
+    if parallel > 1 && chain.is_some() {
+        return Err(CliError::Usage(
+            "'--chain' sends the chunks one at a time, so it can't be used with '--parallel'"
+                .to_string(),
+        ));
+    }
+    if parallel > 1 && !protocol.allows_parallel() {
+        return Err(CliError::Usage(
+            "The chosen protocol can't upload chunks in parallel".to_string(),
+        ));
+    }
+    if probe_offset && protocol != Protocol::Raw {
+        return Err(CliError::Usage(
+            "'--probe-offset' can only be used with the raw protocol".to_string(),
+        ));
+    }
+    if (tar_presize || tar_clamp_mtime.is_some()) && tar.is_none() {
+        return Err(CliError::Usage(
+            "'--tar-presize' and '--tar-clamp-mtime' need '--tar'".to_string(),
+        ));
+    }
+    if tar_presize && total_size.is_some() {
+        return Err(CliError::Usage(
+            "'--tar-presize' and '--total-size' can't be used together".to_string(),
+        ));
+    }
+    if hook_strict && exec_after_chunk.is_none() && exec_on_success.is_none() {
+        return Err(CliError::Usage(
+            "'--hook-strict' needs '--exec-after-chunk' or '--exec-on-success' to be strict about"
+                .to_string(),
+        ));
+    }
+    if check_chunk_soft && check_chunk_url.is_none() {
+        return Err(CliError::Usage(
+            "'--check-chunk-soft' needs '--check-chunk-url' to check the chunks with".to_string(),
+        ));
+    }
+    // The config file or environment may ask for it, this run doesn't
+    if no_verify {
+        verify = None;
+    }
+    if verify_block_size.is_some() && verify.is_none() && !no_verify {
+        return Err(CliError::Usage(
+            "'--verify-block-size' needs '--verify'".to_string(),
+        ));
+    }
+    if skip_existing && protocol != Protocol::Raw {
+        return Err(CliError::Usage(
+            "'--skip-existing' can only be used with the raw protocol".to_string(),
+        ));
+    }
+    if notify_url.is_none() && (notify_on.is_some() || notify_token.is_some()) {
+        return Err(CliError::Usage(
+            "'--notify-on' and '--notify-token' need '--notify-url' to notify".to_string(),
+        ));
+    }
+    if let Some(Err(_)) = notify_token.as_deref().map(HeaderValue::from_str) {
+        return Err(CliError::Usage(
+            "The notification token contains characters invalid in a header".to_string(),
+        ));
+    }
+    if abort_url.is_some() && protocol != Protocol::Raw {
+        return Err(CliError::Usage(
+            "'--abort-url' can only be used with the raw protocol, the others abort their own way"
+                .to_string(),
+        ));
+    }
+    if total_size.is_some() && protocol != Protocol::Raw {
+        return Err(CliError::Usage(
+            "'--total-size' can only be used with the raw protocol".to_string(),
+        ));
+    }
+    if redirects == Redirects::Sticky && protocol != Protocol::Raw {
+        return Err(CliError::Usage(
+            "'--redirects sticky' can only be used with the raw protocol".to_string(),
+        ));
+    }
+    if legacy_range && protocol != Protocol::Raw {
+        return Err(CliError::Usage(
+            "'--legacy-range' can only be used with the raw protocol".to_string(),
+        ));
+    }
+    if form_field.is_some() && protocol != Protocol::Raw {
+        return Err(CliError::Usage(
+            "'--form-field' can only be used with the raw protocol".to_string(),
+        ));
+    }
+    if compress != Compression::None && protocol != Protocol::Raw {
+        return Err(CliError::Usage(
+            "'--compress' can only be used with the raw protocol".to_string(),
+        ));
+    }
+    if compress != Compression::None && form_field.is_some() {
+        return Err(CliError::Usage(
+            "'--compress' can't be used with '--form-field'".to_string(),
+        ));
+    }
+    if adaptive_chunk && !matches!(protocol, Protocol::Raw | Protocol::Tus) {
+        return Err(CliError::Usage(
+            "'--adaptive-chunk' can only be used with the raw and tus protocols".to_string(),
+        ));
+    }
+    if init_url.is_some() && protocol != Protocol::Raw {
+        return Err(CliError::Usage(
+            "'--init-url' can only be used with the raw protocol".to_string(),
+        ));
+    }
+    if init_url.is_none() && (init_body.is_some() || upload_url_from.is_some()) {
+        return Err(CliError::Usage(
+            "'--init-body' and '--upload-url-from' need '--init-url' to create the upload with"
+                .to_string(),
+        ));
+    }
+    if finalize_body_template.is_some() && finalize_url.is_none() {
+        return Err(CliError::Usage(
+            "'--finalize-body-template' needs '--finalize-url' to be sent to".to_string(),
+        ));
+    }
+    if expect_status.is_some() && protocol != Protocol::Raw {
+        return Err(CliError::Usage(
+            "'--expect-status' can only be used with the raw protocol".to_string(),
+        ));
+    }
+
+    if quiet && verbose {
+        return Err(CliError::Usage(
+            "Only one of '--quiet' and '--verbose' can be used".to_string(),
+        ));
+    }
+    let verbosity = match (quiet, verbose) {
+        (true, _) => Verbosity::Quiet,
+        (_, true) => Verbosity::Verbose,
+        _ => Verbosity::Normal,
+    };
+
+    if (progress_file.is_some() || progress_fd.is_some()) && !progress_jsonl {
+        return Err(CliError::Usage(
+            "'--progress-file' and '--progress-fd' need '--progress-format jsonl'".to_string(),
+        ));
+    }
+    if progress_file.is_some() && progress_fd.is_some() {
+        return Err(CliError::Usage(
+            "Only one of '--progress-file' and '--progress-fd' can be used".to_string(),
+        ));
+    }
+    // The events then have stdout to themselves, the rest goes to stderr as with '--output json'
+    let events_to_stdout = progress_jsonl && progress_file.is_none() && progress_fd.is_none();
+    if events_to_stdout && json {
+        return Err(CliError::Usage(
+            "'--progress-format jsonl' and '--output json' can't both write to stdout, give \
+             '--progress-file' or '--progress-fd' for the events"
+                .to_string(),
+        ));
+    }
+    if json && dry_run {
+        return Err(CliError::Usage(
+            "'--dry-run' only prints text, not '--output json'".to_string(),
+        ));
+    }
+    if token.is_some() && user.is_some() {
+        return Err(CliError::Usage(
+            "Only one of '--token' and '--user' can be used".to_string(),
+        ));
+    }
+
+    Ok(Some(Args {
+        config,
+        paths,
+        use_stdin,
+        dir,
+        files_from,
+        null_separated,
+        continue_on_error,
+        error_log,
+        filter,
+        include_hidden,
+        follow_symlinks,
+        total_size,
+        file_range,
+        clamp_range,
+        chunk_size,
+        adaptive_chunk,
+        min_chunk_size,
+        mmap,
+        tar,
+        tar_presize,
+        tar_clamp_mtime,
+        url,
+        prefix,
+        url_given,
+        method,
+        print_file_bytes,
+        print_config,
+        stats,
+        save_responses,
+        save_final_response,
+        manifest,
+        delta_from,
+        metrics_csv,
+        metrics_textfile,
+        log_level,
+        log_file,
+        dry_run,
+        yes,
+        benchmark,
+        watch,
+        watch_poll_interval,
+        watch_settle,
+        watch_fail_fast,
+        benchmark_sweep,
+        quiet,
+        json,
+        progress_jsonl,
+        progress_file,
+        progress_fd,
+        verbose,
+        show_progress,
+        color,
+        resume,
+        probe_offset,
+        check_chunk_url,
+        check_chunk_soft,
+        exec_before_chunk,
+        exec_after_chunk,
+        exec_on_success,
+        exec_on_failure,
+        hook_strict,
+        hook_timeout,
+        if_match,
+        if_match_file,
+        if_none_match,
+        skip_existing,
+        skip_existing_by,
+        chunk_md5,
+        legacy_range,
+        sha256,
+        checksum,
+        checksum_header,
+        verify,
+        verify_block_size,
+        final_digest_header,
+        chain,
+        idempotency_key_header,
+        init_url,
+        init_method,
+        init_body,
+        upload_url_from,
+        finalize_url,
+        on_failure,
+        abort_url,
+        notify_url,
+        notify_on,
+        notify_token,
+        abort_method,
+        finalize_method,
+        finalize_body_template,
+        chunk_headers,
+        index_header,
+        count_header,
+        offset_header,
+        expect_status,
+        headers,
+        content_type,
+        detect_content_type,
+        protocol,
+        tus_metadata,
+        form_field,
+        compress,
+        compress_stream,
+        form,
+        token,
+        user,
+        aws_sigv4,
+        aws_region,
+        aws_service,
+        aws_access_key,
+        aws_secret_key,
+        aws_session_token,
+        aws_sign_payload,
+        hmac_secret,
+        hmac_secret_file,
+        hmac_payload,
+        hmac_header,
+        encrypt,
+        encrypt_key_file,
+        encrypt_passphrase,
+        parallel,
+        jobs,
+        limit_rate,
+        connect_timeout,
+        timeout,
+        chunk_delay,
+        max_retry_wait,
+        stall_threshold,
+        http_version,
+        pool_idle_timeout,
+        pool_max_idle_per_host,
+        tcp_keepalive,
+        tcp_nodelay,
+        redirects,
+        no_proxy,
+        proxy,
+        cacert,
+        insecure,
+        cert,
+        key,
+        identity,
+        identity_password,
+        cookie_store,
+        cookies,
+        cookie_jar,
+        retries,
+        retry_delay,
+        retry_jitter,
+        verbosity,
+        events_to_stdout,
+    }))
+}
+
+/// The path of the last '--config', read before the other flags so they can override it
+///
+/// The flags taking a value skip it, so a value that only looks like '--config', as in
+/// `--header --config`, isn't taken for it.
+fn config_flag(args: &[String]) -> Option<&str> {
+    let mut path = None;
+    let mut i = 1;
+    while i < args.len() {
+        match flags::find(&args[i]) {
+            Some(flag) if flag.long == "--config" => {
+                path = args.get(i + 1).map(String::as_str);
+                i += 1;
+            }
+            Some(flag) if flag.kind != Kind::Switch => i += 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A config file of its own holding `config`, so the user's one isn't read
+    fn config_file(name: &str, config: &str) -> String {
+        let dir = std::env::temp_dir().join(format!(
+            "chunk_uploader_args_{}_{}",
+            name,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        std::fs::write(&path, config).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn cli(config: &str, args: &[&str]) -> Vec<String> {
+        ["chunk_uploader", "--config", config]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.to_string())
+            .collect()
+    }
+
+    fn parsed(args: &[&str]) -> Args {
+        let config = config_file("parsed", "");
+        match parse(&cli(&config, args)) {
+            Ok(Some(args)) => args,
+            Ok(None) => panic!("nothing to upload"),
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn refused(args: &[&str]) -> String {
+        let config = config_file("refused", "");
+        match parse(&cli(&config, args)) {
+            Err(CliError::Usage(message)) => message,
+            Err(err) => panic!("not a usage error: {}", err),
+            Ok(_) => panic!("{:?} should be refused", args),
+        }
+    }
+
+    #[test]
+    fn reads_flags_and_trailing_paths() {
+        let args = parsed(&[
+            "-c",
+            "8MiB",
+            "-u",
+            "http://localhost/up",
+            "a.bin",
+            "-",
+            "b.bin",
+        ]);
+        assert_eq!(args.chunk_size, 8 * 1024 * 1024);
+        assert_eq!(args.url.as_deref(), Some("http://localhost/up"));
+        assert_eq!(args.paths, ["a.bin", "-", "b.bin"]);
+        assert_eq!(args.verbosity, Verbosity::Normal);
+    }
+
+    #[test]
+    fn takes_the_config_from_the_last_config_flag_not_a_value_like_it() {
+        let config = config_file("config_flag", "chunk_size = 1234\n");
+        let args = cli(&config, &["--prefix", "--config"]);
+        assert_eq!(config_flag(&args), Some(config.as_str()));
+        let Ok(Some(args)) = parse(&args) else {
+            panic!("the config file should be read");
+        };
+        assert_eq!(args.chunk_size, 1234);
+        assert_eq!(args.prefix.as_deref(), Some("--config"));
+
+        let args = cli("first.toml", &["--config", &config]);
+        assert_eq!(config_flag(&args), Some(config.as_str()));
+        assert_eq!(config_flag(&["x".to_string(), "-v".to_string()]), None);
+    }
+
+    #[test]
+    fn refuses_flags_that_exclude_each_other() {
+        let refusals = [
+            (
+                &["--quiet", "--verbose"][..],
+                "Only one of '--quiet' and '--verbose'",
+            ),
+            (
+                &["--dry-run", "--output", "json"],
+                "'--dry-run' only prints text",
+            ),
+            (
+                &["--token", "t", "--user", "u:p"],
+                "Only one of '--token' and '--user'",
+            ),
+            (
+                &["--tar", "dir", "--tar-presize", "--total-size", "10"],
+                "'--tar-presize' and '--total-size' can't be used together",
+            ),
+            (
+                &["--compress", "gzip", "--form-field", "file"],
+                "'--compress' can't be used with '--form-field'",
+            ),
+            (
+                &["--progress-format", "jsonl", "--output", "json"],
+                "can't both write to stdout",
+            ),
+            (
+                &[
+                    "--progress-format",
+                    "jsonl",
+                    "--progress-file",
+                    "p",
+                    "--progress-fd",
+                    "3",
+                ],
+                "Only one of '--progress-file' and '--progress-fd'",
+            ),
+            (
+                &["--parallel", "2", "--chain", "header:X-Next"],
+                "so it can't be used with '--parallel'",
+            ),
+            (
+                &["--protocol", "tus", "--legacy-range"],
+                "'--legacy-range' can only be used with the raw protocol",
+            ),
+        ];
+        for (args, expected) in refusals {
+            let message = refused(args);
+            assert!(message.contains(expected), "{:?}: {}", args, message);
+        }
+    }
+
+    #[test]
+    fn refuses_flags_missing_the_one_they_need() {
+        let refusals = [
+            (&["--tar-presize"][..], "need '--tar'"),
+            (&["--hook-strict"], "'--hook-strict' needs"),
+            (&["--check-chunk-soft"], "needs '--check-chunk-url'"),
+            (
+                &["--verify-block-size", "1M"],
+                "'--verify-block-size' needs '--verify'",
+            ),
+            (&["--notify-token", "t"], "need '--notify-url'"),
+            (&["--init-body", "{}"], "need '--init-url'"),
+            (
+                &["--finalize-body-template", "{}"],
+                "needs '--finalize-url'",
+            ),
+            (&["--chain-request-header", "X-Next"], "needs '--chain'"),
+            (&["--progress-file", "p"], "need '--progress-format jsonl'"),
+        ];
+        for (args, expected) in refusals {
+            let message = refused(args);
+            assert!(message.contains(expected), "{:?}: {}", args, message);
+        }
+        // Given with what they need they're taken
+        parsed(&["--tar", "dir", "--tar-presize"]);
+        parsed(&["--verify", "readback", "--verify-block-size", "1M"]);
+        parsed(&["--notify-url", "http://localhost/n", "--notify-token", "t"]);
+    }
+
+    #[test]
+    fn lets_no_verify_undo_the_config_file() {
+        let config = config_file("no_verify", "verify = \"readback\"\n");
+        let Ok(Some(args)) = parse(&cli(&config, &["--no-verify"])) else {
+            panic!("--no-verify should be taken");
+        };
+        assert!(args.verify.is_none());
+        let Ok(Some(args)) = parse(&cli(&config, &[])) else {
+            panic!("the config file should be read");
+        };
+        assert!(args.verify.is_some());
+    }
+}
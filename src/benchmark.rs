@@ -0,0 +1,136 @@
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use hyper::body::HttpBody;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use serde_json::{json, Value};
+
+use chunk_uploader::output::Output;
+use chunk_uploader::walk::Entry;
+use chunk_uploader::{format_bytes, ChunkUploader, Source, UploadError};
+
+/// Starts the server `--benchmark` uploads to on a free local port and returns its URL
+///
+/// Every request is answered with an empty 200 once its body was read and thrown away, so
+/// only the reading, chunking and sending is measured and never a real server.
+pub fn sink() -> io::Result<String> {
+    let service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|request: Request<Body>| async move {
+            let mut body = request.into_body();
+            while let Some(data) = body.data().await {
+                data?;
+            }
+            Ok::<_, hyper::Error>(Response::new(Body::empty()))
+        }))
+    });
+    let server = Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+        .map_err(io::Error::other)?
+        .serve(service);
+    let url = format!("http://{}/benchmark", server.local_addr());
+    tokio::spawn(server);
+    Ok(url)
+}
+
+/// How one chunk size did over all the files
+pub struct Run {
+    pub chunk_size: u64,
+    pub parallel: usize,
+    pub bytes: u64,
+    pub chunks: u64,
+    pub retries: u64,
+    pub elapsed: Duration,
+}
+
+impl Run {
+    /// Bytes per second from the first request of the first file to the end of the last
+    fn rate(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.bytes as f64 / secs,
+            _ => 0.0,
+        }
+    }
+
+    pub fn json(&self) -> Value {
+        json!({
+            "chunk_size": self.chunk_size,
+            "parallel": self.parallel,
+            "bytes": self.bytes,
+            "chunks": self.chunks,
+            "retries": self.retries,
+            "elapsed_ms": self.elapsed.as_millis() as u64,
+            "bytes_per_second": self.rate() as u64,
+        })
+    }
+}
+
+/// One row per chunk size, the fastest marked when there are several
+pub fn print_table(runs: &[Run], out: Output) {
+    let fastest = runs.iter().map(Run::rate).fold(0.0, f64::max);
+    out.result(&format!(
+        "{:>12}  {:>8}  {:>12}  {:>7}  {:>9}  {:>14}",
+        "Chunk size", "Parallel", "Sent", "Chunks", "Time", "Throughput"
+    ));
+    for run in runs {
+        let mark = match runs.len() > 1 && run.rate() == fastest {
+            true => "  fastest",
+            false => "",
+        };
+        out.result(&format!(
+            "{:>12}  {:>8}  {:>12}  {:>7}  {:>8.2}s  {:>12}/s{}",
+            format_bytes(run.chunk_size),
+            run.parallel,
+            format_bytes(run.bytes),
+            run.chunks,
+            run.elapsed.as_secs_f64(),
+            format_bytes(run.rate() as u64),
+            mark
+        ));
+    }
+}
+
+/// Uploads every file to the sink once for each chunk size, stopping at the first that fails
+pub async fn sweep(
+    uploader: &ChunkUploader,
+    files: &[Entry],
+    url: &str,
+    chunk_sizes: &[u64],
+    parallel: usize,
+    announce: bool,
+    out: Output,
+) -> Result<Vec<Run>, UploadError> {
+    let mut runs = Vec::new();
+    for &chunk_size in chunk_sizes {
+        let uploader = uploader.with_chunk_size(chunk_size)?;
+        if announce {
+            out.line(&format!(
+                "Benchmarking chunks of {}, {} in parallel",
+                format_bytes(chunk_size),
+                parallel
+            ));
+        }
+        let mut run = Run {
+            chunk_size,
+            parallel,
+            bytes: 0,
+            chunks: 0,
+            retries: 0,
+            elapsed: Duration::ZERO,
+        };
+        let started = Instant::now();
+        for file in files {
+            let report = uploader
+                .upload(Source::File(file.path.clone().into()), url)
+                .await?;
+            let stats = report.stats();
+            run.bytes += report.total_bytes;
+            run.chunks += stats.chunks;
+            run.retries += stats.retries;
+        }
+        run.elapsed = started.elapsed();
+        runs.push(run);
+    }
+    Ok(runs)
+}
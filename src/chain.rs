@@ -0,0 +1,65 @@
+use reqwest::header::HeaderName;
+use serde_json::Value;
+
+use crate::responses::ReadResponse;
+use crate::JsonPath;
+
+/// Where a chunk response carries the value the next chunk request has to echo, like a
+/// continuation token
+#[derive(Clone, PartialEq, Debug)]
+pub enum ChainFrom {
+    /// A response header like `X-Next-Token`
+    Header(HeaderName),
+    /// A string or number in the JSON body, found with a path like `$.next_token`
+    Json(JsonPath),
+}
+
+impl ChainFrom {
+    /// The value the chunk's response carries for the next chunk
+    pub(crate) fn extract(&self, res: &ReadResponse) -> Result<String, String> {
+        match self {
+            ChainFrom::Header(name) => res
+                .headers
+                .get(name)
+                .ok_or_else(|| format!("its response has no {} header", name))?
+                .to_str()
+                .map(|value| value.trim().to_string())
+                .map_err(|_| format!("its response's {} header isn't text", name)),
+            ChainFrom::Json(path) => {
+                let document: Value = serde_json::from_slice(&res.body)
+                    .map_err(|e| format!("its response isn't JSON: {}", e))?;
+                match path.find(&document) {
+                    Some(Value::String(value)) => Ok(value.clone()),
+                    Some(Value::Number(value)) => Ok(value.to_string()),
+                    Some(_) => Err(format!("{} in its response isn't a string", path)),
+                    None => Err(format!("its response has nothing at {}", path)),
+                }
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for ChainFrom {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("header", name)) => HeaderName::from_bytes(name.trim().as_bytes())
+                .map(ChainFrom::Header)
+                .map_err(|_| format!("Invalid header name '{}' in '{}'", name, s)),
+            Some(("json", path)) => path.trim().parse().map(ChainFrom::Json),
+            _ => Err(format!(
+                "Invalid chained value source '{s}', expected header:<name> or json:<path>"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for ChainFrom {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ChainFrom::Header(name) => write!(f, "header:{}", name),
+            ChainFrom::Json(path) => write!(f, "json:{}", path),
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use base64::prelude::*;
+use md5::{Digest, Md5};
+use reqwest::header::HeaderName;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::Sha256;
+
+use crate::xxh3::Xxh3;
+
+/// The digest [`crate::ChunkUploaderBuilder::checksum`] sends with each chunk and computes for
+/// the range and the manifest
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Checksum {
+    Md5,
+    Sha1,
+    #[default]
+    Sha256,
+    /// CRC-32 with the Castagnoli polynomial, as S3 and GCS take it
+    Crc32c,
+    /// 64-bit XXH3, fast to compute but only good to catch accidents
+    Xxh3,
+}
+
+impl std::str::FromStr for Checksum {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "md5" => Ok(Checksum::Md5),
+            "sha1" => Ok(Checksum::Sha1),
+            "sha256" => Ok(Checksum::Sha256),
+            "crc32c" => Ok(Checksum::Crc32c),
+            "xxh3" => Ok(Checksum::Xxh3),
+            _ => Err(format!(
+                "Unknown checksum algorithm '{s}', expected md5, sha1, sha256, crc32c or xxh3"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Checksum {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Checksum::Md5 => "md5",
+            Checksum::Sha1 => "sha1",
+            Checksum::Sha256 => "sha256",
+            Checksum::Crc32c => "crc32c",
+            Checksum::Xxh3 => "xxh3",
+        })
+    }
+}
+
+impl Checksum {
+    /// The name the algorithm is printed with, e.g. `SHA-256`
+    pub fn label(self) -> &'static str {
+        match self {
+            Checksum::Md5 => "MD5",
+            Checksum::Sha1 => "SHA-1",
+            Checksum::Sha256 => "SHA-256",
+            Checksum::Crc32c => "CRC32C",
+            Checksum::Xxh3 => "XXH3",
+        }
+    }
+
+    /// The header each chunk's digest is sent in unless another is asked for
+    pub fn header(self) -> HeaderName {
+        HeaderName::from_static(match self {
+            Checksum::Md5 => "content-md5",
+            Checksum::Sha1 => "x-amz-checksum-sha1",
+            Checksum::Sha256 => "x-amz-checksum-sha256",
+            Checksum::Crc32c => "x-goog-hash",
+            Checksum::Xxh3 => "x-checksum-xxh3",
+        })
+    }
+
+    /// The header value for a chunk's raw `digest`, base64 encoded and for `x-goog-hash` named
+    /// as GCS wants it, e.g. `crc32c=...`
+    pub(crate) fn header_value(self, header: &HeaderName, digest: &[u8]) -> String {
+        let encoded = BASE64_STANDARD.encode(digest);
+        match header.as_str() == "x-goog-hash" {
+            true => format!("{}={}", self, encoded),
+            false => encoded,
+        }
+    }
+}
+
+/// Any of the algorithms, fed a piece at a time
+#[derive(Clone)]
+pub(crate) enum Hasher {
+    Md5(Md5),
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Crc32c(u32),
+    Xxh3(Box<Xxh3>),
+}
+
+impl Hasher {
+    pub fn new(checksum: Checksum) -> Self {
+        match checksum {
+            Checksum::Md5 => Hasher::Md5(Md5::new()),
+            Checksum::Sha1 => Hasher::Sha1(Sha1::new()),
+            Checksum::Sha256 => Hasher::Sha256(Sha256::new()),
+            Checksum::Crc32c => Hasher::Crc32c(!0),
+            Checksum::Xxh3 => Hasher::Xxh3(Box::new(Xxh3::new())),
+        }
+    }
+
+    /// The digest of `data` in one go
+    pub fn digest(checksum: Checksum, data: &[u8]) -> Vec<u8> {
+        let mut hasher = Hasher::new(checksum);
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Md5(md5) => md5.update(data),
+            Hasher::Sha1(sha1) => sha1.update(data),
+            Hasher::Sha256(sha256) => sha256.update(data),
+            Hasher::Crc32c(crc) => *crc = crc32c(*crc, data),
+            Hasher::Xxh3(xxh3) => xxh3.update(data),
+        }
+    }
+
+    /// The raw digest, big-endian for the CRC and XXH3 as their hex is usually written
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Md5(md5) => md5.finalize().to_vec(),
+            Hasher::Sha1(sha1) => sha1.finalize().to_vec(),
+            Hasher::Sha256(sha256) => sha256.finalize().to_vec(),
+            Hasher::Crc32c(crc) => (!crc).to_be_bytes().to_vec(),
+            Hasher::Xxh3(xxh3) => xxh3.digest().to_be_bytes().to_vec(),
+        }
+    }
+}
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0x82F6_3B78,
+                _ => crc >> 1,
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Continues the CRC-32C `crc`, kept inverted between calls
+fn crc32c(mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        crc = CRC32C_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_the_crc32c_check_value() {
+        assert_eq!(
+            Hasher::digest(Checksum::Crc32c, b"123456789"),
+            0xE306_9283u32.to_be_bytes()
+        );
+        assert_eq!(Hasher::digest(Checksum::Crc32c, b""), [0; 4]);
+
+        // RFC 3720 B.4, 32 bytes of zeros and of ones
+        assert_eq!(
+            Hasher::digest(Checksum::Crc32c, &[0; 32]),
+            0x8A91_36AAu32.to_be_bytes()
+        );
+        assert_eq!(
+            Hasher::digest(Checksum::Crc32c, &[0xFF; 32]),
+            0x62A8_AB43u32.to_be_bytes()
+        );
+
+        let mut hasher = Hasher::new(Checksum::Crc32c);
+        hasher.update(b"1234");
+        hasher.update(b"56789");
+        assert_eq!(hasher.finalize(), 0xE306_9283u32.to_be_bytes());
+    }
+}
@@ -0,0 +1,267 @@
+use std::str::FromStr;
+
+use crate::flags::{Flag, Kind, FLAGS};
+
+/// The name the scripts complete for, the binary's
+const COMMAND: &str = "chunk_uploader";
+
+/// Flags whose value is a file, completed with the paths around
+const FILE_FLAGS: &[&str] = &[
+    "--file",
+    "--files-from",
+    "--error-log",
+    "--cacert",
+    "--cert",
+    "--key",
+    "--identity",
+    "--cookie-jar",
+    "--if-match-file",
+    "--save-final-response",
+    "--manifest",
+    "--delta-from",
+    "--metrics-csv",
+    "--metrics-textfile",
+    "--log-file",
+    "--progress-file",
+    "--config",
+];
+
+/// Flags whose value is a directory
+const DIR_FLAGS: &[&str] = &["--dir", "--tar", "--save-responses"];
+
+const METHODS: &[&str] = &["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"];
+
+/// Flags taking one of a few values, offered as they are
+const CHOICES: &[(&str, &[&str])] = &[
+    ("--method", METHODS),
+    ("--init-method", METHODS),
+    ("--finalize-method", METHODS),
+    ("--abort-method", METHODS),
+    ("--protocol", &["raw", "tus", "s3", "gcs", "azure"]),
+    ("--compress", &["none", "gzip"]),
+    ("--compress-stream", &["zstd"]),
+    ("--retry-jitter", &["none", "full", "equal"]),
+    ("--redirects", &["follow", "none", "sticky"]),
+    ("--tcp-nodelay", &["true", "false"]),
+    ("--on-failure", &["keep", "abort"]),
+    ("--notify-on", &["success", "failure", "always"]),
+    ("--skip-existing-by", &["size", "hash"]),
+    ("--checksum", &["md5", "sha1", "sha256", "crc32c", "xxh3"]),
+    ("--output", &["text", "json"]),
+    ("--progress-format", &["text", "jsonl"]),
+    ("--color", &["auto", "always", "never"]),
+];
+
+/// A shell `--completions` writes a script for
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            "powershell" => Ok(Shell::Powershell),
+            _ => Err(format!(
+                "Unknown shell '{s}', expected bash, zsh, fish or powershell"
+            )),
+        }
+    }
+}
+
+/// What a flag's value completes to
+enum Complete {
+    Nothing,
+    Files,
+    Dirs,
+    Choices(&'static [&'static str]),
+}
+
+fn complete(flag: &Flag) -> Complete {
+    if let Some((_, choices)) = CHOICES.iter().find(|(long, _)| *long == flag.long) {
+        return Complete::Choices(choices);
+    }
+    match flag.long {
+        long if FILE_FLAGS.contains(&long) => Complete::Files,
+        long if DIR_FLAGS.contains(&long) => Complete::Dirs,
+        _ => Complete::Nothing,
+    }
+}
+
+/// Every name the flag goes by
+fn names(flag: &Flag) -> Vec<&'static str> {
+    flag.short.into_iter().chain([flag.long]).collect()
+}
+
+/// The completion script for `shell`, generated from the flags parsing works from
+pub fn script(shell: Shell) -> String {
+    match shell {
+        Shell::Bash => bash(),
+        Shell::Zsh => zsh(),
+        Shell::Fish => fish(),
+        Shell::Powershell => powershell(),
+    }
+}
+
+fn bash() -> String {
+    let mut cases = String::new();
+    for flag in FLAGS.iter().filter(|flag| flag.kind != Kind::Switch) {
+        let reply = match complete(flag) {
+            Complete::Nothing => "return".to_string(),
+            Complete::Files => "COMPREPLY=($(compgen -f -- \"$cur\")); return".to_string(),
+            Complete::Dirs => "COMPREPLY=($(compgen -d -- \"$cur\")); return".to_string(),
+            Complete::Choices(choices) => format!(
+                "COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return",
+                choices.join(" ")
+            ),
+        };
+        cases.push_str(&format!(
+            "        {})\n            {}\n            ;;\n",
+            names(flag).join("|"),
+            reply
+        ));
+    }
+    let all = FLAGS.iter().flat_map(names).collect::<Vec<_>>().join(" ");
+    format!(
+        r#"_{COMMAND}() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    local prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    case "$prev" in
+{cases}    esac
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W "{all}" -- "$cur"))
+    else
+        COMPREPLY=($(compgen -f -- "$cur"))
+    fi
+}}
+complete -o filenames -F _{COMMAND} {COMMAND}
+"#
+    )
+}
+
+fn zsh() -> String {
+    // Brackets end the description and the spec is single quoted
+    let describe = |help: &str| {
+        help.replace('\\', "\\\\")
+            .replace('[', "\\[")
+            .replace(']', "\\]")
+            .replace(':', "\\:")
+            .replace('\'', "'\\''")
+    };
+    let mut specs = String::new();
+    for flag in FLAGS {
+        let value = match (flag.kind == Kind::Switch, complete(flag)) {
+            (true, _) => String::new(),
+            (false, Complete::Nothing) => ":value: ".to_string(),
+            (false, Complete::Files) => ":file:_files".to_string(),
+            (false, Complete::Dirs) => ":directory:_files -/".to_string(),
+            (false, Complete::Choices(choices)) => format!(":value:({})", choices.join(" ")),
+        };
+        let repeat = if flag.kind == Kind::List { "*" } else { "" };
+        for name in names(flag) {
+            specs.push_str(&format!(
+                "    '{}{}[{}]{}' \\\n",
+                repeat,
+                name,
+                describe(flag.help),
+                value
+            ));
+        }
+    }
+    format!("#compdef {COMMAND}\n\n_arguments \\\n{specs}    '*:file:_files'\n")
+}
+
+fn fish() -> String {
+    let mut lines = String::new();
+    for flag in FLAGS {
+        let mut line = format!("complete -c {COMMAND}");
+        // Single letters are short options, longer ones like -fb old style
+        if let Some(short) = flag.short.map(|short| &short[1..]) {
+            match short.len() {
+                1 => line.push_str(&format!(" -s {short}")),
+                _ => line.push_str(&format!(" -o {short}")),
+            }
+        }
+        line.push_str(&format!(" -l {}", &flag.long[2..]));
+        line.push_str(&format!(
+            " -d '{}'",
+            flag.help.replace('\\', "\\\\").replace('\'', "\\'")
+        ));
+        if flag.kind != Kind::Switch {
+            line.push_str(match complete(flag) {
+                Complete::Nothing => " -x",
+                Complete::Files => " -r -F",
+                Complete::Dirs => " -x -a '(__fish_complete_directories)'",
+                Complete::Choices(_) => " -x",
+            });
+            if let Complete::Choices(choices) = complete(flag) {
+                line.push_str(&format!(" -a '{}'", choices.join(" ")));
+            }
+        }
+        lines.push_str(&line);
+        lines.push('\n');
+    }
+    lines
+}
+
+fn powershell() -> String {
+    let quote = |s: &str| format!("'{}'", s.replace('\'', "''"));
+    let mut choices = String::new();
+    for flag in FLAGS {
+        if let Complete::Choices(values) = complete(flag) {
+            let names = names(flag)
+                .iter()
+                .map(|name| quote(name))
+                .collect::<Vec<_>>();
+            let values = values.iter().map(|value| quote(value)).collect::<Vec<_>>();
+            choices.push_str(&format!(
+                "        {{ $_ -in {} }} {{ {} }}\n",
+                names.join(","),
+                values.join(",")
+            ));
+        }
+    }
+    let mut flags = String::new();
+    for flag in FLAGS {
+        for name in names(flag) {
+            flags.push_str(&format!(
+                "        @({}, {})\n",
+                quote(name),
+                quote(flag.help)
+            ));
+        }
+    }
+    // Returning nothing falls back to PowerShell's own path completion
+    format!(
+        r#"Register-ArgumentCompleter -Native -CommandName {COMMAND} -ScriptBlock {{
+    param($wordToComplete, $commandAst, $cursorPosition)
+    $words = @($commandAst.CommandElements | ForEach-Object {{ $_.ToString() }})
+    $previous = if ($wordToComplete) {{ $words[-2] }} else {{ $words[-1] }}
+    $choices = switch ($previous) {{
+{choices}        default {{ $null }}
+    }}
+    if ($choices) {{
+        $choices | Where-Object {{ $_ -like "$wordToComplete*" }} | ForEach-Object {{
+            [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)
+        }}
+        return
+    }}
+    if ($wordToComplete -notlike '-*') {{
+        return
+    }}
+    @(
+{flags}    ) | Where-Object {{ $_[0] -like "$wordToComplete*" }} | ForEach-Object {{
+        [System.Management.Automation.CompletionResult]::new($_[0], $_[0], 'ParameterName', $_[1])
+    }}
+}}
+"#
+    )
+}
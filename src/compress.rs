@@ -0,0 +1,200 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use flate2::write::GzEncoder;
+
+use crate::checksum::{Checksum, Hasher};
+use crate::pipe::{self, Reader};
+use crate::{digest, tar, zstd, UploadOptions};
+
+/// How chunk bodies are encoded on the wire, their Content-Range still counts the file's bytes
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Compression {
+    /// Send the bytes as they are in the file
+    None,
+    /// Gzip every chunk on its own and send it with `Content-Encoding: gzip`
+    Gzip,
+}
+
+impl Compression {
+    /// The Content-Encoding naming the compression, none when the bytes are sent as they are
+    pub fn content_encoding(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+        }
+    }
+
+    /// Encodes a chunk's bytes, data that doesn't compress comes out slightly larger
+    pub fn apply(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            _ => Err(format!("Unknown compression '{s}', expected gzip or none")),
+        }
+    }
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+        })
+    }
+}
+
+/// How the whole upload is compressed as one stream before it's chunked, so the server stores
+/// the compressed object, see [`crate::ChunkUploaderBuilder::compress_stream`]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StreamCompression {
+    /// A zstd frame at the level from 1 to 19
+    Zstd(u8),
+}
+
+impl StreamCompression {
+    /// The extension the compressed object is usually named with
+    pub fn extension(self) -> &'static str {
+        match self {
+            StreamCompression::Zstd(_) => "zst",
+        }
+    }
+}
+
+impl std::str::FromStr for StreamCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, level) = match s.split_once(':') {
+            Some((name, level)) => (name, Some(level)),
+            None => (s, None),
+        };
+        if name != "zstd" {
+            return Err(format!(
+                "Unknown stream compression '{s}', expected zstd or zstd:<level>"
+            ));
+        }
+        match level.map(str::parse::<u8>) {
+            None => Ok(StreamCompression::Zstd(3)),
+            Some(Ok(level @ 1..=19)) => Ok(StreamCompression::Zstd(level)),
+            Some(_) => Err(format!("Invalid zstd level in '{s}', expected 1 to 19")),
+        }
+    }
+}
+
+impl std::fmt::Display for StreamCompression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StreamCompression::Zstd(level) => write!(f, "zstd:{}", level),
+        }
+    }
+}
+
+/// What the compressed stream was made of, see [`crate::UploadReport::compressed`]
+#[derive(Debug, Clone)]
+pub struct Compressed {
+    pub compression: StreamCompression,
+    /// Bytes read before compressing
+    pub original_bytes: u64,
+    /// The [`crate::ChunkUploaderBuilder::checksum`] algorithm, or SHA-256 with
+    /// [`crate::ChunkUploaderBuilder::sha256`], with the hex digest of the bytes before
+    /// compressing
+    pub original_checksum: Option<(Checksum, String)>,
+}
+
+impl Compressed {
+    /// How many times smaller the stream got, of the compressed length `sent`
+    pub fn ratio(&self, sent: u64) -> f64 {
+        self.original_bytes as f64 / sent.max(1) as f64
+    }
+}
+
+/// What's compressed as a stream
+pub(crate) enum Input {
+    File(PathBuf),
+    Stdin,
+    Tar(Arc<tar::Tar>),
+}
+
+/// Counts and hashes what's written on its way to the encoder
+struct Tally<W: Write> {
+    out: W,
+    len: u64,
+    hasher: Option<Hasher>,
+}
+
+impl<W: Write> Write for Tally<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let len = self.out.write(data)?;
+        self.len += len as u64;
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(&data[..len]);
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// The input compressed on a thread of its own as it's read, with what it was made of left in
+/// `done` once it's all written
+pub(crate) fn stream(
+    compression: StreamCompression,
+    input: Input,
+    opts: &UploadOptions,
+    done: Arc<Mutex<Option<Compressed>>>,
+) -> Reader {
+    let StreamCompression::Zstd(level) = compression;
+    let opts = opts.clone();
+    let checksum = opts.checksum.or(opts.sha256.then_some(Checksum::Sha256));
+    pipe::spawn("zstd encoder", move |out| {
+        let mut tally = Tally {
+            out: zstd::Encoder::new(out, level),
+            len: 0,
+            hasher: checksum.map(Hasher::new),
+        };
+        match input {
+            Input::File(path) => {
+                let mut file = File::open(&path).map_err(|err| {
+                    io::Error::new(
+                        err.kind(),
+                        format!("Error reading '{}': {}", path.display(), err),
+                    )
+                })?;
+                io::copy(&mut file, &mut tally)?;
+            }
+            Input::Stdin => {
+                io::copy(&mut io::stdin().lock(), &mut tally)?;
+            }
+            Input::Tar(tar) => tar.write_to(&mut tally, &opts)?,
+        }
+        tally.out.finish()?;
+        *done.lock().unwrap() = Some(Compressed {
+            compression,
+            original_bytes: tally.len,
+            original_checksum: checksum
+                .zip(tally.hasher)
+                .map(|(checksum, hasher)| (checksum, digest::hex(&hasher.finalize()))),
+        });
+        Ok(())
+    })
+}
@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+use serde::{Deserialize, Serialize};
+
+use chunk_uploader::output::print;
+
+/// Defaults for the command-line options read from a TOML file, the flags themselves win
+///
+/// Keys are the long flag names with `_` for `-`, e.g. `chunk_size` or `retry_delay` in ms.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    pub url: Option<String>,
+    pub prefix: Option<String>,
+    pub method: Option<String>,
+    pub chunk_size: Option<u64>,
+    pub adaptive_chunk: Option<bool>,
+    /// Bytes like `chunk_size`, the floor of `adaptive_chunk`
+    pub min_chunk_size: Option<u64>,
+    pub mmap: Option<bool>,
+    pub tar_presize: Option<bool>,
+    /// Seconds since the epoch like `--tar-clamp-mtime`
+    pub tar_clamp_mtime: Option<u64>,
+    pub clamp_range: Option<bool>,
+    pub protocol: Option<String>,
+    pub token: Option<String>,
+    pub user: Option<String>,
+    pub aws_sigv4: Option<bool>,
+    pub aws_region: Option<String>,
+    pub aws_service: Option<String>,
+    pub aws_access_key: Option<String>,
+    pub aws_secret_key: Option<String>,
+    pub aws_session_token: Option<String>,
+    pub aws_sign_payload: Option<bool>,
+    pub hmac_secret: Option<String>,
+    pub hmac_secret_file: Option<String>,
+    pub hmac_payload: Option<String>,
+    pub hmac_header: Option<String>,
+    /// `aes-gcm` like `--encrypt`
+    pub encrypt: Option<String>,
+    pub encrypt_key_file: Option<String>,
+    pub encrypt_passphrase: Option<String>,
+    pub parallel: Option<usize>,
+    pub jobs: Option<usize>,
+    pub retries: Option<u32>,
+    pub retry_delay: Option<u64>,
+    /// `none`, `full` or `equal` like `--retry-jitter`
+    pub retry_jitter: Option<String>,
+    /// Bytes per second like `--limit-rate`, e.g. `"500k"`
+    pub limit_rate: Option<String>,
+    /// Seconds or a duration like `"30s"` or `"5m"`, like `--connect-timeout`
+    pub connect_timeout: Option<String>,
+    /// Like `connect_timeout`, for `--timeout`
+    pub timeout: Option<String>,
+    /// Like `connect_timeout`, for `--max-retry-wait`
+    pub max_retry_wait: Option<String>,
+    /// Like `connect_timeout`, for `--chunk-delay`
+    pub chunk_delay: Option<String>,
+    /// Like `connect_timeout`, for `--pool-idle-timeout`
+    pub pool_idle_timeout: Option<String>,
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Like `connect_timeout`, for `--tcp-keepalive`
+    pub tcp_keepalive: Option<String>,
+    pub tcp_nodelay: Option<bool>,
+    /// `follow`, `none` or `sticky` like `--redirects`
+    pub redirects: Option<String>,
+    /// `auto`, `http1` or `http2`, the last two like `--http1` and `--http2`
+    pub http_version: Option<String>,
+    pub proxy: Option<String>,
+    pub no_proxy: Option<bool>,
+    /// Path of a PEM file like `--cacert`
+    pub cacert: Option<String>,
+    pub insecure: Option<bool>,
+    pub cookies: Option<bool>,
+    pub cookie_jar: Option<String>,
+    /// Paths of PEM files like `--cert` and `--key`
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    /// Path of a PKCS#12 file like `--identity`
+    pub identity: Option<String>,
+    pub identity_password: Option<String>,
+    /// `auto`, `require` or `off`, like no flag, `--resume` and `--no-resume`
+    pub resume: Option<String>,
+    pub chunk_md5: Option<bool>,
+    pub sha256: Option<bool>,
+    /// `md5`, `sha1`, `sha256`, `crc32c` or `xxh3` like `--checksum`
+    pub checksum: Option<String>,
+    pub checksum_header: Option<String>,
+    /// `readback` like `--verify`
+    pub verify: Option<String>,
+    pub verify_block_size: Option<u64>,
+    pub final_digest_header: Option<String>,
+    pub idempotency_key_header: Option<String>,
+    /// `header:<name>` or `json:<path>` like `--chain`
+    pub chain: Option<String>,
+    pub chain_request_header: Option<String>,
+    pub init_url: Option<String>,
+    pub init_method: Option<String>,
+    pub init_body: Option<String>,
+    /// `header:<name>` or `json:<path>` like `--upload-url-from`
+    pub upload_url_from: Option<String>,
+    pub finalize_url: Option<String>,
+    pub finalize_method: Option<String>,
+    pub finalize_body_template: Option<String>,
+    /// `keep` or `abort` like `--on-failure`
+    pub on_failure: Option<String>,
+    pub abort_url: Option<String>,
+    pub abort_method: Option<String>,
+    pub notify_url: Option<String>,
+    /// `success`, `failure` or `always` like `--notify-on`
+    pub notify_on: Option<String>,
+    pub notify_token: Option<String>,
+    pub chunk_headers: Option<bool>,
+    pub index_header: Option<String>,
+    pub count_header: Option<String>,
+    pub probe_offset: Option<bool>,
+    pub check_chunk_url: Option<String>,
+    pub check_chunk_soft: Option<bool>,
+    pub exec_before_chunk: Option<String>,
+    pub exec_after_chunk: Option<String>,
+    pub exec_on_success: Option<String>,
+    pub exec_on_failure: Option<String>,
+    pub hook_strict: Option<bool>,
+    pub hook_timeout: Option<String>,
+    pub skip_existing: Option<bool>,
+    /// `size` or `hash` like `--skip-existing-by`
+    pub skip_existing_by: Option<String>,
+    /// An ETag like `--if-match`, bare ones get quoted
+    pub if_match: Option<String>,
+    pub if_match_file: Option<String>,
+    pub if_none_match: Option<String>,
+    pub legacy_range: Option<bool>,
+    pub offset_header: Option<String>,
+    /// Comma separated like `--expect-status`, e.g. `"200,201,204"`
+    pub expect_status: Option<String>,
+    pub content_type: Option<String>,
+    pub detect_content_type: Option<bool>,
+    pub form_field: Option<String>,
+    pub compress: Option<String>,
+    /// `zstd` or `zstd:<level>` like `--compress-stream`
+    pub compress_stream: Option<String>,
+    pub progress: Option<bool>,
+    /// `auto`, `always` or `never` like `--color`
+    pub color: Option<String>,
+    /// Like `connect_timeout`, for `--stall-threshold`
+    pub stall_threshold: Option<String>,
+    pub stats: Option<bool>,
+    pub save_responses: Option<String>,
+    pub save_final_response: Option<String>,
+    pub manifest: Option<String>,
+    pub delta_from: Option<String>,
+    pub metrics_csv: Option<String>,
+    pub metrics_textfile: Option<String>,
+    /// Like `--log-level`, e.g. `"info,h2=off"`
+    pub log_level: Option<String>,
+    pub log_file: Option<String>,
+    pub hidden: Option<bool>,
+    pub follow_symlinks: Option<bool>,
+    /// Header names with one value or a list of them
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, Values>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub tus_metadata: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub form: BTreeMap<String, String>,
+    /// Cookie names with their value like `--cookie`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub cookie: BTreeMap<String, String>,
+    /// Anything not listed above, warned about rather than rejected
+    #[serde(flatten, skip_serializing)]
+    pub unknown: BTreeMap<String, toml::Value>,
+}
+
+/// One or several values for the same header
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Values {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Values {
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        match self {
+            Values::One(value) => std::slice::from_ref(value).iter(),
+            Values::Many(values) => values.iter(),
+        }
+    }
+}
+
+/// `~/.config/chunk-uploader/config.toml`, read when present and no `--config` is given
+pub fn default_path() -> Option<PathBuf> {
+    let home = env::var_os("HOME").filter(|h| !h.is_empty())?;
+    Some(PathBuf::from(home).join(".config/chunk-uploader/config.toml"))
+}
+
+/// Reads the config file, which only has to exist when it was given explicitly
+pub fn load(path: &Path, explicit: bool) -> Result<Option<Config>, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if !explicit && err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            return Err(format!(
+                "Error reading config file '{}': {}",
+                path.display(),
+                err
+            ))
+        }
+    };
+
+    let config: Config = toml::from_str(&text)
+        .map_err(|e| format!("Invalid config file '{}': {}", path.display(), e))?;
+    for key in config.unknown.keys() {
+        print(&format!(
+            "Warning: unknown key '{}' in config file '{}'",
+            key,
+            path.display()
+        ));
+    }
+    Ok(Some(config))
+}
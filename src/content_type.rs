@@ -0,0 +1,93 @@
+use std::path::Path;
+
+use reqwest::header::HeaderValue;
+
+/// Content types by lowercase file extension, for what's commonly uploaded in chunks
+const BY_EXTENSION: &[(&str, &str)] = &[
+    ("7z", "application/x-7z-compressed"),
+    ("avi", "video/x-msvideo"),
+    ("bz2", "application/x-bzip2"),
+    ("csv", "text/csv"),
+    ("flac", "audio/flac"),
+    ("gif", "image/gif"),
+    ("gz", "application/gzip"),
+    ("htm", "text/html"),
+    ("html", "text/html"),
+    ("iso", "application/x-iso9660-image"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("json", "application/json"),
+    ("jsonl", "application/x-ndjson"),
+    ("log", "text/plain"),
+    ("mkv", "video/x-matroska"),
+    ("mov", "video/quicktime"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+    ("ndjson", "application/x-ndjson"),
+    ("ogg", "audio/ogg"),
+    ("parquet", "application/vnd.apache.parquet"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("svg", "image/svg+xml"),
+    ("tar", "application/x-tar"),
+    ("tgz", "application/gzip"),
+    ("tsv", "text/tab-separated-values"),
+    ("txt", "text/plain"),
+    ("wav", "audio/wav"),
+    ("webm", "video/webm"),
+    ("webp", "image/webp"),
+    ("xml", "application/xml"),
+    ("xz", "application/x-xz"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("zip", "application/zip"),
+    ("zst", "application/zstd"),
+];
+
+/// Parses a MIME type like `text/csv` or `application/json; charset=utf-8`
+pub fn parse_content_type(value: &str) -> Result<HeaderValue, String> {
+    let invalid = || {
+        format!(
+            "Invalid content type '{}', expected e.g. text/csv or application/json; charset=utf-8",
+            value
+        )
+    };
+    let mut parts = value.trim().split(';');
+    let essence = parts.next().unwrap_or_default().trim();
+    match essence.split_once('/') {
+        Some((kind, subtype)) if is_token(kind) && is_token(subtype) => {}
+        _ => return Err(invalid()),
+    }
+    for param in parts {
+        match param.trim().split_once('=') {
+            Some((name, value)) if is_token(name) && (is_token(value) || is_quoted(value)) => {}
+            _ => return Err(invalid()),
+        }
+    }
+    HeaderValue::from_str(value.trim()).map_err(|_| invalid())
+}
+
+/// Guesses a file's content type from its extension, none if it isn't a known one
+pub fn detect_content_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    BY_EXTENSION
+        .iter()
+        .find(|(known, _)| *known == extension)
+        .map(|(_, content_type)| *content_type)
+}
+
+/// An RFC 9110 token, what type, subtype and parameter names are made of
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+fn is_quoted(s: &str) -> bool {
+    s.len() >= 2
+        && s.starts_with('"')
+        && s.ends_with('"')
+        && s[1..s.len() - 1]
+            .bytes()
+            .all(|b| b == b'\t' || (b' '..=b'~').contains(&b))
+}
@@ -0,0 +1,114 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock};
+
+use cookie_store::{CookieStore, RawCookie};
+use reqwest::header::HeaderValue;
+use reqwest::Url;
+
+/// The cookies responses set, sent back with later requests to the hosts and paths they're for
+///
+/// Each request, a redirected one too, gets the cookies matching its own URL, so a cookie never
+/// follows a redirect to a host it wasn't set for.
+pub(crate) struct Cookies {
+    store: RwLock<CookieStore>,
+    /// Cookies given up front, set for every host an upload sends requests to
+    given: Vec<(String, String)>,
+    /// Hosts the given cookies were set for, once so what a server sets instead stays
+    seeded: Mutex<HashSet<String>>,
+    /// The file the cookies are read from and saved to between runs
+    jar: Option<PathBuf>,
+}
+
+impl Cookies {
+    /// Reads the jar's cookies, a jar that doesn't exist yet starts empty
+    pub(crate) fn new(given: Vec<(String, String)>, jar: Option<PathBuf>) -> Result<Self, String> {
+        for (name, value) in &given {
+            match RawCookie::parse(format!("{}={}", name, value)) {
+                Ok(cookie) if cookie.name() == name && cookie.value() == value => {}
+                _ => {
+                    return Err(format!(
+                        "Invalid cookie '{}={}', expected 'name=value'",
+                        name, value
+                    ))
+                }
+            }
+        }
+        let store = match &jar {
+            Some(path) if path.exists() => File::open(path)
+                .map_err(|e| e.to_string())
+                .and_then(|file| {
+                    CookieStore::load_json_all(BufReader::new(file)).map_err(|e| e.to_string())
+                })
+                .map_err(|e| format!("Error reading the cookie jar '{}': {}", path.display(), e))?,
+            _ => CookieStore::default(),
+        };
+        Ok(Cookies {
+            store: RwLock::new(store),
+            given,
+            seeded: Mutex::default(),
+            jar,
+        })
+    }
+
+    /// Sets the given cookies for all of `url`'s host the first time, as if it had set them
+    pub(crate) fn seed(&self, url: &Url) {
+        let host = url.host_str().unwrap_or_default().to_string();
+        if self.given.is_empty() || !self.seeded.lock().unwrap().insert(host) {
+            return;
+        }
+        let mut store = self.store.write().unwrap();
+        for (name, value) in &self.given {
+            let mut cookie = RawCookie::new(name.clone(), value.clone());
+            cookie.set_path("/");
+            // A URL without a host can't be sent to, the upload fails on it by itself
+            let _ = store.insert_raw(&cookie, url);
+        }
+    }
+
+    /// Saves the cookies to the jar, if there is one
+    ///
+    /// Session cookies are kept too, as resuming the upload later continues the same session.
+    pub(crate) fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.jar else {
+            return Ok(());
+        };
+        let mut json = Vec::new();
+        self.store
+            .read()
+            .unwrap()
+            .save_incl_expired_and_nonpersistent_json(&mut json)
+            .map_err(|e| e.to_string())
+            .and_then(|()| fs::write(path, json).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Error saving the cookie jar '{}': {}", path.display(), e))
+    }
+}
+
+impl reqwest::cookie::CookieStore for Cookies {
+    fn set_cookies(&self, headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let cookies = headers.filter_map(|value| {
+            let value = value.to_str().ok()?;
+            RawCookie::parse(value).ok().map(RawCookie::into_owned)
+        });
+        self.store
+            .write()
+            .unwrap()
+            .store_response_cookies(cookies, url);
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let header = self
+            .store
+            .read()
+            .unwrap()
+            .get_request_values(url)
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        (!header.is_empty())
+            .then(|| HeaderValue::from_str(&header).ok())
+            .flatten()
+    }
+}
@@ -0,0 +1,196 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+
+use serde_json::Value;
+
+use chunk_uploader::output::{print, ColorChoice, Output};
+use chunk_uploader::{format_bytes, EncryptionKey, EncryptionRecord};
+
+use crate::exit::{CliError, Exit};
+use crate::flags;
+
+/// Where `decrypt` falls back to for its key, the same as the upload's
+const KEY_FILE_ENV: &str = "CHUNK_UPLOADER_ENCRYPT_KEY_FILE";
+const PASSPHRASE_ENV: &str = "CHUNK_UPLOADER_ENCRYPT_PASSPHRASE";
+
+/// What `decrypt` was started with
+pub struct Options {
+    record: EncryptionRecord,
+    key: EncryptionKey,
+    /// Stdin when none or `-`
+    input: Option<PathBuf>,
+    /// Stdout when none
+    output: Option<PathBuf>,
+}
+
+/// Parses the arguments after `decrypt`, none when only the help was asked for
+pub fn parse(args: &[String]) -> Result<Option<Options>, CliError> {
+    let (mut record, mut file) = (None, None);
+    let (mut key_file, mut passphrase) = (None, None);
+    let (mut input, mut output) = (None, None);
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-h" | "--help" => {
+                print(&flags::decrypt_help());
+                return Ok(None);
+            }
+            "--record" | "--file" | "--key-file" | "--passphrase" | "-o" | "--output" => {
+                let Some(value) = args.get(i + 1).cloned() else {
+                    return Err(CliError::Usage(format!(
+                        "Missing value after argument '{}'",
+                        args[i]
+                    )));
+                };
+                match args[i].as_str() {
+                    "--record" => record = Some(value),
+                    "--file" => file = Some(value),
+                    "--key-file" => key_file = Some(value),
+                    "--passphrase" => passphrase = Some(value),
+                    _ => output = Some(PathBuf::from(value)),
+                }
+                i += 1;
+            }
+            a if a == "-" || !a.starts_with('-') => {
+                if input.is_some() {
+                    return Err(CliError::Usage(format!(
+                        "Unexpected argument '{a}', decrypt takes one input"
+                    )));
+                }
+                input = Some(PathBuf::from(a));
+            }
+            a => {
+                return Err(CliError::Usage(format!(
+                    "Unknown argument '{a}' for decrypt, use 'decrypt --help' for help"
+                )));
+            }
+        }
+        i += 1;
+    }
+    let Some(record) = record else {
+        return Err(CliError::Usage(
+            "Missing '--record' with how the upload was encrypted".to_string(),
+        ));
+    };
+    if key_file.is_none() && passphrase.is_none() {
+        key_file = env::var(KEY_FILE_ENV).ok();
+        passphrase = env::var(PASSPHRASE_ENV).ok();
+    }
+    let Some(key) = key(key_file.as_deref(), passphrase.as_deref())? else {
+        return Err(CliError::Usage(
+            "Missing '--key-file' or '--passphrase' to decrypt with".to_string(),
+        ));
+    };
+    Ok(Some(Options {
+        record: read_record(&record, file.as_deref())?,
+        key,
+        input: input.filter(|input| input.as_os_str() != "-"),
+        output,
+    }))
+}
+
+/// The key from a key file or a passphrase, for the upload and `decrypt` alike
+pub fn key(
+    key_file: Option<&str>,
+    passphrase: Option<&str>,
+) -> Result<Option<EncryptionKey>, CliError> {
+    match (key_file, passphrase) {
+        (Some(_), Some(_)) => Err(CliError::Usage(
+            "A key file and a passphrase can't be used together".to_string(),
+        )),
+        (Some(path), None) => match fs::read(path) {
+            Ok(contents) => EncryptionKey::from_key_file(&contents)
+                .map(Some)
+                .map_err(|err| CliError::Usage(format!("Invalid key file '{}': {}", path, err))),
+            Err(err) => Err(CliError::Io(format!(
+                "Error reading key file '{}': {}",
+                path, err
+            ))),
+        },
+        (None, Some("")) => Err(CliError::Usage("The passphrase is empty".to_string())),
+        (None, Some(passphrase)) => Ok(Some(EncryptionKey::Passphrase(passphrase.to_string()))),
+        (None, None) => Ok(None),
+    }
+}
+
+/// The record in a file holding it alone, the `--output json` of its upload or a manifest,
+/// whose encrypted upload of `file` is taken when there are several
+fn read_record(path: &str, file: Option<&str>) -> Result<EncryptionRecord, CliError> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| CliError::Io(format!("Error reading record '{}': {}", path, e)))?;
+    let invalid = |msg: &str| CliError::Usage(format!("Invalid record '{}': {}", path, msg));
+    let document: Value = serde_json::from_str(&text).map_err(|e| invalid(&e.to_string()))?;
+    let record = match document.get("files").and_then(Value::as_array) {
+        Some(files) => {
+            let mut encrypted = files.iter().filter(|entry| {
+                let name = entry
+                    .get("file")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                // The manifest has canonical paths, a file name is enough to pick one
+                entry.get("encryption").is_some()
+                    && file.is_none_or(|file| name == file || name.ends_with(&format!("/{file}")))
+            });
+            match (encrypted.next(), encrypted.next()) {
+                (Some(entry), None) => entry["encryption"].clone(),
+                (None, _) => return Err(invalid("the manifest has no encrypted upload of it")),
+                (Some(_), Some(_)) => {
+                    return Err(invalid(
+                        "the manifest has several encrypted uploads, pick one with '--file'",
+                    ))
+                }
+            }
+        }
+        None => document.get("encryption").cloned().unwrap_or(document),
+    };
+    serde_json::from_value(record).map_err(|e| invalid(&e.to_string()))
+}
+
+/// Writes the plaintext, a partly written output file being removed when decrypting fails
+pub fn run(options: Options) -> Result<Exit, CliError> {
+    let input: Box<dyn Read> = match options.input.as_ref() {
+        Some(path) => Box::new(
+            File::open(path)
+                .map_err(|e| CliError::Io(format!("Error opening '{}': {}", path.display(), e)))?,
+        ),
+        None => Box::new(io::stdin().lock()),
+    };
+    let output: Box<dyn Write> = match options.output.as_ref() {
+        Some(path) => Box::new(
+            File::create(path)
+                .map_err(|e| CliError::Io(format!("Error creating '{}': {}", path.display(), e)))?,
+        ),
+        None => Box::new(io::stdout().lock()),
+    };
+    let decrypted = chunk_uploader::decrypt(
+        &options.record,
+        &options.key,
+        BufReader::new(input),
+        BufWriter::new(output),
+    );
+    match decrypted {
+        Ok(bytes) => {
+            if let Some(path) = options.output.as_ref() {
+                let decrypted =
+                    format!("Decrypted {} to '{}'", format_bytes(bytes), path.display());
+                Output::detect(ColorChoice::Auto).success(&decrypted);
+            }
+            Ok(Exit::Success)
+        }
+        // What reads stdout had enough, like `head`
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe && options.output.is_none() => {
+            Ok(Exit::Success)
+        }
+        Err(err) => {
+            if let Some(path) = options.output.as_ref() {
+                let _ = fs::remove_file(path);
+            }
+            Err(match err.kind() {
+                io::ErrorKind::InvalidData => CliError::Other(format!("Error decrypting: {}", err)),
+                _ => CliError::Io(format!("Error decrypting: {}", err)),
+            })
+        }
+    }
+}
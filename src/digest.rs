@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use sha2::{Digest, Sha256};
+use tokio::sync::Notify;
+
+use crate::checksum::{Checksum, Hasher};
+
+/// Digest of the uploaded range, fed with chunks as they're read in whatever order that is
+pub struct FileDigest {
+    inner: Mutex<Inner>,
+    fed: Notify,
+}
+
+struct Inner {
+    /// Everything before this offset went into the hasher
+    next: u64,
+    /// Chunks read ahead of `next`, keyed by their start offset
+    pending: BTreeMap<u64, Vec<u8>>,
+    hasher: Hasher,
+    /// Set when a chunk couldn't be read, so its bytes will never arrive
+    aborted: bool,
+    blocks: Option<Blocks>,
+}
+
+/// SHA-256 of every block of the range on its own, to compare with what's read back
+struct Blocks {
+    size: u64,
+    /// Where the block being hashed starts
+    start: u64,
+    hasher: Sha256,
+    done: Vec<String>,
+}
+
+impl FileDigest {
+    /// Starts hashing at `offset`, earlier bytes have to be fed with [`FileDigest::update`] first
+    ///
+    /// With a `block` size every block from `offset` on is also hashed on its own, always with
+    /// SHA-256.
+    pub fn new(checksum: Checksum, offset: u64, block: Option<u64>) -> Self {
+        FileDigest {
+            inner: Mutex::new(Inner {
+                next: offset,
+                pending: BTreeMap::new(),
+                hasher: Hasher::new(checksum),
+                aborted: false,
+                blocks: block.map(|size| Blocks {
+                    size,
+                    start: offset,
+                    hasher: Sha256::new(),
+                    done: Vec::new(),
+                }),
+            }),
+            fed: Notify::new(),
+        }
+    }
+
+    /// Adds the bytes read at `start`, anything before the digest's position is skipped over
+    /// so chunks that are sent again don't count twice
+    pub fn update(&self, start: u64, data: &[u8]) {
+        let mut inner = self.inner.lock().unwrap();
+        if start > inner.next {
+            inner.pending.insert(start, data.to_vec());
+            return;
+        }
+
+        inner.feed(start, data);
+        while let Some((&start, _)) = inner.pending.first_key_value() {
+            if start > inner.next {
+                break;
+            }
+            let data = inner.pending.pop_first().unwrap().1;
+            inner.feed(start, &data);
+        }
+        self.fed.notify_waiters();
+    }
+
+    /// Waits until every byte before `offset` has been fed, false if that won't happen anymore
+    pub async fn wait_for(&self, offset: u64) -> bool {
+        loop {
+            // Registered before checking, so a feed in between isn't missed
+            let fed = self.fed.notified();
+            tokio::pin!(fed);
+            fed.as_mut().enable();
+
+            {
+                let inner = self.inner.lock().unwrap();
+                if inner.aborted || inner.next >= offset {
+                    return !inner.aborted;
+                }
+            }
+            fed.await;
+        }
+    }
+
+    /// Wakes up everyone waiting, a chunk is missing for good
+    pub fn abort(&self) {
+        self.inner.lock().unwrap().aborted = true;
+        self.fed.notify_waiters();
+    }
+
+    /// The hex digest of everything fed so far
+    pub fn hex(&self) -> String {
+        hex(&self.inner.lock().unwrap().hasher.clone().finalize())
+    }
+
+    /// The hex digest of every block fed so far, the last one cut short where the bytes end
+    pub fn block_hexes(&self) -> Vec<String> {
+        let inner = self.inner.lock().unwrap();
+        let Some(blocks) = inner.blocks.as_ref() else {
+            return Vec::new();
+        };
+        let mut hexes = blocks.done.clone();
+        if inner.next > blocks.start {
+            hexes.push(hex(&blocks.hasher.clone().finalize()));
+        }
+        hexes
+    }
+}
+
+/// Lowercase hex of a digest
+pub fn hex(digest: &[u8]) -> String {
+    digest.iter().fold(String::new(), |mut hex, b| {
+        let _ = write!(hex, "{b:02x}");
+        hex
+    })
+}
+
+/// HMAC-SHA256 of `data` with `key`, RFC 2104
+pub(crate) fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    const BLOCK: usize = 64;
+    let mut block = [0u8; BLOCK];
+    match key.len() > BLOCK {
+        true => block[..32].copy_from_slice(&Sha256::digest(key)),
+        false => block[..key.len()].copy_from_slice(key),
+    }
+    let pad = |byte: u8| block.iter().map(|b| b ^ byte).collect::<Vec<_>>();
+    let inner = Sha256::new()
+        .chain_update(pad(0x36))
+        .chain_update(data)
+        .finalize();
+    Sha256::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize()
+        .to_vec()
+}
+
+impl Inner {
+    fn feed(&mut self, start: u64, data: &[u8]) {
+        let skip = (self.next - start) as usize;
+        if skip < data.len() {
+            self.hasher.update(&data[skip..]);
+            if let Some(blocks) = self.blocks.as_mut() {
+                let (mut data, mut at) = (&data[skip..], self.next);
+                while !data.is_empty() {
+                    let n = data.len().min((blocks.start + blocks.size - at) as usize);
+                    blocks.hasher.update(&data[..n]);
+                    (data, at) = (&data[n..], at + n as u64);
+                    if at == blocks.start + blocks.size {
+                        blocks.done.push(hex(&blocks.hasher.finalize_reset()));
+                        blocks.start = at;
+                    }
+                }
+            }
+            self.next = start + data.len() as u64;
+        }
+    }
+}
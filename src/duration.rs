@@ -0,0 +1,21 @@
+use std::time::Duration;
+
+/// Parses a duration in seconds like `30`, or with a unit like `500ms`, `30s`, `5m` or `1h`
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid duration '{}', expected e.g. 30, 30s or 5m", value);
+    let trimmed = value.trim();
+    let (number, unit) = match trimmed.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => trimmed.split_at(i),
+        None => (trimmed, ""),
+    };
+    let number = number.parse::<u64>().map_err(|_| invalid())?;
+
+    let secs = match unit.trim_start().to_ascii_lowercase().as_str() {
+        "ms" => return Ok(Duration::from_millis(number)),
+        "" | "s" => Some(number),
+        "m" => number.checked_mul(60),
+        "h" => number.checked_mul(60 * 60),
+        _ => None,
+    };
+    secs.map(Duration::from_secs).ok_or_else(invalid)
+}
@@ -0,0 +1,810 @@
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+use aes_gcm::aead::consts::U12;
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::Aes256Gcm;
+use age::secrecy::{ExposeSecret, SecretString};
+use age::{Identity as _, Recipient as _};
+use age_core::format::{FileKey, Stanza};
+use base64::prelude::*;
+use chacha20poly1305::ChaCha20Poly1305;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::digest::{hex, hmac};
+
+/// PBKDF2 rounds a passphrase is stretched with, as OWASP recommends for HMAC-SHA256
+pub const DEFAULT_PBKDF2_ITERATIONS: u32 = 600_000;
+
+/// How [`EncryptionRecord::nonce`] names the nonces of AES-GCM segments
+const NONCE_SCHEME: &str = "index-last";
+
+/// How [`EncryptionRecord::nonce`] names the nonces of age's STREAM
+const AGE_NONCE_SCHEME: &str = "age-stream";
+
+/// Bytes of a key file, or of the key its hex spells out
+const KEY_LEN: usize = 32;
+
+/// Bytes of the tag after every segment's ciphertext, with AES-GCM and age alike
+const TAG_LEN: usize = 16;
+
+/// Plaintext bytes of each chunk of age's STREAM but the last, fixed by the format
+const AGE_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// The first line of an age file
+const AGE_VERSION: &[u8] = b"age-encryption.org/v1\n";
+
+/// How chunks are encrypted before they're sent, see [`crate::ChunkUploaderBuilder::encrypt`]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Encryption {
+    /// AES-256-GCM, each chunk on its own with its tag after it
+    #[serde(rename = "aes-gcm")]
+    AesGcm,
+    /// An age file, see https://age-encryption.org/v1, that `age -d` decrypts too
+    #[serde(rename = "age")]
+    Age,
+}
+
+impl std::str::FromStr for Encryption {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "aes-gcm" => Ok(Encryption::AesGcm),
+            "age" => Ok(Encryption::Age),
+            _ => Err(format!("Unknown encryption '{s}', expected age or aes-gcm")),
+        }
+    }
+}
+
+impl std::fmt::Display for Encryption {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Encryption::AesGcm => "aes-gcm",
+            Encryption::Age => "age",
+        })
+    }
+}
+
+/// What the key of each upload is derived from
+#[derive(Clone)]
+pub enum EncryptionKey {
+    /// 32 random bytes, e.g. from a key file
+    Key([u8; KEY_LEN]),
+    /// Stretched with PBKDF2-HMAC-SHA256 and the upload's salt, or with scrypt by age
+    Passphrase(String),
+    /// An age identity, `AGE-SECRET-KEY-1…` as `age-keygen` writes it, age only
+    Identity(String),
+}
+
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            EncryptionKey::Key(_) => "Key(..)",
+            EncryptionKey::Passphrase(_) => "Passphrase(..)",
+            EncryptionKey::Identity(_) => "Identity(..)",
+        })
+    }
+}
+
+impl EncryptionKey {
+    /// The key a key file holds, as 32 bytes or 64 hex digits with whitespace around them, or
+    /// the age identity on a line of its own, the comments `age-keygen` writes around it
+    /// skipped
+    pub fn from_key_file(contents: &[u8]) -> Result<Self, String> {
+        if let Ok(key) = <[u8; KEY_LEN]>::try_from(contents) {
+            return Ok(EncryptionKey::Key(key));
+        }
+        let text = std::str::from_utf8(contents).unwrap_or_default();
+        let identity = text
+            .lines()
+            .map(str::trim)
+            .find(|line| line.starts_with("AGE-SECRET-KEY-1"));
+        if let Some(identity) = identity {
+            age::x25519::Identity::from_str(identity)
+                .map_err(|err| format!("The age identity is invalid, {}", err))?;
+            return Ok(EncryptionKey::Identity(identity.to_string()));
+        }
+        let digits = text.trim();
+        let mut key = [0u8; KEY_LEN];
+        let decoded = (digits.len() == 2 * KEY_LEN)
+            .then(|| {
+                key.iter_mut().enumerate().try_for_each(|(i, b)| {
+                    *b = u8::from_str_radix(digits.get(2 * i..2 * i + 2)?, 16).ok()?;
+                    Some(())
+                })
+            })
+            .flatten();
+        match decoded {
+            Some(()) => Ok(EncryptionKey::Key(key)),
+            None => Err(format!(
+                "A key file holds {} bytes, {} hex digits or an age identity, not {} bytes",
+                KEY_LEN,
+                2 * KEY_LEN,
+                contents.len()
+            )),
+        }
+    }
+}
+
+/// How the key was made from what [`EncryptionKey`] holds
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kdf {
+    /// The key was given as it is
+    Key,
+    Pbkdf2Sha256,
+    /// age's file key is wrapped to the public key of an age identity
+    X25519,
+    /// age's file key is wrapped with a passphrase stretched by scrypt
+    Scrypt,
+}
+
+/// Everything besides the key needed to decrypt an upload, kept in the manifest, the state
+/// file and the report, and given to the finalize request as `{encryption}`
+///
+/// With AES-GCM the upload's key is the HMAC-SHA256 of the salt keyed with the given key or
+/// the stretched passphrase. Every `segment_size` bytes of the range are encrypted on their
+/// own, the ciphertext of each followed by its 16-byte tag, so the encrypted upload is 16 bytes
+/// per segment longer, an empty range being one segment.
+///
+/// With age the upload is an age file: the header, the salt as the payload's nonce and every
+/// 64 KiB of the range encrypted with ChaCha20-Poly1305, each followed by its tag.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct EncryptionRecord {
+    pub algorithm: Encryption,
+    pub kdf: Kdf,
+    /// PBKDF2 rounds for a passphrase
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iterations: Option<u32>,
+    /// Hex, drawn at random for each upload
+    pub salt: String,
+    /// `index-last`: a segment's index as 8 bytes big-endian, 3 zero bytes and a byte that's 1
+    /// for the last segment, so a resumed upload encrypts every segment the same again.
+    /// `age-stream`: age's, the index of its 64 KiB chunk as 11 bytes and that byte.
+    pub nonce: String,
+    /// Bytes of the range in each segment but the last, the chunk size, a multiple of 64 KiB
+    /// with age
+    pub segment_size: u64,
+    /// Bytes of the range, none for stdin until it ended
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_size: Option<u64>,
+    /// Hex of the start of an HMAC keyed with the upload's key, which tells a wrong key from
+    /// damaged data
+    pub key_check: String,
+    /// Base64 of the age header, the file key wrapped in it, which a resumed upload sends again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<String>,
+}
+
+/// Encrypts and decrypts the segments of one upload
+pub(crate) struct Cipher {
+    backend: Backend,
+    pub record: EncryptionRecord,
+}
+
+enum Backend {
+    AesGcm(Box<Aes256Gcm>),
+    /// The payload of an age file, `prefix` being the header and nonce ahead of its first chunk
+    Age {
+        payload: ChaCha20Poly1305,
+        prefix: Vec<u8>,
+    },
+}
+
+impl Cipher {
+    /// A cipher with a new random salt, for an upload starting over
+    pub fn create(
+        encryption: Encryption,
+        key: &EncryptionKey,
+        segment_size: u64,
+        original_size: Option<u64>,
+    ) -> Result<Self, String> {
+        let mut salt = [0u8; 16];
+        getrandom::fill(&mut salt).map_err(|e| format!("Error drawing a salt: {}", e))?;
+        if encryption == Encryption::Age {
+            return Cipher::create_age(key, salt, segment_size, original_size);
+        }
+        let (kdf, iterations) = match key {
+            EncryptionKey::Key(_) => (Kdf::Key, None),
+            EncryptionKey::Passphrase(_) => (Kdf::Pbkdf2Sha256, Some(DEFAULT_PBKDF2_ITERATIONS)),
+            EncryptionKey::Identity(_) => return Err(IDENTITY_WITHOUT_AGE.to_string()),
+        };
+        let upload_key = upload_key(key, iterations.unwrap_or_default(), &salt);
+        Ok(Cipher {
+            backend: Backend::AesGcm(Box::new(Aes256Gcm::new(&upload_key.into()))),
+            record: EncryptionRecord {
+                algorithm: encryption,
+                kdf,
+                iterations,
+                salt: hex(&salt),
+                nonce: NONCE_SCHEME.to_string(),
+                segment_size,
+                original_size,
+                key_check: key_check(&upload_key),
+                header: None,
+            },
+        })
+    }
+
+    /// A new age file, its file key drawn at random and wrapped to the identity or passphrase
+    fn create_age(
+        key: &EncryptionKey,
+        nonce: [u8; 16],
+        segment_size: u64,
+        original_size: Option<u64>,
+    ) -> Result<Self, String> {
+        age_segment_size(segment_size)?;
+        let mut file_key = [0u8; 16];
+        getrandom::fill(&mut file_key).map_err(|e| format!("Error drawing a key: {}", e))?;
+        let wrapping = FileKey::new(Box::new(file_key));
+        let (kdf, wrapped) = match key {
+            EncryptionKey::Identity(identity) => (
+                Kdf::X25519,
+                age_identity(identity)?.to_public().wrap_file_key(&wrapping),
+            ),
+            EncryptionKey::Passphrase(passphrase) => (
+                Kdf::Scrypt,
+                age::scrypt::Recipient::new(SecretString::from(passphrase.clone()))
+                    .wrap_file_key(&wrapping),
+            ),
+            EncryptionKey::Key(_) => {
+                return Err(
+                    "age takes an identity file made with age-keygen or a passphrase, not a \
+                     raw key"
+                        .to_string(),
+                )
+            }
+        };
+        let (stanzas, _) = wrapped.map_err(|e| format!("Error wrapping the file key: {}", e))?;
+        let header = age_header(&stanzas, &file_key);
+        Ok(Cipher {
+            backend: Backend::Age {
+                payload: age_payload(&file_key, &nonce),
+                prefix: [&header[..], &nonce].concat(),
+            },
+            record: EncryptionRecord {
+                algorithm: Encryption::Age,
+                kdf,
+                iterations: None,
+                salt: hex(&nonce),
+                nonce: AGE_NONCE_SCHEME.to_string(),
+                segment_size,
+                original_size,
+                key_check: key_check(&file_key),
+                header: Some(BASE64_STANDARD.encode(&header)),
+            },
+        })
+    }
+
+    /// The cipher `record` was made with, refusing a key that isn't the one it was made with
+    pub fn resume(key: &EncryptionKey, record: EncryptionRecord) -> Result<Self, String> {
+        if record.algorithm == Encryption::Age {
+            return Cipher::resume_age(key, record);
+        }
+        if record.nonce != NONCE_SCHEME {
+            return Err(format!("Unknown nonce scheme '{}'", record.nonce));
+        }
+        if record.segment_size == 0 {
+            return Err("The segment size is 0".to_string());
+        }
+        let salt = unhex(&record.salt).ok_or("The salt isn't hex")?;
+        let iterations = match (record.kdf, key) {
+            (_, EncryptionKey::Identity(_)) => return Err(IDENTITY_WITHOUT_AGE.to_string()),
+            (Kdf::Key, EncryptionKey::Key(_)) => 0,
+            (Kdf::Pbkdf2Sha256, EncryptionKey::Passphrase(_)) => match record.iterations {
+                Some(iterations @ 1..) => iterations,
+                _ => return Err("The PBKDF2 iterations are missing".to_string()),
+            },
+            (Kdf::Key, _) => return Err("It was encrypted with a key file".to_string()),
+            (Kdf::Pbkdf2Sha256, _) => return Err("It was encrypted with a passphrase".to_string()),
+            (Kdf::X25519 | Kdf::Scrypt, _) => {
+                return Err(format!("AES-GCM doesn't use {:?}", record.kdf))
+            }
+        };
+        let upload_key = upload_key(key, iterations, &salt);
+        if key_check(&upload_key) != record.key_check {
+            return Err("The key isn't the one it was encrypted with".to_string());
+        }
+        Ok(Cipher {
+            backend: Backend::AesGcm(Box::new(Aes256Gcm::new(&upload_key.into()))),
+            record,
+        })
+    }
+
+    /// The age file `record` started, its file key unwrapped from the header again
+    fn resume_age(key: &EncryptionKey, record: EncryptionRecord) -> Result<Self, String> {
+        if record.nonce != AGE_NONCE_SCHEME {
+            return Err(format!("Unknown nonce scheme '{}'", record.nonce));
+        }
+        age_segment_size(record.segment_size)?;
+        let nonce: [u8; 16] = unhex(&record.salt)
+            .and_then(|salt| salt.try_into().ok())
+            .ok_or("The salt isn't 16 bytes of hex")?;
+        let header = record
+            .header
+            .as_deref()
+            .and_then(|header| BASE64_STANDARD.decode(header).ok())
+            .ok_or("The age header is missing")?;
+        let stanzas = age_stanzas(&header).ok_or("The age header is invalid")?;
+        let unwrapped = match (record.kdf, key) {
+            (Kdf::X25519, EncryptionKey::Identity(identity)) => {
+                age_identity(identity)?.unwrap_stanzas(&stanzas)
+            }
+            (Kdf::Scrypt, EncryptionKey::Passphrase(passphrase)) => {
+                age::scrypt::Identity::new(SecretString::from(passphrase.clone()))
+                    .unwrap_stanzas(&stanzas)
+            }
+            (Kdf::X25519, _) => return Err("It was encrypted to an age identity".to_string()),
+            (Kdf::Scrypt, _) => return Err("It was encrypted with a passphrase".to_string()),
+            (Kdf::Key | Kdf::Pbkdf2Sha256, _) => {
+                return Err(format!("age doesn't use {:?}", record.kdf))
+            }
+        };
+        let file_key = match unwrapped {
+            Some(Ok(file_key)) => *file_key.expose_secret(),
+            Some(Err(err)) => return Err(format!("The age header doesn't open, {}", err)),
+            None => return Err("The key isn't the one it was encrypted with".to_string()),
+        };
+        if key_check(&file_key) != record.key_check || age_header(&stanzas, &file_key) != header {
+            return Err("The age header doesn't match the record".to_string());
+        }
+        Ok(Cipher {
+            backend: Backend::Age {
+                payload: age_payload(&file_key, &nonce),
+                prefix: [&header[..], &nonce].concat(),
+            },
+            record,
+        })
+    }
+
+    /// The ciphertext of the chunk `offset` bytes into the range, segment `index`, with the
+    /// tags after its segments, and with age the header and nonce ahead of the first
+    pub fn seal(&self, index: u64, offset: u64, last: bool, plaintext: &[u8]) -> Vec<u8> {
+        match &self.backend {
+            Backend::AesGcm(gcm) => seal_segment(gcm.as_ref(), &nonce(index, last), plaintext),
+            Backend::Age { payload, prefix } => {
+                let first = offset / AGE_CHUNK_SIZE;
+                let mut pieces: Vec<_> = plaintext.chunks(AGE_CHUNK_SIZE as usize).collect();
+                if pieces.is_empty() {
+                    pieces.push(&[]);
+                }
+                let count = pieces.len();
+                let mut sealed =
+                    Vec::with_capacity(prefix.len() + plaintext.len() + TAG_LEN * count);
+                if offset == 0 {
+                    sealed.extend_from_slice(prefix);
+                }
+                for (i, piece) in pieces.into_iter().enumerate() {
+                    let nonce = age_nonce(first + i as u64, last && i + 1 == count);
+                    sealed.extend(seal_segment(payload, &nonce, piece));
+                }
+                sealed
+            }
+        }
+    }
+
+    fn open(&self, index: u64, last: bool, sealed: &[u8]) -> Option<Vec<u8>> {
+        match &self.backend {
+            Backend::AesGcm(gcm) => gcm.decrypt(&nonce(index, last).into(), sealed).ok(),
+            Backend::Age { .. } => unreachable!("age files are read with the age crate"),
+        }
+    }
+
+    /// Where the ciphertext of the chunk from `start` up to `end` bytes into the range goes in
+    /// the encrypted upload, every segment before it being a tag longer
+    pub fn sealed_range(&self, start: u64, end: u64, last: bool) -> (u64, u64) {
+        let (segment, prefix) = match &self.backend {
+            Backend::AesGcm(_) => (self.record.segment_size, 0),
+            Backend::Age { prefix, .. } => (AGE_CHUNK_SIZE, prefix.len() as u64),
+        };
+        let sealed_start = match start {
+            0 => 0,
+            _ => prefix + start + TAG_LEN as u64 * (start / segment),
+        };
+        let segments = match last {
+            true => end.div_ceil(segment).max(1),
+            false => end / segment,
+        };
+        (sealed_start, prefix + end + TAG_LEN as u64 * segments)
+    }
+
+    /// Length of the encrypted upload of a range of `len` bytes
+    pub fn sealed_len(&self, len: u64) -> u64 {
+        self.sealed_range(0, len, true).1
+    }
+}
+
+/// What AES-GCM refuses a key file holding an age identity with
+const IDENTITY_WITHOUT_AGE: &str =
+    "An age identity only encrypts with age, aes-gcm takes 32 bytes of key or a passphrase";
+
+/// The ciphertext of `plaintext` with its tag after it, as both AEADs lay it out
+fn seal_segment(aead: &impl Aead<NonceSize = U12>, nonce: &[u8; 12], plaintext: &[u8]) -> Vec<u8> {
+    // Only fails past what the counter covers, 64 GiB for AES-GCM, far above any segment
+    aead.encrypt(nonce.into(), plaintext)
+        .expect("the segment fits under one nonce")
+}
+
+/// The nonce of segment `index`, never the same twice under one upload's key
+fn nonce(index: u64, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..8].copy_from_slice(&index.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+/// The nonce of age's chunk `index`, as 11 bytes big-endian and the last flag
+fn age_nonce(index: u64, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[3..11].copy_from_slice(&index.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+pub(crate) fn age_segment_size(segment_size: u64) -> Result<(), String> {
+    match segment_size > 0 && segment_size.is_multiple_of(AGE_CHUNK_SIZE) {
+        true => Ok(()),
+        false => Err(format!(
+            "age encrypts 64 KiB at a time, so the chunk size has to be a multiple of it like \
+             4MiB, not {}",
+            segment_size
+        )),
+    }
+}
+
+fn age_identity(identity: &str) -> Result<age::x25519::Identity, String> {
+    age::x25519::Identity::from_str(identity)
+        .map_err(|err| format!("The age identity is invalid, {}", err))
+}
+
+/// The payload cipher of an age file, keyed from the file key and the nonce after the header
+fn age_payload(file_key: &[u8; 16], nonce: &[u8; 16]) -> ChaCha20Poly1305 {
+    let key = age_core::primitives::hkdf(nonce, b"payload", file_key);
+    ChaCha20Poly1305::new(&key.into())
+}
+
+/// The age header of `stanzas`, its MAC keyed from the file key
+///
+/// Each stanza's body is unpadded base64 in lines of 64 columns, the last one shorter and so
+/// empty when the body fills the one before.
+fn age_header(stanzas: &[Stanza], file_key: &[u8; 16]) -> Vec<u8> {
+    let mut header = AGE_VERSION.to_vec();
+    for stanza in stanzas {
+        let line = std::iter::once(stanza.tag.as_str())
+            .chain(stanza.args.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ");
+        header.extend_from_slice(format!("-> {}\n", line).as_bytes());
+        let body = BASE64_STANDARD_NO_PAD.encode(&stanza.body);
+        for line in body.as_bytes().chunks(64) {
+            header.extend_from_slice(line);
+            header.push(b'\n');
+        }
+        if body.len().is_multiple_of(64) {
+            header.push(b'\n');
+        }
+    }
+    header.extend_from_slice(b"---");
+    let mac_key = age_core::primitives::hkdf(&[], b"header", file_key);
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&mac_key).expect("HMAC takes any key");
+    mac.update(&header);
+    let mac = BASE64_STANDARD_NO_PAD.encode(mac.finalize().into_bytes());
+    header.extend_from_slice(format!(" {}\n", mac).as_bytes());
+    header
+}
+
+/// The stanzas of an age header, none when it isn't one
+fn age_stanzas(header: &[u8]) -> Option<Vec<Stanza>> {
+    let mut rest = header.strip_prefix(AGE_VERSION)?;
+    let mut stanzas = Vec::new();
+    while !rest.starts_with(b"---") {
+        let (after, stanza) = age_core::format::read::age_stanza(rest).ok()?;
+        stanzas.push(Stanza::from(stanza));
+        rest = after;
+    }
+    Some(stanzas)
+}
+
+fn upload_key(key: &EncryptionKey, iterations: u32, salt: &[u8]) -> [u8; KEY_LEN] {
+    let master = match key {
+        EncryptionKey::Key(key) => *key,
+        EncryptionKey::Passphrase(passphrase) => {
+            pbkdf2::pbkdf2_hmac_array::<Sha256, KEY_LEN>(passphrase.as_bytes(), salt, iterations)
+        }
+        EncryptionKey::Identity(_) => unreachable!("AES-GCM refuses age identities"),
+    };
+    hmac(&master, salt).try_into().unwrap()
+}
+
+fn key_check(upload_key: &[u8]) -> String {
+    hex(&hmac(upload_key, b"chunk_uploader key check")[..8])
+}
+
+fn unhex(digits: &str) -> Option<Vec<u8>> {
+    digits
+        .len()
+        .is_multiple_of(2)
+        .then(|| {
+            (0..digits.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok())
+                .collect()
+        })
+        .flatten()
+}
+
+/// Writes the plaintext of an upload encrypted as `record` says, read from `input`, returning
+/// how many bytes it came to
+///
+/// Fails before writing a segment that was changed, cut short or put in another's place, and
+/// when the upload ends early.
+pub fn decrypt(
+    record: &EncryptionRecord,
+    key: &EncryptionKey,
+    mut input: impl Read,
+    mut output: impl Write,
+) -> io::Result<u64> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    if record.algorithm == Encryption::Age {
+        return decrypt_age(record, key, input, output);
+    }
+    let cipher = Cipher::resume(key, record.clone()).map_err(invalid)?;
+    let sealed_size = usize::try_from(record.segment_size)
+        .ok()
+        .and_then(|size| size.checked_add(TAG_LEN))
+        .ok_or_else(|| invalid("The segment size is too large".to_string()))?;
+
+    // A segment is only known to be the last once nothing follows it
+    let mut current = read_segment(&mut input, sealed_size)?;
+    let (mut index, mut written) = (0u64, 0u64);
+    loop {
+        let next = match current.len() == sealed_size {
+            true => read_segment(&mut input, sealed_size)?,
+            false => Vec::new(),
+        };
+        let last = next.is_empty();
+        let plaintext = cipher.open(index, last, &current).ok_or_else(|| {
+            invalid(format!(
+                "Segment {} doesn't decrypt, the data was changed or cut short",
+                index
+            ))
+        })?;
+        output.write_all(&plaintext)?;
+        written += plaintext.len() as u64;
+        if last {
+            break;
+        }
+        (current, index) = (next, index + 1);
+    }
+    output.flush()?;
+    match record.original_size {
+        Some(size) if size != written => Err(invalid(format!(
+            "Decrypted {} bytes of the {} that were encrypted",
+            written, size
+        ))),
+        _ => Ok(written),
+    }
+}
+
+/// An age file read with the age crate, as `age -d` would, which checks its header and every
+/// chunk before they're written
+fn decrypt_age(
+    record: &EncryptionRecord,
+    key: &EncryptionKey,
+    input: impl Read,
+    mut output: impl Write,
+) -> io::Result<u64> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let identity: Box<dyn age::Identity> = match key {
+        EncryptionKey::Identity(identity) => Box::new(age_identity(identity).map_err(invalid)?),
+        EncryptionKey::Passphrase(passphrase) => Box::new(age::scrypt::Identity::new(
+            SecretString::from(passphrase.clone()),
+        )),
+        EncryptionKey::Key(_) => {
+            return Err(invalid(
+                "age takes the identity or passphrase it was encrypted with".to_string(),
+            ))
+        }
+    };
+    let decryptor = age::Decryptor::new(input)
+        .map_err(|e| invalid(format!("The age header doesn't open, {}", e)))?;
+    let mut reader = decryptor
+        .decrypt(std::iter::once(identity.as_ref()))
+        .map_err(|e| match e {
+            age::DecryptError::NoMatchingKeys => {
+                invalid("The key isn't the one it was encrypted with".to_string())
+            }
+            e => invalid(format!("The age header doesn't open, {}", e)),
+        })?;
+    let written = io::copy(&mut reader, &mut output)?;
+    output.flush()?;
+    match record.original_size {
+        Some(size) if size != written => Err(invalid(format!(
+            "Decrypted {} bytes of the {} that were encrypted",
+            written, size
+        ))),
+        _ => Ok(written),
+    }
+}
+
+/// Up to `size` bytes, fewer only at the end of the input
+fn read_segment(input: &mut impl Read, size: usize) -> io::Result<Vec<u8>> {
+    let mut segment = Vec::with_capacity(size);
+    input.take(size as u64).read_to_end(&mut segment)?;
+    Ok(segment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(digits: &str) -> Vec<u8> {
+        unhex(digits).unwrap()
+    }
+
+    /// The AES-256 test cases of the GCM specification without additional data, 13 to 15
+    #[test]
+    fn seals_the_gcm_test_cases() {
+        let cases = [
+            (
+                "00".repeat(32),
+                "00".repeat(12),
+                String::new(),
+                String::new(),
+                "530f8afbc74536b9a963b4f1c4cb738b",
+            ),
+            (
+                "00".repeat(32),
+                "00".repeat(12),
+                "00".repeat(16),
+                "cea7403d4d606b6e074ec5d3baf39d18".into(),
+                "d0d1c8a799996bf0265b98b5d48ab919",
+            ),
+            (
+                "feffe9928665731c6d6a8f9467308308".repeat(2),
+                "cafebabefacedbaddecaf888".into(),
+                "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+                 1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b391aafd255"
+                    .into(),
+                "522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa\
+                 8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662898015ad"
+                    .into(),
+                "b094dac5d93471bdec1a502270e3cc6c",
+            ),
+        ];
+        for (key, nonce, plaintext, ciphertext, tag) in cases {
+            let gcm = Aes256Gcm::new_from_slice(&bytes(&key)).unwrap();
+            let nonce: [u8; 12] = bytes(&nonce).try_into().unwrap();
+            let sealed = seal_segment(&gcm, &nonce, &bytes(&plaintext));
+            assert_eq!(sealed, bytes(&(ciphertext + tag)));
+            assert_eq!(
+                gcm.decrypt(&nonce.into(), &sealed[..]).ok(),
+                Some(bytes(&plaintext))
+            );
+
+            let mut forged = sealed;
+            *forged.last_mut().unwrap() ^= 1;
+            assert!(gcm.decrypt(&nonce.into(), &forged[..]).is_err());
+        }
+    }
+
+    /// A record written by an earlier version still decrypts to the same bytes
+    #[test]
+    fn keeps_the_aes_gcm_format() {
+        let key = EncryptionKey::Key([7; 32]);
+        let record = EncryptionRecord {
+            algorithm: Encryption::AesGcm,
+            kdf: Kdf::Key,
+            iterations: None,
+            salt: "000102030405060708090a0b0c0d0e0f".to_string(),
+            nonce: NONCE_SCHEME.to_string(),
+            segment_size: 5,
+            original_size: Some(10),
+            key_check: "ea505c402094da7d".to_string(),
+            header: None,
+        };
+        let cipher = Cipher::resume(&key, record.clone()).unwrap();
+        let sealed = cipher.seal(1, 5, true, b"hello");
+        assert_eq!(hex(&sealed), "902f24f29ac779e4c8208d53bf17e5a2a91c18ed88");
+        assert_eq!(
+            cipher.open(1, true, &sealed).as_deref(),
+            Some(&b"hello"[..])
+        );
+        assert_eq!(cipher.open(1, false, &sealed), None);
+        assert_eq!(cipher.open(0, true, &sealed), None);
+        assert_eq!(cipher.sealed_range(5, 10, true), (21, 42));
+        assert_eq!(cipher.sealed_len(10), 42);
+
+        let wrong = EncryptionKey::Key([8; 32]);
+        assert!(Cipher::resume(&wrong, record).is_err());
+    }
+
+    #[test]
+    fn writes_an_age_file_in_chunks() {
+        let identity = age::x25519::Identity::generate();
+        let key = EncryptionKey::Identity(identity.to_string().expose_secret().to_string());
+        let segment = 2 * AGE_CHUNK_SIZE;
+        let plaintext: Vec<u8> = (0..5 * AGE_CHUNK_SIZE + 1000).map(|i| i as u8).collect();
+        let len = plaintext.len() as u64;
+        let cipher = Cipher::create(Encryption::Age, &key, segment, Some(len)).unwrap();
+
+        let seal_all = |cipher: &Cipher| {
+            let mut sealed = Vec::new();
+            for (index, chunk) in plaintext.chunks(segment as usize).enumerate() {
+                let start = index as u64 * segment;
+                let last = start + chunk.len() as u64 == len;
+                let range = cipher.sealed_range(start, start + chunk.len() as u64, last);
+                assert_eq!(range.0, sealed.len() as u64);
+                sealed.extend(cipher.seal(index as u64, start, last, chunk));
+                assert_eq!(range.1, sealed.len() as u64);
+            }
+            sealed
+        };
+        let sealed = seal_all(&cipher);
+        assert_eq!(cipher.sealed_len(len), sealed.len() as u64);
+
+        let decryptor = age::Decryptor::new(&sealed[..]).unwrap();
+        let mut reader = decryptor
+            .decrypt(std::iter::once(&identity as &dyn age::Identity))
+            .unwrap();
+        let mut opened = Vec::new();
+        reader.read_to_end(&mut opened).unwrap();
+        assert_eq!(opened, plaintext);
+
+        // Resuming unwraps the same file key, so the chunks come out the same again
+        let resumed = Cipher::resume(&key, cipher.record.clone()).unwrap();
+        assert_eq!(seal_all(&resumed), sealed);
+
+        let other = age::x25519::Identity::generate();
+        let other = EncryptionKey::Identity(other.to_string().expose_secret().to_string());
+        assert!(Cipher::resume(&other, cipher.record.clone()).is_err());
+    }
+
+    #[test]
+    fn writes_an_empty_age_file() {
+        let identity = age::x25519::Identity::generate();
+        let key = EncryptionKey::Identity(identity.to_string().expose_secret().to_string());
+        let cipher = Cipher::create(Encryption::Age, &key, AGE_CHUNK_SIZE, Some(0)).unwrap();
+        let sealed = cipher.seal(0, 0, true, &[]);
+        assert_eq!(cipher.sealed_len(0), sealed.len() as u64);
+
+        let decryptor = age::Decryptor::new(&sealed[..]).unwrap();
+        let mut reader = decryptor
+            .decrypt(std::iter::once(&identity as &dyn age::Identity))
+            .unwrap();
+        let mut opened = Vec::new();
+        reader.read_to_end(&mut opened).unwrap();
+        assert!(opened.is_empty());
+    }
+
+    #[test]
+    fn reads_back_the_age_header_it_writes() {
+        for body_len in [0, 32, 47, 48, 49, 200] {
+            let stanza = Stanza {
+                tag: "X25519".to_string(),
+                args: vec!["abc".to_string(), "def".to_string()],
+                body: (0..body_len).map(|i| i as u8).collect(),
+            };
+            let header = age_header(std::slice::from_ref(&stanza), &[9; 16]);
+            let stanzas = age_stanzas(&header).unwrap();
+            assert_eq!(stanzas.len(), 1);
+            assert_eq!(
+                (&stanzas[0].tag, &stanzas[0].args, &stanzas[0].body),
+                (&stanza.tag, &stanza.args, &stanza.body)
+            );
+            assert_eq!(age_header(&stanzas, &[9; 16]), header);
+        }
+        assert!(age_stanzas(b"age-encryption.org/v2\n---").is_none());
+    }
+
+    #[test]
+    fn wants_age_chunks_in_whole_64_kib() {
+        assert!(age_segment_size(4 * 1024 * 1024).is_ok());
+        assert!(age_segment_size(AGE_CHUNK_SIZE).is_ok());
+        assert!(age_segment_size(5_000_000).is_err());
+        assert!(age_segment_size(0).is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::{UploadError, UploadReport};
+
+/// Where the progress events go, one JSON object per line, see
+/// [`crate::ChunkUploaderBuilder::progress_events`]
+pub(crate) struct Events {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Events {
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        Events {
+            out: Mutex::new(out),
+        }
+    }
+
+    /// Writes the event and flushes it right away, so whoever reads it is never a chunk behind
+    ///
+    /// A reader that went away doesn't stop the upload, its events are dropped.
+    fn emit(&self, event: &str, file: &str, mut fields: Value) {
+        fields["event"] = event.into();
+        fields["file"] = file.into();
+        let mut out = self.out.lock().unwrap();
+        let _ = writeln!(out, "{fields}").and_then(|_| out.flush());
+    }
+
+    /// `bytes` left to send from `offset` on, unknown for stdin
+    pub fn upload_started(
+        &self,
+        file: &str,
+        bytes: Option<u64>,
+        offset: u64,
+        chunk_count: Option<u64>,
+    ) {
+        self.emit(
+            "upload_started",
+            file,
+            json!({"bytes": bytes, "offset": offset, "chunks": chunk_count}),
+        );
+    }
+
+    pub fn chunk_done(
+        &self,
+        file: &str,
+        index: u64,
+        offset: u64,
+        bytes: u64,
+        status: Option<u16>,
+        elapsed: Duration,
+    ) {
+        self.emit(
+            "chunk_done",
+            file,
+            json!({
+                "index": index,
+                "offset": offset,
+                "bytes": bytes,
+                "status": status,
+                "elapsed_ms": elapsed.as_millis() as u64,
+            }),
+        );
+    }
+
+    pub fn retrying(&self, file: &str, index: u64, attempt: u32, delay: Duration, reason: &str) {
+        self.emit(
+            "retrying",
+            file,
+            json!({
+                "index": index,
+                "attempt": attempt,
+                "delay_ms": delay.as_millis() as u64,
+                "reason": reason,
+            }),
+        );
+    }
+
+    /// The upload ended, also when it failed before sending anything
+    pub fn upload_finished(
+        &self,
+        file: &str,
+        result: &Result<UploadReport, UploadError>,
+        elapsed: Duration,
+    ) {
+        let report = match result {
+            Ok(report) => Some(report),
+            Err(err) => err.report(),
+        };
+        self.emit(
+            "upload_finished",
+            file,
+            json!({
+                "success": result.is_ok(),
+                "bytes": report.map(|report| report.total_bytes),
+                "skipped_existing": report.is_some_and(|report| report.skipped_existing),
+                "sha256": report.and_then(|report| report.sha256.as_ref()),
+                "finalize_response": report.and_then(|report| report.finalize_response.as_ref()),
+                "elapsed_ms": elapsed.as_millis() as u64,
+                "error": result.as_ref().err().map(|err| err.to_string()),
+            }),
+        );
+    }
+}
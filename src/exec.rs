@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use chunk_uploader::hook;
+use chunk_uploader::output::Output;
+use chunk_uploader::{UploadError, UploadReport};
+
+use crate::exit::Exit;
+
+/// The commands `--exec-on-success` and `--exec-on-failure` run once each upload ended
+#[derive(Clone, Default)]
+pub struct UploadHooks {
+    pub on_success: Option<String>,
+    pub on_failure: Option<String>,
+    /// A failing command fails an upload that went through
+    pub strict: bool,
+    pub timeout: Option<Duration>,
+    /// Its output is shown
+    pub verbose: bool,
+}
+
+impl UploadHooks {
+    /// Runs the command for how the upload of `path` ended, an interrupted one counting as
+    /// failed, the upload described by environment variables
+    ///
+    /// A failing command is warned about, unless the upload went through and it's strict,
+    /// which is what the upload then failed of.
+    pub async fn run(
+        &self,
+        out: &Output,
+        path: &str,
+        url: &str,
+        result: &Result<UploadReport, UploadError>,
+        elapsed: Duration,
+    ) -> Result<(), String> {
+        let (name, command) = match result {
+            Ok(_) => ("on-success", self.on_success.as_deref()),
+            Err(_) => ("on-failure", self.on_failure.as_deref()),
+        };
+        let Some(command) = command else {
+            return Ok(());
+        };
+        let report = match result {
+            Ok(report) => Some(report),
+            Err(err) => err.report(),
+        };
+        let exit = result.as_ref().err().map_or(Exit::Success, Exit::of);
+        let env = [
+            ("FILE", path.to_string()),
+            ("URL", url.to_string()),
+            (
+                "BYTES_SENT",
+                report.map_or(0, |r| r.stats().bytes_sent).to_string(),
+            ),
+            ("DURATION_MS", elapsed.as_millis().to_string()),
+            ("EXIT_CODE", exit.code().to_string()),
+            ("EXIT_CLASS", exit.class().to_string()),
+            (
+                "ERROR",
+                result
+                    .as_ref()
+                    .err()
+                    .map(ToString::to_string)
+                    .unwrap_or_default(),
+            ),
+        ];
+        let failed = match hook::exec(command, &env, self.timeout).await {
+            Ok(ran) => {
+                if self.verbose {
+                    for line in ran.stdout.lines().chain(ran.stderr.lines()) {
+                        out.line(&format!("{} hook: {}", name, line));
+                    }
+                }
+                ran.failed
+                    .map(|failed| format!("The {} hook {}", name, failed))
+            }
+            Err(err) => Some(format!("Error running the {} hook: {}", name, err)),
+        };
+        match failed {
+            Some(failed) if self.strict && result.is_ok() => Err(failed),
+            Some(failed) => {
+                out.warn(&format!("Warning: {}", failed));
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
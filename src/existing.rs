@@ -0,0 +1,129 @@
+use std::io::SeekFrom;
+
+use reqwest::header::{CONTENT_LENGTH, ETAG};
+use reqwest::{Client, Method, StatusCode};
+use tokio::fs::File;
+use tokio::io::AsyncSeekExt;
+
+use crate::checksum::{Checksum, Hasher};
+use crate::digest::hex;
+use crate::{
+    build_request, describe_error, read_full, send, unauthorized_message, Failure, UploadOptions,
+    PROXY_AUTH_MESSAGE,
+};
+
+/// How a file the server already has is told apart from one to upload
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SkipExisting {
+    /// The object's Content-Length is the length of the upload
+    Size,
+    /// Its ETag is also the hex MD5 or SHA-256 of the uploaded bytes
+    Hash,
+}
+
+impl std::str::FromStr for SkipExisting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "size" => Ok(SkipExisting::Size),
+            "hash" => Ok(SkipExisting::Hash),
+            _ => Err(format!(
+                "Unknown way to compare '{s}' with the server's file, expected size or hash"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for SkipExisting {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            SkipExisting::Size => "size",
+            SkipExisting::Hash => "hash",
+        })
+    }
+}
+
+/// Why the object at the upload's URL already holds the bytes to upload, found with a HEAD,
+/// none when it doesn't
+///
+/// A 404 or 405 leaves nothing to compare with, so the file is uploaded as usual.
+pub(crate) async fn check(
+    client: &Client,
+    opts: &UploadOptions,
+    by: SkipExisting,
+    file: &mut File,
+) -> Result<Option<String>, Failure> {
+    let failed = |reason: &str| format!("Error checking for an existing file: {}", reason);
+    let res = send(opts, build_request(client, opts, Method::HEAD, &opts.url))
+        .await
+        .map_err(|e| Failure::transport(&e, failed(&describe_error(opts, &e))))?;
+    match res.status() {
+        StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED => return Ok(None),
+        status @ StatusCode::UNAUTHORIZED => {
+            return Err(Failure::status(status, failed(unauthorized_message(opts))));
+        }
+        status @ StatusCode::PROXY_AUTHENTICATION_REQUIRED => {
+            return Err(Failure::status(status, failed(PROXY_AUTH_MESSAGE)));
+        }
+        status if !status.is_success() => {
+            let reason = format!("server responded with {}", status);
+            return Err(Failure::status(status, failed(&reason)));
+        }
+        _ => {}
+    }
+
+    let header = |name| {
+        res.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+    };
+    let len = opts.range.1 - opts.range.0;
+    if header(CONTENT_LENGTH).and_then(|value| value.parse().ok()) != Some(len) {
+        return Ok(None);
+    }
+    if by == SkipExisting::Size {
+        return Ok(Some(format!("its Content-Length of {} matches", len)));
+    }
+    // A weak ETag still names the same bytes, S3's multipart ones never match a digest
+    let Some(etag) = header(ETAG) else {
+        return Ok(None);
+    };
+    let etag = etag.trim_start_matches("W/").trim_matches('"');
+    let digests = hash_range(file, opts.range, &[Checksum::Md5, Checksum::Sha256])
+        .await
+        .map_err(|e| Failure::io(format!("Error reading file: {}", e)))?;
+    let (md5, sha256) = (&digests[0], &digests[1]);
+    Ok(if etag.eq_ignore_ascii_case(md5) {
+        Some("its ETag matches the MD5 of the file".to_string())
+    } else if etag.eq_ignore_ascii_case(sha256) {
+        Some("its ETag matches the SHA-256 of the file".to_string())
+    } else {
+        None
+    })
+}
+
+/// The hex digests of bytes `range` of the file with each of `checksums`
+pub(crate) async fn hash_range(
+    file: &mut File,
+    range: (u64, u64),
+    checksums: &[Checksum],
+) -> std::io::Result<Vec<String>> {
+    let mut hashers: Vec<_> = checksums.iter().map(|&c| Hasher::new(c)).collect();
+    let mut buf = vec![0; 1024 * 1024];
+    let mut offset = range.0;
+    file.seek(SeekFrom::Start(offset)).await?;
+    while offset < range.1 {
+        let len = buf.len().min((range.1 - offset) as usize);
+        let n = read_full(file, &mut buf[..len]).await?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        for hasher in hashers.iter_mut() {
+            hasher.update(&buf[..n]);
+        }
+        offset += n as u64;
+    }
+    Ok(hashers.into_iter().map(|h| hex(&h.finalize())).collect())
+}
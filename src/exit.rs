@@ -0,0 +1,172 @@
+use std::fmt;
+use std::process::ExitCode;
+
+use chunk_uploader::{FailureKind, UploadError};
+
+/// How a run ends, its code is kept stable so scripts can tell failures apart
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Exit {
+    Success,
+    /// Files failed in different ways, or in a way none of the others describe
+    Failure,
+    /// The arguments, environment variables or config file are invalid, nothing was sent
+    Usage,
+    Io,
+    Network,
+    Http,
+    /// Ctrl-C stopped the upload, it can be resumed
+    Interrupted,
+    /// Every chunk was stored but the finalize request failed
+    FinalizeFailed,
+    /// The server refused the `--if-match` or `--if-none-match` precondition with 412
+    PreconditionFailed,
+    /// The upload completed but reading it back with `--verify` didn't match or failed
+    VerifyFailed,
+}
+
+/// Every exit in order of its code, with what it means for the help
+pub const EXITS: &[(Exit, &str)] = &[
+    (
+        Exit::Success,
+        "Every file was uploaded, or is already on the server",
+    ),
+    (Exit::Failure, "Files failed for different reasons"),
+    (
+        Exit::Usage,
+        "Invalid arguments, environment variables or config file",
+    ),
+    (
+        Exit::Io,
+        "A file couldn't be read, a local file written or a --hook-strict hook failed",
+    ),
+    (
+        Exit::Network,
+        "The server couldn't be reached or the connection broke",
+    ),
+    (
+        Exit::Http,
+        "The server refused a request or answered something unusable",
+    ),
+    (
+        Exit::Interrupted,
+        "Stopped with Ctrl-C, the upload can be resumed",
+    ),
+    (
+        Exit::FinalizeFailed,
+        "Every chunk was stored but the finalize request failed",
+    ),
+    (
+        Exit::PreconditionFailed,
+        "The server refused --if-match or --if-none-match with 412",
+    ),
+    (
+        Exit::VerifyFailed,
+        "The upload completed but --verify read back something else, or couldn't",
+    ),
+];
+
+impl Exit {
+    pub fn code(self) -> u8 {
+        match self {
+            Exit::Success => 0,
+            Exit::Failure => 1,
+            Exit::Usage => 2,
+            Exit::Io => 3,
+            Exit::Network => 4,
+            Exit::Http => 5,
+            Exit::Interrupted => 6,
+            Exit::FinalizeFailed => 7,
+            Exit::PreconditionFailed => 8,
+            Exit::VerifyFailed => 9,
+        }
+    }
+
+    /// What a file failed of in the summary, e.g. `network`
+    pub fn class(self) -> &'static str {
+        match self {
+            Exit::Success => "success",
+            Exit::Failure => "failure",
+            Exit::Usage => "usage",
+            Exit::Io => "io",
+            Exit::Network => "network",
+            Exit::Http => "http",
+            Exit::Interrupted => "interrupted",
+            Exit::FinalizeFailed => "finalize",
+            Exit::PreconditionFailed => "precondition",
+            Exit::VerifyFailed => "verify",
+        }
+    }
+
+    /// The exit for an upload that failed with `err`
+    pub fn of(err: &UploadError) -> Self {
+        match err {
+            UploadError::Invalid(_) => Exit::Usage,
+            UploadError::Interrupted { .. } => Exit::Interrupted,
+            UploadError::Finalize { .. } => Exit::FinalizeFailed,
+            UploadError::PreconditionFailed { .. } => Exit::PreconditionFailed,
+            UploadError::Verification { .. } => Exit::VerifyFailed,
+            _ => match err.kind() {
+                Some(FailureKind::Io) => Exit::Io,
+                Some(FailureKind::Network) => Exit::Network,
+                Some(FailureKind::Http) => Exit::Http,
+                // The options made a request that can't be sent
+                Some(FailureKind::Request) => Exit::Usage,
+                None => Exit::Failure,
+            },
+        }
+    }
+
+    /// The exit for several files, that of their failures when they all failed the same way
+    pub fn of_all(failures: &[Exit]) -> Self {
+        if failures.contains(&Exit::Interrupted) {
+            return Exit::Interrupted;
+        }
+        match failures.split_first() {
+            None => Exit::Success,
+            Some((first, rest)) if rest.iter().all(|exit| exit == first) => *first,
+            Some(_) => Exit::Failure,
+        }
+    }
+
+    /// Ends the process right away, when waiting for `main` to return isn't an option
+    pub fn now(self) -> ! {
+        std::process::exit(self.code().into())
+    }
+}
+
+impl From<Exit> for ExitCode {
+    fn from(exit: Exit) -> Self {
+        ExitCode::from(exit.code())
+    }
+}
+
+/// Why a run stopped before uploading anything, printed on stderr
+#[derive(Debug)]
+pub enum CliError {
+    /// An invalid argument, environment variable or config file value
+    Usage(String),
+    /// A file or directory the options name couldn't be read
+    Io(String),
+    /// Anything else, like failing to print the config
+    Other(String),
+}
+
+impl CliError {
+    pub fn exit(&self) -> Exit {
+        match self {
+            CliError::Usage(_) => Exit::Usage,
+            CliError::Io(_) => Exit::Io,
+            CliError::Other(_) => Exit::Failure,
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::Usage(message) | CliError::Io(message) | CliError::Other(message) => {
+                f.write_str(message)
+            }
+        }
+    }
+}
@@ -0,0 +1,177 @@
+/// A `--include` or `--exclude` pattern, matched against a path relative to the walked directory
+///
+/// `*` and `?` match within one path segment, `**` across them and `[a-z]` or `[!a-z]` one
+/// character of a class, `\` takes the next character as it is. A pattern without a `/`
+/// matches the file name at any depth, one with a `/` the whole relative path.
+pub struct Glob {
+    pattern: String,
+    tokens: Vec<Token>,
+    whole_path: bool,
+    ignore_case: bool,
+}
+
+enum Token {
+    Char(char),
+    /// `?`
+    Any,
+    /// `*`
+    Star,
+    /// `**/`, no directories or any number of them
+    Dirs,
+    /// `**` anywhere else, anything at all
+    Anything,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Glob {
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<Glob, String> {
+        let folded = match ignore_case {
+            true => pattern.to_lowercase(),
+            false => pattern.to_string(),
+        };
+        let trimmed = folded.trim_start_matches('/');
+        let mut tokens = Vec::new();
+        let mut chars = trimmed.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(c) => tokens.push(Token::Char(c)),
+                    None => return Err(format!("Pattern '{pattern}' ends in a lone '\\'")),
+                },
+                '?' => tokens.push(Token::Any),
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    match chars.peek() {
+                        Some('/') => {
+                            chars.next();
+                            tokens.push(Token::Dirs);
+                        }
+                        _ => tokens.push(Token::Anything),
+                    }
+                }
+                '*' => tokens.push(Token::Star),
+                '[' => {
+                    let negated = chars.next_if(|&c| c == '!' || c == '^').is_some();
+                    let mut ranges = Vec::new();
+                    // A `]` right at the start is a member rather than the end
+                    let mut first = true;
+                    loop {
+                        let start = match chars.next() {
+                            Some(']') if !first => break,
+                            Some(c) => c,
+                            None => return Err(format!("Pattern '{pattern}' has an unclosed '['")),
+                        };
+                        first = false;
+                        let end = match chars.next_if_eq(&'-') {
+                            Some(_) if chars.peek().is_some_and(|&c| c != ']') => {
+                                chars.next().unwrap()
+                            }
+                            // A `-` before the end is a member too
+                            Some(_) => {
+                                ranges.push(('-', '-'));
+                                start
+                            }
+                            None => start,
+                        };
+                        ranges.push((start, end));
+                    }
+                    tokens.push(Token::Class { negated, ranges });
+                }
+                c => tokens.push(Token::Char(c)),
+            }
+        }
+        Ok(Glob {
+            pattern: pattern.to_string(),
+            tokens,
+            whole_path: trimmed.contains('/'),
+            ignore_case,
+        })
+    }
+
+    /// The pattern as it was given
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Whether the pattern matches `relative`, a path with `/` separators
+    pub fn matches(&self, relative: &str) -> bool {
+        let folded;
+        let mut path = relative;
+        if self.ignore_case {
+            folded = relative.to_lowercase();
+            path = &folded;
+        }
+        if !self.whole_path {
+            path = path.rsplit('/').next().unwrap_or(path);
+        }
+        let path: Vec<char> = path.chars().collect();
+        matches(&self.tokens, &path)
+    }
+}
+
+fn matches(tokens: &[Token], path: &[char]) -> bool {
+    let Some((token, rest)) = tokens.split_first() else {
+        return path.is_empty();
+    };
+    match token {
+        Token::Char(c) => path.first() == Some(c) && matches(rest, &path[1..]),
+        Token::Any => path.first().is_some_and(|&c| c != '/') && matches(rest, &path[1..]),
+        Token::Class { negated, ranges } => {
+            path.first().is_some_and(|&c| {
+                c != '/' && ranges.iter().any(|&(a, b)| (a..=b).contains(&c)) != *negated
+            }) && matches(rest, &path[1..])
+        }
+        Token::Star => {
+            let segment = path.iter().position(|&c| c == '/').unwrap_or(path.len());
+            (0..=segment).any(|n| matches(rest, &path[n..]))
+        }
+        Token::Anything => (0..=path.len()).any(|n| matches(rest, &path[n..])),
+        Token::Dirs => {
+            matches(rest, path)
+                || (0..path.len()).any(|n| path[n] == '/' && matches(rest, &path[n + 1..]))
+        }
+    }
+}
+
+/// Which files of a directory or list are uploaded
+///
+/// A file is uploaded when it matches an `--include`, or no `--include` was given, and no
+/// `--exclude`. The order the patterns come in doesn't matter.
+#[derive(Default)]
+pub struct Filter {
+    include: Vec<Glob>,
+    exclude: Vec<Glob>,
+}
+
+impl Filter {
+    pub fn include(&mut self, glob: Glob) {
+        self.include.push(glob);
+    }
+
+    pub fn exclude(&mut self, glob: Glob) {
+        self.exclude.push(glob);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Why the file at `relative` isn't uploaded, or `None` when it is
+    pub fn excludes(&self, relative: &str) -> Option<String> {
+        if let Some(glob) = self.exclude.iter().find(|glob| glob.matches(relative)) {
+            let flag = if glob.ignore_case {
+                "--iexclude"
+            } else {
+                "--exclude"
+            };
+            return Some(format!("matches {} '{}'", flag, glob.as_str()));
+        }
+        if !self.include.is_empty() && !self.include.iter().any(|glob| glob.matches(relative)) {
+            return Some("matches no --include".to_string());
+        }
+        None
+    }
+}
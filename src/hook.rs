@@ -0,0 +1,143 @@
+//! Running the commands of the hooks, around each chunk or once an upload ended
+
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::process::Command;
+
+use crate::progress::{format_duration, Progress};
+use crate::{protocol, Chunk, ChunkRecord, UploadOptions};
+
+/// The commands run around each chunk, see [`crate::ChunkUploaderBuilder::exec_before_chunk`]
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    pub before: Option<String>,
+    pub after: Option<String>,
+    /// The command after a chunk failing fails the upload rather than only being warned about
+    pub strict: bool,
+    pub timeout: Option<Duration>,
+}
+
+/// What a command printed and how it ended
+#[derive(Clone, Debug)]
+pub struct Ran {
+    pub stdout: String,
+    pub stderr: String,
+    /// Why it counts as failed, like its exit status with the last line of its stderr or that
+    /// it didn't finish in time, none when it exited with 0
+    pub failed: Option<String>,
+}
+
+/// Runs `command` with the shell, `sh -c` or `cmd /C` on Windows, with `env` added to the
+/// environment and stdin closed
+///
+/// A command still running after `timeout` is killed. Fails only when it couldn't be started.
+pub async fn exec(
+    command: &str,
+    env: &[(&str, String)],
+    timeout: Option<Duration>,
+) -> std::io::Result<Ran> {
+    let mut shell = shell(command);
+    shell
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // A command that timed out is killed as its wait is dropped
+        .kill_on_drop(true);
+    let child = shell.spawn()?;
+    let output = match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, child.wait_with_output()).await {
+            Ok(output) => output?,
+            Err(_) => {
+                return Ok(Ran {
+                    stdout: String::new(),
+                    stderr: String::new(),
+                    failed: Some(format!("didn't finish within {}", format_duration(timeout))),
+                })
+            }
+        },
+        None => child.wait_with_output().await?,
+    };
+    let (stdout, stderr) = (
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    );
+    let failed = (!output.status.success()).then(|| {
+        match stderr.lines().rev().find(|line| !line.trim().is_empty()) {
+            Some(line) => format!("failed with {}: {}", output.status, line.trim()),
+            None => format!("failed with {}", output.status),
+        }
+    });
+    Ok(Ran {
+        stdout,
+        stderr,
+        failed,
+    })
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+/// Which of the chunk's hooks is run, as it's named in messages
+#[derive(Clone, Copy)]
+pub(crate) enum When {
+    Before,
+    After,
+}
+
+impl When {
+    fn name(self) -> &'static str {
+        match self {
+            When::Before => "before-chunk",
+            When::After => "after-chunk",
+        }
+    }
+}
+
+/// Runs one of the chunk's hooks, the chunk described by environment variables
+///
+/// What it prints is shown line by line when verbose.
+pub(crate) async fn run_chunk(
+    command: &str,
+    when: When,
+    opts: &UploadOptions,
+    chunk: &Chunk,
+    record: &ChunkRecord,
+    progress: &Progress,
+) -> Result<(), String> {
+    let env = [
+        ("CHUNK_INDEX", chunk.index.to_string()),
+        ("CHUNK_OFFSET", chunk.start.to_string()),
+        ("CHUNK_BYTES", (chunk.end - chunk.start).to_string()),
+        (
+            "CHUNK_STATUS",
+            record.status.map(|s| s.to_string()).unwrap_or_default(),
+        ),
+        ("CHUNK_ATTEMPT", (record.retries + 1).to_string()),
+        ("FILE", opts.path.clone()),
+        ("URL", protocol::chunk_url(opts, chunk)),
+    ];
+    let hook = format!("{} hook of chunk {}", when.name(), chunk.index);
+    let ran = exec(command, &env, opts.hooks.timeout)
+        .await
+        .map_err(|err| format!("Error running the {}: {}", hook, err))?;
+    for line in ran.stdout.lines().chain(ran.stderr.lines()) {
+        progress.detail(&format!("{}: {}", hook, line));
+    }
+    match ran.failed {
+        Some(failed) => Err(format!("The {} {}", hook, failed)),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,139 @@
+use reqwest::header::{HeaderMap, HeaderName, LOCATION};
+use reqwest::Url;
+use serde_json::Value;
+
+/// Where the response to the init request names the URL the chunks go to
+#[derive(Clone, PartialEq, Debug)]
+pub enum UploadUrlFrom {
+    /// A response header like `Location`
+    Header(HeaderName),
+    /// A string in the JSON body, found with a path like `$.upload.url` or `$.links[0]`
+    Json(JsonPath),
+}
+
+/// A path into a JSON document of object keys and array indices, starting at `$`
+#[derive(Clone, PartialEq, Debug)]
+pub struct JsonPath {
+    path: String,
+    steps: Vec<Step>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Step {
+    Key(String),
+    Index(usize),
+}
+
+impl Default for UploadUrlFrom {
+    fn default() -> Self {
+        UploadUrlFrom::Header(LOCATION)
+    }
+}
+
+impl UploadUrlFrom {
+    /// The upload URL the init response names, relative ones taken relative to `base`
+    pub(crate) fn extract(
+        &self,
+        base: &Url,
+        headers: &HeaderMap,
+        body: &str,
+    ) -> Result<Url, String> {
+        let found = match self {
+            UploadUrlFrom::Header(name) => headers
+                .get(name)
+                .ok_or_else(|| format!("the init response has no {} header", name))?
+                .to_str()
+                .map_err(|_| format!("the init response's {} header isn't text", name))?
+                .to_string(),
+            UploadUrlFrom::Json(path) => {
+                let document: Value = serde_json::from_str(body)
+                    .map_err(|e| format!("the init response isn't JSON: {}", e))?;
+                match path.find(&document) {
+                    Some(Value::String(url)) => url.clone(),
+                    Some(_) => return Err(format!("{} in the init response isn't a string", path)),
+                    None => return Err(format!("the init response has nothing at {}", path)),
+                }
+            }
+        };
+        match base.join(found.trim()) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(url),
+            _ => Err(format!(
+                "the init response names an invalid upload URL '{}'",
+                found
+            )),
+        }
+    }
+}
+
+impl JsonPath {
+    pub(crate) fn find<'a>(&self, document: &'a Value) -> Option<&'a Value> {
+        self.steps
+            .iter()
+            .try_fold(document, |value, step| match step {
+                Step::Key(key) => value.get(key),
+                Step::Index(index) => value.get(index),
+            })
+    }
+}
+
+impl std::str::FromStr for JsonPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || format!("Invalid JSON path '{s}', expected e.g. $.upload_url or $.links[0].href");
+        let mut rest = s.strip_prefix('$').ok_or_else(invalid)?;
+        let mut steps = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                if end == 0 {
+                    return Err(invalid());
+                }
+                steps.push(Step::Key(after[..end].to_string()));
+                rest = &after[end..];
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let (index, after) = after.split_once(']').ok_or_else(invalid)?;
+                steps.push(Step::Index(index.parse().map_err(|_| invalid())?));
+                rest = after;
+            } else {
+                return Err(invalid());
+            }
+        }
+        Ok(JsonPath {
+            path: s.to_string(),
+            steps,
+        })
+    }
+}
+
+impl std::fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.path)
+    }
+}
+
+impl std::str::FromStr for UploadUrlFrom {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("header", name)) => HeaderName::from_bytes(name.trim().as_bytes())
+                .map(UploadUrlFrom::Header)
+                .map_err(|_| format!("Invalid header name '{}' in '{}'", name, s)),
+            Some(("json", path)) => path.trim().parse().map(UploadUrlFrom::Json),
+            _ => Err(format!(
+                "Invalid upload URL source '{s}', expected header:<name> or json:<path>"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for UploadUrlFrom {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            UploadUrlFrom::Header(name) => write!(f, "header:{}", name),
+            UploadUrlFrom::Json(path) => write!(f, "json:{}", path),
+        }
+    }
+}
@@ -0,0 +1,387 @@
+//! The `tracing` subscriber behind `--log-level` and `--log-file`
+//!
+//! Events are written to stderr as readable lines and to the log file as one JSON object each,
+//! with the spans they happened in. The messages the output module prints itself only go to the
+//! file, so a terminal doesn't get them twice.
+//!
+//! It stands in for `tracing-subscriber`'s `fmt` layer and `EnvFilter`, as far as the binary
+//! needs them: no span events, timings or ANSI colors, and only a part of `EnvFilter`'s
+//! directives, see [`Filter`].
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+use std::fs::File;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde_json::{json, Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+use chunk_uploader::timestamp;
+
+/// The target of the messages the output module already printed
+const OUTPUT_TARGET: &str = "chunk_uploader::output";
+
+/// Which events are logged, `RUST_LOG`'s syntax of a level and `target=level` directives
+/// separated by commas, e.g. `info,chunk_uploader=debug,h2=off`
+///
+/// A level is `off`, `error`, `warn`, `info`, `debug` or `trace` in any case, or 0 to 5. A
+/// target covers itself and the modules under it, the longest one matching deciding and the
+/// later of two the same, everything else getting the bare level or nothing. A bare target
+/// without a level and span directives like `[upload{file=a}]=debug` aren't supported and are
+/// refused, rather than logging something else than was asked.
+#[derive(Clone, Debug)]
+pub struct Filter {
+    default: LevelFilter,
+    /// The longest target prefix an event's target starts with decides
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    fn level(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .filter(|(prefix, _)| {
+                target == prefix
+                    || target
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.level(metadata.target()) >= *metadata.level()
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, LevelFilter::max)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let level = |level: &str| {
+            level.parse::<LevelFilter>().map_err(|_| {
+                format!(
+                    "Invalid log level '{}', expected off, error, warn, info, debug or trace",
+                    level
+                )
+            })
+        };
+        let mut filter = Filter {
+            default: LevelFilter::OFF,
+            targets: Vec::new(),
+        };
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, _))
+                    if !target
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | ':')) =>
+                {
+                    return Err(format!(
+                        "Unsupported log directive '{}', expected a level or target=level",
+                        directive
+                    ))
+                }
+                Some((target, value)) => filter.targets.push((target.to_string(), level(value)?)),
+                None => filter.default = level(directive)?,
+            }
+        }
+        Ok(filter)
+    }
+}
+
+/// A span's name and fields, kept until its last handle is dropped
+struct SpanData {
+    name: &'static str,
+    fields: Vec<(&'static str, Value)>,
+    parent: Option<Id>,
+    handles: usize,
+}
+
+thread_local! {
+    /// The spans entered on this thread, the innermost last
+    static ENTERED: RefCell<Vec<Id>> = const { RefCell::new(Vec::new()) };
+}
+
+pub struct Logger {
+    filter: Filter,
+    /// Readable lines on stderr for the events besides the output's own messages
+    terminal: bool,
+    file: Option<Mutex<File>>,
+    spans: Mutex<HashMap<u64, SpanData>>,
+    next_id: AtomicU64,
+}
+
+impl Logger {
+    pub fn new(filter: Filter, terminal: bool, file: Option<File>) -> Self {
+        Logger {
+            filter,
+            terminal,
+            file: file.map(Mutex::new),
+            spans: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// The span the event happened in and those around it, outermost first
+    fn scope(&self, parent: Option<&Id>) -> Vec<(&'static str, Vec<(&'static str, Value)>)> {
+        let current = ENTERED.with(|entered| entered.borrow().last().cloned());
+        let mut next = parent.cloned().or(current);
+        let spans = self.spans.lock().unwrap();
+        let mut scope = Vec::new();
+        while let Some(span) = next.and_then(|id| spans.get(&id.into_u64())) {
+            scope.push((span.name, span.fields.clone()));
+            next = span.parent.clone();
+        }
+        scope.reverse();
+        scope
+    }
+}
+
+/// Collects an event's or span's fields, the message apart
+#[derive(Default)]
+struct Fields {
+    message: Option<String>,
+    fields: Vec<(&'static str, Value)>,
+}
+
+impl Fields {
+    fn push(&mut self, field: &Field, value: Value) {
+        match (field.name(), value) {
+            ("message", Value::String(message)) => self.message = Some(message),
+            (name, value) => self.fields.push((name, value)),
+        }
+    }
+}
+
+impl Visit for Fields {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.push(field, value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.push(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.push(field, value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.push(field, value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.push(field, format!("{:?}", value).into());
+    }
+}
+
+/// A field as the terminal shows it, strings without their quotes
+fn shown(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(self.filter.max())
+    }
+
+    fn new_span(&self, attributes: &Attributes) -> Id {
+        let mut fields = Fields::default();
+        attributes.record(&mut fields);
+        let parent = match attributes.parent() {
+            Some(parent) => Some(parent.clone()),
+            None if attributes.is_contextual() => {
+                ENTERED.with(|entered| entered.borrow().last().cloned())
+            }
+            None => None,
+        };
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let span = SpanData {
+            name: attributes.metadata().name(),
+            fields: fields.fields,
+            parent,
+            handles: 1,
+        };
+        self.spans.lock().unwrap().insert(id, span);
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record) {
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+        if let Some(span) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            span.fields.extend(fields.fields);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event) {
+        let metadata = event.metadata();
+        let own_message = metadata.target() == OUTPUT_TARGET;
+        if self.file.is_none() && (own_message || !self.terminal) {
+            return;
+        }
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let scope = self.scope(event.parent());
+        let time = timestamp(SystemTime::now());
+        let message = fields.message.unwrap_or_default();
+
+        if self.terminal && !own_message {
+            let mut line = format!("{} {:>5} ", time, metadata.level());
+            for (name, span_fields) in scope.iter() {
+                line.push_str(name);
+                if !span_fields.is_empty() {
+                    let shown: Vec<_> = span_fields
+                        .iter()
+                        .map(|(name, value)| format!("{}={}", name, shown(value)))
+                        .collect();
+                    let _ = write!(line, "{{{}}}", shown.join(" "));
+                }
+                line.push(':');
+            }
+            let _ = write!(line, " {}", message);
+            for (name, value) in fields.fields.iter() {
+                let _ = write!(line, " {}={}", name, shown(value));
+            }
+            let _ = writeln!(std::io::stderr().lock(), "{}", line);
+        }
+        if let Some(file) = self.file.as_ref() {
+            let object = |fields: &[(&str, Value)]| {
+                let map: Map<String, Value> = fields
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.clone()))
+                    .collect();
+                map
+            };
+            let spans: Vec<Value> = scope
+                .iter()
+                .map(|(name, span_fields)| {
+                    let mut span = object(span_fields);
+                    span.insert("name".to_string(), Value::from(*name));
+                    Value::Object(span)
+                })
+                .collect();
+            let line = json!({
+                "timestamp": time,
+                "level": metadata.level().as_str().to_lowercase(),
+                "target": metadata.target(),
+                "message": message,
+                "fields": object(&fields.fields),
+                "spans": spans,
+            });
+            let mut file = file.lock().unwrap();
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.clone()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(at) = entered.iter().rposition(|id| id == span) {
+                entered.remove(at);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(span) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            span.handles += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock().unwrap();
+        let Some(data) = spans.get_mut(&span.into_u64()) else {
+            return false;
+        };
+        data.handles -= 1;
+        if data.handles > 0 {
+            return false;
+        }
+        spans.remove(&span.into_u64());
+        true
+    }
+}
+
+/// The level the log file gets when neither `--log-level` nor `RUST_LOG` says
+pub fn file_default() -> Filter {
+    Filter {
+        default: LevelFilter::from_level(Level::INFO),
+        targets: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_by_the_longest_target() {
+        let filter: Filter =
+            "warn, chunk_uploader=debug,chunk_uploader::protocol=off,h2=4,hyper=DEBUG,h2=1"
+                .parse()
+                .unwrap();
+        assert_eq!(filter.level("chunk_uploader"), LevelFilter::DEBUG);
+        assert_eq!(filter.level("chunk_uploader::output"), LevelFilter::DEBUG);
+        assert_eq!(
+            filter.level("chunk_uploader::protocol::s3"),
+            LevelFilter::OFF
+        );
+        assert_eq!(filter.level("chunk_uploader_extra"), LevelFilter::WARN);
+        assert_eq!(filter.level("h2::proto"), LevelFilter::ERROR);
+        assert_eq!(filter.level("hyper"), LevelFilter::DEBUG);
+        assert_eq!(filter.level("reqwest"), LevelFilter::WARN);
+        assert_eq!(filter.max(), LevelFilter::DEBUG);
+
+        let nothing: Filter = "".parse().unwrap();
+        assert_eq!(nothing.max(), LevelFilter::OFF);
+    }
+
+    #[test]
+    fn refuses_what_it_does_not_support() {
+        for directive in [
+            "h2",
+            "verbose",
+            "[upload]=debug",
+            "[upload{file=a}]=debug",
+            "h2=loud",
+        ] {
+            assert!(directive.parse::<Filter>().is_err(), "{directive}");
+        }
+        assert_eq!(
+            "[upload]=debug".parse::<Filter>().unwrap_err(),
+            "Unsupported log directive '[upload]=debug', expected a level or target=level"
+        );
+    }
+}
@@ -1,237 +1,2703 @@
+use std::collections::HashMap;
 use std::env;
-use std::fs::File;
+use std::fs;
 use std::io::*;
-use std::path::Path;
+use std::iter;
+use std::path::{Component, Path};
 use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
 
-use reqwest::blocking::Client;
-use reqwest::{Method, StatusCode};
+use futures::StreamExt;
+use reqwest::header::{HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{Certificate, Identity, Proxy, StatusCode, Url};
+use serde_json::json;
 
-macro_rules! exit {
-    ($success:literal, $($arg:tt)*) => {
-        println!($($arg)*);
-        if $success {
-            std::process::exit(0);
-        } else {
-            std::process::exit(1);
+use args::Args;
+use chunk_uploader::output::{self, ColorChoice, Output};
+use chunk_uploader::walk::{self, Entry};
+use chunk_uploader::{
+    check_url, fill_url, parse_duration, timestamp, url_placeholders, AwsCredentials,
+    ChunkUploader, Compression, Encryption, EncryptionKey, Protocol, ResumeMode, Source,
+    UploadError, UploadPlan, UploadReport, DEFAULT_HMAC_PAYLOAD, URL_PLACEHOLDERS,
+};
+use config::Config;
+use exec::UploadHooks;
+use exit::{CliError, Exit};
+use textfile::FileMetrics;
+
+mod args;
+mod benchmark;
+mod completions;
+mod config;
+mod decrypt;
+mod exec;
+mod exit;
+mod flags;
+mod logging;
+mod serve;
+mod signals;
+mod textfile;
+mod watch;
+
+/// Environment variable read for the bearer token when `--token` isn't given
+const TOKEN_ENV: &str = "CHUNK_UPLOADER_TOKEN";
+
+/// Environment variable read for the password when `--user` doesn't include one
+const PASSWORD_ENV: &str = "CHUNK_UPLOADER_PASSWORD";
+
+/// Printed in place of secrets by `--print-config`
+const REDACTED: &str = "<redacted>";
+
+/// Most requests `--jobs` times `--parallel` may keep in flight at once
+const MAX_REQUESTS: usize = 64;
+
+/// How the run prints, once `--color` and what takes stdout are known
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+
+/// Results go to stdout and errors to stderr, so the output can be piped on its own
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(exit) => exit.into(),
+        Err(err) => {
+            // Errors before '--color' was read are colored as it defaults to
+            let out = OUTPUT.get().copied();
+            out.unwrap_or_else(|| Output::detect(ColorChoice::Auto))
+                .error(&err.to_string());
+            err.exit().into()
         }
-    };
+    }
 }
 
-#[allow(clippy::print_literal)]
-fn main() -> Result<ExitCode> {
-    let args: Vec<String> = env::args().collect();
+/// Runs the subcommand or the upload the command line asks for
+async fn run() -> std::result::Result<Exit, CliError> {
+    let cli: Vec<String> = env::args().collect();
+    // The local test server takes only its own flags, and none of the variables
+    if cli.get(1).is_some_and(|arg| arg == "serve") {
+        return match serve::parse(&cli[2..])? {
+            Some(options) => serve::run(options).await,
+            None => Ok(Exit::Success),
+        };
+    }
+    if cli.get(1).is_some_and(|arg| arg == "decrypt") {
+        return match decrypt::parse(&cli[2..])? {
+            Some(options) => decrypt::run(options),
+            None => Ok(Exit::Success),
+        };
+    }
+    let Some(mut args) = args::parse(&cli)? else {
+        return Ok(Exit::Success);
+    };
+    let out = Output::detect(args.color).taking_stdout(args.stdout_taken());
+    let _ = OUTPUT.set(out);
+    start_logging(args.log_level.as_deref(), args.log_file.as_deref())?;
+
+    merge_config(&mut args)?;
+    let credentials = credentials(&args)?;
+    if args.print_config {
+        return print_config(&args, &credentials);
+    }
+    let authorization = authorize(&mut args)?;
+    let uploads = uploads(&mut args)?;
+    let url = upload_url(&mut args, &uploads.entries)?;
+    let (uploader, hooks) = build_uploader(&args, credentials, authorization, out)?;
+    let prefix = url_prefix(&args);
+
+    if args.dry_run {
+        return Ok(dry_run(&args, &uploader, &uploads, &url, out));
+    }
+
+    // Asked once everything is checked so what's shown is what runs, only with someone at a
+    // terminal to answer, and stdin can't be both the upload and the answer
+    let ask = !args.yes
+        && !args.benchmark
+        && !args.use_stdin
+        && args.tar.is_none()
+        && args.compress_stream.is_none()
+        && stdin().is_terminal()
+        && stdout().is_terminal();
+    if ask && !confirm(&out, &uploader, &uploads.entries, &url, prefix)? {
+        return Err(CliError::Usage(
+            "Nothing was sent, the upload wasn't confirmed".to_string(),
+        ));
+    }
+
+    // The first Ctrl-C or SIGTERM stops cleanly so the upload can be resumed, a second one
+    // right away
+    let interrupter = uploader.interrupter();
+    tokio::spawn(async move {
+        let mut stop = signals::Stop::listen();
+        if !stop.next().await {
+            return;
+        }
+        out.warn("Stopping, press Ctrl-C again to quit right away");
+        interrupter.interrupt();
+        if stop.next().await {
+            Exit::Interrupted.now();
+        }
+    });
+    signals::pause_on_usr1(uploader.interrupter(), out);
 
-    let mut path: Option<String> = None;
-    let mut file_range: Option<(u64, u64)> = None;
-    let mut chunk_size: u64 = 5000000;
-    let mut url: Option<String> = None;
-    let mut method: Method = Method::PUT;
-    let mut print_file_bytes = false;
+    if args.benchmark {
+        return Ok(run_benchmark(&args, &uploader, &uploads.entries, &url, out).await);
+    }
+    if args.watch {
+        let upload = &uploads.entries[0];
+        return Ok(run_watch(&args, &uploader, hooks, upload, &url, out).await);
+    }
+    let started = Instant::now();
+    let ended = upload_all(&args, &uploader, &hooks, &uploads, &url, out).await;
+    Ok(report_all(&args, &uploads, ended, &url, started, out))
+}
 
-    let mut i = 1;
-    while i < args.len() {
-        match args[i].as_str() {
-            "-f" | "--file" => {
-                if i + 1 < args.len() {
-                    path = Some(args[i + 1].clone());
-                    i += 1;
-                } else {
-                    exit!(false, "Missing file path after argument '{}'", args[i]);
+/// Fills in what no flag set from the config file, the same name given as a flag wins
+fn merge_config(args: &mut Args) -> std::result::Result<(), CliError> {
+    let Args {
+        ref config,
+        ref mut headers,
+        ref content_type,
+        ref mut tus_metadata,
+        ref mut form,
+        ref form_field,
+        ref mut cookies,
+        ref mut token,
+        ref mut user,
+        aws_sigv4,
+        ..
+    } = *args;
+    for (name, values) in config.headers.iter() {
+        let Ok(name) = HeaderName::from_bytes(name.as_bytes()) else {
+            return Err(CliError::Usage(format!(
+                "Invalid header name '{}' in the config file",
+                name
+            )));
+        };
+        if headers.contains_key(&name) {
+            continue;
+        }
+        for value in values.iter() {
+            match HeaderValue::from_str(value) {
+                Ok(value) => headers.append(&name, value),
+                Err(_) => {
+                    return Err(CliError::Usage(format!(
+                        "Invalid value for header '{}' in the config file",
+                        name
+                    )));
                 }
-            }
-            "-r" | "--file-range" => {
-                if i + 1 < args.len() {
-                    let range_arg = args[i + 1].split('-').collect::<Vec<&str>>();
-                    if range_arg.len() == 2 {
-                        let start = match range_arg[0].parse::<u64>() {
-                            Ok(v) => v,
-                            Err(_) => {
-                                exit!(false, "Invalid start range of '{}'", args[i]);
-                            }
-                        };
+            };
+        }
+    }
+    if content_type.is_some() && headers.contains_key(CONTENT_TYPE) {
+        return Err(CliError::Usage(
+            "Only one of '--content-type' and a Content-Type header can be used".to_string(),
+        ));
+    }
+    for (key, value) in config.tus_metadata.iter() {
+        if !tus_metadata.iter().any(|(k, _)| k == key) {
+            tus_metadata.push((key.clone(), value.clone()));
+        }
+    }
+    for (key, value) in config.form.iter() {
+        if !form.iter().any(|(k, _)| k == key) {
+            form.push((key.clone(), value.clone()));
+        }
+    }
+    for (name, value) in config.cookie.iter() {
+        if !cookies.iter().any(|(n, _)| n == name) {
+            cookies.push((name.clone(), value.clone()));
+        }
+    }
+    if !form.is_empty() && form_field.is_none() {
+        return Err(CliError::Usage(
+            "'--form' needs '--form-field' to put the chunk in".to_string(),
+        ));
+    }
+    if token.is_none() && user.is_none() {
+        *token = config.token.clone();
+        *user = config.user.clone();
+    }
 
-                        let end = match range_arg[1].parse::<u64>() {
-                            Ok(v) => v,
-                            Err(_) => {
-                                exit!(false, "Invalid end range of '{}'", args[i]);
-                            }
-                        };
+    // An explicit '--user' beats a token lingering in the environment
+    if user.is_none() && !aws_sigv4 {
+        *token = token
+            .take()
+            .or_else(|| env::var(TOKEN_ENV).ok().filter(|t| !t.is_empty()));
+    }
+    Ok(())
+}
 
-                        file_range = Some((start, end));
-                        i += 1;
-                    } else {
-                        exit!(false, "Invalid byte range of {}", args[i + 1]);
+/// The secrets requests are signed and chunks encrypted with, read from their files and the
+/// environment
+struct Credentials {
+    hmac_secret: Option<Vec<u8>>,
+    encryption: Option<(Encryption, EncryptionKey)>,
+    /// The region, service and keys to sign with
+    aws: Option<(String, String, AwsCredentials)>,
+}
+
+fn credentials(args: &Args) -> std::result::Result<Credentials, CliError> {
+    let Args {
+        aws_sigv4,
+        ref aws_region,
+        ref aws_service,
+        ref aws_access_key,
+        ref aws_secret_key,
+        ref aws_session_token,
+        aws_sign_payload,
+        ref hmac_secret,
+        ref hmac_secret_file,
+        ref hmac_payload,
+        ref hmac_header,
+        encrypt,
+        ref encrypt_key_file,
+        ref encrypt_passphrase,
+        ..
+    } = *args;
+
+    let aws_flags = [
+        &aws_region,
+        &aws_service,
+        &aws_access_key,
+        &aws_secret_key,
+        &aws_session_token,
+    ];
+    if !aws_sigv4 && (aws_sign_payload || aws_flags.iter().any(|flag| flag.is_some())) {
+        return Err(CliError::Usage(
+            "The '--aws-*' flags need '--aws-sigv4' to sign requests".to_string(),
+        ));
+    }
+    if hmac_secret.is_some() && hmac_secret_file.is_some() {
+        return Err(CliError::Usage(
+            "'--hmac-secret' and '--hmac-secret-file' can't be used together".to_string(),
+        ));
+    }
+    let hmac_secret = match hmac_secret_file.as_ref() {
+        // A single trailing newline is what editors and `echo` leave behind
+        Some(path) => match fs::read(path) {
+            Ok(mut secret) => {
+                if secret.ends_with(b"\n") {
+                    secret.pop();
+                    if secret.ends_with(b"\r") {
+                        secret.pop();
                     }
-                } else {
-                    exit!(false, "Missing byte range after argument '{}'", args[i]);
                 }
+                Some(secret)
             }
-            "-c" | "--chunk" => {
-                if i + 1 < args.len() {
-                    chunk_size = if let Ok(c) = args[i + 1].parse::<u64>() {
-                        c
-                    } else {
-                        exit!(false, "Missing chunk size with arg '{}'", args[i]);
-                    };
-                    i += 1;
-                }
+            Err(err) => {
+                return Err(CliError::Io(format!(
+                    "Error reading HMAC secret file '{}': {}",
+                    path, err
+                )));
             }
-            "-u" | "--url" => {
-                if i + 1 < args.len() {
-                    url = Some(args[i + 1].to_string());
-                    i += 1;
-                } else {
-                    exit!(false, "Missing URL with '{}'", args[i]);
+        },
+        None => hmac_secret.clone().map(String::into_bytes),
+    };
+    if hmac_secret.is_none() && (hmac_payload.is_some() || hmac_header.is_some()) {
+        return Err(CliError::Usage(
+            "'--hmac-payload' and '--hmac-header' need '--hmac-secret' to sign with".to_string(),
+        ));
+    }
+    if encrypt_key_file.is_some() && encrypt_passphrase.is_some() {
+        return Err(CliError::Usage(
+            "'--encrypt-key-file' and '--encrypt-passphrase' can't be used together".to_string(),
+        ));
+    }
+    let encryption = match encrypt {
+        Some(encryption) => {
+            match decrypt::key(encrypt_key_file.as_deref(), encrypt_passphrase.as_deref())? {
+                Some(key) => Some((encryption, key)),
+                None => {
+                    return Err(CliError::Usage(
+                        "'--encrypt' needs '--encrypt-key-file' or '--encrypt-passphrase' to \
+                         derive the key from"
+                            .to_string(),
+                    ));
                 }
             }
-            "-m" | "--method" => {
-                if i + 1 < args.len() {
-                    method = if let Ok(m) = args[i + 1].parse::<Method>() {
-                        m
-                    } else {
-                        exit!(false, "Invalid HTTP method '{}'", args[i + 1]);
-                    };
-                    i += 1;
-                } else {
-                    exit!(false, "Missing HTTP method after argument '{}'", args[i]);
+        }
+        None if encrypt_key_file.is_some() || encrypt_passphrase.is_some() => {
+            return Err(CliError::Usage(
+                "'--encrypt-key-file' and '--encrypt-passphrase' need '--encrypt' to say how to \
+                 encrypt"
+                    .to_string(),
+            ));
+        }
+        None => None,
+    };
+    // The keys and their token come together, from the flags or the environment
+    let aws = match aws_sigv4 {
+        false => None,
+        true => {
+            let var = |name: &str| env::var(name).ok().filter(|v| !v.is_empty());
+            let Some(region) = aws_region
+                .clone()
+                .or_else(|| var("AWS_REGION"))
+                .or_else(|| var("AWS_DEFAULT_REGION"))
+            else {
+                return Err(CliError::Usage(
+                    "No region to sign for, use '--aws-region' or set AWS_REGION".to_string(),
+                ));
+            };
+            let credentials = match (aws_access_key.clone(), aws_secret_key.clone()) {
+                (Some(access_key_id), Some(secret_access_key)) => AwsCredentials {
+                    access_key_id,
+                    secret_access_key,
+                    session_token: aws_session_token.clone(),
+                },
+                (None, None) => match (var("AWS_ACCESS_KEY_ID"), var("AWS_SECRET_ACCESS_KEY")) {
+                    (Some(access_key_id), Some(secret_access_key)) => AwsCredentials {
+                        access_key_id,
+                        secret_access_key,
+                        session_token: aws_session_token
+                            .clone()
+                            .or_else(|| var("AWS_SESSION_TOKEN")),
+                    },
+                    _ => {
+                        return Err(CliError::Usage(
+                            "No AWS credentials, use '--aws-access-key' and '--aws-secret-key' \
+                             or set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY"
+                                .to_string(),
+                        ));
+                    }
+                },
+                _ => {
+                    return Err(CliError::Usage(
+                        "'--aws-access-key' and '--aws-secret-key' go together".to_string(),
+                    ));
                 }
+            };
+            let service = aws_service.clone().unwrap_or_else(|| String::from("s3"));
+            Some((region, service, credentials))
+        }
+    };
+    Ok(Credentials {
+        hmac_secret,
+        encryption,
+        aws,
+    })
+}
+
+/// `--print-config`, the options as they'd be used, as TOML a config file takes, secrets
+/// redacted
+fn print_config(args: &Args, credentials: &Credentials) -> std::result::Result<Exit, CliError> {
+    let Args {
+        include_hidden,
+        follow_symlinks,
+        clamp_range,
+        chunk_size,
+        adaptive_chunk,
+        min_chunk_size,
+        mmap,
+        tar_presize,
+        tar_clamp_mtime,
+        ref url,
+        ref prefix,
+        ref method,
+        stats,
+        ref save_responses,
+        ref save_final_response,
+        ref manifest,
+        ref delta_from,
+        ref metrics_csv,
+        ref metrics_textfile,
+        ref log_level,
+        ref log_file,
+        show_progress,
+        color,
+        resume,
+        probe_offset,
+        ref check_chunk_url,
+        check_chunk_soft,
+        ref exec_before_chunk,
+        ref exec_after_chunk,
+        ref exec_on_success,
+        ref exec_on_failure,
+        hook_strict,
+        hook_timeout,
+        ref if_match,
+        ref if_match_file,
+        ref if_none_match,
+        skip_existing,
+        skip_existing_by,
+        chunk_md5,
+        legacy_range,
+        sha256,
+        checksum,
+        ref checksum_header,
+        verify,
+        verify_block_size,
+        ref final_digest_header,
+        ref chain,
+        ref idempotency_key_header,
+        ref init_url,
+        ref init_method,
+        ref init_body,
+        ref upload_url_from,
+        ref finalize_url,
+        on_failure,
+        ref abort_url,
+        ref notify_url,
+        notify_on,
+        ref notify_token,
+        ref abort_method,
+        ref finalize_method,
+        ref finalize_body_template,
+        chunk_headers,
+        ref index_header,
+        ref count_header,
+        ref offset_header,
+        ref expect_status,
+        ref headers,
+        ref content_type,
+        detect_content_type,
+        protocol,
+        ref tus_metadata,
+        ref form_field,
+        compress,
+        compress_stream,
+        ref form,
+        ref token,
+        ref user,
+        aws_sigv4,
+        aws_sign_payload,
+        ref hmac_secret_file,
+        ref hmac_payload,
+        ref hmac_header,
+        encrypt,
+        ref encrypt_key_file,
+        ref encrypt_passphrase,
+        parallel,
+        jobs,
+        limit_rate,
+        connect_timeout,
+        timeout,
+        chunk_delay,
+        max_retry_wait,
+        stall_threshold,
+        http_version,
+        pool_idle_timeout,
+        pool_max_idle_per_host,
+        tcp_keepalive,
+        tcp_nodelay,
+        redirects,
+        no_proxy,
+        ref proxy,
+        ref cacert,
+        insecure,
+        ref cert,
+        ref key,
+        ref identity,
+        ref identity_password,
+        cookie_store,
+        ref cookies,
+        ref cookie_jar,
+        retries,
+        retry_delay,
+        retry_jitter,
+        ..
+    } = *args;
+    let Credentials {
+        hmac_secret, aws, ..
+    } = credentials;
+    let effective = Config {
+        url: url.clone(),
+        prefix: prefix.clone(),
+        method: Some(method.to_string()),
+        chunk_size: Some(chunk_size),
+        adaptive_chunk: Some(adaptive_chunk),
+        min_chunk_size: Some(min_chunk_size),
+        mmap: Some(mmap),
+        tar_presize: Some(tar_presize),
+        tar_clamp_mtime,
+        clamp_range: Some(clamp_range),
+        protocol: Some(protocol.to_string()),
+        token: token.as_ref().map(|_| REDACTED.to_string()),
+        user: user.as_ref().map(|user| match user.split_once(':') {
+            Some((user, _)) => format!("{user}:{REDACTED}"),
+            None => user.clone(),
+        }),
+        aws_sigv4: Some(aws_sigv4),
+        aws_region: aws.as_ref().map(|(region, _, _)| region.clone()),
+        aws_service: aws.as_ref().map(|(_, service, _)| service.clone()),
+        aws_access_key: aws
+            .as_ref()
+            .map(|(_, _, credentials)| credentials.access_key_id.clone()),
+        aws_secret_key: aws.as_ref().map(|_| REDACTED.to_string()),
+        aws_session_token: aws
+            .as_ref()
+            .and_then(|(_, _, credentials)| credentials.session_token.as_ref())
+            .map(|_| REDACTED.to_string()),
+        aws_sign_payload: Some(aws_sign_payload),
+        hmac_secret: hmac_secret
+            .as_ref()
+            .filter(|_| hmac_secret_file.is_none())
+            .map(|_| REDACTED.to_string()),
+        hmac_secret_file: hmac_secret_file.clone(),
+        hmac_payload: hmac_secret.as_ref().map(|_| {
+            hmac_payload
+                .as_deref()
+                .unwrap_or(DEFAULT_HMAC_PAYLOAD)
+                .to_string()
+        }),
+        hmac_header: hmac_header.as_ref().map(|h| h.to_string()),
+        encrypt: encrypt.map(|e| e.to_string()),
+        encrypt_key_file: encrypt_key_file.clone(),
+        encrypt_passphrase: encrypt_passphrase.as_ref().map(|_| REDACTED.to_string()),
+        parallel: Some(parallel),
+        jobs: Some(jobs),
+        retries: Some(retries),
+        retry_delay: Some(retry_delay.as_millis() as u64),
+        retry_jitter: Some(retry_jitter.to_string()),
+        limit_rate: limit_rate.map(|r| r.to_string()),
+        max_retry_wait: Some(format_duration(max_retry_wait)),
+        chunk_delay: Some(format_duration(chunk_delay)),
+        connect_timeout: Some(format_duration(connect_timeout)),
+        timeout: Some(format_duration(timeout)),
+        pool_idle_timeout: Some(format_duration(pool_idle_timeout)),
+        pool_max_idle_per_host,
+        tcp_keepalive: Some(format_duration(tcp_keepalive)),
+        tcp_nodelay: Some(tcp_nodelay),
+        redirects: Some(redirects.to_string()),
+        http_version: Some(http_version.to_string()),
+        proxy: proxy.as_deref().map(redact_url),
+        no_proxy: Some(no_proxy),
+        cacert: cacert.clone(),
+        insecure: Some(insecure),
+        cookies: Some(cookie_store || !cookies.is_empty() || cookie_jar.is_some()),
+        cookie_jar: cookie_jar.clone(),
+        cert: cert.clone(),
+        key: key.clone(),
+        identity: identity.clone(),
+        identity_password: identity_password.as_ref().map(|_| REDACTED.to_string()),
+        resume: Some(resume.to_string()),
+        chunk_md5: Some(chunk_md5),
+        sha256: Some(sha256),
+        checksum: checksum.map(|checksum| checksum.to_string()),
+        checksum_header: checksum_header.as_ref().map(|h| h.to_string()),
+        verify: verify.map(|verify| verify.to_string()),
+        verify_block_size,
+        final_digest_header: final_digest_header.as_ref().map(|h| h.to_string()),
+        idempotency_key_header: idempotency_key_header.as_ref().map(|h| h.to_string()),
+        chain: chain.as_ref().map(|(from, _)| from.to_string()),
+        chain_request_header: chain.as_ref().map(|(_, header)| header.to_string()),
+        init_url: init_url.clone(),
+        init_method: Some(init_method.to_string()),
+        init_body: init_body.clone(),
+        upload_url_from: upload_url_from.as_ref().map(|from| from.to_string()),
+        finalize_url: finalize_url.clone(),
+        finalize_method: Some(finalize_method.to_string()),
+        on_failure: on_failure.map(|on_failure| on_failure.to_string()),
+        abort_url: abort_url.clone(),
+        notify_url: notify_url.clone(),
+        notify_on: notify_on.map(|on| on.to_string()),
+        notify_token: notify_token.as_ref().map(|_| REDACTED.to_string()),
+        abort_method: Some(abort_method.to_string()),
+        finalize_body_template: finalize_body_template.clone(),
+        chunk_headers: Some(chunk_headers),
+        index_header: Some(index_header.to_string()),
+        count_header: Some(count_header.to_string()),
+        probe_offset: Some(probe_offset),
+        check_chunk_url: check_chunk_url.clone(),
+        check_chunk_soft: Some(check_chunk_soft),
+        exec_before_chunk: exec_before_chunk.clone(),
+        exec_after_chunk: exec_after_chunk.clone(),
+        exec_on_success: exec_on_success.clone(),
+        exec_on_failure: exec_on_failure.clone(),
+        hook_strict: Some(hook_strict),
+        hook_timeout: Some(format_duration(hook_timeout)),
+        if_match: if_match.clone(),
+        if_match_file: if_match_file.clone(),
+        if_none_match: if_none_match.clone(),
+        skip_existing: Some(skip_existing),
+        skip_existing_by: Some(skip_existing_by.to_string()),
+        legacy_range: Some(legacy_range),
+        offset_header: Some(offset_header.clone()),
+        expect_status: expect_status.as_ref().map(|statuses| {
+            statuses
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>()
+                .join(",")
+        }),
+        content_type: content_type
+            .as_ref()
+            .map(|c| String::from_utf8_lossy(c.as_bytes()).into_owned()),
+        detect_content_type: Some(detect_content_type),
+        form_field: form_field.clone(),
+        compress: Some(compress.to_string()),
+        compress_stream: compress_stream.map(|c| c.to_string()),
+        progress: Some(show_progress),
+        color: Some(color.to_string()),
+        stall_threshold: Some(format_duration(stall_threshold)),
+        stats: Some(stats),
+        save_responses: save_responses.clone(),
+        save_final_response: save_final_response.clone(),
+        manifest: manifest.clone(),
+        delta_from: delta_from.clone(),
+        metrics_csv: metrics_csv.clone(),
+        metrics_textfile: metrics_textfile.clone(),
+        log_level: log_level.clone(),
+        log_file: log_file.clone(),
+        hidden: Some(include_hidden),
+        follow_symlinks: Some(follow_symlinks),
+        headers: headers
+            .keys()
+            .map(|name| {
+                let values = headers
+                    .get_all(name)
+                    .iter()
+                    .map(|value| shown_value(name, value))
+                    .collect();
+                (name.to_string(), config::Values::Many(values))
+            })
+            .collect(),
+        tus_metadata: tus_metadata.iter().cloned().collect(),
+        form: form.iter().cloned().collect(),
+        cookie: cookies
+            .iter()
+            .map(|(name, _)| (name.clone(), REDACTED.to_string()))
+            .collect(),
+        ..Config::default()
+    };
+    match toml::to_string(&effective) {
+        Ok(toml) => {
+            output::print(toml.trim_end());
+            Ok(Exit::Success)
+        }
+        Err(err) => Err(CliError::Other(format!(
+            "Error printing the config: {}",
+            err
+        ))),
+    }
+}
+
+/// What each request carries besides `--header`, the bearer token put among the headers
+struct Authorization {
+    basic_auth: Option<(String, String)>,
+    if_match: Option<HeaderValue>,
+    if_none_match: Option<HeaderValue>,
+}
+
+/// Asks for the `--user` password when it isn't given, and reads the ETag file
+fn authorize(args: &mut Args) -> std::result::Result<Authorization, CliError> {
+    let basic_auth = match args.user.as_ref() {
+        None => None,
+        Some(user) => Some(match user.split_once(':') {
+            Some((user, password)) => (user.to_string(), password.to_string()),
+            None => {
+                let password = match env::var(PASSWORD_ENV) {
+                    Ok(password) => password,
+                    Err(_) if stdin().is_terminal() => {
+                        match rpassword::prompt_password(format!("Password for '{user}': ")) {
+                            Ok(password) => password,
+                            Err(err) => {
+                                return Err(CliError::Io(format!(
+                                    "Error reading password: {}",
+                                    err
+                                )));
+                            }
+                        }
+                    }
+                    Err(_) => {
+                        return Err(CliError::Usage(format!(
+                            "No password for '{}', set {} or use '--user user:password'",
+                            user, PASSWORD_ENV
+                        )));
+                    }
+                };
+                (user.clone(), password)
             }
-            "-fb" | "--file-bytes" => {
-                print_file_bytes = true;
+        }),
+    };
+
+    if let Some(token) = args.token.as_ref() {
+        // The token itself is never echoed, not even when it's rejected
+        match HeaderValue::from_str(&format!("Bearer {token}")) {
+            Ok(mut value) => {
+                value.set_sensitive(true);
+                args.headers.insert(AUTHORIZATION, value);
             }
-            "-h" | "--help" => {
-                let mut help = String::from("Chunk Uploader - Help\n");
-                help.push_str("\t -f, --file    File to upload \n");
-                help.push_str("\t -c, --chunk   Chunk size to use for upload \n");
-                help.push_str("\t -u, --url     URL to upload to \n");
-                help.push_str("\t -r, --range   Byte range of the file to upload e.g. 0-1000 for first 1000 bytes (Default: Input file's byte range [0-filesize]) \n");
-                help.push_str("\t -m, --method  HTTP Method to use (Default: PUT) \n");
-                help.push_str("\t -h, --help    Show help (This command) \n");
-                help.push_str("\t -v, --version Show version \n");
+            Err(_) => {
+                return Err(CliError::Usage(
+                    "The bearer token contains characters invalid in a header".to_string(),
+                ));
+            }
+        }
+    }
 
-                exit!(true, "{help}");
+    // The file holds the ETag a previous download or HEAD saved
+    if let Some(path) = args.if_match_file.clone() {
+        match fs::read_to_string(&path) {
+            Ok(etag) => args.if_match = Some(etag.trim().to_string()),
+            Err(err) => {
+                return Err(CliError::Io(format!(
+                    "Error reading ETag file '{}': {}",
+                    path, err
+                )));
             }
-            "-v" | "--version" => {
-                exit!(true, "V0.1.0");
+        }
+    }
+    let if_match = match args.if_match.as_deref().map(parse_etags) {
+        Some(Err(err)) => {
+            return Err(CliError::Usage(format!("{} for '--if-match'", err)));
+        }
+        if_match => if_match.and_then(|e| e.ok()),
+    };
+    let if_none_match = match args.if_none_match.as_deref().map(parse_etags) {
+        Some(Err(err)) => {
+            return Err(CliError::Usage(format!("{} for '--if-none-match'", err)));
+        }
+        if_none_match => if_none_match.and_then(|e| e.ok()),
+    };
+    Ok(Authorization {
+        basic_auth,
+        if_match,
+        if_none_match,
+    })
+}
+
+/// The files to upload, from the command line, `--files-from` and `--dir`
+struct Uploads {
+    entries: Vec<Entry>,
+    /// The files the patterns leave out, with the reason for the dry run
+    excluded: Vec<(Entry, String)>,
+    /// Files the walk and the patterns left out
+    skipped: usize,
+    /// A single file, which gets no summary
+    single: bool,
+}
+
+/// Finds the files to upload, refusing the options those files can't be uploaded with
+fn uploads(args: &mut Args) -> std::result::Result<Uploads, CliError> {
+    if args.paths.iter().any(|p| p == "-") {
+        args.use_stdin = true;
+    }
+    let Args {
+        ref paths,
+        use_stdin,
+        ref dir,
+        ref files_from,
+        null_separated,
+        continue_on_error,
+        ref error_log,
+        ref filter,
+        include_hidden,
+        follow_symlinks,
+        total_size,
+        file_range,
+        ref tar,
+        tar_presize,
+        print_file_bytes,
+        ref save_responses,
+        dry_run,
+        benchmark,
+        watch,
+        watch_poll_interval,
+        watch_settle,
+        watch_fail_fast,
+        resume,
+        probe_offset,
+        skip_existing,
+        protocol,
+        compress,
+        compress_stream,
+        encrypt,
+        parallel,
+        jobs,
+        ..
+    } = *args;
+    let mut paths = paths.clone();
+    if tar.is_some() && (!paths.is_empty() || use_stdin || dir.is_some() || files_from.is_some()) {
+        return Err(CliError::Usage(
+            "A tar stream can't be combined with uploading files or stdin".to_string(),
+        ));
+    }
+    if use_stdin || tar.is_some() {
+        let name = match tar.is_some() {
+            true => "a tar stream",
+            false => "stdin",
+        };
+        let others = paths.len() > 1 || dir.is_some() || files_from.is_some();
+        if paths.iter().any(|p| p != "-") || others {
+            return Err(CliError::Usage(
+                "Reading from stdin can't be combined with uploading files".to_string(),
+            ));
+        }
+        if file_range.is_some() {
+            return Err(CliError::Usage(format!(
+                "'--file-range' can't be used when reading {}",
+                name
+            )));
+        }
+        if watch {
+            return Err(CliError::Usage(format!(
+                "'--watch' needs a file, not {}",
+                name
+            )));
+        }
+        if protocol != Protocol::Raw {
+            return Err(CliError::Usage(format!(
+                "Reading from {} only works with the raw protocol",
+                name
+            )));
+        }
+        if resume == ResumeMode::Require || probe_offset {
+            return Err(CliError::Usage(format!(
+                "An upload from {} can't be resumed",
+                name
+            )));
+        }
+        if print_file_bytes {
+            return Err(CliError::Usage(format!(
+                "'--file-bytes' needs a file, not {}",
+                name
+            )));
+        }
+        if skip_existing {
+            return Err(CliError::Usage(format!(
+                "'--skip-existing' needs a file, not {}",
+                name
+            )));
+        }
+        if dry_run {
+            return Err(CliError::Usage(format!(
+                "'--dry-run' needs a file, not {}",
+                name
+            )));
+        }
+        if benchmark {
+            return Err(CliError::Usage(format!(
+                "'--benchmark' needs a file, not {}",
+                name
+            )));
+        }
+        paths = vec![tar.clone().unwrap_or_else(|| "-".to_string())];
+    }
+    // A compressed file is streamed like stdin, its length only known once it's compressed
+    if compress_stream.is_some() {
+        if protocol != Protocol::Raw {
+            return Err(CliError::Usage(
+                "'--compress-stream' can only be used with the raw protocol".to_string(),
+            ));
+        }
+        let refused = [
+            ("--compress", compress != Compression::None),
+            ("--encrypt", encrypt.is_some()),
+            ("--file-range", file_range.is_some()),
+            ("--total-size", total_size.is_some()),
+            ("--tar-presize", tar_presize),
+            ("--resume require", resume == ResumeMode::Require),
+            ("--probe-offset", probe_offset),
+            ("--file-bytes", print_file_bytes),
+            ("--skip-existing", skip_existing),
+            ("--watch", watch),
+            ("--dry-run", dry_run),
+            ("--benchmark", benchmark),
+        ];
+        if let Some((flag, _)) = refused.iter().find(|(_, given)| *given) {
+            return Err(CliError::Usage(format!(
+                "'--compress-stream' and '{}' can't be used together",
+                flag
+            )));
+        }
+    }
+
+    let mut uploads: Vec<Entry> = paths
+        .iter()
+        .map(|path| Entry {
+            path: path.clone(),
+            relative: Path::new(path)
+                .file_name()
+                .map_or_else(|| path.clone(), |n| n.to_string_lossy().into_owned()),
+        })
+        .collect();
+    // The archive is named after its directory, `.` too
+    if let (Some(dir), Some(upload)) = (tar.as_ref(), uploads.first_mut()) {
+        let name = std::fs::canonicalize(dir)
+            .ok()
+            .and_then(|dir| dir.file_name().map(|n| n.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| upload.relative.clone());
+        upload.relative = format!("{}.tar", name);
+    }
+    // The server stores the compressed object, named like it
+    if let Some(compression) = compress_stream {
+        for upload in uploads.iter_mut() {
+            upload.relative = format!("{}.{}", upload.relative, compression.extension());
+        }
+    }
+    if null_separated && files_from.is_none() && error_log.is_none() {
+        return Err(CliError::Usage(
+            "'--null' needs '--files-from' or '--error-log'".to_string(),
+        ));
+    }
+    if !filter.is_empty() && dir.is_none() && files_from.is_none() {
+        return Err(CliError::Usage(
+            "'--include' and '--exclude' need '--dir' or '--files-from'".to_string(),
+        ));
+    }
+    // The files the patterns leave out, with the reason for the dry run
+    let mut excluded = Vec::new();
+    let mut matching = |entries: Vec<Entry>| {
+        let mut kept = Vec::new();
+        for entry in entries {
+            match filter.excludes(&entry.relative) {
+                Some(reason) => excluded.push((entry, reason)),
+                None => kept.push(entry),
             }
-            a => {
-                exit!(
-                    true,
-                    "Unknown argument '{a}', use '-h' or '--help' for help"
-                );
+        }
+        kept
+    };
+    if let Some(list) = files_from.as_deref() {
+        let listed = read_file_list(list, null_separated)?;
+        let count = listed.len();
+        let listed = matching(
+            listed
+                .into_iter()
+                .map(|path| Entry {
+                    relative: listed_relative(&path),
+                    path,
+                })
+                .collect(),
+        );
+        // Found missing before anything is sent, unless the others are to go ahead regardless
+        let missing: Vec<_> = listed
+            .iter()
+            .filter_map(|entry| match fs::metadata(&entry.path) {
+                Ok(meta) if meta.is_file() => None,
+                Ok(_) => Some(format!("\t '{}': not a file", entry.path)),
+                Err(err) => Some(format!("\t '{}': {}", entry.path, err)),
+            })
+            .collect();
+        if !missing.is_empty() && !continue_on_error {
+            return Err(CliError::Io(format!(
+                "{} of the {} listed files can't be uploaded, '--continue-on-error' uploads the \
+                 others:\n{}",
+                missing.len(),
+                count,
+                missing.join("\n")
+            )));
+        }
+        uploads.extend(listed);
+    }
+    let mut skipped = 0;
+    if let Some(dir) = dir.as_ref() {
+        match walk::walk(Path::new(dir), include_hidden, follow_symlinks) {
+            Ok(walk) => {
+                uploads.extend(matching(walk.files));
+                skipped = walk.skipped;
+            }
+            Err(err) => {
+                return Err(CliError::Io(format!(
+                    "Error reading directory '{}': {}",
+                    dir, err
+                )));
             }
         }
-        i += 1;
     }
+    skipped += excluded.len();
+    // A directory or list always gets the summary, even when it only holds one file
+    let single = uploads.len() == 1 && dir.is_none() && files_from.is_none();
 
-    let file = match path {
-        Some(f) => {
-            if Path::new(f.as_str()).exists() {
-                match std::fs::OpenOptions::new().read(true).open(&f) {
-                    Ok(file) => file,
-                    Err(err) => {
-                        exit!(false, "Error opening file: {}", err);
-                    }
+    if uploads.is_empty() && dir.is_none() && files_from.is_none() {
+        return Err(CliError::Usage(
+            "No file was given, use '-f' or '--file' to specify a file".to_string(),
+        ));
+    }
+    if save_responses.is_some() && !single {
+        return Err(CliError::Usage(
+            "'--save-responses' can only be used when uploading a single file, the chunks of \
+             several would overwrite each other's responses"
+                .to_string(),
+        ));
+    }
+    if file_range.is_some() && !single {
+        return Err(CliError::Usage(
+            "'--file-range' can only be used when uploading a single file".to_string(),
+        ));
+    }
+    if total_size.is_some() && !single {
+        return Err(CliError::Usage(
+            "'--total-size' can only be used when uploading a single file".to_string(),
+        ));
+    }
+    if watch {
+        if !single {
+            return Err(CliError::Usage(
+                "'--watch' can only be used when uploading a single file".to_string(),
+            ));
+        }
+        if let Some((flag, _)) = [("--benchmark", benchmark), ("--dry-run", dry_run)]
+            .iter()
+            .find(|(_, given)| *given)
+        {
+            return Err(CliError::Usage(format!(
+                "'--watch' and '{}' can't be used together",
+                flag
+            )));
+        }
+        if watch_poll_interval.is_some_and(|interval| interval.is_zero()) {
+            return Err(CliError::Usage(
+                "'--watch-poll-interval' must be longer than zero".to_string(),
+            ));
+        }
+    } else {
+        let given = [
+            ("--watch-poll-interval", watch_poll_interval.is_some()),
+            ("--watch-settle", watch_settle.is_some()),
+            ("--watch-fail-fast", watch_fail_fast),
+        ];
+        if let Some((flag, _)) = given.iter().find(|(_, given)| *given) {
+            return Err(CliError::Usage(format!("'{}' needs '--watch'", flag)));
+        }
+    }
+    if jobs.saturating_mul(parallel) > MAX_REQUESTS {
+        return Err(CliError::Usage(format!(
+            "'--jobs {}' with '--parallel {}' would keep {} requests in flight, at most {} \
+             are allowed",
+            jobs,
+            parallel,
+            jobs.saturating_mul(parallel),
+            MAX_REQUESTS
+        )));
+    }
+    Ok(Uploads {
+        entries: uploads,
+        excluded,
+        skipped,
+        single,
+    })
+}
+
+/// The URL to upload to, a local server's for `--benchmark`, refusing placeholders the files
+/// can't fill in
+fn upload_url(args: &mut Args, uploads: &[Entry]) -> std::result::Result<String, CliError> {
+    let Args {
+        url_given,
+        dry_run,
+        benchmark,
+        ref benchmark_sweep,
+        verify,
+        ref init_url,
+        ref finalize_url,
+        ref notify_url,
+        protocol,
+        jobs,
+        ..
+    } = *args;
+    if benchmark_sweep.is_some() && !benchmark {
+        return Err(CliError::Usage(
+            "'--benchmark-sweep' needs '--benchmark'".to_string(),
+        ));
+    }
+    if benchmark {
+        // Refused rather than ignored, a mistyped command mustn't upload for real
+        if url_given {
+            return Err(CliError::Usage(
+                "'--benchmark' never sends to a real server and can't be used with '--url'"
+                    .to_string(),
+            ));
+        }
+        let real = [
+            ("--init-url", init_url.is_some()),
+            ("--finalize-url", finalize_url.is_some()),
+            ("--notify-url", notify_url.is_some()),
+        ];
+        if let Some((flag, _)) = real.iter().find(|(_, given)| *given) {
+            return Err(CliError::Usage(format!(
+                "'--benchmark' can't be used with '{}', which would reach a real server",
+                flag
+            )));
+        }
+        if protocol != Protocol::Raw {
+            return Err(CliError::Usage(
+                "'--benchmark' only works with the raw protocol".to_string(),
+            ));
+        }
+        if verify.is_some() {
+            return Err(CliError::Usage(
+                "'--benchmark' can't be used with '--verify', its server keeps nothing to read back"
+                    .to_string(),
+            ));
+        }
+        if dry_run {
+            return Err(CliError::Usage(
+                "'--benchmark' and '--dry-run' can't be used together".to_string(),
+            ));
+        }
+        if jobs > 1 {
+            return Err(CliError::Usage(
+                "'--benchmark' uploads one file at a time and can't be used with '--jobs'"
+                    .to_string(),
+            ));
+        }
+        // Every chunk size sends the whole file, and no state is left for a real upload
+        args.resume = ResumeMode::Off;
+        args.url = match benchmark::sink() {
+            Ok(url) => Some(url),
+            Err(err) => {
+                return Err(CliError::Io(format!(
+                    "Error starting the benchmark's local server: {}",
+                    err
+                )));
+            }
+        };
+    }
+    let args = &*args;
+    let Args {
+        use_stdin,
+        ref tar,
+        ref url,
+        ref init_url,
+        ..
+    } = *args;
+    // The init request names where the chunks go, its URL keys the resume state then
+    let Some(url) = url.clone().or_else(|| init_url.clone()) else {
+        return Err(CliError::Usage(
+            "No URL was given, use '-u' or '--url' to specify a URL".to_string(),
+        ));
+    };
+    // The file's placeholders are filled in here, the library does the others
+    let file_placeholders = ["{filename}", "{path}"];
+    if let Some(unknown) = url_placeholders(&url)
+        .find(|p| !file_placeholders.contains(p) && !URL_PLACEHOLDERS.contains(p))
+    {
+        return Err(CliError::Usage(format!(
+            "Unknown placeholder '{}' in the URL, expected one of {}, {}",
+            unknown,
+            file_placeholders.join(", "),
+            URL_PLACEHOLDERS.join(", ")
+        )));
+    }
+    if let Err(err) = check_url(&url) {
+        return Err(CliError::Usage(err));
+    }
+    let prefix = url_prefix(args);
+    let file_named = url.contains("{filename}") || url.contains("{path}");
+    if !prefix.is_empty() && !file_named {
+        return Err(CliError::Usage(
+            "'--prefix' goes in front of '{filename}' or '{path}', which the URL doesn't have"
+                .to_string(),
+        ));
+    }
+    // Found before anything is sent, the second file would overwrite the first
+    if file_named && uploads.len() > 1 {
+        let mut sources: HashMap<String, Vec<&str>> = HashMap::new();
+        for upload in uploads.iter() {
+            let url = expand_url(&url, upload, prefix);
+            sources.entry(url).or_default().push(&upload.path);
+        }
+        let mut collisions: Vec<_> = sources
+            .into_iter()
+            .filter(|(_, paths)| paths.len() > 1)
+            .map(|(url, paths)| format!("\t {}: '{}'", url, paths.join("', '")))
+            .collect();
+        if !collisions.is_empty() {
+            collisions.sort();
+            return Err(CliError::Usage(format!(
+                "Several files would be uploaded to the same URL, use '{{path}}' to tell \
+                 them apart:\n{}",
+                collisions.join("\n")
+            )));
+        }
+    }
+    if use_stdin && (url.contains("{filename}") || url.contains("{path}")) {
+        return Err(CliError::Usage(
+            "'{filename}' and '{path}' can't be used when reading from stdin".to_string(),
+        ));
+    }
+    if use_stdin && (url.contains("{count}") || url.contains("{filesize}")) {
+        return Err(CliError::Usage(
+            "'{count}' and '{filesize}' can't be used when reading from stdin".to_string(),
+        ));
+    }
+    if tar.is_some() && (url.contains("{count}") || url.contains("{filesize}")) {
+        return Err(CliError::Usage(
+            "'{count}' and '{filesize}' can't be used with a tar stream".to_string(),
+        ));
+    }
+    Ok(url)
+}
+
+/// What `--prefix` puts in front of the file's name, slashes around it would only double
+/// those of the URL
+fn url_prefix(args: &Args) -> &str {
+    args.prefix.as_deref().unwrap_or_default().trim_matches('/')
+}
+
+/// The uploader the options describe, and the commands run once each upload ended
+fn build_uploader(
+    args: &Args,
+    credentials: Credentials,
+    authorization: Authorization,
+    out: Output,
+) -> std::result::Result<(ChunkUploader, UploadHooks), CliError> {
+    let Args {
+        total_size,
+        file_range,
+        clamp_range,
+        chunk_size,
+        adaptive_chunk,
+        min_chunk_size,
+        mmap,
+        tar_presize,
+        tar_clamp_mtime,
+        ref method,
+        ref save_responses,
+        ref save_final_response,
+        ref manifest,
+        ref delta_from,
+        ref metrics_csv,
+        json,
+        progress_jsonl,
+        ref progress_file,
+        progress_fd,
+        verbose,
+        show_progress,
+        resume,
+        probe_offset,
+        ref check_chunk_url,
+        check_chunk_soft,
+        ref exec_before_chunk,
+        ref exec_after_chunk,
+        ref exec_on_success,
+        ref exec_on_failure,
+        hook_strict,
+        hook_timeout,
+        skip_existing,
+        skip_existing_by,
+        chunk_md5,
+        legacy_range,
+        sha256,
+        checksum,
+        ref checksum_header,
+        verify,
+        verify_block_size,
+        ref final_digest_header,
+        ref chain,
+        ref idempotency_key_header,
+        ref init_url,
+        ref init_method,
+        ref init_body,
+        ref upload_url_from,
+        ref finalize_url,
+        on_failure,
+        ref abort_url,
+        ref notify_url,
+        notify_on,
+        ref notify_token,
+        ref abort_method,
+        ref finalize_method,
+        ref finalize_body_template,
+        chunk_headers,
+        ref index_header,
+        ref count_header,
+        ref offset_header,
+        ref expect_status,
+        ref headers,
+        ref content_type,
+        detect_content_type,
+        protocol,
+        ref tus_metadata,
+        ref form_field,
+        compress,
+        compress_stream,
+        ref form,
+        aws_sign_payload,
+        ref hmac_payload,
+        ref hmac_header,
+        parallel,
+        jobs,
+        limit_rate,
+        connect_timeout,
+        timeout,
+        chunk_delay,
+        max_retry_wait,
+        stall_threshold,
+        http_version,
+        pool_idle_timeout,
+        pool_max_idle_per_host,
+        tcp_keepalive,
+        tcp_nodelay,
+        redirects,
+        no_proxy,
+        ref proxy,
+        ref cacert,
+        insecure,
+        ref cert,
+        ref key,
+        ref identity,
+        ref identity_password,
+        cookie_store,
+        ref cookies,
+        ref cookie_jar,
+        retries,
+        retry_delay,
+        retry_jitter,
+        verbosity,
+        ..
+    } = *args;
+    let Credentials {
+        hmac_secret,
+        encryption,
+        aws,
+    } = credentials;
+    let Authorization {
+        basic_auth,
+        if_match,
+        if_none_match,
+    } = authorization;
+    let mut builder = ChunkUploader::builder()
+        .chunk_size(chunk_size)
+        .method(method.clone())
+        .protocol(protocol)
+        .headers(headers.clone())
+        .retries(retries)
+        .retry_delay(retry_delay)
+        .retry_jitter(retry_jitter)
+        .max_retry_wait(max_retry_wait)
+        .chunk_delay(chunk_delay)
+        .connect_timeout(connect_timeout)
+        .timeout(timeout)
+        .pool_idle_timeout(pool_idle_timeout)
+        .tcp_keepalive(tcp_keepalive)
+        .tcp_nodelay(tcp_nodelay)
+        .redirects(redirects)
+        .http_version(http_version)
+        .no_proxy(no_proxy)
+        .insecure(insecure)
+        .cookie_store(cookie_store)
+        .parallel(parallel)
+        .prefix_lines(jobs > 1)
+        .progress(show_progress && !json)
+        .stall_threshold(stall_threshold)
+        .verbosity(verbosity)
+        .log_to_stderr(args.stdout_taken())
+        .output(out)
+        .detect_content_type(detect_content_type)
+        .compress(compress)
+        .mmap(mmap)
+        .clamp_range(clamp_range)
+        .chunk_md5(chunk_md5)
+        .legacy_range(legacy_range)
+        .sha256(sha256)
+        .resume(resume);
+    if adaptive_chunk {
+        builder = builder.adaptive_chunk(min_chunk_size);
+    }
+    if let Some(range) = file_range {
+        builder = builder.byte_range(range);
+    }
+    if let Some(total_size) = total_size {
+        builder = builder.total_size(total_size);
+    }
+    if tar_presize {
+        builder = builder.tar_presize(true);
+    }
+    if let Some(compression) = compress_stream {
+        builder = builder.compress_stream(compression);
+    }
+    if let Some(seconds) = tar_clamp_mtime {
+        builder = builder.tar_clamp_mtime(seconds);
+    }
+    for (key, value) in tus_metadata.clone() {
+        builder = builder.tus_metadata(key, value);
+    }
+    if let Some(content_type) = content_type.clone() {
+        builder = builder.content_type(content_type);
+    }
+    if let Some(field) = form_field.clone() {
+        builder = builder.form_field(field);
+    }
+    for (key, value) in form.clone() {
+        builder = builder.form(key, value);
+    }
+    if let Some((user, password)) = basic_auth {
+        builder = builder.basic_auth(user, password);
+    }
+    if let Some(secret) = hmac_secret {
+        let payload = hmac_payload
+            .clone()
+            .unwrap_or_else(|| DEFAULT_HMAC_PAYLOAD.to_string());
+        builder = builder.hmac_signature(secret, payload);
+        if let Some(header) = hmac_header.clone() {
+            builder = builder.hmac_header(header);
+        }
+    }
+    if let Some((encryption, key)) = encryption {
+        builder = builder.encrypt(encryption, key);
+    }
+    if let Some((region, service, credentials)) = aws {
+        builder = builder
+            .aws_sigv4(region, service, credentials)
+            .aws_sign_payload(aws_sign_payload);
+    }
+    if let Some(rate) = limit_rate {
+        builder = builder.limit_rate(rate);
+    }
+    if let Some(path) = cacert.as_deref() {
+        match load_certificates(path) {
+            Ok(certificates) => {
+                for certificate in certificates {
+                    builder = builder.root_certificate(certificate);
                 }
-            } else {
-                exit!(false, "File '{}' does not exist", f);
+            }
+            Err(err) => {
+                return Err(CliError::Usage(err.to_string()));
+            }
+        }
+    }
+    match load_identity(
+        cert.clone(),
+        key.clone(),
+        identity.clone(),
+        identity_password.clone(),
+    ) {
+        Ok(Some(identity)) => builder = builder.identity(identity),
+        Ok(None) => {}
+        Err(err) => {
+            return Err(CliError::Usage(err.to_string()));
+        }
+    }
+    if let Some(max) = pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(max);
+    }
+    for (name, value) in cookies.clone() {
+        builder = builder.cookie(name, value);
+    }
+    if let Some(path) = cookie_jar.clone() {
+        builder = builder.cookie_jar(path);
+    }
+    if let Some(dir) = save_responses.clone() {
+        builder = builder.save_responses(dir);
+    }
+    if let Some(path) = save_final_response.clone() {
+        builder = builder.save_final_response(path);
+    }
+    if let Some(path) = manifest.clone() {
+        builder = builder.manifest(path);
+    }
+    if let Some(path) = delta_from.clone() {
+        builder = builder.delta_from(path);
+    }
+    if let Some(path) = metrics_csv.clone() {
+        builder = builder.metrics_csv(path);
+    }
+    if let Some(url) = notify_url.clone() {
+        builder = builder.notify(url);
+        if let Some(on) = notify_on {
+            builder = builder.notify_on(on);
+        }
+        if let Some(token) = notify_token.clone() {
+            builder = builder.notify_token(token);
+        }
+    }
+    if let Some(path) = progress_file.as_deref() {
+        match fs::File::create(path) {
+            Ok(file) => builder = builder.progress_events(file),
+            Err(err) => {
+                return Err(CliError::Io(format!(
+                    "Error creating progress file '{}': {}",
+                    path, err
+                )));
+            }
+        }
+    } else if let Some(fd) = progress_fd {
+        builder = builder.progress_events(progress_fd_file(fd)?);
+    } else if progress_jsonl {
+        builder = builder.progress_events(stdout());
+    }
+    if insecure {
+        out.warn("Warning: '--insecure' turns off TLS certificate verification, anyone in between can read and change the upload");
+    }
+    if let Some(proxy) = proxy.as_deref() {
+        match Proxy::all(proxy) {
+            Ok(proxy) => builder = builder.proxy(proxy),
+            Err(_) => {
+                return Err(CliError::Usage(format!(
+                    "Invalid proxy URL '{}' in the config file",
+                    proxy
+                )));
             }
         }
-        None => {
-            exit!(
-                false,
-                "No file was given, use '-f' or '--file' to specify a file"
-            );
+    }
+    if let Some(verify) = verify {
+        builder = builder.verify(verify);
+        if let Some(size) = verify_block_size {
+            builder = builder.verify_block_size(size);
         }
+    }
+    if let Some(checksum) = checksum {
+        builder = builder.checksum(checksum);
+    }
+    if let Some(header) = checksum_header.clone() {
+        builder = builder.checksum_header(header);
+    }
+    if let Some(header) = final_digest_header.clone() {
+        builder = builder.final_digest_header(header);
+    }
+    if let Some(header) = idempotency_key_header.clone() {
+        builder = builder.idempotency_key_header(header);
+    }
+    if let Some((from, header)) = chain.clone() {
+        builder = builder.chain(from, header);
+    }
+    if chunk_headers {
+        builder = builder.chunk_headers(index_header.clone(), count_header.clone());
+    }
+    if let Some(url) = init_url.clone() {
+        builder = builder.init(init_method.clone(), url);
+    }
+    if let Some(body) = init_body.clone() {
+        builder = builder.init_body(body);
+    }
+    if let Some(from) = upload_url_from.clone() {
+        builder = builder.upload_url_from(from);
+    }
+    if let Some(url) = finalize_url.clone() {
+        builder = builder.finalize(finalize_method.clone(), url);
+    }
+    if let Some(on_failure) = on_failure {
+        builder = builder.on_failure(on_failure);
+    }
+    if protocol == Protocol::Raw {
+        builder = builder.abort_request(abort_method.clone(), abort_url.clone());
+    }
+    if let Some(template) = finalize_body_template.clone() {
+        builder = builder.finalize_body(template);
+    }
+    if skip_existing {
+        builder = builder.skip_existing(skip_existing_by);
+    }
+    if let Some(etag) = if_match {
+        builder = builder.if_match(etag);
+    }
+    if let Some(etags) = if_none_match {
+        builder = builder.if_none_match(etags);
+    }
+    if probe_offset {
+        builder = builder.probe_offset(offset_header.clone());
+    }
+    if let Some(url) = check_chunk_url.clone() {
+        builder = builder
+            .check_chunk_url(url)
+            .check_chunk_soft(check_chunk_soft);
+    }
+    if let Some(command) = exec_before_chunk.clone() {
+        builder = builder.exec_before_chunk(command);
+    }
+    if let Some(command) = exec_after_chunk.clone() {
+        builder = builder.exec_after_chunk(command).hook_strict(hook_strict);
+    }
+    if !hook_timeout.is_zero() {
+        builder = builder.hook_timeout(hook_timeout);
+    }
+    let hooks = UploadHooks {
+        on_success: exec_on_success.clone(),
+        on_failure: exec_on_failure.clone(),
+        strict: hook_strict,
+        timeout: (!hook_timeout.is_zero()).then_some(hook_timeout),
+        verbose,
     };
+    if let Some(statuses) = expect_status.clone() {
+        builder = builder.expect_status(statuses);
+    }
+    match builder.build() {
+        Ok(uploader) => Ok((uploader, hooks)),
+        Err(err) => Err(CliError::Usage(err.to_string())),
+    }
+}
 
-    if let Some(r) = file_range.as_ref() {
-        if r.1 > file.metadata().unwrap().len() {
-            exit!(
-                false,
-                "Byte range of {} is larger than the file's size of {}",
-                r.1,
-                file.metadata().unwrap().len()
-            );
-        }
+/// Where the file's bytes are read from, stdin, a tarball of the path or the file itself
+fn source(args: &Args, upload: &Entry) -> Source {
+    match (args.use_stdin, args.tar.is_some()) {
+        (true, _) => Source::Stdin,
+        (false, true) => Source::Tar(upload.path.clone().into()),
+        (false, false) => Source::File(upload.path.clone().into()),
     }
+}
 
-    if print_file_bytes {
-        println!("File size: {} bytes", file.metadata().unwrap().len());
+/// `--dry-run`, what each upload would send and which files can't be uploaded
+fn dry_run(
+    args: &Args,
+    uploader: &ChunkUploader,
+    uploads: &Uploads,
+    url: &str,
+    out: Output,
+) -> Exit {
+    let mut invalid = Vec::new();
+    for upload in uploads.entries.iter() {
+        match uploader.plan(
+            source(args, upload),
+            &expand_url(url, upload, url_prefix(args)),
+        ) {
+            Ok(plan) => print_plan(&out, &plan),
+            Err(err) => {
+                invalid.push(Exit::of(&err));
+                out.error(&format!("'{}' can't be uploaded: {}", upload.path, err));
+            }
+        }
+    }
+    for (entry, reason) in uploads.excluded.iter() {
+        out.line(&format!("Excluded '{}', it {}", entry.path, reason));
+    }
+    if !invalid.is_empty() {
+        out.error(&format!(
+            "Dry run: {} of {} files can't be uploaded",
+            invalid.len(),
+            uploads.entries.len()
+        ));
+        return Exit::of_all(&invalid);
     }
+    out.line("Dry run: nothing was sent");
+    Exit::Success
+}
 
-    do_upload(
-        file_range.unwrap_or((0, file.metadata().unwrap().len())),
-        file,
+/// `--benchmark`, the file sent to a local server in each chunk size and the times compared
+async fn run_benchmark(
+    args: &Args,
+    uploader: &ChunkUploader,
+    uploads: &[Entry],
+    url: &str,
+    out: Output,
+) -> Exit {
+    let Args {
         chunk_size,
-        url.ok_or_else(|| {
-            exit!(
-                false,
-                "No URL was given, use '-u' or '--url' to specify a URL"
-            );
-        })
-        .unwrap(),
-        method,
+        ref benchmark_sweep,
+        quiet,
+        json,
+        parallel,
+        ..
+    } = *args;
+    let chunk_sizes = benchmark_sweep.clone().unwrap_or_else(|| vec![chunk_size]);
+    let announce = !quiet && !args.stdout_taken();
+    let runs = benchmark::sweep(
+        uploader,
+        uploads,
+        url,
+        &chunk_sizes,
+        parallel,
+        announce,
+        out,
     )
+    .await;
+    match runs {
+        Ok(runs) if json => {
+            let runs: Vec<_> = runs.iter().map(benchmark::Run::json).collect();
+            out.result(&json!({ "benchmark": runs }).to_string());
+            Exit::Success
+        }
+        Ok(runs) => {
+            benchmark::print_table(&runs, out);
+            Exit::Success
+        }
+        Err(err) => {
+            out.error(&format!("Benchmark failed: {}", err));
+            Exit::of(&err)
+        }
+    }
 }
 
-fn do_upload(
-    (file_start, file_end): (u64, u64),
-    mut file: File,
-    chunk_size: u64,
-    url: String,
-    method: Method,
-) -> Result<ExitCode> {
-    let client = Client::new();
-    let request_url = url.as_str();
-
-    if let Err(e) = file.seek(SeekFrom::Start(file_start)) {
-        exit!(false, "Error reading file: {}", e);
-    };
-    
-    let mut start = file_start;
-    while start < file_end {
-        let (end, mut buf) = if start + chunk_size > file_end {
-            let end_chunk = file_end - start;
-            (start + end_chunk,  vec![0; end_chunk as usize])
-        } else {
-            (start + chunk_size, vec![0; chunk_size as usize])
-        };
+/// `--watch`, the file uploaded again after every change until Ctrl-C
+async fn run_watch(
+    args: &Args,
+    uploader: &ChunkUploader,
+    hooks: UploadHooks,
+    upload: &Entry,
+    url: &str,
+    out: Output,
+) -> Exit {
+    let Args {
+        chunk_size,
+        stats,
+        ref metrics_textfile,
+        watch_poll_interval,
+        watch_settle,
+        watch_fail_fast,
+        quiet,
+        json,
+        ..
+    } = *args;
+    let url = expand_url(url, upload, url_prefix(args));
+    let watch = watch::Watch {
+        poll_interval: watch_poll_interval.unwrap_or(Duration::from_secs(1)),
+        settle: watch_settle.unwrap_or(Duration::from_secs(2)),
+        fail_fast: watch_fail_fast,
+        hooks,
+        out,
+    };
+    if !quiet && !args.stdout_taken() {
+        out.line(&format!(
+            "Watching '{}', uploading it again once a change settled for {}",
+            upload.path,
+            format_duration(watch.settle)
+        ));
+    }
+    // One line per upload, the document of each with '--output json'
+    let done = |result: &std::result::Result<UploadReport, UploadError>,
+                hooked: &std::result::Result<(), String>,
+                elapsed| {
+        let time = timestamp(SystemTime::now());
+        if let Some(path) = metrics_textfile.as_deref() {
+            let success = result.is_ok() && hooked.is_ok();
+            let metrics = FileMetrics::of(&upload.path, &url, result, elapsed, success);
+            write_metrics_textfile(&out, path, &[metrics]);
+        }
+        if json {
+            let mut document = upload_json(&upload.path, &url, chunk_size, result, elapsed, stats);
+            if let Err(err) = hooked {
+                document["success"] = false.into();
+                document["error"] = err.as_str().into();
+            }
+            document["timestamp"] = time.into();
+            out.result(&document.to_string());
+            return;
+        }
+        match (result, hooked) {
+            (Err(err), _) => out.error(&format!("[{}] {}: failed, {}", time, upload.path, err)),
+            (Ok(_), Err(err)) => out.error(&format!("[{}] {}: failed, {}", time, upload.path, err)),
+            (Ok(report), Ok(())) if !quiet => {
+                out.success(&format!("[{}] {}: {}", time, upload.path, report))
+            }
+            (Ok(_), Ok(())) => {}
+        }
+    };
+    watch::run(uploader, &upload.path, &url, &watch, done).await
+}
 
-        let n = file.read(&mut buf)?;
+/// How the uploads of a run ended, in the order they were started
+struct Ended {
+    /// Each file's `--output json` document
+    documents: Vec<serde_json::Value>,
+    file_metrics: Vec<FileMetrics>,
+    /// What's printed about each file, its report or why it failed
+    results: Vec<std::result::Result<String, String>>,
+    /// Which files the server already had, which count as uploaded
+    existing: Vec<bool>,
+    interrupted: bool,
+    /// How each failed file failed, which decides the exit code
+    failures: Vec<Exit>,
+    /// How each file ended, next to its result
+    failed_as: Vec<Option<Exit>>,
+    /// The failure that ended the run, as it would fail every file
+    fatal: Option<Exit>,
+}
 
-        let res = client
-            .request(method.clone(), request_url)
-            .header(
-                "Content-Range",
-                format!("bytes {}-{}/{}", start, end, file_end),
+/// Uploads the files, `--jobs` at a time, until they're all done, Ctrl-C or a failure that ends
+/// the run
+async fn upload_all(
+    args: &Args,
+    uploader: &ChunkUploader,
+    hooks: &UploadHooks,
+    uploads: &Uploads,
+    url: &str,
+    out: Output,
+) -> Ended {
+    let Args {
+        continue_on_error,
+        chunk_size,
+        print_file_bytes,
+        stats,
+        ref metrics_textfile,
+        quiet,
+        json,
+        jobs,
+        ..
+    } = *args;
+    let (prefix, stdout_taken) = (url_prefix(args), args.stdout_taken());
+    let mut ended = Ended {
+        documents: Vec::new(),
+        file_metrics: Vec::new(),
+        results: Vec::new(),
+        existing: Vec::new(),
+        interrupted: false,
+        failures: Vec::new(),
+        failed_as: Vec::new(),
+        fatal: None,
+    };
+    // Set by the first failure that ends the run, no file starts after it
+    let stop = AtomicBool::new(false);
+    // With several files at once, what's printed about each starts with its path
+    let label = |upload: &Entry| match jobs > 1 {
+        true => format!("{}: ", upload.path),
+        false => String::new(),
+    };
+    let interrupter = uploader.interrupter();
+    let textfile = metrics_textfile.is_some();
+    // Each upload's lines are printed once it's done, they run on this task so they can't
+    // come between another's. The files not started before Ctrl-C or a failure ending the run
+    // are left out.
+    let uploading = futures::stream::iter(uploads.entries.iter()).map(|upload| {
+        let (label, interrupter, stop) = (label(upload), &interrupter, &stop);
+        async move {
+            if interrupter.is_interrupted() || stop.load(Ordering::SeqCst) {
+                return None;
+            }
+            if !uploads.single && !quiet && !stdout_taken {
+                out.line(&format!("Uploading '{}'", upload.path));
+            }
+            let source = source(args, upload);
+            if let (Source::File(path), true) = (&source, print_file_bytes) {
+                if let Ok(meta) = std::fs::metadata(path) {
+                    out.line(&format!("{}File size: {} bytes", label, meta.len()));
+                }
+            }
+            let url = expand_url(url, upload, prefix);
+            let file_started = Instant::now();
+            let result = uploader.upload(source, &url).await;
+            let elapsed = file_started.elapsed();
+            // A strict hook failing fails an upload that went through
+            let hooked = hooks.run(&out, &upload.path, &url, &result, elapsed).await;
+            let document = json.then(|| {
+                let mut document =
+                    upload_json(&upload.path, &url, chunk_size, &result, elapsed, stats);
+                if let Err(err) = hooked.as_ref() {
+                    document["success"] = false.into();
+                    document["error"] = err.as_str().into();
+                }
+                document
+            });
+            let success = result.is_ok() && hooked.is_ok();
+            let metrics =
+                textfile.then(|| FileMetrics::of(&upload.path, &url, &result, elapsed, success));
+            let report = match result.as_ref() {
+                Ok(report) => Some(report),
+                Err(err) => err.report(),
+            };
+            if let (Ok(report), false) = (result.as_ref(), stdout_taken) {
+                if let Some(sha256) = report.sha256.as_ref() {
+                    out.result(&format!("{}SHA-256: {}", label, sha256));
+                }
+                if let Some((checksum, digest)) = report.checksum.as_ref() {
+                    if report.sha256.is_none() {
+                        out.result(&format!("{}{}: {}", label, checksum.label(), digest));
+                    }
+                }
+                let original = report.compressed.as_ref();
+                if let Some((checksum, digest)) =
+                    original.and_then(|c| c.original_checksum.as_ref())
+                {
+                    out.result(&format!(
+                        "{}Original {}: {}",
+                        label,
+                        checksum.label(),
+                        digest
+                    ));
+                }
+                // Needed to decrypt the upload, so it's printed even when quiet
+                if let Some(record) = report.encryption.as_ref() {
+                    let record = serde_json::to_string(record).unwrap_or_default();
+                    out.result(&format!("{}Encryption record: {}", label, record));
+                }
+                // Often the new object's ID, so it's printed even when quiet
+                if let Some(body) = report.finalize_response.as_ref() {
+                    if !body.trim().is_empty() {
+                        out.result(&format!("{}Finalize response: {}", label, body.trim()));
+                    }
+                }
+            }
+            let existed = report.is_some_and(|report| report.skipped_existing);
+            // Failed uploads get what they got done, it shows where the time went
+            if let (Some(report), false, false) = (report, quiet || stdout_taken, existed) {
+                if stats {
+                    print_chunk_stats(&out, report);
+                }
+                out.line(&format!("{}{}", label, report.stats()));
+            }
+            let failure = match (result.as_ref(), hooked.as_ref()) {
+                (Err(err), _) => Some(Exit::of(err)),
+                (Ok(_), Err(_)) => Some(Exit::Io),
+                (Ok(_), Ok(())) => None,
+            };
+            let stopped = matches!(result, Err(UploadError::Interrupted { .. }));
+            let fatal = result
+                .as_ref()
+                .err()
+                .filter(|err| fails_every_file(err))
+                .is_some();
+            if failure.is_some() && (fatal || !continue_on_error) {
+                stop.store(true, Ordering::SeqCst);
+            }
+            let result = match (result, hooked) {
+                (Ok(report), Ok(())) => Ok(report.to_string()),
+                (Ok(_), Err(err)) => Err(err),
+                (Err(err), _) => Err(err.to_string()),
+            };
+            if !uploads.single && !quiet && !stdout_taken {
+                match result.as_ref() {
+                    Ok(msg) => out.success(&format!("{label}{msg}")),
+                    Err(msg) => out.error(&format!("{label}{msg}")),
+                }
+            }
+            Some((document, metrics, result, existed, failure, stopped, fatal))
+        }
+    });
+    let mut uploading = std::pin::pin!(uploading.buffered(jobs));
+    while let Some(outcome) = uploading.next().await {
+        let Some((document, metrics, result, existed, failure, stopped, ends_all)) = outcome else {
+            continue;
+        };
+        if let (true, None, Some(class)) = (ends_all, ended.fatal, failure) {
+            ended.fatal = Some(class);
+        }
+        ended.documents.extend(document);
+        ended.file_metrics.extend(metrics);
+        ended.results.push(result);
+        ended.existing.push(existed);
+        ended.failures.extend(failure);
+        ended.failed_as.push(failure);
+        ended.interrupted |= stopped;
+    }
+    ended
+}
+
+/// Writes the error log and the metrics, and prints how the files ended, one document with
+/// `--output json` and a summary for several
+fn report_all(
+    args: &Args,
+    uploads: &Uploads,
+    ended: Ended,
+    url: &str,
+    started: Instant,
+    out: Output,
+) -> Exit {
+    let Args {
+        null_separated,
+        ref error_log,
+        ref metrics_textfile,
+        quiet,
+        json,
+        events_to_stdout,
+        ..
+    } = *args;
+    let Ended {
+        mut documents,
+        mut file_metrics,
+        mut results,
+        existing,
+        interrupted,
+        failures,
+        failed_as,
+        fatal,
+    } = ended;
+    let Uploads {
+        entries: ref uploads,
+        skipped,
+        single,
+        ..
+    } = *uploads;
+    let code = Exit::of_all(&failures);
+    let not_started = uploads.len() - results.len();
+    if let Some(path) = error_log.as_deref() {
+        // The files not started come after the last one that was
+        let again: Vec<_> = uploads
+            .iter()
+            .zip(
+                failed_as
+                    .iter()
+                    .map(Option::is_some)
+                    .chain(iter::repeat(true)),
             )
-            .body(buf)
-            .send();
-
-        match res {
-            Ok(res) => {
-                if res.status() != StatusCode::OK {
-                    exit!(
-                        false,
-                        "Http Error uploading chunk: {}",
-                        res.text()
-                            .unwrap_or_else(|_| "Response body is empty".to_string())
-                    );
+            .filter(|(_, again)| *again)
+            .map(|(upload, _)| upload.path.as_str())
+            .collect();
+        let separator = if null_separated { "\0" } else { "\n" };
+        let text: String = again
+            .iter()
+            .map(|path| format!("{path}{separator}"))
+            .collect();
+        if let Err(err) = fs::write(path, text) {
+            out.warn(&format!(
+                "Warning: Error writing the error log '{}': {}",
+                path, err
+            ));
+        }
+    }
+    if let Some(path) = metrics_textfile.as_deref() {
+        // The files not started count as failed, so a run that ended early still shows
+        for upload in uploads[results.len()..].iter() {
+            let url = expand_url(url, upload, url_prefix(args));
+            file_metrics.push(FileMetrics::not_started(&upload.path, &url));
+        }
+        write_metrics_textfile(&out, path, &file_metrics);
+    }
+
+    // Nothing but the document goes to stdout, so it can be parsed as a whole
+    if json {
+        let success = results.iter().all(|r| r.is_ok());
+        let document = match single {
+            true => documents.remove(0),
+            false => json!({
+                "success": success,
+                "elapsed_ms": started.elapsed().as_millis() as u64,
+                "skipped": skipped,
+                "files": documents,
+            }),
+        };
+        out.result(&document.to_string());
+        return code;
+    }
+
+    // Quiet runs only report what failed, on stderr where cron mails it, as do those whose
+    // events have stdout, their upload_finished event telling the rest
+    if quiet || events_to_stdout {
+        for (upload, result) in uploads.iter().zip(results.iter()) {
+            if let Err(err) = result {
+                match single {
+                    true => out.error(err),
+                    false => out.error(&format!("{}: failed, {}", upload.path, err)),
                 }
             }
+        }
+        return code;
+    }
+
+    if single {
+        match results.remove(0) {
+            Ok(msg) => {
+                out.success(&msg);
+                return Exit::Success;
+            }
+            Err(msg) => {
+                out.error(&msg);
+                return code;
+            }
+        }
+    }
+
+    let failed = results.iter().filter(|r| r.is_err()).count();
+    let already = existing.iter().filter(|&&existed| existed).count();
+    // How many failed of each class, in the order the classes first came up
+    let mut classes: Vec<(&str, usize)> = Vec::new();
+    for class in failed_as.iter().flatten().map(|exit| exit.class()) {
+        match classes.iter_mut().find(|(seen, _)| *seen == class) {
+            Some((_, count)) => *count += 1,
+            None => classes.push((class, 1)),
+        }
+    }
+    let classes = match classes.as_slice() {
+        [] => String::new(),
+        classes => format!(
+            " ({})",
+            classes
+                .iter()
+                .map(|(class, count)| format!("{} {}", count, class))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    out.line(&format!(
+        "Uploaded {} of {} files, {} failed{}, {} skipped{}:",
+        results.len() - failed,
+        uploads.len(),
+        failed,
+        classes,
+        skipped,
+        match already {
+            0 => String::new(),
+            n => format!(", {} already on the server", n),
+        }
+    ));
+    let ended = results.iter().zip(existing.iter()).zip(failed_as.iter());
+    for (upload, ((result, existed), failure)) in uploads.iter().zip(ended) {
+        match (result, failure) {
+            (Ok(_), _) if *existed => {
+                out.line(&format!("\t {}: already on the server", upload.path))
+            }
+            (Ok(_), _) => out.line(&format!("\t {}: ok", upload.path)),
+            (Err(err), Some(failure)) => out.line(&format!(
+                "\t {}: failed ({}), {}",
+                upload.path,
+                failure.class(),
+                err
+            )),
+            (Err(err), None) => out.line(&format!("\t {}: failed, {}", upload.path, err)),
+        }
+    }
+    if interrupted {
+        out.warn(&format!(
+            "Interrupted, {} files were not started",
+            not_started
+        ));
+        return code;
+    }
+    if not_started > 0 {
+        match fatal {
+            Some(class) => out.error(&format!(
+                "Stopped, the {} failure would fail every file, {} files were not started",
+                class.class(),
+                not_started
+            )),
+            None => out.error(&format!(
+                "Stopped at the first failed file, {} files were not started, \
+                 '--continue-on-error' uploads the others",
+                not_started
+            )),
+        }
+        return code;
+    }
+    if failed > 0 {
+        out.error("Some files failed to upload");
+        return code;
+    }
+    out.success("All files uploaded successfully");
+    Exit::Success
+}
+
+/// Whether every other file would fail like this too, from the options or the credentials,
+/// which ends the run even with '--continue-on-error'
+fn fails_every_file(err: &UploadError) -> bool {
+    Exit::of(err) == Exit::Usage
+        || matches!(
+            err.status(),
+            Some(StatusCode::UNAUTHORIZED | StatusCode::PROXY_AUTHENTICATION_REQUIRED)
+        )
+}
+
+/// Replaces `--metrics-textfile`, a warning being all it takes when that fails
+fn write_metrics_textfile(out: &Output, path: &str, files: &[FileMetrics]) {
+    if let Err(err) = textfile::write(Path::new(path), files) {
+        out.warn(&format!(
+            "Warning: Error writing the metrics textfile '{}': {}",
+            path, err
+        ));
+    }
+}
+
+/// The `--output json` document for one file, the same whether it was uploaded or not
+fn upload_json(
+    path: &str,
+    url: &str,
+    chunk_size: u64,
+    result: &std::result::Result<UploadReport, UploadError>,
+    elapsed: Duration,
+    stats: bool,
+) -> serde_json::Value {
+    let report = match result {
+        Ok(report) => Some(report),
+        Err(err) => err.report(),
+    };
+    let chunks = report.map_or_else(Vec::new, |report| {
+        report
+            .chunks
+            .iter()
+            .map(|chunk| {
+                json!({
+                    "index": chunk.index,
+                    "start": chunk.start,
+                    "end": chunk.end,
+                    "status": chunk.status,
+                    "retries": chunk.retries,
+                    "rate_limited": chunk.rate_limited,
+                    "started_ms": chunk.started.as_millis() as u64,
+                    "delay_ms": chunk.delay.as_millis() as u64,
+                    "duration_ms": chunk.duration.as_millis() as u64,
+                    "bytes_sent": chunk.bytes_sent,
+                    "bytes_confirmed": chunk.bytes_confirmed,
+                    "existed": chunk.existed,
+                    "error": chunk.error,
+                })
+            })
+            .collect()
+    });
+
+    let mut document = json!({
+        "success": result.is_ok(),
+        "path": path,
+        "url": url,
+        "total_bytes": report.map(|r| r.total_bytes),
+        "chunk_size": chunk_size,
+        "chunk_count": report.map(|r| r.chunk_count),
+        "chunks_succeeded": report.map(|r| r.chunks_succeeded),
+        "unchanged_chunks": report.map(|r| r.unchanged_chunks),
+        "unchanged_bytes": report.map(|r| r.unchanged_bytes),
+        "chunks": chunks,
+        "sha256": report.and_then(|r| r.sha256.clone()),
+        "checksum": report.and_then(|r| r.checksum.as_ref()).map(|(c, digest)| json!({
+            "algorithm": c.to_string(),
+            "digest": digest,
+        })),
+        "encryption": report.and_then(|r| r.encryption.clone()),
+        "compressed": report.and_then(|r| r.compressed.as_ref()).map(|c| json!({
+            "compression": c.compression.to_string(),
+            "original_bytes": c.original_bytes,
+            "ratio": c.ratio(report.map_or(0, |r| r.total_bytes)),
+            "original_checksum": c.original_checksum.as_ref().map(|(checksum, digest)| json!({
+                "algorithm": checksum.to_string(),
+                "digest": digest,
+            })),
+        })),
+        "finalize_response": report.and_then(|r| r.finalize_response.clone()),
+        "elapsed_ms": elapsed.as_millis() as u64,
+        "error": result.as_ref().err().map(|err| err.to_string()),
+        "failed_offset": report.and_then(|r| r.failed_offset),
+        "skipped_existing": report.is_some_and(|r| r.skipped_existing),
+        "verified": report.is_some_and(|r| r.verified),
+        "cleanup": report.and_then(|r| r.cleanup.as_ref()).map(|cleanup| match cleanup {
+            Ok(()) => json!({"aborted": true}),
+            Err(err) => json!({"aborted": false, "error": err}),
+        }),
+    });
+    if let (Some(report), true) = (report, stats) {
+        let stats = report.stats();
+        document["stats"] = json!({
+            "bytes_sent": stats.bytes_sent,
+            "bytes_confirmed": stats.bytes_confirmed,
+            "unchanged_bytes": stats.unchanged_bytes,
+            "elapsed_ms": stats.elapsed.as_millis() as u64,
+            "transfer_ms": stats.transfer_time.as_millis() as u64,
+            "delay_ms": stats.delay.as_millis() as u64,
+            "average_bytes_per_sec": stats.average_rate as u64,
+            "peak_bytes_per_sec": stats.peak_rate.map(|rate| rate as u64),
+            "chunks": stats.chunks,
+            "retries": stats.retries,
+            "rate_limited": stats.rate_limited,
+            "rate_limit_wait_ms": stats.rate_limit_wait.as_millis() as u64,
+            "slowest_chunk": stats.slowest.map(|(index, duration)| json!({
+                "index": index,
+                "duration_ms": duration.as_millis() as u64,
+            })),
+        });
+    }
+    document
+}
+
+/// The TLS library native-tls wraps on this platform
+const TLS_BACKEND: &str = if cfg!(target_os = "windows") {
+    "native-tls (SChannel)"
+} else if cfg!(target_vendor = "apple") {
+    "native-tls (Security.framework)"
+} else {
+    "native-tls (OpenSSL)"
+};
+
+/// Prints '--version', the first text line stays the bare version older scripts read
+fn print_version_info(json: bool) {
+    let version = env!("CARGO_PKG_VERSION");
+    let commit = env!("CHUNK_UPLOADER_COMMIT");
+    let build_date = env!("CHUNK_UPLOADER_BUILD_DATE");
+    let reqwest = env!("CHUNK_UPLOADER_REQWEST_VERSION");
+    if json {
+        output::print(
+            &json!({
+                "version": version,
+                "commit": commit,
+                "build_date": build_date,
+                "reqwest": reqwest,
+                "tls": TLS_BACKEND,
+            })
+            .to_string(),
+        );
+        return;
+    }
+    output::print(&format!("V{}", version));
+    output::print(&format!("commit: {}", commit));
+    output::print(&format!("built: {}", build_date));
+    output::print(&format!("reqwest: {}, {}", reqwest, TLS_BACKEND));
+}
+
+/// Lists how each chunk of an upload went for '--stats'
+fn print_chunk_stats(out: &Output, report: &UploadReport) {
+    for chunk in report.chunks.iter() {
+        let status = chunk
+            .status
+            .map_or_else(|| "no response".to_string(), |s| s.to_string());
+        let mut line = format!(
+            "\t chunk {}: bytes {}-{}, {} bytes sent, {}, {} retries, started at {}ms, took {}ms",
+            chunk.index,
+            chunk.start,
+            chunk.end,
+            chunk.bytes_sent,
+            status,
+            chunk.retries,
+            chunk.started.as_millis(),
+            chunk.duration.as_millis()
+        );
+        if !chunk.delay.is_zero() {
+            line.push_str(&format!(" after waiting {}ms", chunk.delay.as_millis()));
+        }
+        out.line(&line);
+    }
+}
+
+/// Prints what a dry run would send for one file
+fn print_plan(out: &Output, plan: &UploadPlan) {
+    out.line(&format!("Plan for '{}'", plan.path));
+    out.line(&format!(
+        "\t {} {} ({} protocol)",
+        plan.method, plan.url, plan.protocol
+    ));
+    for (name, value) in plan.headers.iter() {
+        out.line(&format!("\t {}: {}", name, shown_value(name, value)));
+    }
+    if let Some(content_type) = plan.content_type.as_ref() {
+        out.line(&format!(
+            "\t Content-Type: {}",
+            shown_value(&CONTENT_TYPE, content_type)
+        ));
+    }
+    if let Some((method, url, from)) = plan.init.as_ref() {
+        out.line(&format!(
+            "\t first {} {} to create the upload, its URL taken from {}",
+            method, url, from
+        ));
+    }
+    if plan.offset > plan.range.0 {
+        out.line(&format!("\t Resuming from byte {}", plan.offset));
+    }
+    out.line(&format!(
+        "\t {} chunks of up to {} bytes for bytes {}-{}",
+        plan.chunks.len(),
+        plan.chunk_size,
+        plan.offset,
+        plan.range.1
+    ));
+    for chunk in plan.chunks.iter() {
+        let mut line = format!(
+            "\t chunk {}: bytes {}-{}",
+            chunk.index, chunk.start, chunk.end
+        );
+        if let Some(url) = chunk.url.as_ref() {
+            line.push_str(&format!(", {}", url));
+        }
+        if let Some(range) = chunk.content_range.as_ref() {
+            line.push_str(&format!(", Content-Range: {}", range));
+        }
+        for (name, value) in chunk.headers.iter() {
+            line.push_str(&format!(", {}: {}", name, shown_value(name, value)));
+        }
+        out.line(&line);
+    }
+    if let Some((method, url)) = plan.finalize.as_ref() {
+        out.line(&format!("\t then {} {} to finalize", method, url));
+    }
+}
+
+/// Shows the file, range, chunks, method and URL of each upload and asks to proceed, true
+/// only for a yes. A file that can't be uploaded is shown with why.
+fn confirm(
+    out: &Output,
+    uploader: &ChunkUploader,
+    uploads: &[Entry],
+    url: &str,
+    prefix: &str,
+) -> std::result::Result<bool, CliError> {
+    let mut shown = String::new();
+    for upload in uploads.iter() {
+        let url = expand_url(url, upload, prefix);
+        let plan = match uploader.plan(Source::File(upload.path.clone().into()), &url) {
+            Ok(plan) => plan,
             Err(err) => {
-                exit!(false, "Error uploading chunk: {}", err);
+                shown.push_str(&format!("'{}' can't be uploaded: {}\n", upload.path, err));
+                continue;
             }
+        };
+        shown.push_str(&format!(
+            "Upload '{}' ({} bytes), bytes {}-{} in {} chunks of up to {} bytes\n",
+            plan.path,
+            plan.file_size,
+            plan.offset,
+            plan.range.1,
+            plan.chunks.len(),
+            plan.chunk_size
+        ));
+        if let Some((method, url, _)) = plan.init.as_ref() {
+            shown.push_str(&format!(
+                "\t first {} {} to create the upload\n",
+                method, url
+            ));
+        }
+        shown.push_str(&format!("\t {} {}\n", plan.method, plan.url));
+        if let Some((method, url)) = plan.finalize.as_ref() {
+            shown.push_str(&format!("\t then {} {} to finalize\n", method, url));
+        }
+    }
+    shown.push_str("Proceed? [y/N] ");
+    let mut answer = String::new();
+    out.prompt(&shown)
+        .and_then(|_| stdin().read_line(&mut answer))
+        .map_err(|e| CliError::Io(format!("Error asking to proceed: {}", e)))?;
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+/// A header's value as it's safe to print, credentials replaced
+fn shown_value(name: &HeaderName, value: &HeaderValue) -> String {
+    if value.is_sensitive() || name == AUTHORIZATION {
+        REDACTED.to_string()
+    } else {
+        String::from_utf8_lossy(value.as_bytes()).into_owned()
+    }
+}
+
+/// The paths listed one per line in `list`, or stdin for `-`, or separated by NUL bytes as
+/// `find -print0` writes them
+///
+/// Lines that are blank or start with `#` are skipped, NUL separated paths are taken as they are.
+fn read_file_list(list: &str, null_separated: bool) -> std::result::Result<Vec<String>, CliError> {
+    let read = match list {
+        "-" => {
+            let mut bytes = Vec::new();
+            stdin().read_to_end(&mut bytes).map(|_| bytes)
+        }
+        path => fs::read(path),
+    };
+    let name = match list {
+        "-" => "stdin".to_string(),
+        path => format!("'{}'", path),
+    };
+    let bytes = read.map_err(|err| {
+        CliError::Io(format!(
+            "Error reading the list of files from {}: {}",
+            name, err
+        ))
+    })?;
+    let text = String::from_utf8(bytes).map_err(|_| {
+        CliError::Usage(format!("The list of files from {} isn't valid UTF-8", name))
+    })?;
+    let paths = match null_separated {
+        true => text
+            .split('\0')
+            .filter(|path| !path.is_empty())
+            .map(str::to_string)
+            .collect(),
+        false => text
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect(),
+    };
+    Ok(paths)
+}
+
+/// Fills the file's name and its path within the directory into the URL, behind the
+/// `--prefix`
+fn expand_url(url: &str, upload: &Entry, prefix: &str) -> String {
+    // The name its path ends in, which for a tar stream is the archive's
+    let filename = Path::new(&upload.relative)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    fill_url(
+        url,
+        &[
+            ("{filename}", &format!("{prefix}/{filename}")),
+            ("{path}", &format!("{prefix}/{}", upload.relative)),
+        ],
+    )
+}
+
+/// The `{path}` of a listed file, its path without the `./` find puts in front or a leading
+/// `/`, and with `/` between the directories where Windows has `\`
+fn listed_relative(path: &str) -> String {
+    Path::new(path)
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The file behind '--progress-fd', which the shell opened for the process
+#[cfg(unix)]
+fn progress_fd_file(fd: i32) -> std::result::Result<fs::File, CliError> {
+    use std::os::fd::FromRawFd;
+
+    // Owned from here on, it's only ever handed to the events
+    let file = unsafe { fs::File::from_raw_fd(fd) };
+    match file.metadata() {
+        Ok(_) => Ok(file),
+        Err(err) => {
+            // Not ours to close when it isn't open
+            std::mem::forget(file);
+            Err(CliError::Usage(format!(
+                "File descriptor {} for '--progress-fd' isn't open: {}",
+                fd, err
+            )))
         }
+    }
+}
 
-        if n == 0 || n < chunk_size as usize {
-            break;
+#[cfg(not(unix))]
+fn progress_fd_file(_fd: i32) -> std::result::Result<fs::File, CliError> {
+    Err(CliError::Usage(
+        "'--progress-fd' only works on Unix, use '--progress-file' instead".to_string(),
+    ))
+}
+
+/// Installs the subscriber logging to stderr at `level`, or `RUST_LOG` without one, and to
+/// `file` as JSON lines, nothing when neither is asked for
+fn start_logging(level: Option<&str>, file: Option<&str>) -> std::result::Result<(), CliError> {
+    let rust_log = env::var("RUST_LOG").ok().filter(|value| !value.is_empty());
+    let filter = match (level, rust_log) {
+        (Some(level), _) => Some(level.parse().map_err(CliError::Usage)?),
+        (None, Some(rust_log)) => Some(
+            (rust_log.parse::<logging::Filter>())
+                .map_err(|err| CliError::Usage(format!("{} in RUST_LOG", err)))?,
+        ),
+        (None, None) => None,
+    };
+    let file = match file {
+        Some(path) => Some(
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| CliError::Io(format!("Error opening log file '{}': {}", path, e)))?,
+        ),
+        None => None,
+    };
+    if filter.is_none() && file.is_none() {
+        return Ok(());
+    }
+    let terminal = filter.is_some();
+    let filter = filter.unwrap_or_else(logging::file_default);
+    let _ = tracing::subscriber::set_global_default(logging::Logger::new(filter, terminal, file));
+    Ok(())
+}
+
+/// Parses a value from the config file, an invalid one is an error naming the key
+fn config_value<T: std::str::FromStr>(
+    key: &str,
+    value: Option<&str>,
+) -> std::result::Result<Option<T>, CliError> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    match value.parse() {
+        Ok(parsed) => Ok(Some(parsed)),
+        Err(_) => Err(CliError::Usage(format!(
+            "Invalid {} '{}' in the config file",
+            key, value
+        ))),
+    }
+}
+
+/// Parses a timeout from the config file, zero when it has none
+fn config_duration(value: Option<&str>) -> std::result::Result<Duration, CliError> {
+    match value.map(parse_duration) {
+        Some(Err(err)) => Err(CliError::Usage(format!("{} in the config file", err))),
+        duration => Ok(duration.and_then(|d| d.ok()).unwrap_or_default()),
+    }
+}
+
+/// Reads every certificate of a PEM file
+fn load_certificates(path: &str) -> std::result::Result<Vec<Certificate>, String> {
+    let pem = fs::read(path)
+        .map_err(|e| format!("Error reading CA certificate file '{}': {}", path, e))?;
+    match Certificate::from_pem_bundle(&pem) {
+        Ok(certificates) if certificates.is_empty() => {
+            Err(format!("No certificates in CA certificate file '{}'", path))
+        }
+        Ok(certificates) => Ok(certificates),
+        Err(err) => Err(format!("Invalid CA certificate file '{}': {}", path, err)),
+    }
+}
+
+/// Reads the client certificate and key from PEM files or from one PKCS#12 file
+fn load_identity(
+    cert: Option<String>,
+    key: Option<String>,
+    identity: Option<String>,
+    password: Option<String>,
+) -> std::result::Result<Option<Identity>, String> {
+    let read = |path: &str| {
+        fs::read(path)
+            .map_err(|e| format!("Error reading client certificate file '{}': {}", path, e))
+    };
+    match (cert, key, identity) {
+        (None, None, None) if password.is_some() => {
+            Err("'--identity-password' can only be used with '--identity'".to_string())
+        }
+        (None, None, None) => Ok(None),
+        (Some(cert), Some(key), None) if password.is_none() => {
+            Identity::from_pkcs8_pem(&read(&cert)?, &read(&key)?)
+                .map(Some)
+                .map_err(|e| {
+                    format!(
+                        "Invalid client certificate '{}' or key '{}', the key must be PKCS#8: {}",
+                        cert, key, e
+                    )
+                })
+        }
+        (None, None, Some(identity)) => {
+            Identity::from_pkcs12_der(&read(&identity)?, password.as_deref().unwrap_or(""))
+                .map(Some)
+                .map_err(|e| {
+                    format!(
+                        "Invalid client certificate file '{}' or wrong password: {}",
+                        identity, e
+                    )
+                })
         }
+        (Some(_), Some(_), None) => {
+            Err("'--identity-password' can only be used with '--identity'".to_string())
+        }
+        (_, _, Some(_)) => {
+            Err("'--identity' can't be combined with '--cert' and '--key'".to_string())
+        }
+        _ => Err("'--cert' and '--key' must be given together".to_string()),
+    }
+}
+
+/// The URL with any password replaced, for printing
+fn redact_url(url: &str) -> String {
+    match Url::parse(url).ok().as_ref().and_then(|u| u.password()) {
+        Some(password) => url.replacen(&format!(":{password}@"), &format!(":{REDACTED}@"), 1),
+        None => url.to_string(),
+    }
+}
+
+/// A duration the way `parse_duration` reads it back
+fn format_duration(duration: Duration) -> String {
+    match duration.subsec_millis() {
+        0 => format!("{}s", duration.as_secs()),
+        _ => format!("{}ms", duration.as_millis()),
+    }
+}
+
+/// Parses a comma separated list of HTTP statuses like `200,201,204`
+fn parse_statuses(arg: &str) -> std::result::Result<Vec<StatusCode>, String> {
+    arg.split(',')
+        .map(|status| {
+            status
+                .trim()
+                .parse::<u16>()
+                .ok()
+                .and_then(|code| StatusCode::from_u16(code).ok())
+                .ok_or_else(|| format!("Invalid status code '{}' in '{}'", status.trim(), arg))
+        })
+        .collect()
+}
 
-        start += chunk_size;
+/// Parses an If-Match or If-None-Match value, quoting a bare ETag like `abc123` as the header
+/// needs while `*`, `"abc123"` and `W/"abc123"` are sent as given
+fn parse_etags(arg: &str) -> std::result::Result<HeaderValue, String> {
+    let arg = arg.trim();
+    let value = match arg {
+        "*" => arg.to_string(),
+        _ if arg.starts_with('"') || arg.starts_with("W/\"") => arg.to_string(),
+        _ => format!("\"{}\"", arg),
+    };
+    match HeaderValue::from_str(&value) {
+        Ok(value) if !arg.is_empty() => Ok(value),
+        _ => Err(format!("Invalid ETag '{}'", arg)),
     }
+}
 
-    exit!(true, "Request completed successfully");
+/// Parses a curl style `Name: value` header, only splitting on the first colon
+fn parse_header(arg: &str) -> std::result::Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = arg
+        .split_once(':')
+        .ok_or_else(|| format!("Invalid header '{}', expected 'Name: value'", arg))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|_| format!("Invalid header name '{}'", name.trim()))?;
+    let value = HeaderValue::from_str(value.trim())
+        .map_err(|_| format!("Invalid value for header '{}'", name))?;
+    Ok((name, value))
 }
@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs::File;
+use tokio::io::AsyncSeekExt;
+
+use crate::checksum::{Checksum, Hasher};
+use crate::digest::{hex, FileDigest};
+use crate::encrypt::EncryptionRecord;
+use crate::existing::hash_range;
+use crate::range::split_range;
+
+/// Every chunk sent to the server, kept in a JSON or CSV file for auditing and resuming, see
+/// [`crate::ChunkUploaderBuilder::manifest`]
+pub(crate) struct Manifest {
+    path: PathBuf,
+    /// Written as CSV when the path ends in `.csv`, which isn't read back
+    csv: bool,
+    document: Mutex<Document>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Document {
+    files: Vec<FileEntry>,
+}
+
+/// One upload, told apart from the others by its file and URL
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct FileEntry {
+    pub file: String,
+    pub url: String,
+    pub size: u64,
+    /// The uploaded range of the file, end exclusive
+    pub range: (u64, u64),
+    pub chunk_size: u64,
+    /// Every chunk was confirmed and the upload completed
+    pub complete: bool,
+    /// What the digests are, SHA-256 in manifests from before there was a choice
+    #[serde(default)]
+    pub checksum: Checksum,
+    /// Hex digest of the range, when it was computed
+    #[serde(default, alias = "sha256", skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    /// How the chunks were encrypted, see [`crate::ChunkUploaderBuilder::encrypt`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<EncryptionRecord>,
+    pub chunks: Vec<ChunkEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct ChunkEntry {
+    pub index: u64,
+    pub offset: u64,
+    pub length: u64,
+    /// Hex digest of the chunk's bytes before any compression
+    #[serde(alias = "sha256")]
+    pub digest: Option<String>,
+    pub status: Option<u16>,
+    pub etag: Option<String>,
+    pub attempts: u32,
+    /// When the chunk was first sent, in UTC
+    pub timestamp: String,
+    /// The server stored it, a failed chunk is listed too
+    pub confirmed: bool,
+}
+
+impl Manifest {
+    /// Reads what an earlier run wrote to `path`, refusing a file that isn't a manifest rather
+    /// than overwriting it
+    pub fn open(path: PathBuf) -> Result<Self, String> {
+        let csv = path.extension().is_some_and(|ext| ext == "csv");
+        let document = match fs::read_to_string(&path) {
+            Ok(_) if csv => Document::default(),
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| format!("Invalid manifest '{}': {}", path.display(), e))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Document::default(),
+            Err(err) => {
+                return Err(format!(
+                    "Error reading manifest '{}': {}",
+                    path.display(),
+                    err
+                ))
+            }
+        };
+        Ok(Manifest {
+            path,
+            csv,
+            document: Mutex::new(document),
+        })
+    }
+
+    /// What an earlier run recorded for the upload of `file` to `url`
+    pub fn previous(&self, file: &str, url: &str) -> Option<FileEntry> {
+        let document = self.document.lock().unwrap();
+        let entry = document
+            .files
+            .iter()
+            .find(|f| f.file == file && f.url == url);
+        entry.cloned()
+    }
+
+    /// Starts the upload's entry over, keeping the chunks of an earlier run it resumes from
+    pub fn start(&self, entry: FileEntry) -> io::Result<()> {
+        let mut document = self.document.lock().unwrap();
+        document
+            .files
+            .retain(|f| f.file != entry.file || f.url != entry.url);
+        document.files.push(entry);
+        self.write(&document)
+    }
+
+    /// Records a chunk, replacing what an earlier attempt at the same bytes left
+    pub fn chunk(&self, file: &str, url: &str, chunk: ChunkEntry) -> io::Result<()> {
+        self.update(file, url, |entry| {
+            entry.chunks.retain(|c| c.offset != chunk.offset);
+            let at = entry.chunks.partition_point(|c| c.offset < chunk.offset);
+            entry.chunks.insert(at, chunk);
+        })
+    }
+
+    /// Marks the upload as complete, with the encryption record now that stdin's length is known
+    pub fn finish(
+        &self,
+        file: &str,
+        url: &str,
+        digest: Option<String>,
+        encryption: Option<EncryptionRecord>,
+    ) -> io::Result<()> {
+        self.update(file, url, |entry| {
+            entry.complete = true;
+            entry.digest = digest;
+            entry.encryption = encryption;
+        })
+    }
+
+    fn update(&self, file: &str, url: &str, change: impl FnOnce(&mut FileEntry)) -> io::Result<()> {
+        let mut document = self.document.lock().unwrap();
+        let entry = document
+            .files
+            .iter_mut()
+            .find(|f| f.file == file && f.url == url);
+        if let Some(entry) = entry {
+            change(entry);
+        }
+        self.write(&document)
+    }
+
+    /// Rewrites the whole file to the side and renames it over, so a crash never leaves half a
+    /// manifest
+    fn write(&self, document: &Document) -> io::Result<()> {
+        let text = match self.csv {
+            true => csv(document),
+            false => serde_json::to_string_pretty(document)?,
+        };
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = Path::new(&tmp);
+        fs::write(tmp, text)?;
+        fs::rename(tmp, &self.path)
+    }
+}
+
+impl FileEntry {
+    /// The confirmed chunks from the start of the range on whose bytes the file still holds, up
+    /// to the first that's missing or changed, with where they end
+    pub async fn still_confirmed(&self, path: &str) -> io::Result<(Vec<ChunkEntry>, u64)> {
+        let mut file = File::open(path).await?;
+        let (mut confirmed, mut end) = (Vec::new(), self.range.0);
+        for chunk in self.chunks.iter() {
+            if chunk.offset != end || !chunk.confirmed || chunk.offset + chunk.length > self.range.1
+            {
+                break;
+            }
+            let range = (chunk.offset, chunk.offset + chunk.length);
+            let digests = hash_range(&mut file, range, &[self.checksum]).await?;
+            if chunk.digest.as_ref() != digests.first() {
+                break;
+            }
+            confirmed.push(chunk.clone());
+            end = range.1;
+        }
+        Ok((confirmed, end))
+    }
+
+    /// The confirmed chunks from `offset` on whose bytes the file still holds, wherever they
+    /// are, comparing the file in chunks of `chunk_size` up to the end of the last one listed
+    ///
+    /// What's read is fed into `digest`, which then only needs the chunks sent.
+    pub async fn unchanged(
+        &self,
+        path: &str,
+        (offset, end): (u64, u64),
+        chunk_size: u64,
+        digest: Option<&FileDigest>,
+    ) -> io::Result<Vec<ChunkEntry>> {
+        let listed: BTreeMap<u64, &ChunkEntry> = (self.chunks.iter())
+            .filter(|chunk| chunk.confirmed)
+            .map(|chunk| (chunk.offset, chunk))
+            .collect();
+        let Some(listed_end) = listed.values().map(|c| c.offset + c.length).max() else {
+            return Ok(Vec::new());
+        };
+        let mut file = File::open(path).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut buf = vec![0; 1024 * 1024];
+        let mut unchanged = Vec::new();
+        for (start, len) in split_range((offset, end.min(listed_end)), chunk_size) {
+            let mut hasher = Hasher::new(self.checksum);
+            let mut at = start;
+            while at < start + len {
+                let want = buf.len().min((start + len - at) as usize);
+                let n = crate::read_full(&mut file, &mut buf[..want]).await?;
+                if n == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                hasher.update(&buf[..n]);
+                if let Some(digest) = digest {
+                    digest.update(at, &buf[..n]);
+                }
+                at += n as u64;
+            }
+            let Some(chunk) = listed.get(&start).filter(|chunk| chunk.length == len) else {
+                continue;
+            };
+            if chunk.digest.as_deref() == Some(hex(&hasher.finalize()).as_str()) {
+                unchanged.push((*chunk).clone());
+            }
+        }
+        Ok(unchanged)
+    }
+}
+
+/// `value` as a CSV field, quoted as RFC 4180 wants when it holds a comma, quote or line break
+pub(crate) fn csv_field(value: &str) -> String {
+    match value.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_string(),
+    }
+}
+
+fn csv(document: &Document) -> String {
+    let mut text = String::from(
+        "file,url,index,offset,length,checksum,digest,status,etag,attempts,timestamp,confirmed\n",
+    );
+    for entry in document.files.iter() {
+        for chunk in entry.chunks.iter() {
+            let row = [
+                csv_field(&entry.file),
+                csv_field(&entry.url),
+                chunk.index.to_string(),
+                chunk.offset.to_string(),
+                chunk.length.to_string(),
+                entry.checksum.to_string(),
+                chunk.digest.clone().unwrap_or_default(),
+                chunk.status.map(|s| s.to_string()).unwrap_or_default(),
+                csv_field(chunk.etag.as_deref().unwrap_or_default()),
+                chunk.attempts.to_string(),
+                chunk.timestamp.clone(),
+                chunk.confirmed.to_string(),
+            ];
+            text.push_str(&row.join(","));
+            text.push('\n');
+        }
+    }
+    text
+}
+
+/// `time` as an RFC 3339 timestamp in UTC to the millisecond, e.g. `2024-05-01T12:30:05.123Z`
+pub fn timestamp(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO);
+    let secs = since.as_secs();
+    // Days to civil date, from http://howardhinnant.github.io/date_algorithms.html
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+        since.subsec_millis()
+    )
+}
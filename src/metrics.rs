@@ -0,0 +1,126 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::manifest::{csv_field, timestamp};
+
+const HEADER: &str =
+    "timestamp,file,index,offset,bytes,attempt,status,result,ttfb_ms,duration_ms\n";
+
+/// The CSV file every chunk attempt is appended to, see
+/// [`crate::ChunkUploaderBuilder::metrics_csv`]
+pub(crate) struct Metrics {
+    file: Mutex<File>,
+}
+
+impl Metrics {
+    /// Opens `path` for appending, writing the header when this creates it
+    pub fn open(path: PathBuf) -> Result<Self, String> {
+        let failed =
+            |err: io::Error| format!("Error opening metrics file '{}': {}", path.display(), err);
+        // Creating it exclusively tells whether another run already wrote the header
+        let file = match OpenOptions::new().append(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(HEADER.as_bytes()).map_err(failed)?;
+                file
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .map_err(failed)?,
+            Err(err) => return Err(failed(err)),
+        };
+        Ok(Metrics {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// One attempt at sending chunk `index`, written as a row once it's dropped
+    pub fn attempt<'a>(
+        &'a self,
+        file: &'a str,
+        index: u64,
+        offset: u64,
+        bytes: u64,
+        attempt: u32,
+    ) -> Attempt<'a> {
+        Attempt {
+            metrics: self,
+            file,
+            index,
+            offset,
+            bytes,
+            attempt,
+            started: SystemTime::now(),
+            sent: Instant::now(),
+            ttfb: None,
+            status: None,
+            result: "failed",
+        }
+    }
+
+    /// Each row goes out in a single write to the file opened for appending, so the rows of runs
+    /// sharing the file never interleave
+    ///
+    /// A row that can't be written is dropped rather than failing the upload.
+    fn write(&self, row: &str) {
+        let _ = self.file.lock().unwrap().write_all(row.as_bytes());
+    }
+}
+
+/// What's known about a chunk attempt so far, the row is written however the attempt ends
+pub(crate) struct Attempt<'a> {
+    metrics: &'a Metrics,
+    file: &'a str,
+    index: u64,
+    offset: u64,
+    bytes: u64,
+    attempt: u32,
+    started: SystemTime,
+    sent: Instant,
+    /// Until the response's headers arrived, `None` when none did
+    ttfb: Option<Duration>,
+    status: Option<u16>,
+    /// `ok`, `retried` or `failed`
+    result: &'static str,
+}
+
+impl Attempt<'_> {
+    /// The response's headers arrived with `status`
+    pub fn responded(&mut self, status: u16) {
+        self.ttfb = Some(self.sent.elapsed());
+        self.status = Some(status);
+    }
+
+    /// The server stored the chunk
+    pub fn stored(&mut self) {
+        self.result = "ok";
+    }
+
+    /// The chunk is sent again after this attempt
+    pub fn retried(&mut self) {
+        self.result = "retried";
+    }
+}
+
+impl Drop for Attempt<'_> {
+    fn drop(&mut self) {
+        let row = [
+            timestamp(self.started),
+            csv_field(self.file),
+            self.index.to_string(),
+            self.offset.to_string(),
+            self.bytes.to_string(),
+            self.attempt.to_string(),
+            self.status.map(|s| s.to_string()).unwrap_or_default(),
+            self.result.to_string(),
+            self.ttfb
+                .map(|ttfb| ttfb.as_millis().to_string())
+                .unwrap_or_default(),
+            self.sent.elapsed().as_millis().to_string(),
+        ];
+        self.metrics.write(&format!("{}\n", row.join(",")));
+    }
+}
@@ -0,0 +1,121 @@
+use std::time::Duration;
+
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Client;
+use serde_json::json;
+
+use crate::{describe_error, snippet, UploadError, UploadOptions, UploadReport};
+
+/// Which uploads [`crate::ChunkUploaderBuilder::notify`] tells the webhook about
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NotifyOn {
+    Success,
+    /// Also an interrupted upload
+    Failure,
+    Always,
+}
+
+impl std::str::FromStr for NotifyOn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "success" => Ok(NotifyOn::Success),
+            "failure" => Ok(NotifyOn::Failure),
+            "always" => Ok(NotifyOn::Always),
+            _ => Err(format!(
+                "Unknown notification policy '{s}', expected success, failure or always"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for NotifyOn {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            NotifyOn::Success => "success",
+            NotifyOn::Failure => "failure",
+            NotifyOn::Always => "always",
+        })
+    }
+}
+
+/// The webhook told how each upload ended
+#[derive(Clone)]
+pub(crate) struct Notify {
+    pub url: String,
+    pub on: NotifyOn,
+    /// Sent as a bearer token, apart from the upload's own credentials
+    pub token: Option<String>,
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Notify {
+            url: String::new(),
+            on: NotifyOn::Always,
+            token: None,
+        }
+    }
+}
+
+impl Notify {
+    /// POSTs how the upload of `file` ended, a webhook that can't be reached or refuses it only
+    /// gets a warning as the upload's outcome stays what it was
+    pub async fn send(
+        &self,
+        client: &Client,
+        opts: &UploadOptions,
+        file: &str,
+        result: &Result<UploadReport, UploadError>,
+        elapsed: Duration,
+    ) {
+        let wanted = match self.on {
+            NotifyOn::Success => result.is_ok(),
+            NotifyOn::Failure => result.is_err(),
+            NotifyOn::Always => true,
+        };
+        if !wanted {
+            return;
+        }
+        let report = match result {
+            Ok(report) => Some(report),
+            Err(err) => err.report(),
+        };
+        let payload = json!({
+            "file": file,
+            "size": report.map(|report| report.total_bytes),
+            "success": result.is_ok(),
+            "duration_ms": elapsed.as_millis() as u64,
+            "error": result.as_ref().err().map(|err| err.to_string()),
+            // What the server refused the upload with, when it did
+            "status": result.as_ref().err().and_then(|err| err.status()).map(|s| s.as_u16()),
+            // The start of the finalize response, which often names the new object
+            "response": report
+                .and_then(|report| report.finalize_response.as_deref())
+                .map(snippet),
+        });
+
+        // Built on the bare client, so the upload's headers and credentials stay out of it
+        let mut request = client
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(payload.to_string());
+        if let Some(token) = self.token.as_ref() {
+            request = request.bearer_auth(token);
+        }
+        match request.send().await {
+            Ok(res) if res.status().is_success() => {}
+            Ok(res) => opts.warn(&format!(
+                "Warning: the notification to {} got {}",
+                self.url,
+                res.status()
+            )),
+            Err(err) => opts.warn(&format!(
+                "Warning: failed to send the notification to {}: {}",
+                self.url,
+                describe_error(opts, &err)
+            )),
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! Where the lines an upload prints go, and whether they're colored
+//!
+//! Whether stdout and stderr are terminals is looked up once, so the progress bar, the
+//! messages and the summary all agree on it. Every message is also a `tracing` event of this
+//! module's target, so a log of the upload has them in order with the rest.
+
+use std::fmt;
+use std::io::{self, stderr, stdout, IsTerminal, Write};
+use std::str::FromStr;
+
+/// When lines are colored
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum ColorChoice {
+    /// On a terminal, unless `NO_COLOR` is set
+    #[default]
+    Auto,
+    /// Even into a file or pipe, and despite `NO_COLOR`
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!(
+                "Invalid color '{}', expected auto, always or never",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ColorChoice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ColorChoice::Auto => "auto",
+            ColorChoice::Always => "always",
+            ColorChoice::Never => "never",
+        })
+    }
+}
+
+/// What a line says, which picks its color
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Tone {
+    Plain,
+    /// Green, an upload that went through
+    Success,
+    /// Yellow, something worth knowing that didn't stop the upload
+    Warning,
+    /// Red, what failed
+    Error,
+}
+
+/// How one of stdout and stderr is written to
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Stream {
+    /// The progress bar is only drawn on a terminal
+    pub terminal: bool,
+    pub color: bool,
+}
+
+impl Stream {
+    /// Colored when asked to, or on a terminal unless `no_color`, which `NO_COLOR` being set
+    /// to anything means, see https://no-color.org
+    pub fn new(terminal: bool, choice: ColorChoice, no_color: bool) -> Self {
+        let color = match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => terminal && !no_color,
+        };
+        Stream { terminal, color }
+    }
+
+    /// `msg` in the tone's color, as it is when not colored
+    pub fn paint(&self, msg: &str, tone: Tone) -> String {
+        if !self.color {
+            return msg.to_string();
+        }
+        let code = match tone {
+            Tone::Plain => return msg.to_string(),
+            Tone::Success => "32",
+            Tone::Warning => "33",
+            Tone::Error => "31",
+        };
+        format!("\x1b[{}m{}\x1b[0m", code, msg)
+    }
+}
+
+/// Prints the messages, results and progress of a run
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Output {
+    stdout: Stream,
+    stderr: Stream,
+    /// Stdout holds a JSON document or progress events, the messages go to stderr then
+    stdout_taken: bool,
+}
+
+impl Output {
+    /// Looks up whether stdout and stderr are terminals and whether `NO_COLOR` is set
+    pub fn detect(choice: ColorChoice) -> Self {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        Output::new(
+            Stream::new(stdout().is_terminal(), choice, no_color),
+            Stream::new(stderr().is_terminal(), choice, no_color),
+        )
+    }
+
+    pub fn new(stdout: Stream, stderr: Stream) -> Self {
+        Output {
+            stdout,
+            stderr,
+            stdout_taken: false,
+        }
+    }
+
+    /// Sends the messages to stderr, for stdout holding something the caller parses
+    pub fn taking_stdout(mut self, taken: bool) -> Self {
+        self.stdout_taken = taken;
+        self
+    }
+
+    pub fn stdout_taken(&self) -> bool {
+        self.stdout_taken
+    }
+
+    /// Where the progress bar would be drawn, stdout unless it's taken
+    pub fn shows_progress(&self) -> bool {
+        !self.stdout_taken && self.stdout.terminal
+    }
+
+    /// A message, on stdout unless it's taken
+    pub fn line(&self, msg: &str) {
+        self.message(msg, Tone::Plain);
+    }
+
+    /// A message about something that went through, on stdout unless it's taken
+    pub fn success(&self, msg: &str) {
+        self.message(msg, Tone::Success);
+    }
+
+    /// A problem that didn't stop the upload, on stderr
+    pub fn warn(&self, msg: &str) {
+        tracing::warn!("{}", msg);
+        print_err(&self.stderr.paint(msg, Tone::Warning));
+    }
+
+    /// What failed, on stderr
+    pub fn error(&self, msg: &str) {
+        tracing::error!("{}", msg);
+        print_err(&self.stderr.paint(msg, Tone::Error));
+    }
+
+    /// What the caller asked for, like the JSON document or a digest, on stdout as it is
+    pub fn result(&self, msg: &str) {
+        print(msg);
+    }
+
+    /// Asks `question` where the messages go, leaving the cursor after it for the answer
+    pub fn prompt(&self, question: &str) -> io::Result<()> {
+        match self.stdout_taken {
+            true => stderr().write_all(question.as_bytes()),
+            false => stdout()
+                .write_all(question.as_bytes())
+                .and_then(|_| stdout().flush()),
+        }
+    }
+
+    /// Replaces the progress bar on stdout with `bar`, without moving to the next line
+    pub fn draw(&self, bar: &str) {
+        let mut stdout = stdout().lock();
+        let _ = write!(stdout, "\r\x1b[K{bar}").and_then(|_| stdout.flush());
+    }
+
+    /// Clears the progress bar so a line can take its place
+    pub fn clear(&self) {
+        let mut stdout = stdout().lock();
+        let _ = write!(stdout, "\r\x1b[K").and_then(|_| stdout.flush());
+    }
+
+    /// Moves past the progress bar so following output starts on a fresh line
+    pub fn end_bar(&self) {
+        print("");
+    }
+
+    /// `msg` painted for stdout, where the bar goes
+    pub fn paint(&self, msg: &str, tone: Tone) -> String {
+        self.stdout.paint(msg, tone)
+    }
+
+    fn message(&self, msg: &str, tone: Tone) {
+        tracing::info!("{}", msg);
+        match self.stdout_taken {
+            true => print_err(&self.stderr.paint(msg, tone)),
+            false => print(&self.stdout.paint(msg, tone)),
+        }
+    }
+}
+
+/// Writes `text` and a newline to stdout. A reader gone before it, like `| head`, isn't an
+/// error: the line is dropped and the run ends as it would have.
+pub fn print(text: &str) {
+    let _ = writeln!(stdout().lock(), "{text}");
+}
+
+/// `print` for stderr
+fn print_err(text: &str) {
+    let _ = writeln!(stderr().lock(), "{text}");
+}
+
+impl Default for Output {
+    /// Nothing colored, the terminals looked up
+    fn default() -> Self {
+        Output::detect(ColorChoice::Never)
+    }
+}
@@ -0,0 +1,108 @@
+//! Bytes written on a thread of their own, read by the upload as they come like stdin, which
+//! is how the tar archive and the compressed stream are made
+
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use bytes::Bytes;
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::mpsc;
+
+/// What's gathered before it's handed to the upload
+const PIECE: usize = 64 * 1024;
+/// Pieces written ahead of the upload before the writer waits for it
+const AHEAD: usize = 16;
+
+/// What the writer sends, a piece of the stream or none once it's all written
+type Piece = io::Result<Option<Bytes>>;
+
+/// The writing end, failing once the upload stopped reading
+pub(crate) struct Pipe {
+    tx: mpsc::Sender<Piece>,
+    piece: Vec<u8>,
+}
+
+impl Pipe {
+    fn send(&mut self) -> io::Result<()> {
+        let piece = std::mem::replace(&mut self.piece, Vec::with_capacity(PIECE));
+        self.tx
+            .blocking_send(Ok(Some(piece.into())))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let len = data.len().min(PIECE - self.piece.len());
+        self.piece.extend_from_slice(&data[..len]);
+        if self.piece.len() == PIECE {
+            self.send()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.piece.is_empty() {
+            true => Ok(()),
+            false => self.send(),
+        }
+    }
+}
+
+/// Runs `write` on a thread of its own, read as it writes, failing should the thread end
+/// before `write` returned. `writer` names it in that error.
+pub(crate) fn spawn(
+    writer: &'static str,
+    write: impl FnOnce(&mut Pipe) -> io::Result<()> + Send + 'static,
+) -> Reader {
+    let (tx, rx) = mpsc::channel(AHEAD);
+    std::thread::spawn(move || {
+        let mut pipe = Pipe {
+            tx,
+            piece: Vec::with_capacity(PIECE),
+        };
+        let written = write(&mut pipe).and_then(|_| pipe.flush());
+        // Nothing is told when the upload stopped reading
+        let _ = pipe.tx.blocking_send(written.map(|_| None));
+    });
+    Reader {
+        rx,
+        piece: Bytes::new(),
+        ended: false,
+        writer,
+    }
+}
+
+/// Reads what the writer's thread sends
+pub(crate) struct Reader {
+    rx: mpsc::Receiver<Piece>,
+    /// What's left of the piece received last
+    piece: Bytes,
+    ended: bool,
+    writer: &'static str,
+}
+
+impl AsyncRead for Reader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        while self.piece.is_empty() && !self.ended {
+            match ready!(self.rx.poll_recv(cx)) {
+                Some(Ok(Some(piece))) => self.piece = piece,
+                Some(Ok(None)) => self.ended = true,
+                Some(Err(err)) => return Poll::Ready(Err(err)),
+                None => {
+                    let stopped = format!("the {} stopped", self.writer);
+                    return Poll::Ready(Err(io::Error::other(stopped)));
+                }
+            }
+        }
+        let len = self.piece.len().min(buf.remaining());
+        let piece = self.piece.split_to(len);
+        buf.put_slice(&piece);
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,317 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::output::{Output, Tone};
+use crate::{UploadOptions, Verbosity};
+
+/// Width of the bar itself, excluding the numbers printed after it
+const BAR_WIDTH: usize = 30;
+
+/// Chunks the throughput is averaged over, the older ones fading out
+const RATE_WINDOW: f64 = 8.0;
+
+/// How often the progress is logged when there's no terminal to draw the bar on
+const LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A single-line progress bar redrawn on stdout after every completed chunk
+pub struct Progress {
+    enabled: bool,
+    /// Without a terminal the progress is logged as a line now and then instead
+    log_lines: bool,
+    /// Starts every line, the file when several uploads share the terminal
+    prefix: Option<String>,
+    /// A line per stored chunk on the terminal instead of the bar, which several uploads can't
+    /// share
+    chunk_lines: bool,
+    verbosity: Verbosity,
+    /// Messages go to stderr when it has taken stdout, the bar is hidden then as stdout is
+    /// someone else's
+    output: Output,
+    /// Unknown for stdin, which then only shows what was sent so far
+    total: Option<u64>,
+    /// Nothing sent for this long flags the upload as stalled
+    stall_threshold: Option<Duration>,
+    state: Mutex<State>,
+}
+
+struct State {
+    /// Changes when an adaptive chunk size shrinks
+    chunk_count: Option<u64>,
+    sent: u64,
+    chunks_done: u64,
+    last_chunk: u64,
+    /// Moving averages of the recent chunks' bytes and seconds, so a slow start fades out
+    average: Option<(f64, f64)>,
+    /// When a chunk last completed, or the upload started
+    last_progress: Instant,
+    last_logged: Instant,
+    /// Nothing is sent on purpose, see [`crate::Interrupter::pause`]
+    paused: bool,
+}
+
+impl Progress {
+    /// Creates the bar, which stays hidden when disabled, quiet, logging to stderr or writing
+    /// progress events, and is logged once a minute when stdout is not a terminal
+    ///
+    /// With [`crate::ChunkUploaderBuilder::prefix_lines`] the terminal gets a line per stored
+    /// chunk instead, every line starting with the file.
+    pub fn new(total: Option<u64>, chunk_count: Option<u64>, opts: &UploadOptions) -> Self {
+        let shown = opts.show_progress
+            && opts.verbosity > Verbosity::Quiet
+            && !opts.output.stdout_taken()
+            && opts.events.is_none();
+        let prefix = (opts.prefix_lines && !opts.path.is_empty()).then(|| opts.path.clone());
+        let terminal = shown && opts.output.shows_progress();
+        let now = Instant::now();
+        Progress {
+            enabled: terminal && prefix.is_none(),
+            log_lines: shown && !terminal,
+            chunk_lines: terminal && prefix.is_some(),
+            prefix,
+            verbosity: opts.verbosity,
+            output: opts.output,
+            total,
+            stall_threshold: opts.stall_threshold,
+            state: Mutex::new(State {
+                chunk_count,
+                sent: 0,
+                chunks_done: 0,
+                last_chunk: 0,
+                average: None,
+                last_progress: now,
+                last_logged: now,
+                paused: false,
+            }),
+        }
+    }
+
+    /// Records that chunk `index` of `bytes` length made it to the server
+    pub fn chunk_done(&self, index: u64, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.sent += bytes;
+        state.chunks_done += 1;
+        state.last_chunk = index;
+
+        // With parallel workers the time between completions is what they manage together. Bytes
+        // and time are averaged apart, so one chunk that was over in an instant can't dominate.
+        let now = Instant::now();
+        let secs = now.duration_since(state.last_progress).as_secs_f64();
+        let weight = 2.0 / (RATE_WINDOW + 1.0);
+        state.average = Some(match state.average {
+            Some((b, s)) => (b + (bytes as f64 - b) * weight, s + (secs - s) * weight),
+            None => (bytes as f64, secs),
+        });
+        state.last_progress = now;
+        if self.chunk_lines {
+            let chunk = match state.chunk_count {
+                Some(count) => format!("{}/{}", index + 1, count),
+                None => format!("{}", index + 1),
+            };
+            let sent = match self.total {
+                Some(0) => String::new(),
+                Some(total) => format!(
+                    ", {} of {} ({:.1}%)",
+                    format_bytes(state.sent),
+                    format_bytes(total),
+                    state.sent as f64 / total as f64 * 100.0
+                ),
+                None => format!(", {}", format_bytes(state.sent)),
+            };
+            let line = format!("Stored chunk {}{}", chunk, sent);
+            self.print_line(&state, &line, false);
+        }
+        self.draw(&state);
+    }
+
+    /// Counts this many chunks from now on, the bar catches up when it's next drawn
+    pub fn set_chunk_count(&self, chunk_count: Option<u64>) {
+        self.state.lock().unwrap().chunk_count = chunk_count;
+    }
+
+    /// Shows the upload as paused rather than stalled, the time it was paused not counting
+    /// towards the rate once it carries on
+    pub fn set_paused(&self, paused: bool) {
+        let mut state = self.state.lock().unwrap();
+        if state.paused && !paused {
+            state.last_progress = Instant::now();
+        }
+        state.paused = paused;
+        self.draw(&state);
+    }
+
+    /// Redraws the bar so a stall shows while no chunk completes, or logs the progress once
+    /// a minute without a terminal
+    pub fn tick(&self) {
+        let mut state = self.state.lock().unwrap();
+        if self.enabled {
+            self.draw(&state);
+        } else if self.log_lines && state.last_logged.elapsed() >= LOG_INTERVAL {
+            state.last_logged = Instant::now();
+            let sent = match self.total {
+                Some(total) => format!("{} of {}", format_bytes(state.sent), format_bytes(total)),
+                None => format_bytes(state.sent),
+            };
+            let line = format!("Sent {}, {}", sent, self.eta(&state));
+            self.print_line(&state, &line, false);
+        }
+    }
+
+    /// Prints a message unless quiet
+    pub fn info(&self, msg: &str) {
+        if self.verbosity >= Verbosity::Normal {
+            self.println(msg);
+        }
+    }
+
+    /// Prints a message only meant for verbose output
+    pub fn detail(&self, msg: &str) {
+        if self.verbosity >= Verbosity::Verbose {
+            self.println(msg);
+        }
+    }
+
+    /// Prints a problem on stderr, also when quiet so it's still seen
+    pub fn warn(&self, msg: &str) {
+        let state = self.state.lock().unwrap();
+        self.print_line(&state, msg, true);
+    }
+
+    /// Prints a message on its own line without garbling the bar
+    fn println(&self, msg: &str) {
+        let state = self.state.lock().unwrap();
+        self.print_line(&state, msg, false);
+    }
+
+    /// Prints to stdout, or stderr for a problem or when logging there
+    fn print_line(&self, state: &State, msg: &str, problem: bool) {
+        let prefixed;
+        let mut msg = msg;
+        if let Some(prefix) = self.prefix.as_ref() {
+            prefixed = format!("{}: {}", prefix, msg);
+            msg = &prefixed;
+        }
+        if self.enabled {
+            self.output.clear();
+        }
+        match problem {
+            true => self.output.warn(msg),
+            false => self.output.line(msg),
+        }
+        self.draw(state);
+    }
+
+    /// Moves past the bar so following output starts on a fresh line
+    pub fn finish(&self) {
+        let _state = self.state.lock().unwrap();
+        if self.enabled {
+            self.output.end_bar();
+        }
+    }
+
+    fn draw(&self, state: &State) {
+        if !self.enabled {
+            return;
+        }
+
+        let chunk = match state.chunk_count {
+            Some(count) => format!("{}/{}", state.last_chunk + 1, count),
+            None => format!("{}", state.last_chunk + 1),
+        };
+
+        match self.total {
+            Some(total) => {
+                let ratio = if total == 0 {
+                    1.0
+                } else {
+                    state.sent as f64 / total as f64
+                };
+                let filled = ((ratio * BAR_WIDTH as f64) as usize).min(BAR_WIDTH);
+                self.output.draw(&format!(
+                    "[{}{}] {:>5.1}% {}/{} {} chunk {}",
+                    self.output.paint(&"#".repeat(filled), Tone::Success),
+                    "-".repeat(BAR_WIDTH - filled),
+                    ratio * 100.0,
+                    format_bytes(state.sent),
+                    format_bytes(total),
+                    self.eta(state),
+                    chunk
+                ));
+            }
+            None => self.output.draw(&format!(
+                "{} {} chunk {}",
+                format_bytes(state.sent),
+                self.eta(state),
+                chunk
+            )),
+        }
+    }
+
+    /// `ETA 7m32s @ 18.4 MiB/s`, only the rate for stdin, or how long the upload has stalled
+    fn eta(&self, state: &State) -> String {
+        if state.paused {
+            return "paused".to_string();
+        }
+        let idle = state.last_progress.elapsed();
+        if self
+            .stall_threshold
+            .is_some_and(|threshold| idle >= threshold)
+        {
+            return format!("stalled, nothing sent for {}", format_eta(idle));
+        }
+        let Some(rate) = state
+            .average
+            .filter(|(bytes, secs)| *bytes > 0.0 && *secs > 0.0)
+            .map(|(bytes, secs)| bytes / secs)
+        else {
+            return "ETA --".to_string();
+        };
+        let speed = format!("{}/s", format_bytes(rate as u64));
+        match self.total {
+            Some(total) => {
+                let left = total.saturating_sub(state.sent) as f64 / rate;
+                format!(
+                    "ETA {} @ {}",
+                    format_eta(Duration::from_secs_f64(left)),
+                    speed
+                )
+            }
+            None => format!("@ {}", speed),
+        }
+    }
+}
+
+/// Formats a duration to the second as it's shown for an ETA, e.g. `45s`, `7m32s` or `1h05m`
+fn format_eta(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Formats a duration in milliseconds below a second, e.g. `850ms` or `12.3s`
+pub fn format_duration(duration: Duration) -> String {
+    match duration.as_millis() {
+        ms @ 0..=999 => format!("{}ms", ms),
+        _ => format!("{:.1}s", duration.as_secs_f64()),
+    }
+}
+
+/// Formats a byte count with a binary unit, e.g. `1.5 MiB`
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
@@ -0,0 +1,384 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_ENCODING, CONTENT_TYPE};
+use reqwest::multipart::Form;
+use reqwest::{Client, RequestBuilder, Response};
+use reqwest::{Method, StatusCode};
+use sha1::{Digest, Sha1};
+
+use crate::state::StateTracker;
+use crate::{build_request, describe_error, send, Chunk, ChunkBody, Failure, UploadOptions};
+
+pub mod azure;
+pub mod gcs;
+pub mod s3;
+mod tus;
+
+/// The wire protocol chunks are uploaded with
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Protocol {
+    /// Every chunk is sent to the URL as is with a Content-Range header
+    Raw,
+    /// tus 1.0 resumable uploads, see <https://tus.io/protocols/resumable-upload>
+    Tus,
+    /// S3 multipart uploads where every chunk is a part
+    S3,
+    /// Google Cloud Storage resumable uploads to a session URI
+    Gcs,
+    /// Azure block blobs, staged with Put Block and committed with Put Block List
+    Azure,
+}
+
+impl Protocol {
+    /// Whether the server can take chunks out of order, e.g. with `--parallel`
+    pub fn allows_parallel(self) -> bool {
+        match self {
+            Protocol::Raw | Protocol::S3 | Protocol::Azure => true,
+            Protocol::Tus | Protocol::Gcs => false,
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(Protocol::Raw),
+            "tus" => Ok(Protocol::Tus),
+            "s3" => Ok(Protocol::S3),
+            "gcs" => Ok(Protocol::Gcs),
+            "azure" => Ok(Protocol::Azure),
+            _ => Err(format!(
+                "Unknown protocol '{s}', expected raw, tus, s3, gcs or azure"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Protocol::Raw => "raw",
+            Protocol::Tus => "tus",
+            Protocol::S3 => "s3",
+            Protocol::Gcs => "gcs",
+            Protocol::Azure => "azure",
+        })
+    }
+}
+
+/// The server side state of one upload, established before the first chunk is sent
+pub enum Session {
+    Raw,
+    Tus(tus::Tus),
+    S3(s3::S3),
+    Gcs(gcs::Gcs),
+    Azure(azure::Azure),
+}
+
+impl Session {
+    /// Sets up the upload on the server, which may move the state's offset when resuming
+    pub async fn begin(
+        client: &Client,
+        opts: &UploadOptions,
+        state: &StateTracker,
+    ) -> Result<Self, Failure> {
+        match opts.protocol {
+            Protocol::Raw => Ok(Session::Raw),
+            Protocol::Tus => tus::Tus::begin(client, opts, state).await.map(Session::Tus),
+            Protocol::S3 => s3::S3::begin(client, opts, state).await.map(Session::S3),
+            Protocol::Gcs => gcs::Gcs::begin(client, opts, state).await.map(Session::Gcs),
+            Protocol::Azure => azure::Azure::begin(opts).map(Session::Azure),
+        }
+    }
+
+    /// Builds the request carrying `body` as the given chunk
+    pub fn request(
+        &self,
+        client: &Client,
+        opts: &UploadOptions,
+        chunk: &Chunk,
+        body: ChunkBody,
+    ) -> RequestBuilder {
+        match self {
+            Session::Raw => {
+                let sticky = opts.sticky_url.lock().unwrap().clone();
+                let url = sticky.unwrap_or_else(|| chunk_url(opts, chunk));
+                let mut req = build_request(client, opts, opts.method.clone(), &url)
+                    .header("Content-Range", raw_content_range(opts, chunk));
+                if let Some(encoding) = opts.compress.content_encoding() {
+                    req = req.header(CONTENT_ENCODING, encoding);
+                }
+                match (opts.form_field.as_ref(), opts.content_type.as_ref()) {
+                    (Some(field), _) => req.multipart(chunk_form(opts, field, chunk, body)),
+                    (None, Some(content_type)) => {
+                        body.attach(req.header(CONTENT_TYPE, content_type))
+                    }
+                    (None, None) => body.attach(req),
+                }
+            }
+            Session::Tus(tus) => tus.request(client, opts, chunk, body),
+            Session::S3(s3) => s3.request(client, opts, chunk, body),
+            Session::Gcs(gcs) => gcs.request(client, opts, chunk, body),
+            Session::Azure(azure) => azure.request(client, opts, chunk, body),
+        }
+    }
+
+    /// Whether a chunk response with this status counts as stored
+    pub fn is_success(&self, opts: &UploadOptions, status: StatusCode) -> bool {
+        match self {
+            Session::Raw => opts.expect_status.as_ref().map_or_else(
+                || status.is_success(),
+                |expected| expected.contains(&status),
+            ),
+            Session::S3(_) => status == StatusCode::OK,
+            Session::Tus(_) | Session::Azure(_) => status.is_success(),
+            Session::Gcs(_) => gcs::is_success(status),
+        }
+    }
+
+    /// Checks a successful chunk response, returning the offset up to which the chunk was stored
+    pub fn confirm(
+        &self,
+        opts: &UploadOptions,
+        chunk: &Chunk,
+        res: &Response,
+    ) -> Result<u64, String> {
+        match self {
+            Session::Raw | Session::Azure(_) => Ok(chunk.end),
+            Session::Tus(tus) => tus.confirm(opts, chunk, res).map(|_| chunk.end),
+            Session::S3(s3) => s3.confirm(chunk, res).map(|_| chunk.end),
+            Session::Gcs(gcs) => gcs.confirm(opts, chunk, res),
+        }
+    }
+
+    /// Wraps up the upload once every chunk is stored
+    pub async fn finish(&self, client: &Client, opts: &UploadOptions) -> Result<(), Failure> {
+        match self {
+            Session::Raw | Session::Tus(_) | Session::Gcs(_) => Ok(()),
+            Session::S3(s3) => s3.finish(client, opts).await,
+            Session::Azure(azure) => azure.finish(client, opts).await,
+        }
+    }
+
+    /// Throws away whatever the server kept of a failed upload, where the protocol allows it
+    pub async fn abort(&self, client: &Client, opts: &UploadOptions) -> Result<(), String> {
+        match self {
+            Session::Raw => raw_abort(client, opts).await,
+            Session::Tus(tus) => tus.abort(client, opts).await,
+            Session::Gcs(gcs) => gcs.abort(client, opts).await,
+            Session::S3(s3) => s3.abort(client, opts).await,
+            // Uncommitted Azure blocks are garbage collected by the service
+            Session::Azure(_) => Ok(()),
+        }
+    }
+}
+
+/// Sends the user's abort request for a raw upload, to the URL the chunks went to by default
+async fn raw_abort(client: &Client, opts: &UploadOptions) -> Result<(), String> {
+    let sticky = opts.sticky_url.lock().unwrap().clone();
+    let url = (opts.abort_url.clone())
+        .or(sticky)
+        .unwrap_or_else(|| opts.url.clone());
+    let req = build_request(client, opts, opts.abort_method.clone(), &url);
+    let res = send(opts, req)
+        .await
+        .map_err(|e| format!("Error aborting upload: {}", describe_error(opts, &e)))?;
+    if !res.status().is_success() {
+        return Err(format!(
+            "Error aborting upload: server responded with {}",
+            res.status()
+        ));
+    }
+    Ok(())
+}
+
+/// Method of the chunk requests, which only the raw protocol leaves to the user
+pub fn chunk_method(opts: &UploadOptions) -> Method {
+    match opts.protocol {
+        Protocol::Raw => opts.method.clone(),
+        Protocol::Tus => Method::PATCH,
+        Protocol::S3 | Protocol::Gcs | Protocol::Azure => Method::PUT,
+    }
+}
+
+/// The chunk's Content-Range for the protocols that send one, the others name it in the URL
+pub fn content_range(opts: &UploadOptions, chunk: &Chunk) -> Option<String> {
+    match opts.protocol {
+        Protocol::Raw => Some(raw_content_range(opts, chunk)),
+        Protocol::Gcs => Some(gcs::content_range(opts, chunk)),
+        Protocol::Tus | Protocol::S3 | Protocol::Azure => None,
+    }
+}
+
+/// The raw chunk's URL with its `{index}`, `{count}`, `{offset}` and `{end}` filled in
+pub fn chunk_url(opts: &UploadOptions, chunk: &Chunk) -> String {
+    fill_chunk_url(&opts.url, opts, chunk)
+}
+
+/// Fills the chunk's placeholders into `url`, the upload's or another one about the chunk
+pub fn fill_chunk_url(url: &str, opts: &UploadOptions, chunk: &Chunk) -> String {
+    url.replace("{index}", &chunk.index.to_string())
+        .replace("{count}", &opts.chunk_count().to_string())
+        .replace("{offset}", &chunk.start.to_string())
+        .replace("{end}", &chunk.end.to_string())
+}
+
+/// The headers numbering the chunk among all of the range's and carrying its idempotency key
+/// and the preconditions, when they're sent
+pub fn chunk_headers(opts: &UploadOptions, chunk: &Chunk) -> Vec<(HeaderName, HeaderValue)> {
+    let mut headers = Vec::new();
+    if let Some((index, count)) = opts.chunk_headers.as_ref() {
+        headers.push((index.clone(), HeaderValue::from(chunk.index)));
+        headers.push((count.clone(), HeaderValue::from(opts.chunk_count())));
+    }
+    if let Some((header, identity)) = opts.idempotency_key.as_ref() {
+        let key = idempotency_key(identity, chunk);
+        headers.push((header.clone(), HeaderValue::from_str(&key).unwrap()));
+    }
+    if opts.preconditions_on_chunks() {
+        headers.extend(opts.preconditions.iter().cloned());
+    }
+    headers
+}
+
+/// Namespace of the idempotency keys, so they can't collide with other UUIDv5s of the same name
+const IDEMPOTENCY_NAMESPACE: [u8; 16] = [
+    0x3c, 0x9e, 0x5f, 0x1a, 0x7d, 0x42, 0x4b, 0x8e, 0x9a, 0x61, 0x2f, 0xd0, 0x84, 0x17, 0xc3, 0x55,
+];
+
+/// The UUIDv5 of the chunk's bytes within the upload's identity
+fn idempotency_key(identity: &str, chunk: &Chunk) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(IDEMPOTENCY_NAMESPACE);
+    sha1.update(format!("{}\n{}-{}", identity, chunk.start, chunk.end));
+    let mut uuid = [0; 16];
+    uuid.copy_from_slice(&sha1.finalize()[..16]);
+    uuid[6] = (uuid[6] & 0x0f) | 0x50;
+    uuid[8] = (uuid[8] & 0x3f) | 0x80;
+    let hex: String = uuid.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// The multipart form of a raw chunk, its bytes in `field` of the content type after the static
+/// fields
+fn chunk_form(opts: &UploadOptions, field: &str, chunk: &Chunk, body: ChunkBody) -> Form {
+    let name = match opts.path.as_str() {
+        "-" => "stdin".into(),
+        path => Path::new(path)
+            .file_name()
+            .map_or_else(|| path.into(), |n| n.to_string_lossy()),
+    };
+    let mut part = body.part().file_name(format!("{}.{}", name, chunk.index));
+    if let Some(content_type) = opts.content_type.as_ref() {
+        part = part.headers(HeaderMap::from_iter([(CONTENT_TYPE, content_type.clone())]));
+    }
+    let form = opts.form.iter().fold(Form::new(), |form, (key, value)| {
+        form.text(key.clone(), value.clone())
+    });
+    form.part(field.to_string(), part)
+}
+
+/// The Content-Range of the chunk's bytes, see [`format_content_range`]
+///
+/// An encrypted chunk's range is where its ciphertext goes in the encrypted range, which is
+/// all that's uploaded.
+fn raw_content_range(opts: &UploadOptions, chunk: &Chunk) -> String {
+    let (start, end, total) = match opts.cipher.as_ref() {
+        Some(cipher) => {
+            let (start, end) = (chunk.start - opts.range.0, chunk.end - opts.range.0);
+            let (start, end) = cipher.sealed_range(start, end, chunk.last);
+            let total = opts
+                .total_size
+                .map(|_| cipher.sealed_len(opts.range.1 - opts.range.0));
+            (start, end, total)
+        }
+        None => (chunk.start, chunk.end, opts.total_size),
+    };
+    format_content_range(start, end, total, opts.legacy_range)
+}
+
+/// `bytes first-last/total` for the bytes from `start` up to `end` with the last byte
+/// included, or `end` itself with `legacy`, the total `*` while it isn't known
+fn format_content_range(start: u64, end: u64, total: Option<u64>, legacy: bool) -> String {
+    let total = total.map_or_else(|| "*".to_string(), |total| total.to_string());
+    if legacy {
+        format!("bytes {}-{}/{}", start, end, total)
+    } else if start == end {
+        // An empty upload has no first and last byte to name
+        format!("bytes */{}", total)
+    } else {
+        format!("bytes {}-{}/{}", start, end - 1, total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::split_range;
+
+    /// The ranges of each chunk of a file of `len` bytes
+    fn ranges(len: u64, chunk_size: u64, legacy: bool) -> Vec<String> {
+        split_range((0, len), chunk_size)
+            .map(|(start, n)| format_content_range(start, start + n, Some(len), legacy))
+            .collect()
+    }
+
+    #[test]
+    fn names_the_first_and_last_byte_of_each_chunk() {
+        assert_eq!(
+            ranges(12_345, 5_000, false),
+            [
+                "bytes 0-4999/12345",
+                "bytes 5000-9999/12345",
+                "bytes 10000-12344/12345"
+            ]
+        );
+    }
+
+    #[test]
+    fn ends_a_file_of_whole_chunks_on_its_last_byte() {
+        assert_eq!(
+            ranges(10_000, 5_000, false),
+            ["bytes 0-4999/10000", "bytes 5000-9999/10000"]
+        );
+    }
+
+    #[test]
+    fn sends_a_file_smaller_than_a_chunk_in_one() {
+        assert_eq!(ranges(1, 5_000, false), ["bytes 0-0/1"]);
+        assert_eq!(ranges(4_999, 5_000, false), ["bytes 0-4998/4999"]);
+        assert_eq!(format_content_range(0, 0, Some(0), false), "bytes */0");
+    }
+
+    #[test]
+    fn names_one_past_the_last_byte_with_the_legacy_range() {
+        assert_eq!(
+            ranges(12_345, 5_000, true),
+            [
+                "bytes 0-5000/12345",
+                "bytes 5000-10000/12345",
+                "bytes 10000-12345/12345"
+            ]
+        );
+        assert_eq!(format_content_range(0, 0, Some(0), true), "bytes 0-0/0");
+    }
+
+    #[test]
+    fn leaves_the_total_out_while_it_is_unknown() {
+        assert_eq!(
+            format_content_range(5_000, 10_000, None, false),
+            "bytes 5000-9999/*"
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use base64::prelude::{Engine, BASE64_STANDARD};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, RequestBuilder};
+use reqwest::{Method, StatusCode, Url};
+
+use crate::{build_request, with_body, Chunk, ChunkBody, Failure, UploadOptions};
+
+/// A blob can consist of at most this many committed blocks
+pub const MAX_BLOCKS: u64 = 50000;
+
+const API_VERSION: &str = "2020-10-02";
+
+/// An Azure block blob upload, authenticated by a SAS token in the URL
+///
+/// Block IDs are derived from the chunk index alone, so blocks staged by an earlier run are
+/// picked up again when resuming and the commit order always follows the file.
+pub struct Azure {
+    url: Url,
+}
+
+impl Azure {
+    pub fn begin(opts: &UploadOptions) -> Result<Self, Failure> {
+        // Only a URL an init response gave can be invalid by now
+        let url = Url::parse(&opts.url)
+            .map_err(|e| Failure::http(format!("Invalid Azure blob URL '{}': {}", opts.url, e)))?;
+        Ok(Azure { url })
+    }
+
+    /// Builds the Put Block request staging the chunk
+    pub fn request(
+        &self,
+        client: &Client,
+        opts: &UploadOptions,
+        chunk: &Chunk,
+        body: ChunkBody,
+    ) -> RequestBuilder {
+        let mut url = self.url.clone();
+        url.query_pairs_mut()
+            .append_pair("comp", "block")
+            .append_pair("blockid", &block_id(chunk.index));
+        body.attach(
+            build_request(client, opts, Method::PUT, url.as_str())
+                .header("x-ms-version", API_VERSION),
+        )
+    }
+
+    /// Commits every block of the range in file order with Put Block List
+    pub async fn finish(&self, client: &Client, opts: &UploadOptions) -> Result<(), Failure> {
+        let mut list = String::from(r#"<?xml version="1.0" encoding="utf-8"?><BlockList>"#);
+        for index in 0..block_count(opts.chunk_size, opts.range.1 - opts.range.0) {
+            list.push_str(&format!("<Latest>{}</Latest>", block_id(index)));
+        }
+        list.push_str("</BlockList>");
+
+        let mut url = self.url.clone();
+        url.query_pairs_mut().append_pair("comp", "blocklist");
+        let mut req = build_request(client, opts, Method::PUT, url.as_str())
+            .header("x-ms-version", API_VERSION)
+            .header(CONTENT_TYPE, "application/xml");
+        // The blob gets its type when it's committed, the blocks have none
+        if let Some(content_type) = opts.content_type.as_ref() {
+            req = req.header("x-ms-blob-content-type", content_type);
+        }
+        if opts.preconditions_on_commit() {
+            for (name, value) in opts.preconditions.iter() {
+                req = req.header(name, value);
+            }
+        }
+        let res = req.body(list).send().await.map_err(|e| {
+            Failure::transport(&e, format!("Error committing Azure block list: {}", e))
+        })?;
+        let status = res.status();
+        if status == StatusCode::PRECONDITION_FAILED && opts.preconditions_on_commit() {
+            return Err(Failure::status(
+                status,
+                format!(
+                    "Error committing Azure block list: {}",
+                    opts.precondition_failed()
+                ),
+            ));
+        }
+        if !status.is_success() {
+            let err = format!(
+                "Error committing Azure block list: server responded with {}",
+                status
+            );
+            let body = res.text().await.unwrap_or_default();
+            return Err(Failure::status(status, with_body(err, &body)));
+        }
+        Ok(())
+    }
+}
+
+/// Rejects ranges that would need more blocks than a blob can hold
+pub fn validate_chunk_size(chunk_size: u64, range_len: u64) -> Result<(), String> {
+    let blocks = block_count(chunk_size, range_len);
+    if blocks > MAX_BLOCKS {
+        return Err(format!(
+            "Azure blobs can have at most {} blocks, a chunk size of {} would need {}",
+            MAX_BLOCKS, chunk_size, blocks
+        ));
+    }
+    Ok(())
+}
+
+fn block_count(chunk_size: u64, range_len: u64) -> u64 {
+    range_len.div_ceil(chunk_size)
+}
+
+/// Azure requires all block IDs of a blob to have the same length, hence the padding
+fn block_id(index: u64) -> String {
+    BASE64_STANDARD.encode(format!("block-{index:010}"))
+}
@@ -0,0 +1,173 @@
+use reqwest::header::{CONTENT_RANGE, CONTENT_TYPE, RANGE};
+use reqwest::{Client, RequestBuilder, Response};
+use reqwest::{Method, StatusCode};
+
+use crate::state::StateTracker;
+use crate::{build_request, Chunk, ChunkBody, Failure, UploadOptions};
+
+/// GCS only takes chunks in multiples of this size, except for the last one
+pub const CHUNK_GRANULARITY: u64 = 256 * 1024;
+
+/// A GCS resumable upload session, the URL being the session URI
+///
+/// The object is the selected range, so offsets sent to GCS count from its start.
+pub struct Gcs;
+
+impl Gcs {
+    /// Asks the session how much it already persisted and continues from there
+    pub async fn begin(
+        client: &Client,
+        opts: &UploadOptions,
+        state: &StateTracker,
+    ) -> Result<Self, Failure> {
+        let res = build_request(client, opts, Method::PUT, &opts.url)
+            .header(CONTENT_RANGE, "bytes */*")
+            .body(Vec::new())
+            .send()
+            .await
+            .map_err(|e| {
+                Failure::transport(&e, format!("Error querying GCS upload status: {}", e))
+            })?;
+
+        let offset = match res.status() {
+            StatusCode::PERMANENT_REDIRECT => {
+                opts.range.0 + persisted(&res).map_err(Failure::http)?
+            }
+            StatusCode::OK | StatusCode::CREATED => {
+                opts.info("GCS reports the upload is already complete");
+                opts.range.1
+            }
+            status @ (StatusCode::NOT_FOUND | StatusCode::GONE) => {
+                return Err(Failure::status(
+                    status,
+                    "GCS upload session doesn't exist or has expired",
+                ));
+            }
+            status => {
+                return Err(Failure::status(
+                    status,
+                    format!(
+                        "Error querying GCS upload status: server responded with {}",
+                        status
+                    ),
+                ));
+            }
+        };
+
+        if offset != state.offset() {
+            opts.info(&format!(
+                "GCS has persisted bytes up to {}, continuing from there",
+                offset
+            ));
+            state.set_offset(offset);
+        }
+        Ok(Gcs)
+    }
+
+    /// Cancels the session, which GCS confirms with its own 499
+    pub async fn abort(&self, client: &Client, opts: &UploadOptions) -> Result<(), String> {
+        let res = build_request(client, opts, Method::DELETE, &opts.url)
+            .send()
+            .await
+            .map_err(|e| format!("Error canceling GCS upload: {}", e))?;
+        if !res.status().is_success() && res.status().as_u16() != 499 {
+            return Err(format!(
+                "Error canceling GCS upload: server responded with {}",
+                res.status()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Builds the PUT for the chunk, whose Content-Range carries the full object size
+    pub fn request(
+        &self,
+        client: &Client,
+        opts: &UploadOptions,
+        chunk: &Chunk,
+        body: ChunkBody,
+    ) -> RequestBuilder {
+        let req = build_request(client, opts, Method::PUT, &opts.url)
+            .header(CONTENT_RANGE, content_range(opts, chunk));
+        match opts.content_type.as_ref() {
+            Some(content_type) => body.attach(req.header(CONTENT_TYPE, content_type)),
+            None => body.attach(req),
+        }
+    }
+
+    /// Works out how much of the chunk GCS persisted, which may be less than was sent
+    pub fn confirm(
+        &self,
+        opts: &UploadOptions,
+        chunk: &Chunk,
+        res: &Response,
+    ) -> Result<u64, String> {
+        match res.status() {
+            StatusCode::PERMANENT_REDIRECT if chunk.last => {
+                Err("GCS expects more data after the final chunk".to_string())
+            }
+            StatusCode::PERMANENT_REDIRECT => {
+                let stored = opts.range.0 + persisted(res)?;
+                if stored < chunk.start {
+                    return Err(format!(
+                        "GCS only persisted bytes up to {}, before the start of the chunk",
+                        stored
+                    ));
+                }
+                Ok(stored.min(chunk.end))
+            }
+            _ if !chunk.last => Err("GCS finished the upload before the final chunk".to_string()),
+            _ => Ok(chunk.end),
+        }
+    }
+}
+
+/// The chunk's place in the object, which starts at the beginning of the range
+pub fn content_range(opts: &UploadOptions, chunk: &Chunk) -> String {
+    let (start, end) = opts.range;
+    // An empty object has no first and last byte to name
+    if chunk.start == chunk.end {
+        format!("bytes */{}", end - start)
+    } else {
+        format!(
+            "bytes {}-{}/{}",
+            chunk.start - start,
+            chunk.end - start - 1,
+            end - start
+        )
+    }
+}
+
+/// 308 Resume Incomplete is how GCS acknowledges every chunk but the last
+pub fn is_success(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::OK | StatusCode::CREATED | StatusCode::PERMANENT_REDIRECT
+    )
+}
+
+/// Rejects chunk sizes GCS would refuse for anything but the last chunk
+pub fn validate_chunk_size(chunk_size: u64, range_len: u64) -> Result<(), String> {
+    if !chunk_size.is_multiple_of(CHUNK_GRANULARITY) && range_len > chunk_size {
+        return Err(format!(
+            "GCS chunk sizes must be a multiple of {} bytes, got {}",
+            CHUNK_GRANULARITY, chunk_size
+        ));
+    }
+    Ok(())
+}
+
+/// The number of bytes GCS persisted according to a `Range: bytes=0-N` header, none if absent
+fn persisted(res: &Response) -> Result<u64, String> {
+    let Some(range) = res.headers().get(RANGE) else {
+        return Ok(0);
+    };
+
+    range
+        .to_str()
+        .ok()
+        .and_then(|r| r.strip_prefix("bytes=0-"))
+        .and_then(|n| n.trim().parse::<u64>().ok())
+        .map(|n| n + 1)
+        .ok_or_else(|| format!("GCS responded with an invalid Range header {:?}", range))
+}
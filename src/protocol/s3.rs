@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use reqwest::header::{CONTENT_TYPE, ETAG};
+use reqwest::{Client, RequestBuilder, Response};
+use reqwest::{Method, StatusCode, Url};
+
+use crate::state::StateTracker;
+use crate::{build_request, send, with_body, Chunk, ChunkBody, Failure, UploadOptions};
+
+/// S3 refuses to complete uploads with smaller parts, except for the last one
+pub const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+
+/// S3 numbers parts from 1 to 10000
+pub const MAX_PARTS: u64 = 10000;
+
+/// An S3 multipart upload, where chunk `n` becomes part `n + 1`
+pub struct S3 {
+    upload_id: String,
+    /// ETag of every stored part by part number, they make up the completion manifest
+    etags: Mutex<BTreeMap<u64, String>>,
+}
+
+impl S3 {
+    /// Starts a new multipart upload with CreateMultipartUpload
+    pub async fn begin(
+        client: &Client,
+        opts: &UploadOptions,
+        state: &StateTracker,
+    ) -> Result<Self, Failure> {
+        // Parts of a failed upload are aborted, so there's never anything to resume
+        if state.offset() != opts.range.0 {
+            opts.info("S3 multipart uploads can't be resumed, starting over");
+            state.set_offset(opts.range.0);
+        }
+
+        let mut url = parse_url(opts).map_err(Failure::http)?;
+        url.query_pairs_mut().append_key_only("uploads");
+        let mut req = build_request(client, opts, Method::POST, url.as_str());
+        // The object gets its type when the upload is created, the parts have none
+        if let Some(content_type) = opts.content_type.as_ref() {
+            req = req.header(CONTENT_TYPE, content_type);
+        }
+        let res = send(opts, req).await.map_err(|e| {
+            Failure::transport(&e, format!("Error creating S3 multipart upload: {}", e))
+        })?;
+        let status = res.status();
+        let body = res.text().await.unwrap_or_default();
+        if !status.is_success() {
+            let err = format!(
+                "Error creating S3 multipart upload: server responded with {}",
+                status
+            );
+            return Err(Failure::status(status, with_body(err, &body)));
+        }
+
+        let upload_id = xml_value(&body, "UploadId").ok_or_else(|| {
+            Failure::http("Error creating S3 multipart upload: response has no UploadId")
+        })?;
+        Ok(S3 {
+            upload_id,
+            etags: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Builds the UploadPart request for the chunk
+    pub fn request(
+        &self,
+        client: &Client,
+        opts: &UploadOptions,
+        chunk: &Chunk,
+        body: ChunkBody,
+    ) -> RequestBuilder {
+        let url = self.url(opts, Some(chunk.index + 1));
+        body.attach(build_request(client, opts, Method::PUT, url.as_str()))
+    }
+
+    /// Remembers the part's ETag for the completion manifest
+    pub fn confirm(&self, chunk: &Chunk, res: &Response) -> Result<(), String> {
+        let etag = res
+            .headers()
+            .get(ETAG)
+            .and_then(|v| v.to_str().ok())
+            .ok_or("response has no ETag header")?;
+        self.etags
+            .lock()
+            .unwrap()
+            .insert(chunk.index + 1, etag.to_string());
+        Ok(())
+    }
+
+    /// Assembles the stored parts into the object with CompleteMultipartUpload
+    pub async fn finish(&self, client: &Client, opts: &UploadOptions) -> Result<(), Failure> {
+        let mut manifest = String::from("<CompleteMultipartUpload>");
+        for (part, etag) in self.etags.lock().unwrap().iter() {
+            manifest.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                part,
+                xml_escape(etag)
+            ));
+        }
+        manifest.push_str("</CompleteMultipartUpload>");
+
+        let mut req = build_request(client, opts, Method::POST, self.url(opts, None).as_str());
+        if opts.preconditions_on_commit() {
+            for (name, value) in opts.preconditions.iter() {
+                req = req.header(name, value);
+            }
+        }
+        let res = send(opts, req.body(manifest)).await.map_err(|e| {
+            Failure::transport(&e, format!("Error completing S3 multipart upload: {}", e))
+        })?;
+        let status = res.status();
+        if status == StatusCode::PRECONDITION_FAILED && opts.preconditions_on_commit() {
+            return Err(Failure::status(
+                status,
+                format!(
+                    "Error completing S3 multipart upload: {}",
+                    opts.precondition_failed()
+                ),
+            ));
+        }
+        let body = res.text().await.unwrap_or_default();
+
+        // S3 may report a failed completion inside a 200 response
+        if !status.is_success() || body.contains("<Error>") {
+            let err = format!(
+                "Error completing S3 multipart upload: server responded with {}",
+                status
+            );
+            return Err(Failure::status(status, with_body(err, &body)));
+        }
+        Ok(())
+    }
+
+    /// Drops every stored part with AbortMultipartUpload
+    pub async fn abort(&self, client: &Client, opts: &UploadOptions) -> Result<(), String> {
+        let req = build_request(client, opts, Method::DELETE, self.url(opts, None).as_str());
+        let res = send(opts, req)
+            .await
+            .map_err(|e| format!("Error aborting S3 multipart upload: {}", e))?;
+        if !res.status().is_success() {
+            return Err(format!(
+                "Error aborting S3 multipart upload: server responded with {}",
+                res.status()
+            ));
+        }
+        Ok(())
+    }
+
+    /// The object URL addressing this upload, or one of its parts
+    fn url(&self, opts: &UploadOptions, part: Option<u64>) -> Url {
+        // Already parsed successfully in begin
+        let mut url = parse_url(opts).unwrap();
+        {
+            let mut query = url.query_pairs_mut();
+            if let Some(part) = part {
+                query.append_pair("partNumber", &part.to_string());
+            }
+            query.append_pair("uploadId", &self.upload_id);
+        }
+        url
+    }
+}
+
+/// Rejects chunk sizes that S3 would only refuse once every part is uploaded
+pub fn validate_chunk_size(chunk_size: u64, range_len: u64) -> Result<(), String> {
+    if chunk_size < MIN_PART_SIZE && range_len > chunk_size {
+        return Err(format!(
+            "S3 parts must be at least {} bytes except for the last one, got a chunk size of {}",
+            MIN_PART_SIZE, chunk_size
+        ));
+    }
+    if range_len.div_ceil(chunk_size) > MAX_PARTS {
+        return Err(format!(
+            "S3 uploads can have at most {} parts, a chunk size of {} would need {}",
+            MAX_PARTS,
+            chunk_size,
+            range_len.div_ceil(chunk_size)
+        ));
+    }
+    Ok(())
+}
+
+fn parse_url(opts: &UploadOptions) -> Result<Url, String> {
+    Url::parse(&opts.url).map_err(|e| format!("Invalid S3 object URL '{}': {}", opts.url, e))
+}
+
+/// The text of the first `<tag>` element, good enough for S3's flat responses
+fn xml_value(xml: &str, tag: &str) -> Option<String> {
+    let start = xml.find(&format!("<{tag}>"))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{tag}>"))?;
+    Some(xml[start..end].to_string())
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
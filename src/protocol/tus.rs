@@ -0,0 +1,166 @@
+use base64::prelude::{Engine, BASE64_STANDARD};
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use reqwest::{Client, RequestBuilder, Response};
+use reqwest::{Method, StatusCode, Url};
+
+use crate::state::StateTracker;
+use crate::{build_request, Chunk, ChunkBody, Failure, UploadOptions};
+
+const TUS_RESUMABLE: &str = "Tus-Resumable";
+const TUS_VERSION: &str = "1.0.0";
+const UPLOAD_OFFSET: &str = "Upload-Offset";
+
+/// A tus upload, whose offsets count from the start of the selected range
+pub struct Tus {
+    location: String,
+}
+
+impl Tus {
+    /// Continues the upload recorded in the state file, or creates a new one on the server
+    pub async fn begin(
+        client: &Client,
+        opts: &UploadOptions,
+        state: &StateTracker,
+    ) -> Result<Self, Failure> {
+        let (file_start, file_end) = opts.range;
+
+        if let Some(location) = state.upload_url() {
+            match Self::server_offset(client, opts, &location).await {
+                Ok(offset) if offset <= file_end - file_start => {
+                    opts.info(&format!("Resuming tus upload at {} from byte {}", location, file_start + offset));
+                    state.set_offset(file_start + offset);
+                    return Ok(Tus { location });
+                }
+                Ok(offset) => opts.info(&format!(
+                    "tus upload at {} reports offset {} past the end of the range, creating a new one",
+                    location, offset
+                )),
+                Err(err) => opts.info(&format!("Can't resume tus upload at {} ({}), creating a new one", location, err)),
+            }
+        }
+
+        let mut req = build_request(client, opts, Method::POST, &opts.url)
+            .header(TUS_RESUMABLE, TUS_VERSION)
+            .header("Upload-Length", (file_end - file_start).to_string());
+        // The PATCH requests must be application/offset+octet-stream, so the type goes here
+        let mut metadata = opts.tus_metadata.clone();
+        if let Some(content_type) = opts.content_type.as_ref() {
+            if !metadata.iter().any(|(k, _)| k == "filetype") {
+                let content_type = String::from_utf8_lossy(content_type.as_bytes());
+                metadata.push(("filetype".to_string(), content_type.into_owned()));
+            }
+        }
+        if !metadata.is_empty() {
+            let metadata = metadata
+                .iter()
+                .map(|(k, v)| format!("{} {}", k, BASE64_STANDARD.encode(v)))
+                .collect::<Vec<_>>()
+                .join(",");
+            req = req.header("Upload-Metadata", metadata);
+        }
+
+        let res = req
+            .send()
+            .await
+            .map_err(|e| Failure::transport(&e, format!("Error creating tus upload: {}", e)))?;
+        if res.status() != StatusCode::CREATED {
+            return Err(Failure::status(
+                res.status(),
+                format!(
+                    "Error creating tus upload: server responded with {}",
+                    res.status()
+                ),
+            ));
+        }
+
+        // The Location may be relative to the creation URL
+        let location = res
+            .headers()
+            .get(LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| Url::parse(&opts.url).and_then(|base| base.join(v)).ok())
+            .ok_or_else(|| {
+                Failure::http("Error creating tus upload: response has no valid Location header")
+            })?
+            .to_string();
+
+        state.set_offset(file_start);
+        if let Err(err) = state.set_upload_url(location.clone()) {
+            opts.warn(&format!("Failed to save resume state: {}", err));
+        }
+        Ok(Tus { location })
+    }
+
+    /// Builds the PATCH appending `body` at the chunk's offset
+    pub fn request(
+        &self,
+        client: &Client,
+        opts: &UploadOptions,
+        chunk: &Chunk,
+        body: ChunkBody,
+    ) -> RequestBuilder {
+        let req = build_request(client, opts, Method::PATCH, &self.location)
+            .header(TUS_RESUMABLE, TUS_VERSION)
+            .header(UPLOAD_OFFSET, (chunk.start - opts.range.0).to_string())
+            .header(CONTENT_TYPE, "application/offset+octet-stream");
+        body.attach(req)
+    }
+
+    /// Terminates the upload, for servers with the termination extension
+    pub async fn abort(&self, client: &Client, opts: &UploadOptions) -> Result<(), String> {
+        let res = build_request(client, opts, Method::DELETE, &self.location)
+            .header(TUS_RESUMABLE, TUS_VERSION)
+            .send()
+            .await
+            .map_err(|e| format!("Error terminating tus upload: {}", e))?;
+        if !res.status().is_success() {
+            return Err(format!(
+                "Error terminating tus upload: server responded with {}",
+                res.status()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Makes sure the server's new offset lines up with the end of the chunk
+    pub fn confirm(
+        &self,
+        opts: &UploadOptions,
+        chunk: &Chunk,
+        res: &Response,
+    ) -> Result<(), String> {
+        let expected = chunk.end - opts.range.0;
+        match parse_offset(res) {
+            Some(offset) if offset == expected => Ok(()),
+            Some(offset) => Err(format!(
+                "server reports offset {} but {} was expected",
+                offset, expected
+            )),
+            None => Err(format!("response has no valid {} header", UPLOAD_OFFSET)),
+        }
+    }
+
+    /// Asks the server via HEAD how far along the upload at `location` is
+    async fn server_offset(
+        client: &Client,
+        opts: &UploadOptions,
+        location: &str,
+    ) -> Result<u64, String> {
+        let res = build_request(client, opts, Method::HEAD, location)
+            .header(TUS_RESUMABLE, TUS_VERSION)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !res.status().is_success() {
+            return Err(format!("server responded with {}", res.status()));
+        }
+        parse_offset(&res).ok_or_else(|| format!("response has no valid {} header", UPLOAD_OFFSET))
+    }
+}
+
+fn parse_offset(res: &Response) -> Option<u64> {
+    res.headers()
+        .get(UPLOAD_OFFSET)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+}
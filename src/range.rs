@@ -0,0 +1,138 @@
+/// Which bytes of a file to upload, written like the ranges of an HTTP Range header
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ByteRange {
+    /// Bytes `start..end`, written `start-end`
+    Between(u64, u64),
+    /// From `start` to the end of the file, written `start-`
+    From(u64),
+    /// The file's last bytes, written `-length`
+    Last(u64),
+}
+
+impl ByteRange {
+    /// The range's `start..end` in a file of `len` bytes, which must hold at least one byte
+    ///
+    /// A range reaching past the end of the file is refused, unless `clamp` cuts it short there.
+    pub(crate) fn resolve(self, len: u64, clamp: bool) -> Result<(u64, u64), String> {
+        let (start, end) = match self {
+            ByteRange::Between(start, end) => (start, end),
+            ByteRange::From(start) => (start, len),
+            ByteRange::Last(length) => (len.saturating_sub(length), len),
+        };
+        if self.exceeds(len) && !clamp {
+            return Err(format!(
+                "Byte range {} is larger than the file's size of {}",
+                self, len
+            ));
+        }
+        let (start, end) = (start.min(len), end.min(len));
+        if start >= end {
+            return Err(format!(
+                "Byte range {} selects no bytes of the {}-byte file",
+                self, len
+            ));
+        }
+        Ok((start, end))
+    }
+
+    /// Whether the range reaches past the end of a file of `len` bytes
+    pub(crate) fn exceeds(self, len: u64) -> bool {
+        match self {
+            ByteRange::Between(start, end) => start > len || end > len,
+            ByteRange::From(start) => start > len,
+            ByteRange::Last(length) => length > len,
+        }
+    }
+}
+
+impl std::str::FromStr for ByteRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((start, end)) = s.trim().split_once('-') else {
+            return Err(format!(
+                "Invalid byte range '{}', expected e.g. 0-1000, 1000- or -4096",
+                s
+            ));
+        };
+        let number = |part: &str| {
+            part.trim().parse::<u64>().map_err(|_| {
+                format!(
+                    "Invalid byte range '{}', '{}' isn't a number of bytes",
+                    s, part
+                )
+            })
+        };
+        match (start.trim().is_empty(), end.trim().is_empty()) {
+            (true, true) => Err(format!(
+                "Invalid byte range '{}', expected e.g. 0-1000, 1000- or -4096",
+                s
+            )),
+            (false, true) => Ok(ByteRange::From(number(start)?)),
+            (true, false) => Ok(ByteRange::Last(number(end)?)),
+            (false, false) => match (number(start)?, number(end)?) {
+                (start, end) if start > end => Err(format!(
+                    "Invalid byte range '{}', it starts after it ends",
+                    s
+                )),
+                (start, end) => Ok(ByteRange::Between(start, end)),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ByteRange::Between(start, end) => write!(f, "{}-{}", start, end),
+            ByteRange::From(start) => write!(f, "{}-", start),
+            ByteRange::Last(length) => write!(f, "-{}", length),
+        }
+    }
+}
+
+/// Cuts the bytes `start..end` into `(offset, len)` chunks of `chunk_size`, the last one shorter
+/// when the range doesn't divide evenly, the one place the chunk arithmetic is done
+///
+/// An empty range has no chunks. Panics when `chunk_size` is 0.
+pub fn split_range(range: (u64, u64), chunk_size: u64) -> Split {
+    assert!(chunk_size > 0, "the chunk size must be greater than 0");
+    Split {
+        next: range.0,
+        end: range.1.max(range.0),
+        chunk_size,
+    }
+}
+
+/// The chunks of a range still to come, see [`split_range`]
+#[derive(Clone, Debug)]
+pub struct Split {
+    next: u64,
+    end: u64,
+    chunk_size: u64,
+}
+
+impl Iterator for Split {
+    type Item = (u64, u64);
+
+    fn next(&mut self) -> Option<(u64, u64)> {
+        if self.next >= self.end {
+            return None;
+        }
+        // Measured from what's left rather than added to the offset, which can't overflow
+        let len = self.chunk_size.min(self.end - self.next);
+        let chunk = (self.next, len);
+        self.next += len;
+        Some(chunk)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len(), Some(self.len()))
+    }
+}
+
+impl ExactSizeIterator for Split {
+    fn len(&self) -> usize {
+        (self.end - self.next).div_ceil(self.chunk_size) as usize
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Paces every request of the run so the average stays under a byte rate
+///
+/// Each send books the time its bytes take at the rate and waits for the bookings before it,
+/// so idle stretches don't build up credit to burst with later.
+pub struct RateLimiter {
+    bytes_per_sec: u64,
+    /// When the next send may start
+    next: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        RateLimiter {
+            bytes_per_sec,
+            next: Mutex::new(None),
+        }
+    }
+
+    /// Waits until `bytes` may be sent without going over the rate
+    pub async fn acquire(&self, bytes: u64) {
+        let start = {
+            let mut next = self.next.lock().unwrap();
+            let now = Instant::now();
+            let start = next.map_or(now, |next| next.max(now));
+            *next = Some(start + Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64));
+            start
+        };
+        tokio::time::sleep(start.saturating_duration_since(Instant::now())).await;
+    }
+}
+
+/// Parses a rate like `500k`, `2M` or plain bytes per second, with binary units like curl
+pub fn parse_rate(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, unit) = match value.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => value.split_at(i),
+        None => (value, ""),
+    };
+    let multiplier: u64 = match unit.to_ascii_lowercase().as_str() {
+        "" => 1,
+        "k" => 1024,
+        "m" => 1024 * 1024,
+        "g" => 1024 * 1024 * 1024,
+        _ => {
+            return Err(format!(
+                "Invalid rate '{}', expected e.g. 500k or 2M",
+                value
+            ))
+        }
+    };
+
+    match number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+    {
+        Some(0) => Err("The rate limit must be greater than 0".to_string()),
+        Some(rate) => Ok(rate),
+        None => Err(format!(
+            "Invalid rate '{}', expected e.g. 500k or 2M",
+            value
+        )),
+    }
+}
@@ -0,0 +1,102 @@
+use std::borrow::Cow;
+use std::io;
+use std::path::PathBuf;
+
+use bytes::Bytes;
+use reqwest::header::HeaderMap;
+use reqwest::{Response, StatusCode, Version};
+use tokio::fs;
+
+/// Where the chunk responses are written, see [`crate::ChunkUploaderBuilder::save_responses`]
+#[derive(Clone, Default)]
+pub(crate) struct SavedResponses {
+    /// Gets `chunk-<index>.bin` with the body and `chunk-<index>.meta` with the status and headers
+    pub dir: Option<PathBuf>,
+    /// Gets the body of the last chunk's response, or of the finalize request's
+    pub last: Option<PathBuf>,
+}
+
+/// A response read in full, so its body can be saved and still be looked at
+pub(crate) struct ReadResponse {
+    pub status: StatusCode,
+    pub version: Version,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl ReadResponse {
+    /// Reads the body, a connection dropping halfway through leaves what arrived so far out
+    pub async fn read(res: Response) -> Self {
+        ReadResponse {
+            status: res.status(),
+            version: res.version(),
+            headers: res.headers().clone(),
+            body: res.bytes().await.unwrap_or_default(),
+        }
+    }
+
+    pub fn text(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.body)
+    }
+}
+
+impl SavedResponses {
+    pub fn enabled(&self) -> bool {
+        self.dir.is_some() || self.last.is_some()
+    }
+
+    /// Creates the directory, clearing what an earlier upload left in it unless this one
+    /// resumes it, whose earlier chunks aren't sent again
+    pub async fn prepare(&self, resumed: bool) -> io::Result<()> {
+        let Some(dir) = self.dir.as_ref() else {
+            return Ok(());
+        };
+        fs::create_dir_all(dir).await?;
+        if resumed {
+            return Ok(());
+        }
+        let mut entries = fs::read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let is_chunk = name.strip_prefix("chunk-").is_some_and(|rest| {
+                let index = rest
+                    .strip_suffix(".bin")
+                    .or_else(|| rest.strip_suffix(".meta"));
+                index.is_some_and(|index| index.parse::<u64>().is_ok())
+            });
+            if is_chunk {
+                fs::remove_file(entry.path()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the latest response to the chunk, replacing one of an earlier attempt
+    pub async fn save(&self, index: u64, res: &ReadResponse, last: bool) -> io::Result<()> {
+        if let Some(dir) = self.dir.as_ref() {
+            let mut meta = format!("{:?} {}\n", res.version, res.status);
+            for (name, value) in res.headers.iter() {
+                meta.push_str(&format!(
+                    "{}: {}\n",
+                    name,
+                    String::from_utf8_lossy(value.as_bytes())
+                ));
+            }
+            fs::write(dir.join(format!("chunk-{}.bin", index)), &res.body).await?;
+            fs::write(dir.join(format!("chunk-{}.meta", index)), meta).await?;
+        }
+        if last {
+            self.save_final(&res.body).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes the body of the response that completed the upload
+    pub async fn save_final(&self, body: &[u8]) -> io::Result<()> {
+        match self.last.as_ref() {
+            Some(path) => fs::write(path, body).await,
+            None => Ok(()),
+        }
+    }
+}
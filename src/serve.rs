@@ -0,0 +1,593 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use flate2::read::GzDecoder;
+use hyper::header::{ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, RANGE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use sha2::{Digest, Sha256};
+
+use chunk_uploader::output::print;
+
+use crate::exit::{CliError, Exit};
+use crate::flags;
+
+/// What `serve` was started with
+pub struct Options {
+    bind: IpAddr,
+    port: u16,
+    dir: PathBuf,
+    /// Every this many uploaded chunks one is refused with `status` instead of stored
+    fail_every: Option<u64>,
+    status: StatusCode,
+    quiet: bool,
+}
+
+/// Parses the arguments after `serve`, none when only the help was asked for
+pub fn parse(args: &[String]) -> Result<Option<Options>, CliError> {
+    let mut options = Options {
+        bind: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        port: 9000,
+        dir: PathBuf::from("received"),
+        fail_every: None,
+        status: StatusCode::SERVICE_UNAVAILABLE,
+        quiet: false,
+    };
+    let mut status_given = false;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-h" | "--help" => {
+                print(&flags::serve_help());
+                return Ok(None);
+            }
+            "-q" | "--quiet" => {
+                options.quiet = true;
+            }
+            "--bind" | "--port" | "--dir" | "--fail-every" | "--status" => {
+                let Some(value) = args.get(i + 1) else {
+                    return Err(CliError::Usage(format!(
+                        "Missing value after argument '{}'",
+                        args[i]
+                    )));
+                };
+                let invalid = |expected: &str| {
+                    CliError::Usage(format!(
+                        "Invalid value '{}' for '{}', expected {}",
+                        value, args[i], expected
+                    ))
+                };
+                match args[i].as_str() {
+                    "--bind" => {
+                        options.bind = value.parse().map_err(|_| invalid("an IP address"))?;
+                    }
+                    "--port" => {
+                        options.port = value.parse().map_err(|_| invalid("a port"))?;
+                    }
+                    "--dir" => options.dir = PathBuf::from(value),
+                    "--fail-every" => {
+                        options.fail_every = match value.parse() {
+                            Ok(0) | Err(_) => return Err(invalid("a number above 0")),
+                            Ok(n) => Some(n),
+                        };
+                    }
+                    _ => {
+                        options.status = value
+                            .parse::<u16>()
+                            .ok()
+                            .and_then(|code| StatusCode::from_u16(code).ok())
+                            .filter(|status| !status.is_success())
+                            .ok_or_else(|| invalid("a status other than 2xx"))?;
+                        status_given = true;
+                    }
+                }
+                i += 1;
+            }
+            a => {
+                return Err(CliError::Usage(format!(
+                    "Unknown argument '{a}' for serve, use 'serve --help' for help"
+                )));
+            }
+        }
+        i += 1;
+    }
+    if status_given && options.fail_every.is_none() {
+        return Err(CliError::Usage(
+            "'--status' needs '--fail-every' to say which chunks get it".to_string(),
+        ));
+    }
+    Ok(Some(options))
+}
+
+/// What arrived of one file so far
+#[derive(Default)]
+struct Assembly {
+    /// Every chunk stored, end exclusive, in the order they came
+    chunks: Vec<(u64, u64)>,
+    total: Option<u64>,
+    complete: bool,
+}
+
+impl Assembly {
+    /// The bytes up to the total, or the furthest chunk while it isn't known, no chunk has
+    fn gaps(&self) -> Vec<(u64, u64)> {
+        let mut chunks = self.chunks.clone();
+        chunks.sort();
+        let end = self
+            .total
+            .unwrap_or_else(|| chunks.iter().map(|c| c.1).max().unwrap_or(0));
+        let (mut gaps, mut covered) = (Vec::new(), 0);
+        for (start, stop) in chunks {
+            if start > covered {
+                gaps.push((covered, start));
+            }
+            covered = covered.max(stop);
+        }
+        if covered < end {
+            gaps.push((covered, end));
+        }
+        gaps
+    }
+}
+
+/// `first-last` for the end exclusive ranges
+fn describe(ranges: &[(u64, u64)]) -> String {
+    let ranges: Vec<_> = ranges
+        .iter()
+        .map(|(start, end)| format!("{}-{}", start, end - 1))
+        .collect();
+    ranges.join(", ")
+}
+
+struct Receiver {
+    options: Options,
+    /// Uploaded chunks so far, counting the refused ones, for `--fail-every`
+    requests: AtomicU64,
+    files: Mutex<HashMap<PathBuf, Assembly>>,
+}
+
+/// Serves until Ctrl-C, then lists the files that are still missing bytes
+pub async fn run(options: Options) -> Result<Exit, CliError> {
+    if let Err(err) = fs::create_dir_all(&options.dir) {
+        return Err(CliError::Io(format!(
+            "Error creating directory '{}': {}",
+            options.dir.display(),
+            err
+        )));
+    }
+    let address = SocketAddr::new(options.bind, options.port);
+    let receiver = Arc::new(Receiver {
+        options,
+        requests: AtomicU64::new(0),
+        files: Mutex::new(HashMap::new()),
+    });
+    let service = {
+        let receiver = receiver.clone();
+        make_service_fn(move |_| {
+            let receiver = receiver.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let receiver = receiver.clone();
+                    async move { Ok::<_, Infallible>(receiver.handle(request).await) }
+                }))
+            }
+        })
+    };
+    let server = match Server::try_bind(&address) {
+        Ok(server) => server.serve(service),
+        Err(err) => {
+            return Err(CliError::Io(format!(
+                "Error listening on {}: {}",
+                address, err
+            )));
+        }
+    };
+    // The first line names the port, which scripts starting it with `--port 0` read
+    print(&format!(
+        "Listening on http://{}, storing uploads in '{}'",
+        server.local_addr(),
+        receiver.options.dir.display()
+    ));
+    let stopped = server.with_graceful_shutdown(async {
+        let _ = tokio::signal::ctrl_c().await;
+    });
+    if let Err(err) = stopped.await {
+        return Err(CliError::Other(format!("The server stopped: {}", err)));
+    }
+
+    // Streamed uploads never give the total, they're taken to be whole without gaps
+    let files = receiver.files.lock().unwrap();
+    for (path, assembly) in files.iter().filter(|(_, a)| !a.complete) {
+        let gaps = assembly.gaps();
+        if !gaps.is_empty() {
+            print(&format!(
+                "'{}' is incomplete, missing bytes {}",
+                path.display(),
+                describe(&gaps)
+            ));
+            continue;
+        }
+        let received = assembly.chunks.iter().map(|c| c.1).max().unwrap_or(0);
+        match sha256(path) {
+            Ok(sha256) => print(&format!(
+                "Received '{}' without a total, {} bytes, SHA-256: {}",
+                path.display(),
+                received,
+                sha256
+            )),
+            Err(err) => print(&format!("Failed reading '{}': {}", path.display(), err)),
+        }
+    }
+    Ok(Exit::Success)
+}
+
+impl Receiver {
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        let (method, path) = (request.method().clone(), request.uri().path().to_string());
+        if method == Method::GET {
+            let (response, message) =
+                self.read_back(&request)
+                    .unwrap_or_else(|(status, message)| {
+                        let mut response = Response::new(Body::from(format!("{message}\n")));
+                        *response.status_mut() = status;
+                        (response, message)
+                    });
+            if !self.options.quiet {
+                print(&format!(
+                    "{} {} {}: {}",
+                    method,
+                    path,
+                    response.status(),
+                    message
+                ));
+            }
+            return response;
+        }
+        let (status, message) = match self.respond(request).await {
+            Ok(response) => response,
+            Err((status, message)) => (status, message),
+        };
+        if !self.options.quiet {
+            print(&format!("{} {} {}: {}", method, path, status, message));
+        }
+        let mut response = match (method == Method::HEAD, status) {
+            // Checking for an existing file compares its size with the Content-Length
+            (true, StatusCode::OK) => Response::builder()
+                .header(CONTENT_LENGTH, message.trim_end_matches(" bytes"))
+                .body(Body::empty())
+                .unwrap(),
+            _ => Response::new(Body::from(format!("{message}\n"))),
+        };
+        *response.status_mut() = status;
+        response
+    }
+
+    /// What to answer with and why, the same for a stored chunk and a refused one
+    async fn respond(
+        &self,
+        request: Request<Body>,
+    ) -> Result<(StatusCode, String), (StatusCode, String)> {
+        let bad = |message: String| (StatusCode::BAD_REQUEST, message);
+        let path = self.target(request.uri().path()).map_err(bad)?;
+        if request.method() == Method::HEAD {
+            let files = self.files.lock().unwrap();
+            return match files
+                .get(&path)
+                .and_then(|a| a.total.filter(|_| a.complete))
+            {
+                Some(total) => Ok((StatusCode::OK, format!("{} bytes", total))),
+                None => Err((StatusCode::NOT_FOUND, "not received".to_string())),
+            };
+        }
+        if ![Method::PUT, Method::PATCH, Method::POST].contains(request.method()) {
+            return Err((
+                StatusCode::METHOD_NOT_ALLOWED,
+                "only PUT, PATCH and POST upload".to_string(),
+            ));
+        }
+        let range = match request.headers().get(CONTENT_RANGE) {
+            Some(value) => Some(
+                value
+                    .to_str()
+                    .ok()
+                    .and_then(parse_content_range)
+                    .ok_or_else(|| bad(format!("invalid Content-Range {:?}", value)))?,
+            ),
+            None => None,
+        };
+        let gzipped = request
+            .headers()
+            .get(CONTENT_ENCODING)
+            .is_some_and(|encoding| encoding == "gzip");
+        let body = hyper::body::to_bytes(request.into_body())
+            .await
+            .map_err(|e| bad(format!("failed reading the body: {}", e)))?;
+
+        let body = match gzipped {
+            false => body,
+            true => {
+                let mut decoded = Vec::new();
+                GzDecoder::new(&body[..])
+                    .read_to_end(&mut decoded)
+                    .map_err(|e| bad(format!("invalid gzip body: {}", e)))?;
+                Bytes::from(decoded)
+            }
+        };
+        // Without a Content-Range the body is the whole file
+        let (start, end, total) = match range {
+            Some(ContentRange {
+                range: Some((start, end)),
+                total,
+            }) => (start, end, total),
+            Some(ContentRange { range: None, total }) => (0, 0, total),
+            None => (0, body.len() as u64, Some(body.len() as u64)),
+        };
+        if body.len() as u64 != end - start {
+            return Err(bad(format!(
+                "Content-Range names {} bytes but the body has {}",
+                end - start,
+                body.len()
+            )));
+        }
+        // Only chunks that would be stored are refused, a broken one gets its own error
+        let count = self.requests.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Some(every) = self.options.fail_every {
+            if count.is_multiple_of(every) {
+                return Err((
+                    self.options.status,
+                    format!("failing every {} chunks, this one included", every),
+                ));
+            }
+        }
+        self.store(&path, start, end, total, &body)
+    }
+
+    /// The file under the directory the URL's path names
+    /// A complete file, or the bytes its Range header asks for, for `--verify readback`
+    fn read_back(
+        &self,
+        request: &Request<Body>,
+    ) -> Result<(Response<Body>, String), (StatusCode, String)> {
+        let path = self
+            .target(request.uri().path())
+            .map_err(|message| (StatusCode::BAD_REQUEST, message))?;
+        let complete = self
+            .files
+            .lock()
+            .unwrap()
+            .get(&path)
+            .is_some_and(|a| a.complete);
+        if !complete {
+            return Err((StatusCode::NOT_FOUND, "not received".to_string()));
+        }
+        let data = fs::read(&path).map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed reading the file: {}", e),
+            )
+        })?;
+        let len = data.len() as u64;
+        let response = Response::builder().header(ACCEPT_RANGES, "bytes");
+
+        // One range of the form first-last or first-, the only kind the uploader asks for
+        let range = request.headers().get(RANGE).map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.strip_prefix("bytes="))
+                .and_then(|value| value.split_once('-'))
+                .and_then(|(first, last)| {
+                    let first = first.parse::<u64>().ok()?;
+                    match last {
+                        "" => Some((first, len.saturating_sub(1))),
+                        last => Some((first, last.parse::<u64>().ok()?.min(len.saturating_sub(1)))),
+                    }
+                })
+        });
+        match range {
+            None => {
+                let message = format!("sent all {} bytes", len);
+                Ok((response.body(Body::from(data)).unwrap(), message))
+            }
+            Some(None) => Err((StatusCode::BAD_REQUEST, "invalid Range".to_string())),
+            Some(Some((first, last))) if first >= len || first > last => {
+                let response = response
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(CONTENT_RANGE, format!("bytes */{}", len))
+                    .body(Body::empty())
+                    .unwrap();
+                Ok((response, format!("the file holds {} bytes", len)))
+            }
+            Some(Some((first, last))) => {
+                let body = data[first as usize..=last as usize].to_vec();
+                let response = response
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(CONTENT_RANGE, format!("bytes {}-{}/{}", first, last, len))
+                    .body(Body::from(body))
+                    .unwrap();
+                Ok((response, format!("sent bytes {}-{}", first, last)))
+            }
+        }
+    }
+
+    fn target(&self, path: &str) -> Result<PathBuf, String> {
+        let mut target = self.options.dir.clone();
+        let mut named = false;
+        for segment in path.split('/').filter(|s| !s.is_empty()) {
+            let segment = percent_decode(segment).ok_or("invalid percent encoding in the path")?;
+            if segment == "." || segment == ".." || segment.contains('\\') {
+                return Err(format!("the path segment '{segment}' isn't allowed"));
+            }
+            target.push(segment);
+            named = true;
+        }
+        match named {
+            true => Ok(target),
+            false => Err("the path names no file".to_string()),
+        }
+    }
+
+    fn store(
+        &self,
+        path: &Path,
+        start: u64,
+        end: u64,
+        total: Option<u64>,
+        body: &[u8],
+    ) -> Result<(StatusCode, String), (StatusCode, String)> {
+        let conflict = |message: String| (StatusCode::CONFLICT, message);
+        let failed = |err: io::Error| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed writing '{}': {}", path.display(), err),
+            )
+        };
+        let mut files = self.files.lock().unwrap();
+        let assembly = files.entry(path.to_path_buf()).or_default();
+        // The same chunk sent again is fine, one partly over another means the ranges are off
+        let overlapping = |assembly: &Assembly| {
+            assembly
+                .chunks
+                .iter()
+                .find(|&&(s, e)| (s, e) != (start, end) && s < end && start < e)
+                .copied()
+        };
+        // A new upload of the file starts over, like one from the start in other chunks
+        let restart = assembly.complete || (start == 0 && overlapping(assembly).is_some());
+        if restart {
+            *assembly = Assembly::default();
+        }
+        match (assembly.total, total) {
+            (Some(known), Some(total)) if known != total => {
+                return Err(conflict(format!(
+                    "the total of {} differs from the {} earlier chunks gave",
+                    total, known
+                )));
+            }
+            (None, Some(total)) => assembly.total = Some(total),
+            _ => {}
+        }
+        if let Some(total) = assembly.total.filter(|&total| end > total) {
+            return Err(conflict(format!(
+                "bytes up to {} are past the total of {}",
+                end, total
+            )));
+        }
+        if let Some(overlapped) = overlapping(assembly) {
+            return Err(conflict(format!(
+                "bytes {} overlap bytes {} received before",
+                describe(&[(start, end)]),
+                describe(&[overlapped])
+            )));
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(failed)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(restart)
+            .open(path)
+            .map_err(failed)?;
+        file.seek(SeekFrom::Start(start)).map_err(failed)?;
+        file.write_all(body).map_err(failed)?;
+        if !assembly.chunks.contains(&(start, end)) {
+            assembly.chunks.push((start, end));
+        }
+
+        let stored = match start == end {
+            true => "stored nothing".to_string(),
+            false => format!("stored bytes {}", describe(&[(start, end)])),
+        };
+        let Some(total) = assembly.total else {
+            return Ok((StatusCode::OK, stored));
+        };
+        let gaps = assembly.gaps();
+        if !gaps.is_empty() {
+            // Parallel chunks arrive out of order, so gaps only count once the last one is in
+            return Ok(match end == total {
+                true => (
+                    StatusCode::OK,
+                    format!("{}, still missing {}", stored, describe(&gaps)),
+                ),
+                false => (StatusCode::OK, stored),
+            });
+        }
+        file.set_len(total).map_err(failed)?;
+        assembly.complete = true;
+        let sha256 = sha256(path).map_err(failed)?;
+        print(&format!(
+            "Received '{}' complete, {} bytes, SHA-256: {}",
+            path.display(),
+            total,
+            sha256
+        ));
+        Ok((StatusCode::OK, format!("{}, complete", stored)))
+    }
+}
+
+/// A chunk's `bytes first-last/total`, `*` for either leaving it out
+struct ContentRange {
+    /// End exclusive
+    range: Option<(u64, u64)>,
+    total: Option<u64>,
+}
+
+fn parse_content_range(value: &str) -> Option<ContentRange> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let total = match total {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+    let range = match range {
+        "*" => None,
+        range => {
+            let (first, last) = range.split_once('-')?;
+            let (first, last): (u64, u64) = (first.parse().ok()?, last.parse().ok()?);
+            if last < first {
+                return None;
+            }
+            Some((first, last + 1))
+        }
+    };
+    Some(ContentRange { range, total })
+}
+
+fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn sha256(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
@@ -0,0 +1,78 @@
+//! The signals stopping and pausing the uploads
+//!
+//! Windows only has Ctrl-C, so the uploads can't be paused there and SIGTERM doesn't exist.
+
+#[cfg(unix)]
+use tokio::signal::unix::{signal, Signal, SignalKind};
+
+use chunk_uploader::output::Output;
+use chunk_uploader::Interrupter;
+
+/// Ctrl-C, and SIGTERM where there are signals, asking the uploads to stop
+pub struct Stop {
+    #[cfg(unix)]
+    term: Option<Signal>,
+}
+
+impl Stop {
+    pub fn listen() -> Self {
+        Stop {
+            #[cfg(unix)]
+            term: signal(SignalKind::terminate()).ok(),
+        }
+    }
+
+    /// Resolves once the next one arrives, false when they can't be listened for
+    pub async fn next(&mut self) -> bool {
+        #[cfg(unix)]
+        if let Some(term) = self.term.as_mut() {
+            return tokio::select! {
+                ctrl_c = tokio::signal::ctrl_c() => ctrl_c.is_ok(),
+                _ = term.recv() => true,
+            };
+        }
+        tokio::signal::ctrl_c().await.is_ok()
+    }
+}
+
+/// Pauses the uploads on SIGUSR1 and resumes them on the next one or SIGCONT
+#[cfg(unix)]
+pub fn pause_on_usr1(interrupter: Interrupter, out: Output) {
+    let usr1 = signal(SignalKind::user_defined1());
+    let cont = signal(SignalKind::from_raw(libc::SIGCONT));
+    let (Ok(mut usr1), Ok(mut cont)) = (usr1, cont) else {
+        return;
+    };
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                Some(()) = usr1.recv() => match interrupter.is_paused() {
+                    true => resume(&interrupter, out),
+                    false => {
+                        out.warn(
+                            "Pausing once the chunks in flight are in, send SIGUSR1 again or \
+                             SIGCONT to resume",
+                        );
+                        interrupter.pause();
+                    }
+                },
+                Some(()) = cont.recv() => {
+                    if interrupter.is_paused() {
+                        resume(&interrupter, out);
+                    }
+                }
+                else => return,
+            }
+        }
+    });
+}
+
+#[cfg(unix)]
+fn resume(interrupter: &Interrupter, out: Output) {
+    out.warn("Resuming");
+    interrupter.resume();
+}
+
+/// There is nothing to pause with on Windows
+#[cfg(not(unix))]
+pub fn pause_on_usr1(_interrupter: Interrupter, _out: Output) {}
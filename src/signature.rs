@@ -0,0 +1,120 @@
+use reqwest::header::{HeaderName, HeaderValue};
+use reqwest::Request;
+
+use crate::digest::{hex, hmac};
+use crate::url_placeholders;
+
+/// The placeholders the string-to-sign of [`crate::ChunkUploaderBuilder::hmac_signature`] may
+/// hold, filled in per chunk
+pub const HMAC_PLACEHOLDERS: &[&str] = &[
+    "{method}", "{path}", "{query}", "{index}", "{offset}", "{end}", "{length}", "{sha256}",
+];
+
+/// What's signed unless another payload is given
+pub const DEFAULT_HMAC_PAYLOAD: &str = "{method}\\n{path}\\n{offset}\\n{sha256}";
+
+/// Signs each chunk request with the hex HMAC-SHA256 of a string filled in from it
+pub(crate) struct HmacSignature {
+    secret: Vec<u8>,
+    /// With its escapes already turned into the characters they stand for
+    payload: String,
+    header: HeaderName,
+}
+
+/// The chunk a request carries, as the payload names it
+pub(crate) struct Signed<'a> {
+    pub index: u64,
+    pub start: u64,
+    pub end: u64,
+    /// Hex SHA-256 of the body, when the payload has `{sha256}`
+    pub sha256: Option<&'a str>,
+}
+
+impl HmacSignature {
+    /// Refuses a payload with placeholders or escapes it doesn't know
+    pub fn new(secret: Vec<u8>, payload: &str, header: HeaderName) -> Result<Self, String> {
+        if let Some(unknown) = url_placeholders(payload).find(|p| !HMAC_PLACEHOLDERS.contains(p)) {
+            return Err(format!(
+                "Unknown placeholder '{}' in the HMAC payload, expected one of {}",
+                unknown,
+                HMAC_PLACEHOLDERS.join(", ")
+            ));
+        }
+        Ok(HmacSignature {
+            secret,
+            payload: unescape(payload)?,
+            header,
+        })
+    }
+
+    /// The body is read into memory before it's sent to be hashed
+    pub fn hashes_body(&self) -> bool {
+        self.payload.contains("{sha256}")
+    }
+
+    pub fn sign(&self, req: &mut Request, chunk: &Signed) {
+        let payload = self.payload(req, chunk);
+        let signature = hex(&hmac(&self.secret, payload.as_bytes()));
+        let value = HeaderValue::from_str(&signature).expect("hex is a valid header value");
+        req.headers_mut().insert(self.header.clone(), value);
+    }
+
+    /// The string-to-sign, the placeholders filled in one pass so a value holding one isn't
+    /// filled in again
+    fn payload(&self, req: &Request, chunk: &Signed) -> String {
+        let mut filled = String::new();
+        let mut rest = self.payload.as_str();
+        while let Some(start) = rest.find('{') {
+            filled.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let placeholder = HMAC_PLACEHOLDERS
+                .iter()
+                .find(|&&placeholder| rest.starts_with(placeholder));
+            let Some(placeholder) = placeholder else {
+                filled.push('{');
+                rest = &rest[1..];
+                continue;
+            };
+            match *placeholder {
+                "{method}" => filled.push_str(req.method().as_str()),
+                "{path}" => filled.push_str(req.url().path()),
+                "{query}" => filled.push_str(req.url().query().unwrap_or_default()),
+                "{index}" => filled.push_str(&chunk.index.to_string()),
+                "{offset}" => filled.push_str(&chunk.start.to_string()),
+                "{end}" => filled.push_str(&chunk.end.to_string()),
+                "{length}" => filled.push_str(&(chunk.end - chunk.start).to_string()),
+                _ => filled.push_str(chunk.sha256.unwrap_or_default()),
+            }
+            rest = &rest[placeholder.len()..];
+        }
+        filled.push_str(rest);
+        filled
+    }
+}
+
+/// Turns `\n`, `\r`, `\t` and `\\` into the characters they stand for, so a payload given on
+/// the command line can hold them
+fn unescape(payload: &str) -> Result<String, String> {
+    let mut unescaped = String::new();
+    let mut chars = payload.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some('\\') => unescaped.push('\\'),
+            other => {
+                let escape = other.map(String::from).unwrap_or_default();
+                return Err(format!(
+                    "Unknown escape '\\{}' in the HMAC payload, expected \\n, \\r, \\t or \\\\",
+                    escape
+                ));
+            }
+        }
+    }
+    Ok(unescaped)
+}
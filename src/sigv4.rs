@@ -0,0 +1,388 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::header::{HeaderValue, AUTHORIZATION, DATE};
+use reqwest::{Request, Response};
+use sha2::{Digest, Sha256};
+
+use crate::digest::{hex, hmac};
+use crate::manifest::timestamp;
+
+/// What S3 takes in place of the body's SHA-256
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+/// Headers a proxy or the HTTP client may change on the way, which would break the signature
+const UNSIGNED_HEADERS: &[&str] = &[
+    "authorization",
+    "connection",
+    "content-length",
+    "expect",
+    "transfer-encoding",
+    "user-agent",
+];
+
+/// A server clock further off than this is trusted over ours, AWS refuses requests signed
+/// 15 minutes off
+const MAX_SKEW: i64 = 60;
+
+/// The keys requests are signed with, see [`crate::ChunkUploaderBuilder::aws_sigv4`]
+#[derive(Clone)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Sent as `X-Amz-Security-Token` for temporary credentials
+    pub session_token: Option<String>,
+}
+
+impl std::fmt::Debug for AwsCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("AwsCredentials")
+            .field("access_key_id", &self.access_key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Signs requests with AWS Signature Version 4, see
+/// https://docs.aws.amazon.com/IAM/latest/UserGuide/create-signed-request.html
+pub(crate) struct SigV4 {
+    region: String,
+    service: String,
+    credentials: AwsCredentials,
+    /// Sign the SHA-256 of bodies held in memory rather than `UNSIGNED-PAYLOAD`
+    sign_payload: bool,
+    /// Seconds the server's clock is ahead of ours, once it's further off than [`MAX_SKEW`]
+    skew: AtomicI64,
+}
+
+impl SigV4 {
+    pub fn new(
+        region: String,
+        service: String,
+        credentials: AwsCredentials,
+        sign_payload: bool,
+    ) -> Self {
+        SigV4 {
+            region,
+            service,
+            credentials,
+            sign_payload,
+            skew: AtomicI64::new(0),
+        }
+    }
+
+    pub fn signs_payload(&self) -> bool {
+        self.sign_payload
+    }
+
+    /// Adds the `Authorization` header and the `X-Amz-*` ones it covers, replacing those of an
+    /// earlier signature so a request sent again is signed anew
+    pub fn sign(&self, req: &mut Request) {
+        let now = self.now();
+        // 2024-05-01T12:30:05.123Z becomes 20240501T123005Z
+        let stamp = timestamp(now);
+        let amz_date = format!("{}Z", stamp[..19].replace(['-', ':'], ""));
+
+        let payload = match req.body().and_then(|body| body.as_bytes()) {
+            Some(bytes) if self.sign_payload => hex(&Sha256::digest(bytes)),
+            None if self.sign_payload && req.body().is_none() => hex(&Sha256::digest([])),
+            _ => UNSIGNED_PAYLOAD.to_string(),
+        };
+        let headers = req.headers_mut();
+        headers.remove(AUTHORIZATION);
+        headers.insert("x-amz-date", HeaderValue::from_str(&amz_date).unwrap());
+        headers.insert(
+            "x-amz-content-sha256",
+            HeaderValue::from_str(&payload).unwrap(),
+        );
+        if let Some(token) = self.credentials.session_token.as_ref() {
+            if let Ok(token) = HeaderValue::from_str(token) {
+                headers.insert("x-amz-security-token", token);
+            }
+        }
+
+        let authorization = self.authorization(req, &amz_date, &payload);
+        if let Ok(mut authorization) = HeaderValue::from_str(&authorization) {
+            authorization.set_sensitive(true);
+            req.headers_mut().insert(AUTHORIZATION, authorization);
+        }
+    }
+
+    /// The `Authorization` of the request as it is, signed at `amz_date` with the hash of its
+    /// body, every header besides [`UNSIGNED_HEADERS`] signed
+    fn authorization(&self, req: &Request, amz_date: &str, payload: &str) -> String {
+        let date = &amz_date[..8];
+        let url = req.url();
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        let mut signed: Vec<(String, String)> = vec![("host".to_string(), host)];
+        for (name, value) in req.headers().iter() {
+            if UNSIGNED_HEADERS.contains(&name.as_str()) || name == "host" {
+                continue;
+            }
+            let value = String::from_utf8_lossy(value.as_bytes());
+            // Runs of spaces count as one
+            let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+            match signed
+                .iter_mut()
+                .find(|(signed, _)| signed == name.as_str())
+            {
+                Some((_, values)) => {
+                    values.push(',');
+                    values.push_str(&value);
+                }
+                None => signed.push((name.as_str().to_string(), value)),
+            }
+        }
+        signed.sort();
+        let names = signed
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+
+        let canonical = [
+            req.method().as_str().to_string(),
+            self.canonical_path(url.path()),
+            canonical_query(url.query().unwrap_or_default()),
+            signed
+                .iter()
+                .map(|(name, value)| format!("{}:{}\n", name, value))
+                .collect(),
+            names.clone(),
+            payload.to_string(),
+        ]
+        .join("\n");
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, self.service);
+        let to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex(&Sha256::digest(canonical.as_bytes()))
+        );
+
+        let secret = format!("AWS4{}", self.credentials.secret_access_key);
+        let mut key = hmac(secret.as_bytes(), date.as_bytes());
+        for part in [self.region.as_str(), self.service.as_str(), "aws4_request"] {
+            key = hmac(&key, part.as_bytes());
+        }
+        let signature = hex(&hmac(&key, to_sign.as_bytes()));
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.credentials.access_key_id, scope, names, signature
+        )
+    }
+
+    /// Takes the server's clock from the response's Date header when ours is too far off,
+    /// true if that changed the time later requests are signed with
+    pub fn correct_clock(&self, res: &Response) -> bool {
+        let server = res
+            .headers()
+            .get(DATE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| httpdate::parse_http_date(value).ok());
+        let Some(server) = server else {
+            return false;
+        };
+        let seconds = |time: SystemTime| match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(_) => 0,
+        };
+        let skew = seconds(server) - seconds(SystemTime::now());
+        let before = self.skew.load(Ordering::SeqCst);
+        if (skew - before).abs() <= MAX_SKEW {
+            return false;
+        }
+        self.skew.store(skew, Ordering::SeqCst);
+        true
+    }
+
+    /// How far off our clock is from the server's, when it is
+    pub fn skew(&self) -> i64 {
+        self.skew.load(Ordering::SeqCst)
+    }
+
+    fn now(&self) -> SystemTime {
+        let skew = self.skew.load(Ordering::SeqCst);
+        let now = SystemTime::now();
+        match skew >= 0 {
+            true => now + Duration::from_secs(skew as u64),
+            false => now - Duration::from_secs(skew.unsigned_abs()),
+        }
+    }
+
+    /// S3 signs the path as it's sent, every other service encodes it once more, like the AWS
+    /// SDKs do, so a `+` left as it is in the URL stays one for S3
+    fn canonical_path(&self, path: &str) -> String {
+        let path = if path.is_empty() { "/" } else { path };
+        match self.service == "s3" {
+            true => path.to_string(),
+            false => encode(path.as_bytes(), false),
+        }
+    }
+}
+
+/// The query's parameters sorted and encoded the way AWS wants, `uploads` as `uploads=`
+fn canonical_query(query: &str) -> String {
+    let mut pairs: Vec<(String, String)> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            // A `+` in a query string is a space
+            let decoded = |s: &str| encode(&decode(&s.replace('+', " ")), true);
+            (decoded(name), decoded(value))
+        })
+        .collect();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Percent-encodes everything but the unreserved characters, and `/` unless `slash`
+fn encode(bytes: &[u8], slash: bool) -> String {
+    let mut encoded = String::new();
+    for &b in bytes {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(b as char)
+            }
+            b'/' if !slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+fn decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| s.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok());
+        match escaped {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::{Method, Url};
+
+    /// The credentials, date and scope of the AWS SigV4 test suite
+    fn signer(service: &str) -> SigV4 {
+        let credentials = AwsCredentials {
+            access_key_id: "AKIDEXAMPLE".into(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".into(),
+            session_token: None,
+        };
+        SigV4::new("us-east-1".into(), service.into(), credentials, true)
+    }
+
+    fn authorization(
+        sigv4: &SigV4,
+        method: Method,
+        url: &str,
+        headers: &[(&'static str, &str)],
+        body: &[u8],
+    ) -> String {
+        let mut req = Request::new(method, Url::parse(url).unwrap());
+        for (name, value) in headers.iter().chain(&[("x-amz-date", "20150830T123600Z")]) {
+            req.headers_mut()
+                .insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        // What S3 is told the payload is, if it's told
+        let payload = match headers
+            .iter()
+            .find(|(name, _)| *name == "x-amz-content-sha256")
+        {
+            Some((_, payload)) => payload.to_string(),
+            None => hex(&Sha256::digest(body)),
+        };
+        sigv4.authorization(&req, "20150830T123600Z", &payload)
+    }
+
+    #[test]
+    fn signs_get_vanilla() {
+        let authorization = authorization(
+            &signer("service"),
+            Method::GET,
+            "https://example.amazonaws.com/",
+            &[],
+            b"",
+        );
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    #[test]
+    fn signs_post_x_www_form_urlencoded() {
+        let authorization = authorization(
+            &signer("service"),
+            Method::POST,
+            "https://example.amazonaws.com/",
+            &[("content-type", "application/x-www-form-urlencoded")],
+            b"Param1=value1",
+        );
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date, \
+             Signature=ff11897932ad3f4e8b18135d722051e5ac45fc38421b1da7b9d196a0fe09473a"
+        );
+    }
+
+    #[test]
+    fn encodes_the_path_like_the_aws_sdks() {
+        // S3 signs the path as sent, with a `+` in a key left as it is
+        assert_eq!(signer("s3").canonical_path("/a+b/c%20d"), "/a+b/c%20d");
+        assert_eq!(signer("s3").canonical_path(""), "/");
+        assert_eq!(
+            signer("service").canonical_path("/a+b/c%20d"),
+            "/a%2Bb/c%2520d"
+        );
+
+        let s3 = authorization(
+            &signer("s3"),
+            Method::PUT,
+            "https://bucket.s3.amazonaws.com/a+b/c%20d",
+            &[("x-amz-content-sha256", UNSIGNED_PAYLOAD)],
+            b"",
+        );
+        assert!(s3.ends_with(
+            "SignedHeaders=host;x-amz-content-sha256;x-amz-date, \
+             Signature=61826ced7c341de1b8107d3798e1311d1700787957928ed0f88ca410db0c6b1b"
+        ));
+        let service = authorization(
+            &signer("service"),
+            Method::GET,
+            "https://example.amazonaws.com/a+b/c%20d",
+            &[],
+            b"",
+        );
+        assert!(service.ends_with(
+            "Signature=1e257d60a6277d212113aedce3c36080f67cd2d5536ceefe119343503e87781c"
+        ));
+    }
+}
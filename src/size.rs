@@ -0,0 +1,79 @@
+/// Parses a byte size like `5000000`, `5M` or `8MiB`, case-insensitively
+///
+/// `k`, `m` and `g` (or `kB`, `MB`, `GB`) are powers of 1000, `KiB`, `MiB` and `GiB` powers of 1024.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let invalid = || {
+        format!(
+            "Invalid size '{}', expected e.g. 5000000, 5M or 8MiB",
+            value
+        )
+    };
+    let trimmed = value.trim();
+    let (number, unit) = match trimmed.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => trimmed.split_at(i),
+        None => (trimmed, ""),
+    };
+    let multiplier: u64 = match unit.trim_start().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "m" | "mb" => 1000 * 1000,
+        "g" | "gb" => 1000 * 1000 * 1000,
+        "kib" => 1024,
+        "mib" => 1024 * 1024,
+        "gib" => 1024 * 1024 * 1024,
+        _ => return Err(invalid()),
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_decimal_and_binary_units() {
+        assert_eq!(parse_size("5000000"), Ok(5_000_000));
+        assert_eq!(parse_size("7b"), Ok(7));
+        assert_eq!(parse_size("5k"), Ok(5_000));
+        assert_eq!(parse_size("5kB"), Ok(5_000));
+        assert_eq!(parse_size("5M"), Ok(5_000_000));
+        assert_eq!(parse_size("2GB"), Ok(2_000_000_000));
+        assert_eq!(parse_size("5KiB"), Ok(5 * 1024));
+        assert_eq!(parse_size("8MiB"), Ok(8 * 1024 * 1024));
+        assert_eq!(parse_size("2GiB"), Ok(2 * 1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn ignores_the_case_and_spaces_around() {
+        assert_eq!(parse_size("8mib"), Ok(8 * 1024 * 1024));
+        assert_eq!(parse_size("8MIB"), Ok(8 * 1024 * 1024));
+        assert_eq!(parse_size("5m"), parse_size("5Mb"));
+        assert_eq!(parse_size(" 8 MiB "), Ok(8 * 1024 * 1024));
+    }
+
+    #[test]
+    fn refuses_sizes_past_u64_rather_than_wrapping() {
+        assert_eq!(parse_size("18446744073709551615"), Ok(u64::MAX));
+        assert!(parse_size("18446744073709551616").is_err());
+        assert_eq!(parse_size("17179869183GiB"), Ok(17_179_869_183 << 30));
+        assert!(parse_size("17179869184GiB").is_err());
+        assert!(parse_size("18446744073709552k").is_err());
+    }
+
+    #[test]
+    fn refuses_what_isnt_a_whole_size() {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("0MiB"), Ok(0));
+        for value in [
+            "", " ", "MiB", "1.5M", "-5", "+5", "5x", "5 M B", "5MiBs", "5TiB",
+        ] {
+            let err = parse_size(value).unwrap_err();
+            assert!(err.contains(&format!("'{}'", value)), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::encrypt::EncryptionRecord;
+
+/// What is persisted next to the uploaded file so an interrupted upload can pick up where it left off
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UploadState {
+    pub path: String,
+    pub file_size: u64,
+    pub url: String,
+    pub chunk_size: u64,
+    /// Every byte before this one has been confirmed by the server
+    pub offset: u64,
+    /// Where the server said the upload lives, for protocols that create one first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_url: Option<String>,
+    /// What the last confirmed chunk's response gave the next one to echo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain_token: Option<String>,
+    /// How the chunks sent so far were encrypted, so the rest are encrypted the same way
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<EncryptionRecord>,
+    /// Hex SHA-256 of the plaintext of each segment sealed past the offset, by its start, so a
+    /// segment is only ever sealed again under the same salt with the same bytes
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sealed: BTreeMap<u64, String>,
+}
+
+impl UploadState {
+    /// Reads the state file, treating unreadable or malformed files as absent
+    pub fn load(state_path: &Path) -> Option<Self> {
+        let json = fs::read_to_string(state_path).ok()?;
+        serde_json::from_str(&json).ok()
+    }
+
+    /// Whether this state was recorded for the same file and destination
+    pub fn matches(&self, path: &str, file_size: u64, url: &str) -> bool {
+        self.path == canonical_path(path) && self.file_size == file_size && self.url == url
+    }
+
+    fn save(&self, state_path: &Path) -> io::Result<()> {
+        // Written to the side and renamed over so a crash never leaves half a state file
+        let tmp = state_path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, state_path)
+    }
+}
+
+/// Appended to the upload file's path to name its state file
+const STATE_SUFFIX: &str = ".chunkupload.json";
+
+/// Location of the state file for the given upload file, e.g. `video.mp4.chunkupload.json`
+pub fn state_path(path: &str) -> PathBuf {
+    PathBuf::from(format!("{path}{STATE_SUFFIX}"))
+}
+
+/// Whether the file name is one of our state files or its temporary while being written
+pub fn is_state_file(name: &str) -> bool {
+    name.ends_with(STATE_SUFFIX) || name.ends_with(".chunkupload.json.tmp")
+}
+
+/// The form of the path stored in the state file, so `./a` and `a` are the same upload
+pub fn canonical_path(path: &str) -> String {
+    fs::canonicalize(path)
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| path.to_string())
+}
+
+/// Keeps the state file up to date as chunks complete, possibly out of order
+pub struct StateTracker {
+    /// None keeps the state in memory only, e.g. for stdin which can't be resumed
+    state_path: Option<PathBuf>,
+    inner: Mutex<Tracked>,
+}
+
+struct Tracked {
+    state: UploadState,
+    /// Completed chunks past the contiguous offset, keyed by start with their end as value
+    pending: BTreeMap<u64, u64>,
+}
+
+impl StateTracker {
+    pub fn new(state_path: Option<PathBuf>, state: UploadState) -> Self {
+        StateTracker {
+            state_path,
+            inner: Mutex::new(Tracked {
+                state,
+                pending: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Records the confirmed byte range `start..end`, persisting if the contiguous offset moved
+    pub fn complete(&self, start: u64, end: u64) -> io::Result<()> {
+        self.complete_all(&[(start, end)])
+    }
+
+    /// Records several confirmed byte ranges at once, persisting only once
+    pub fn complete_all(&self, ranges: &[(u64, u64)]) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let Tracked { state, pending } = &mut *inner;
+        pending.extend(ranges.iter().copied());
+
+        let before = state.offset;
+        while let Some(end) = pending.remove(&state.offset) {
+            state.offset = end;
+        }
+        // Confirmed segments aren't sent again
+        let offset = state.offset;
+        state.sealed.retain(|&start, _| start >= offset);
+
+        match self.state_path.as_ref() {
+            Some(state_path) if state.offset != before => state.save(state_path),
+            _ => Ok(()),
+        }
+    }
+
+    /// Persists the state as it is, e.g. when the upload is stopped
+    pub fn save(&self) -> io::Result<()> {
+        match self.state_path.as_ref() {
+            Some(state_path) => self.inner.lock().unwrap().state.save(state_path),
+            None => Ok(()),
+        }
+    }
+
+    /// Replaces the contiguous offset with what the server reports, e.g. before the first chunk
+    pub fn set_offset(&self, offset: u64) {
+        self.inner.lock().unwrap().state.offset = offset;
+    }
+
+    /// The upload URL created by the protocol in an earlier run
+    pub fn upload_url(&self) -> Option<String> {
+        self.inner.lock().unwrap().state.upload_url.clone()
+    }
+
+    /// Records and persists a newly created upload URL
+    pub fn set_upload_url(&self, url: String) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state.upload_url = Some(url);
+        match self.state_path.as_ref() {
+            Some(state_path) => inner.state.save(state_path),
+            None => Ok(()),
+        }
+    }
+
+    /// Records what the next chunk has to echo, persisted with the chunk that gave it
+    pub fn set_chain_token(&self, token: Option<String>) {
+        self.inner.lock().unwrap().state.chain_token = token;
+    }
+
+    /// How an earlier run encrypted the chunks it sent
+    pub fn encryption(&self) -> Option<EncryptionRecord> {
+        self.inner.lock().unwrap().state.encryption.clone()
+    }
+
+    /// Records how the chunks are encrypted, persisted with the first chunk confirmed, a new
+    /// salt forgetting the segments sealed under the one before
+    pub fn set_encryption(&self, record: EncryptionRecord) {
+        let state = &mut self.inner.lock().unwrap().state;
+        if state.encryption.as_ref().map(|r| &r.salt) != Some(&record.salt) {
+            state.sealed.clear();
+        }
+        state.encryption = Some(record);
+    }
+
+    /// The segments sealed past the offset, by their start, with the hex SHA-256 of their bytes
+    pub fn sealed(&self) -> BTreeMap<u64, String> {
+        self.inner.lock().unwrap().state.sealed.clone()
+    }
+
+    /// Records and persists the segment at `start` as sealed from bytes with the hex SHA-256
+    /// `digest`, before its ciphertext is sent
+    pub fn seal(&self, start: u64, digest: String) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state.sealed.insert(start, digest);
+        match self.state_path.as_ref() {
+            Some(state_path) => inner.state.save(state_path),
+            None => Ok(()),
+        }
+    }
+
+    /// The last offset up to which everything is confirmed
+    pub fn offset(&self) -> u64 {
+        self.inner.lock().unwrap().state.offset
+    }
+
+    /// Deletes the state file once the upload no longer needs resuming
+    pub fn remove(&self) -> io::Result<()> {
+        let Some(state_path) = self.state_path.as_ref() else {
+            return Ok(());
+        };
+        match fs::remove_file(state_path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
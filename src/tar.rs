@@ -0,0 +1,539 @@
+//! The tar archive of a directory, written as it's sent for [`crate::Source::Tar`]
+//!
+//! Members are ustar headers, with a pax header before one whose path, link target, size or
+//! owner doesn't fit its fields. They come in the order of their names byte by byte, each
+//! directory before what it holds, with the owners as numbers and no access or change times,
+//! so the same tree always makes the same archive.
+//!
+//! The pax records cover a path past ustar's 155-byte prefix and 100-byte name, a link target
+//! past 100 bytes, a size from 8 GiB and ids past 2097151. Nothing else gets one: times are
+//! whole seconds, 0 before 1970 and capped at 11 octal digits, device numbers are capped at 7,
+//! names are the file system's bytes without a `hdrcharset`, and there are no user or group
+//! names, extended attributes, ACLs or sparse files.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::pipe::{self, Reader};
+use crate::UploadOptions;
+
+const BLOCK: usize = 512;
+/// Largest file content read at once
+const PIECE: usize = 64 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Kind {
+    File,
+    Hardlink,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Dir,
+    Fifo,
+    /// The pax extended header of the member after it
+    Pax,
+}
+
+impl Kind {
+    fn flag(self) -> u8 {
+        match self {
+            Kind::File => b'0',
+            Kind::Hardlink => b'1',
+            Kind::Symlink => b'2',
+            Kind::CharDevice => b'3',
+            Kind::BlockDevice => b'4',
+            Kind::Dir => b'5',
+            Kind::Fifo => b'6',
+            Kind::Pax => b'x',
+        }
+    }
+}
+
+/// One member of the archive, as it was when the walk came by
+struct Member {
+    /// Where it is on disk
+    path: PathBuf,
+    /// Its name in the archive, separated by `/` and ending in one for a directory
+    name: Vec<u8>,
+    kind: Kind,
+    /// Length of a file's content, nothing else has any
+    size: u64,
+    mode: u32,
+    uid: u64,
+    gid: u64,
+    mtime: u64,
+    /// Target of a symlink, or the name a hard link's file was archived under first
+    link: Vec<u8>,
+    /// Major and minor number of a device
+    device: (u64, u64),
+}
+
+impl Member {
+    /// The pax records for what its ustar header can't hold
+    fn pax(&self) -> Vec<u8> {
+        let mut records = Vec::new();
+        if split_name(&self.name).is_none() {
+            record(&mut records, "path", &self.name);
+        }
+        if self.link.len() > 100 {
+            record(&mut records, "linkpath", &self.link);
+        }
+        for (key, value, width) in [
+            ("size", self.size, 12),
+            ("uid", self.uid, 8),
+            ("gid", self.gid, 8),
+        ] {
+            if value > max_octal(width) {
+                record(&mut records, key, value.to_string().as_bytes());
+            }
+        }
+        records
+    }
+
+    /// Its header, after a pax header with its records when it needs them
+    fn header(&self) -> Vec<u8> {
+        let pax = self.pax();
+        let mut header = Vec::new();
+        if !pax.is_empty() {
+            // Named after the member like GNU tar does, which only matters to old readers
+            let tail = &self.name[self.name.len().saturating_sub(89)..];
+            let mut name = b"PaxHeaders/".to_vec();
+            name.extend_from_slice(tail);
+            let extended = Member {
+                path: PathBuf::new(),
+                name,
+                kind: Kind::Pax,
+                size: pax.len() as u64,
+                mode: 0o644,
+                uid: 0,
+                gid: 0,
+                mtime: self.mtime,
+                link: Vec::new(),
+                device: (0, 0),
+            };
+            header.extend_from_slice(&extended.block());
+            header.extend_from_slice(&pax);
+            header.resize(header.len() + padding(pax.len() as u64), 0);
+        }
+        header.extend_from_slice(&self.block());
+        header
+    }
+
+    /// Bytes it takes up in the archive, its headers and its padded content
+    fn len(&self) -> u64 {
+        self.header().len() as u64 + self.size + padding(self.size) as u64
+    }
+
+    /// The ustar header block, what doesn't fit left to the pax records
+    fn block(&self) -> [u8; BLOCK] {
+        let mut block = [0; BLOCK];
+        let (prefix, name) = split_name(&self.name).unwrap_or_else(|| (&[], &self.name[..100]));
+        block[..name.len()].copy_from_slice(name);
+        octal(&mut block[100..108], u64::from(self.mode));
+        octal(&mut block[108..116], self.uid);
+        octal(&mut block[116..124], self.gid);
+        octal(&mut block[124..136], self.size);
+        octal(&mut block[136..148], self.mtime);
+        block[156] = self.kind.flag();
+        let link = &self.link[..self.link.len().min(100)];
+        block[157..157 + link.len()].copy_from_slice(link);
+        block[257..263].copy_from_slice(b"ustar\0");
+        block[263..265].copy_from_slice(b"00");
+        if matches!(self.kind, Kind::CharDevice | Kind::BlockDevice) {
+            octal(&mut block[329..337], self.device.0);
+            octal(&mut block[337..345], self.device.1);
+        }
+        block[345..345 + prefix.len()].copy_from_slice(prefix);
+
+        // Summed with the checksum's own field as spaces
+        block[148..156].fill(b' ');
+        let sum: u32 = block.iter().map(|&b| u32::from(b)).sum();
+        block[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+        block
+    }
+}
+
+/// `name` as the ustar prefix and name fields hold it, split at a `/`, none when it's too long
+fn split_name(name: &[u8]) -> Option<(&[u8], &[u8])> {
+    if name.len() <= 100 {
+        return Some((&[], name));
+    }
+    (0..name.len().min(156))
+        .filter(|&at| name[at] == b'/')
+        .map(|at| (&name[..at], &name[at + 1..]))
+        .find(|(_, rest)| !rest.is_empty() && rest.len() <= 100)
+}
+
+fn max_octal(width: usize) -> u64 {
+    (1 << (3 * (width - 1))) - 1
+}
+
+/// Fills the field with `value` in octal and a NUL, capped at what fits
+fn octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let value = value.min(max_octal(field.len()));
+    field[..digits].copy_from_slice(format!("{:0digits$o}", value).as_bytes());
+    field[digits] = 0;
+}
+
+/// Adds a pax record, which starts with its own length in decimal
+fn record(records: &mut Vec<u8>, key: &str, value: &[u8]) {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    records.extend_from_slice(format!("{} {}=", len, key).as_bytes());
+    records.extend_from_slice(value);
+    records.push(b'\n');
+}
+
+/// Zeros after `len` bytes up to the next block
+fn padding(len: u64) -> usize {
+    (BLOCK - (len % BLOCK as u64) as usize) % BLOCK
+}
+
+#[cfg(unix)]
+fn bytes(name: &OsStr) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    name.as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn bytes(name: &OsStr) -> Vec<u8> {
+    name.to_string_lossy().replace('\\', "/").into_bytes()
+}
+
+/// The members in archive order, each directory read once the walk gets to it
+struct Walk<'a> {
+    /// Paths still to visit with their names, the next one last
+    pending: Vec<(PathBuf, Vec<u8>)>,
+    /// The directory given is followed when it's a symlink, those inside it never are
+    root: bool,
+    /// The name each file with several links was archived under first, by device and inode
+    linked: HashMap<(u64, u64), Vec<u8>>,
+    opts: &'a UploadOptions,
+}
+
+impl Walk<'_> {
+    fn member(
+        &mut self,
+        path: PathBuf,
+        name: Vec<u8>,
+        meta: Metadata,
+    ) -> io::Result<Option<Member>> {
+        let file_type = meta.file_type();
+        let kind = if file_type.is_dir() {
+            Kind::Dir
+        } else if file_type.is_symlink() {
+            Kind::Symlink
+        } else if file_type.is_file() {
+            Kind::File
+        } else {
+            match special(&meta) {
+                Some(kind) => kind,
+                None => {
+                    self.opts.warn(&format!(
+                        "Warning: Leaving '{}' out of the tar stream, tar can't hold its type",
+                        path.display()
+                    ));
+                    return Ok(None);
+                }
+            }
+        };
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|mtime| mtime.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_secs());
+        let (uid, gid, device) = owner(&meta);
+        let mut member = Member {
+            link: Vec::new(),
+            size: 0,
+            kind,
+            mode: mode(&meta),
+            uid,
+            gid,
+            mtime: self
+                .opts
+                .tar_clamp_mtime
+                .map_or(mtime, |clamp| mtime.min(clamp)),
+            device,
+            name,
+            path,
+        };
+        match kind {
+            Kind::Symlink => {
+                let target = fs::read_link(&member.path).map_err(|err| {
+                    io::Error::new(
+                        err.kind(),
+                        format!("Error reading link '{}': {}", member.path.display(), err),
+                    )
+                })?;
+                member.link = bytes(target.as_os_str());
+            }
+            Kind::File => {
+                let first = inode(&meta).and_then(|inode| match self.linked.get(&inode) {
+                    Some(first) => Some(first.clone()),
+                    None => {
+                        self.linked.insert(inode, member.name.clone());
+                        None
+                    }
+                });
+                match first {
+                    Some(first) => {
+                        member.kind = Kind::Hardlink;
+                        member.link = first;
+                    }
+                    None => member.size = meta.len(),
+                }
+            }
+            _ => {}
+        }
+        Ok(Some(member))
+    }
+
+    /// What's in the directory, to visit in the order of the names
+    fn push_children(&mut self, member: &Member) -> io::Result<()> {
+        let failed = |err: io::Error| {
+            io::Error::new(
+                err.kind(),
+                format!(
+                    "Error reading directory '{}': {}",
+                    member.path.display(),
+                    err
+                ),
+            )
+        };
+        let mut children = Vec::new();
+        for entry in fs::read_dir(&member.path).map_err(failed)? {
+            let file_name = entry.map_err(failed)?.file_name();
+            children.push((bytes(&file_name), member.path.join(file_name)));
+        }
+        // Reversed, as the next one is taken from the end
+        children.sort_by(|a, b| b.0.cmp(&a.0));
+        for (file_name, path) in children {
+            let mut name = member.name.clone();
+            name.extend_from_slice(&file_name);
+            self.pending.push((path, name));
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Walk<'_> {
+    type Item = io::Result<Member>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((path, mut name)) = self.pending.pop() {
+            let meta = match std::mem::take(&mut self.root) {
+                true => fs::metadata(&path),
+                false => fs::symlink_metadata(&path),
+            };
+            let meta = match meta {
+                Ok(meta) => meta,
+                Err(err) => {
+                    let message = format!("Error reading '{}': {}", path.display(), err);
+                    return Some(Err(io::Error::new(err.kind(), message)));
+                }
+            };
+            if meta.is_dir() {
+                name.push(b'/');
+            }
+            let member = match self.member(path, name, meta) {
+                Ok(Some(member)) => member,
+                Ok(None) => continue,
+                Err(err) => return Some(Err(err)),
+            };
+            if member.kind == Kind::Dir {
+                if let Err(err) = self.push_children(&member) {
+                    return Some(Err(err));
+                }
+            }
+            return Some(Ok(member));
+        }
+        None
+    }
+}
+
+#[cfg(unix)]
+fn special(meta: &Metadata) -> Option<Kind> {
+    use std::os::unix::fs::FileTypeExt;
+    let file_type = meta.file_type();
+    if file_type.is_fifo() {
+        Some(Kind::Fifo)
+    } else if file_type.is_char_device() {
+        Some(Kind::CharDevice)
+    } else if file_type.is_block_device() {
+        Some(Kind::BlockDevice)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn special(_meta: &Metadata) -> Option<Kind> {
+    None
+}
+
+#[cfg(unix)]
+fn mode(meta: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode(meta: &Metadata) -> u32 {
+    match (meta.is_dir(), meta.permissions().readonly()) {
+        (true, _) => 0o755,
+        (false, true) => 0o444,
+        (false, false) => 0o644,
+    }
+}
+
+/// The owner's user and group ids and the device numbers
+#[cfg(unix)]
+fn owner(meta: &Metadata) -> (u64, u64, (u64, u64)) {
+    use std::os::unix::fs::MetadataExt;
+    let rdev = meta.rdev() as libc::dev_t;
+    let device = (libc::major(rdev) as u64, libc::minor(rdev) as u64);
+    (u64::from(meta.uid()), u64::from(meta.gid()), device)
+}
+
+#[cfg(not(unix))]
+fn owner(_meta: &Metadata) -> (u64, u64, (u64, u64)) {
+    (0, 0, (0, 0))
+}
+
+/// A file with several links by its device and inode, archived once with links to it after
+#[cfg(unix)]
+fn inode(meta: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (meta.nlink() > 1).then(|| (meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn inode(_meta: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// A directory uploaded as a tar archive, see [`crate::ChunkUploaderBuilder::tar_presize`]
+pub(crate) struct Tar {
+    root: PathBuf,
+    /// The members of the walk that told the archive's length, for the stream to write
+    presized: Mutex<Option<Vec<Member>>>,
+}
+
+impl Tar {
+    pub(crate) fn new(root: PathBuf) -> Self {
+        Tar {
+            root,
+            presized: Mutex::new(None),
+        }
+    }
+
+    fn walk<'a>(&self, opts: &'a UploadOptions) -> Walk<'a> {
+        // Its own name, also for `.`, with `.` itself only for the root of the filesystem
+        let name = fs::canonicalize(&self.root)
+            .ok()
+            .and_then(|path| path.file_name().map(bytes))
+            .unwrap_or_else(|| b".".to_vec());
+        Walk {
+            pending: vec![(self.root.clone(), name)],
+            root: true,
+            linked: HashMap::new(),
+            opts,
+        }
+    }
+
+    /// Walks the tree for the exact length of the archive, which then holds what this walk saw
+    pub(crate) fn presize(&self, opts: &UploadOptions) -> io::Result<u64> {
+        let members = self.walk(opts).collect::<io::Result<Vec<_>>>()?;
+        let len = members.iter().map(Member::len).sum::<u64>() + 2 * BLOCK as u64;
+        *self.presized.lock().unwrap() = Some(members);
+        Ok(len)
+    }
+
+    /// The archive, written on a thread of its own as it's read
+    pub(crate) fn stream(self: &Arc<Self>, opts: &UploadOptions) -> Reader {
+        let (tar, opts) = (self.clone(), opts.clone());
+        pipe::spawn("tar writer", move |out| tar.write_to(out, &opts))
+    }
+
+    /// Writes the archive, of the members the presize walk saw if there was one
+    pub(crate) fn write_to(&self, out: &mut dyn Write, opts: &UploadOptions) -> io::Result<()> {
+        let presized = self.presized.lock().unwrap().take();
+        match presized {
+            Some(members) => write(members.into_iter().map(Ok), out, opts),
+            None => write(self.walk(opts), out, opts),
+        }
+    }
+}
+
+fn write(
+    members: impl Iterator<Item = io::Result<Member>>,
+    out: &mut dyn Write,
+    opts: &UploadOptions,
+) -> io::Result<()> {
+    for member in members {
+        let member = member?;
+        out.write_all(&member.header())?;
+        if member.kind == Kind::File {
+            write_content(&member, out, opts)?;
+        }
+    }
+    out.write_all(&[0; 2 * BLOCK])
+}
+
+/// Writes the file's content as long as its header says, as it may have changed since
+fn write_content(member: &Member, out: &mut dyn Write, opts: &UploadOptions) -> io::Result<()> {
+    let display = member.path.display();
+    let failed = |err: io::Error| {
+        io::Error::new(err.kind(), format!("Error reading '{}': {}", display, err))
+    };
+    let mut file = File::open(&member.path).map_err(failed)?;
+    let mut piece = vec![0; PIECE.min(member.size as usize)];
+    let mut left = member.size;
+    while left > 0 {
+        let want = PIECE.min(left as usize);
+        let read = file.read(&mut piece[..want]).map_err(failed)?;
+        if read == 0 {
+            opts.warn(&format!(
+                "Warning: '{}' shrank while it was archived, its last {} bytes are zeros",
+                display, left
+            ));
+            io::copy(
+                &mut io::repeat(0).take(left + padding(member.size) as u64),
+                out,
+            )?;
+            return Ok(());
+        }
+        left -= read as u64;
+        out.write_all(&piece[..read])?;
+    }
+    if file.read(&mut [0]).map_err(failed)? > 0 {
+        opts.warn(&format!(
+            "Warning: '{}' grew while it was archived, only its first {} bytes are in it",
+            display, member.size
+        ));
+    }
+    out.write_all(&vec![0; padding(member.size)])
+}
+
+/// Whether `path` is a directory a tar stream can be made of
+pub(crate) fn check_root(path: &Path) -> Result<(), String> {
+    match fs::metadata(path) {
+        Ok(meta) if meta.is_dir() => Ok(()),
+        Ok(_) => Err(format!("'{}' is not a directory", path.display())),
+        Err(err) => Err(format!(
+            "Error reading directory '{}': {}",
+            path.display(),
+            err
+        )),
+    }
+}
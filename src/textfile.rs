@@ -0,0 +1,146 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use reqwest::Url;
+
+use chunk_uploader::{UploadError, UploadReport};
+
+/// What `--metrics-textfile` says about one file of the run
+pub struct FileMetrics {
+    file: String,
+    /// The host the file was sent to, empty when the URL has none
+    host: String,
+    bytes_sent: u64,
+    chunks: u64,
+    retries: u64,
+    duration: Duration,
+    success: bool,
+}
+
+impl FileMetrics {
+    /// What the upload got done, the part of it a failed one did, `success` unless that or its
+    /// hook failed
+    pub fn of(
+        file: &str,
+        url: &str,
+        result: &Result<UploadReport, UploadError>,
+        duration: Duration,
+        success: bool,
+    ) -> Self {
+        let report = match result {
+            Ok(report) => Some(report),
+            Err(err) => err.report(),
+        };
+        let stats = report.map(UploadReport::stats);
+        FileMetrics {
+            host: host(url),
+            bytes_sent: stats.as_ref().map_or(0, |stats| stats.bytes_sent),
+            chunks: report.map_or(0, |report| report.chunks_succeeded),
+            retries: stats.as_ref().map_or(0, |stats| stats.retries),
+            duration,
+            success,
+            file: file.to_string(),
+        }
+    }
+
+    /// A file the run ended before starting, counted as failed
+    pub fn not_started(file: &str, url: &str) -> Self {
+        FileMetrics {
+            file: file.to_string(),
+            host: host(url),
+            bytes_sent: 0,
+            chunks: 0,
+            retries: 0,
+            duration: Duration::ZERO,
+            success: false,
+        }
+    }
+}
+
+fn host(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// The metrics in the text exposition format, one sample per file under each metric, see
+/// https://prometheus.io/docs/instrumenting/exposition_formats
+fn render(files: &[FileMetrics]) -> String {
+    type Sample = fn(&FileMetrics) -> String;
+    let metrics: [(&str, &str, &str, Sample); 5] = [
+        (
+            "chunk_uploader_bytes_sent_total",
+            "counter",
+            "Bytes sent in the run, retries included",
+            |file| file.bytes_sent.to_string(),
+        ),
+        (
+            "chunk_uploader_chunks_total",
+            "counter",
+            "Chunks the server accepted in the run",
+            |file| file.chunks.to_string(),
+        ),
+        (
+            "chunk_uploader_retries_total",
+            "counter",
+            "Chunk attempts that were retried",
+            |file| file.retries.to_string(),
+        ),
+        (
+            "chunk_uploader_duration_seconds",
+            "gauge",
+            "Seconds the upload took",
+            |file| format!("{:.3}", file.duration.as_secs_f64()),
+        ),
+        (
+            "chunk_uploader_success",
+            "gauge",
+            "1 when the file was uploaded, 0 when it failed or wasn't started",
+            |file| u8::from(file.success).to_string(),
+        ),
+    ];
+    let mut text = String::new();
+    for (name, kind, help, sample) in metrics {
+        let _ = writeln!(text, "# HELP {} {}", name, help);
+        let _ = writeln!(text, "# TYPE {} {}", name, kind);
+        for file in files {
+            let _ = writeln!(
+                text,
+                "{}{{file=\"{}\",host=\"{}\"}} {}",
+                name,
+                escape(&file.file),
+                escape(&file.host),
+                sample(file)
+            );
+        }
+    }
+    text
+}
+
+/// A label value with its backslashes, double quotes and line feeds escaped
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Replaces the file at `path`, written to the side and renamed over so the collector never
+/// reads half of it. The side file doesn't end in `.prom`, which the collector skips.
+pub fn write(path: &Path, files: &[FileMetrics]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = Path::new(&tmp);
+    fs::write(tmp, render(files))?;
+    fs::rename(tmp, path)
+}
@@ -0,0 +1,210 @@
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use reqwest::{Client, Method, Response, StatusCode};
+use sha2::{Digest, Sha256};
+
+use crate::digest::hex;
+use crate::progress::{format_bytes, Progress};
+use crate::{build_request, describe_error, send, unauthorized_message, UploadOptions};
+
+/// How [`crate::ChunkUploaderBuilder::verify`] checks the server stored what was uploaded
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Verify {
+    /// Reads the range back with ranged GETs and compares each block's SHA-256 with the one
+    /// computed while uploading
+    Readback,
+}
+
+impl std::str::FromStr for Verify {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "readback" => Ok(Verify::Readback),
+            _ => Err(format!(
+                "Unknown way to verify the upload '{s}', expected readback"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Verify {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Verify::Readback => "readback",
+        })
+    }
+}
+
+/// Reads the uploaded `range` back from the upload's URL a block at a time, comparing each with
+/// `hashes`, the hex SHA-256 of every `block` bytes as they were uploaded
+///
+/// A server that ignores the Range header and answers 200 has its whole body hashed as it
+/// streams in instead. Every block that differs is named in the error.
+pub(crate) async fn readback(
+    client: &Client,
+    opts: &UploadOptions,
+    range: (u64, u64),
+    block: u64,
+    hashes: &[String],
+) -> Result<(), String> {
+    let (start, end) = range;
+    opts.info(&format!(
+        "Verifying the upload by reading back {} in {} blocks of up to {}",
+        format_bytes(end - start),
+        hashes.len(),
+        format_bytes(block)
+    ));
+    let progress = Progress::new(Some(end - start), Some(hashes.len() as u64), opts);
+    let mut check = Check {
+        opts,
+        range,
+        block,
+        hashes,
+        progress: &progress,
+        mismatched: Vec::new(),
+    };
+    let result = check.run(client).await;
+    progress.finish();
+    result?;
+
+    if check.mismatched.is_empty() {
+        opts.info("Verified: every block read back matches what was uploaded");
+        return Ok(());
+    }
+    Err(format!(
+        "Verification failed: {} of {} blocks read back differ from what was uploaded, bytes {}",
+        check.mismatched.len(),
+        hashes.len(),
+        check
+            .mismatched
+            .iter()
+            .map(|(from, to)| format!("{}-{}", from, to - 1))
+            .collect::<Vec<_>>()
+            .join(", ")
+    ))
+}
+
+struct Check<'a> {
+    opts: &'a UploadOptions,
+    range: (u64, u64),
+    block: u64,
+    hashes: &'a [String],
+    progress: &'a Progress,
+    /// The ranges of the blocks that differ, end exclusive
+    mismatched: Vec<(u64, u64)>,
+}
+
+impl Check<'_> {
+    /// Where block `index` starts and ends, end exclusive
+    fn bounds(&self, index: usize) -> (u64, u64) {
+        let from = self.range.0 + index as u64 * self.block;
+        (from, (from + self.block).min(self.range.1))
+    }
+
+    fn compare(&mut self, index: usize, bytes: &[u8]) {
+        let (from, to) = self.bounds(index);
+        if bytes.len() as u64 != to - from || hex(&Sha256::digest(bytes)) != self.hashes[index] {
+            self.mismatched.push((from, to));
+        }
+        self.progress.chunk_done(index as u64, to - from);
+    }
+
+    async fn run(&mut self, client: &Client) -> Result<(), String> {
+        let failed = |reason: &str| format!("Error verifying the upload: {}", reason);
+        for index in 0..self.hashes.len() {
+            let (from, to) = self.bounds(index);
+            let req = build_request(client, self.opts, Method::GET, &self.opts.url)
+                .header(RANGE, format!("bytes={}-{}", from, to - 1));
+            let res = send(self.opts, req)
+                .await
+                .map_err(|e| failed(&describe_error(self.opts, &e)))?;
+            match res.status() {
+                StatusCode::PARTIAL_CONTENT => {
+                    let answered = res
+                        .headers()
+                        .get(CONTENT_RANGE)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| value.strip_prefix("bytes "))
+                        .and_then(|value| value.split_once('-'))
+                        .and_then(|(first, _)| first.trim().parse::<u64>().ok());
+                    if answered != Some(from) {
+                        return Err(failed(&format!(
+                            "asked for bytes {}-{}, the server answered with Content-Range {}",
+                            from,
+                            to - 1,
+                            answered.map_or("missing".to_string(), |first| format!(
+                                "starting at {}",
+                                first
+                            ))
+                        )));
+                    }
+                    let body = res
+                        .bytes()
+                        .await
+                        .map_err(|e| failed(&describe_error(self.opts, &e)))?;
+                    self.compare(index, &body);
+                }
+                // The whole file came back, ranges or not
+                StatusCode::OK => return self.stream(res, index).await,
+                StatusCode::RANGE_NOT_SATISFIABLE => {
+                    return Err(failed(&format!(
+                        "the server holds none of bytes {}-{} (416 Range Not Satisfiable)",
+                        from,
+                        to - 1
+                    )))
+                }
+                StatusCode::UNAUTHORIZED => return Err(failed(unauthorized_message(self.opts))),
+                status => return Err(failed(&format!("server responded with {}", status))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Hashes the blocks from `first` on out of a body holding the whole file from byte 0
+    async fn stream(&mut self, mut res: Response, first: usize) -> Result<(), String> {
+        self.opts
+            .info("The server doesn't answer ranges, hashing its whole file instead");
+        // Where the next byte of the body is in the server's file
+        let mut offset = 0;
+        let mut buf = Vec::new();
+        let mut index = first;
+        while index < self.hashes.len() {
+            let data = res.chunk().await.map_err(|e| {
+                format!(
+                    "Error verifying the upload: {}",
+                    describe_error(self.opts, &e)
+                )
+            })?;
+            let Some(data) = data else {
+                break;
+            };
+            let mut data = &data[..];
+            while !data.is_empty() && index < self.hashes.len() {
+                let (from, to) = self.bounds(index);
+                // Bytes before the range are skipped, those in it gathered a block at a time
+                let n = match offset < from {
+                    true => data.len().min((from - offset) as usize),
+                    false => {
+                        let n = data.len().min((to - offset) as usize);
+                        buf.extend_from_slice(&data[..n]);
+                        n
+                    }
+                };
+                data = &data[n..];
+                offset += n as u64;
+                if offset == to {
+                    self.compare(index, &buf);
+                    buf.clear();
+                    index += 1;
+                }
+            }
+        }
+        // The server's file ends before the range does, so the blocks left differ
+        while index < self.hashes.len() {
+            self.compare(index, &buf);
+            buf.clear();
+            index += 1;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::state;
+
+/// A file to upload along with the path that goes into the URL
+pub struct Entry {
+    pub path: String,
+    /// Path relative to the walked directory with `/` separators, the file name for single files
+    pub relative: String,
+}
+
+/// What a directory walk turned up, in a stable order so reruns upload the same way
+pub struct Walk {
+    pub files: Vec<Entry>,
+    /// Hidden entries, resume state files, symlinks not followed and anything that isn't a
+    /// regular file or directory
+    pub skipped: usize,
+}
+
+struct Walker {
+    include_hidden: bool,
+    follow_symlinks: bool,
+    /// Canonical directories already walked, so symlink loops end
+    visited: HashSet<PathBuf>,
+    walk: Walk,
+}
+
+/// Finds every regular file under `root`, sorted by path
+pub fn walk(root: &Path, include_hidden: bool, follow_symlinks: bool) -> io::Result<Walk> {
+    let mut walker = Walker {
+        include_hidden,
+        follow_symlinks,
+        visited: HashSet::new(),
+        walk: Walk {
+            files: Vec::new(),
+            skipped: 0,
+        },
+    };
+    walker.visit(root, "")?;
+    Ok(walker.walk)
+}
+
+impl Walker {
+    fn visit(&mut self, dir: &Path, relative: &str) -> io::Result<()> {
+        if !self.visited.insert(fs::canonicalize(dir)?) {
+            self.walk.skipped += 1;
+            return Ok(());
+        }
+
+        let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|e| e.file_name());
+
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if (!self.include_hidden && name.starts_with('.')) || state::is_state_file(&name) {
+                self.walk.skipped += 1;
+                continue;
+            }
+
+            let path = entry.path();
+            let relative = if relative.is_empty() {
+                name
+            } else {
+                format!("{relative}/{name}")
+            };
+
+            let mut file_type = entry.file_type()?;
+            if file_type.is_symlink() {
+                // Dangling links are skipped like any other link that isn't followed
+                match fs::metadata(&path) {
+                    Ok(meta) if self.follow_symlinks => file_type = meta.file_type(),
+                    _ => {
+                        self.walk.skipped += 1;
+                        continue;
+                    }
+                }
+            }
+
+            if file_type.is_dir() {
+                self.visit(&path, &relative)?;
+            } else if file_type.is_file() {
+                self.walk.files.push(Entry {
+                    path: path.to_string_lossy().into_owned(),
+                    relative,
+                });
+            } else {
+                self.walk.skipped += 1;
+            }
+        }
+        Ok(())
+    }
+}
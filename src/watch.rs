@@ -0,0 +1,97 @@
+//! `--watch`, uploading a file again whenever it changed
+//!
+//! The file is polled rather than watched with inotify and its kin: a look every second costs
+//! one `stat`, and polling sees changes the same on network file systems, which don't send
+//! events for other machines' writes, and through a file replaced by a rename, as editors and
+//! exporters save. What it misses is a change undone within one interval and a rewrite to the
+//! same size within the file system's timestamp granularity. A change is noticed up to an
+//! interval late, then waits out the settle time.
+
+use std::fs;
+use std::time::{Duration, Instant, SystemTime};
+
+use chunk_uploader::output::Output;
+use chunk_uploader::{ChunkUploader, Source, UploadError, UploadReport};
+
+use crate::exec::UploadHooks;
+use crate::exit::Exit;
+
+/// How `--watch` notices and waits out a change of the file
+pub struct Watch {
+    /// How often the file's size and modification time are looked at
+    pub poll_interval: Duration,
+    /// How long the file must stay as it is before it's uploaded again
+    pub settle: Duration,
+    /// Stop at the first upload that fails instead of waiting for the next change
+    pub fail_fast: bool,
+    /// Run once each upload ended, a strict one failing fails it
+    pub hooks: UploadHooks,
+    pub out: Output,
+}
+
+/// The file as polled, a different size or modification time means it was written
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Seen {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+/// `None` while the file is missing, e.g. between being deleted and written anew
+fn look(path: &str) -> Option<Seen> {
+    let meta = fs::metadata(path).ok()?;
+    Some(Seen {
+        len: meta.len(),
+        modified: meta.modified().ok(),
+    })
+}
+
+/// Uploads the file, then again after every change settled, until Ctrl-C or with
+/// `fail_fast` a failed upload
+///
+/// `done` gets every upload's result, whether its hook failed it and how long it took. Ctrl-C
+/// while waiting ends with the last upload's exit, during an upload as it does without
+/// watching.
+pub async fn run(
+    uploader: &ChunkUploader,
+    path: &str,
+    url: &str,
+    watch: &Watch,
+    mut done: impl FnMut(&Result<UploadReport, UploadError>, &Result<(), String>, Duration),
+) -> Exit {
+    let interrupter = uploader.interrupter();
+    loop {
+        let uploaded = look(path);
+        let started = Instant::now();
+        let result = uploader.upload(Source::File(path.into()), url).await;
+        let elapsed = started.elapsed();
+        let hooked = watch
+            .hooks
+            .run(&watch.out, path, url, &result, elapsed)
+            .await;
+        done(&result, &hooked, elapsed);
+        let exit = match result.as_ref() {
+            Ok(_) if hooked.is_err() && watch.fail_fast => return Exit::Io,
+            Ok(_) if hooked.is_err() => Exit::Io,
+            Ok(_) => Exit::Success,
+            Err(err @ UploadError::Interrupted { .. }) => return Exit::of(err),
+            Err(err) if watch.fail_fast => return Exit::of(err),
+            Err(err) => Exit::of(err),
+        };
+
+        // The first look that differs starts the wait, every later one that differs again
+        // restarts it
+        let (mut last, mut since) = (uploaded, Instant::now());
+        loop {
+            tokio::select! {
+                _ = interrupter.interrupted() => return exit,
+                _ = tokio::time::sleep(watch.poll_interval) => {}
+            }
+            let now = look(path);
+            if now != last {
+                (last, since) = (now, Instant::now());
+            } else if now.is_some() && now != uploaded && since.elapsed() >= watch.settle {
+                break;
+            }
+        }
+    }
+}
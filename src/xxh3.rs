@@ -0,0 +1,280 @@
+//! XXH3 64-bit with the default secret and seed 0, fed a piece at a time
+//!
+//! Follows the reference implementation's scalar code, see
+//! https://github.com/Cyan4973/xxHash/blob/dev/doc/xxhash_spec.md
+
+const PRIME32_1: u64 = 0x9E37_79B1;
+const PRIME32_2: u64 = 0x85EB_CA77;
+const PRIME32_3: u64 = 0xC2B2_AE3D;
+const PRIME64_1: u64 = 0x9E37_79B1_85EB_CA87;
+const PRIME64_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const PRIME64_3: u64 = 0x1656_67B1_9E37_79F9;
+const PRIME64_4: u64 = 0x85EB_CA77_C2B2_AE63;
+const PRIME64_5: u64 = 0x27D4_EB2F_1656_67C5;
+const PRIME_MX1: u64 = 0x1656_6791_9E37_79F9;
+const PRIME_MX2: u64 = 0x9FB2_1C65_1E98_DF25;
+
+#[rustfmt::skip]
+const SECRET: [u8; 192] = [
+    0xb8, 0xfe, 0x6c, 0x39, 0x23, 0xa4, 0x4b, 0xbe, 0x7c, 0x01, 0x81, 0x2c, 0xf7, 0x21, 0xad, 0x1c,
+    0xde, 0xd4, 0x6d, 0xe9, 0x83, 0x90, 0x97, 0xdb, 0x72, 0x40, 0xa4, 0xa4, 0xb7, 0xb3, 0x67, 0x1f,
+    0xcb, 0x79, 0xe6, 0x4e, 0xcc, 0xc0, 0xe5, 0x78, 0x82, 0x5a, 0xd0, 0x7d, 0xcc, 0xff, 0x72, 0x21,
+    0xb8, 0x08, 0x46, 0x74, 0xf7, 0x43, 0x24, 0x8e, 0xe0, 0x35, 0x90, 0xe6, 0x81, 0x3a, 0x26, 0x4c,
+    0x3c, 0x28, 0x52, 0xbb, 0x91, 0xc3, 0x00, 0xcb, 0x88, 0xd0, 0x65, 0x8b, 0x1b, 0x53, 0x2e, 0xa3,
+    0x71, 0x64, 0x48, 0x97, 0xa2, 0x0d, 0xf9, 0x4e, 0x38, 0x19, 0xef, 0x46, 0xa9, 0xde, 0xac, 0xd8,
+    0xa8, 0xfa, 0x76, 0x3f, 0xe3, 0x9c, 0x34, 0x3f, 0xf9, 0xdc, 0xbb, 0xc7, 0xc7, 0x0b, 0x4f, 0x1d,
+    0x8a, 0x51, 0xe0, 0x4b, 0xcd, 0xb4, 0x59, 0x31, 0xc8, 0x9f, 0x7e, 0xc9, 0xd9, 0x78, 0x73, 0x64,
+    0xea, 0xc5, 0xac, 0x83, 0x34, 0xd3, 0xeb, 0xc3, 0xc5, 0x81, 0xa0, 0xff, 0xfa, 0x13, 0x63, 0xeb,
+    0x17, 0x0d, 0xdd, 0x51, 0xb7, 0xf0, 0xda, 0x49, 0xd3, 0x16, 0x55, 0x26, 0x29, 0xd4, 0x68, 0x9e,
+    0x2b, 0x16, 0xbe, 0x58, 0x7d, 0x47, 0xa1, 0xfc, 0x8f, 0xf8, 0xb8, 0xd1, 0x7a, 0xd0, 0x31, 0xce,
+    0x45, 0xcb, 0x3a, 0x8f, 0x95, 0x16, 0x04, 0x28, 0xaf, 0xd7, 0xfb, 0xca, 0xbb, 0x4b, 0x40, 0x7e,
+];
+
+const STRIPE: usize = 64;
+/// Stripes between scrambles of the accumulators, each taking the secret 8 bytes further
+const STRIPES_PER_BLOCK: usize = (SECRET.len() - STRIPE) / 8;
+/// Longer inputs are hashed a stripe at a time, shorter ones all at once
+const MIDSIZE_MAX: usize = 240;
+
+fn read32(bytes: &[u8]) -> u64 {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap()) as u64
+}
+
+fn read64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+fn fold64(a: u64, b: u64) -> u64 {
+    let product = a as u128 * b as u128;
+    product as u64 ^ (product >> 64) as u64
+}
+
+fn avalanche(mut h: u64) -> u64 {
+    h ^= h >> 37;
+    h = h.wrapping_mul(PRIME_MX1);
+    h ^ (h >> 32)
+}
+
+fn xxh64_avalanche(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(PRIME64_2);
+    h ^= h >> 29;
+    h = h.wrapping_mul(PRIME64_3);
+    h ^ (h >> 32)
+}
+
+fn rrmxmx(mut h: u64, len: u64) -> u64 {
+    h ^= h.rotate_left(49) ^ h.rotate_left(24);
+    h = h.wrapping_mul(PRIME_MX2);
+    h ^= (h >> 35).wrapping_add(len);
+    h = h.wrapping_mul(PRIME_MX2);
+    h ^ (h >> 28)
+}
+
+fn mix16(input: &[u8], secret: &[u8]) -> u64 {
+    fold64(
+        read64(input) ^ read64(secret),
+        read64(&input[8..]) ^ read64(&secret[8..]),
+    )
+}
+
+/// The hash of an input of at most [`MIDSIZE_MAX`] bytes
+fn short(input: &[u8]) -> u64 {
+    let len = input.len();
+    let len64 = len as u64;
+    match len {
+        0 => xxh64_avalanche(read64(&SECRET[56..]) ^ read64(&SECRET[64..])),
+        1..=3 => {
+            let combined = (input[0] as u64) << 16
+                | (input[len >> 1] as u64) << 24
+                | input[len - 1] as u64
+                | len64 << 8;
+            xxh64_avalanche(combined ^ (read32(&SECRET) ^ read32(&SECRET[4..])))
+        }
+        4..=8 => {
+            let both = read32(&input[len - 4..]) + (read32(input) << 32);
+            rrmxmx(both ^ (read64(&SECRET[8..]) ^ read64(&SECRET[16..])), len64)
+        }
+        9..=16 => {
+            let low = read64(input) ^ (read64(&SECRET[24..]) ^ read64(&SECRET[32..]));
+            let high = read64(&input[len - 8..]) ^ (read64(&SECRET[40..]) ^ read64(&SECRET[48..]));
+            avalanche(
+                len64
+                    .wrapping_add(low.swap_bytes())
+                    .wrapping_add(high)
+                    .wrapping_add(fold64(low, high)),
+            )
+        }
+        17..=128 => {
+            let mut acc = len64.wrapping_mul(PRIME64_1);
+            // Pairs of 16 bytes from either end, as many as the length has
+            for i in 0..(len - 1) / 32 + 1 {
+                acc = acc
+                    .wrapping_add(mix16(&input[16 * i..], &SECRET[32 * i..]))
+                    .wrapping_add(mix16(&input[len - 16 * (i + 1)..], &SECRET[32 * i + 16..]));
+            }
+            avalanche(acc)
+        }
+        _ => {
+            let mut acc = len64.wrapping_mul(PRIME64_1);
+            for i in 0..8 {
+                acc = acc.wrapping_add(mix16(&input[16 * i..], &SECRET[16 * i..]));
+            }
+            acc = avalanche(acc);
+            for i in 8..len / 16 {
+                acc = acc.wrapping_add(mix16(&input[16 * i..], &SECRET[16 * (i - 8) + 3..]));
+            }
+            acc = acc.wrapping_add(mix16(&input[len - 16..], &SECRET[136 - 17..]));
+            avalanche(acc)
+        }
+    }
+}
+
+fn accumulate(acc: &mut [u64; 8], stripe: &[u8], secret: &[u8]) {
+    for i in 0..8 {
+        let value = read64(&stripe[8 * i..]);
+        let keyed = value ^ read64(&secret[8 * i..]);
+        acc[i ^ 1] = acc[i ^ 1].wrapping_add(value);
+        acc[i] = acc[i].wrapping_add((keyed & 0xFFFF_FFFF).wrapping_mul(keyed >> 32));
+    }
+}
+
+fn scramble(acc: &mut [u64; 8]) {
+    let secret = &SECRET[SECRET.len() - STRIPE..];
+    for (i, acc) in acc.iter_mut().enumerate() {
+        let mut h = *acc;
+        h ^= h >> 47;
+        h ^= read64(&secret[8 * i..]);
+        *acc = h.wrapping_mul(PRIME32_1);
+    }
+}
+
+/// XXH3 fed with the input in pieces of any size, hashing the same as the input in one piece
+#[derive(Clone)]
+pub(crate) struct Xxh3 {
+    acc: [u64; 8],
+    /// Bytes not yet accumulated, the whole input while it's no longer than [`MIDSIZE_MAX`]
+    pending: Vec<u8>,
+    /// The last stripe's worth of the input, which the end of a long input is hashed from
+    tail: Vec<u8>,
+    /// Stripes accumulated since the last scramble
+    stripes: usize,
+    total: u64,
+}
+
+impl Xxh3 {
+    pub fn new() -> Self {
+        Xxh3 {
+            acc: [
+                PRIME32_3, PRIME64_1, PRIME64_2, PRIME64_3, PRIME64_4, PRIME32_2, PRIME64_5,
+                PRIME32_1,
+            ],
+            pending: Vec::new(),
+            tail: Vec::new(),
+            stripes: 0,
+            total: 0,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.total += data.len() as u64;
+        let keep = data.len().min(STRIPE);
+        let drop = (self.tail.len() + keep).saturating_sub(STRIPE);
+        self.tail.drain(..drop);
+        self.tail.extend_from_slice(&data[data.len() - keep..]);
+
+        self.pending.extend_from_slice(data);
+        if self.total <= MIDSIZE_MAX as u64 {
+            return;
+        }
+        // A stripe is only accumulated with more input after it, the last one is hashed
+        // differently
+        let mut at = 0;
+        while self.pending.len() - at > STRIPE {
+            let secret = &SECRET[8 * self.stripes..];
+            accumulate(&mut self.acc, &self.pending[at..at + STRIPE], secret);
+            at += STRIPE;
+            self.stripes += 1;
+            if self.stripes == STRIPES_PER_BLOCK {
+                scramble(&mut self.acc);
+                self.stripes = 0;
+            }
+        }
+        self.pending.drain(..at);
+    }
+
+    pub fn digest(&self) -> u64 {
+        if self.total <= MIDSIZE_MAX as u64 {
+            return short(&self.pending);
+        }
+        let mut acc = self.acc;
+        accumulate(&mut acc, &self.tail, &SECRET[SECRET.len() - STRIPE - 7..]);
+        let mut h = self.total.wrapping_mul(PRIME64_1);
+        for i in 0..4 {
+            h = h.wrapping_add(fold64(
+                acc[2 * i] ^ read64(&SECRET[11 + 16 * i..]),
+                acc[2 * i + 1] ^ read64(&SECRET[19 + 16 * i..]),
+            ));
+        }
+        avalanche(h)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The sanity buffer of xxHash's own tests, the top byte of a running product
+    fn sanity_buffer(len: usize) -> Vec<u8> {
+        let mut byte_gen: u64 = 2_654_435_761;
+        (0..len)
+            .map(|_| {
+                let byte = (byte_gen >> 56) as u8;
+                byte_gen = byte_gen.wrapping_mul(11_400_714_785_074_694_797);
+                byte
+            })
+            .collect()
+    }
+
+    /// XXH3_64bits with seed 0 from xxHash's sanity checks, one or more in each of its length
+    /// classes
+    const VECTORS: [(usize, u64); 13] = [
+        (0, 0x2D06_8005_38D3_94C2),
+        (1, 0xC44B_DFF4_074E_ECDB),
+        (6, 0x27B5_6A84_CD2D_7325),
+        (12, 0xA713_DAF0_DFBB_77E7),
+        (24, 0xA3FE_70BF_9D35_10EB),
+        (48, 0x397D_A259_ECBA_1F11),
+        (80, 0xBCDE_FBBB_2C47_C90A),
+        (195, 0xCD94_217E_E362_EC3A),
+        (403, 0xCDEB_804D_65C6_DEA4),
+        (512, 0x617E_4959_9013_CB6B),
+        (2048, 0xDD59_E2C3_A5F0_38E0),
+        (2240, 0x6E73_A905_39CF_2948),
+        (2367, 0xCB37_AEB9_E5D3_61ED),
+    ];
+
+    #[test]
+    fn hashes_the_reference_vectors() {
+        let buffer = sanity_buffer(2367);
+        for (len, expected) in VECTORS {
+            let mut xxh3 = Xxh3::new();
+            xxh3.update(&buffer[..len]);
+            assert_eq!(xxh3.digest(), expected, "{len} bytes");
+        }
+    }
+
+    #[test]
+    fn hashes_the_same_fed_in_pieces() {
+        let buffer = sanity_buffer(2367);
+        for (len, expected) in VECTORS {
+            for piece in [1, 3, 63, 64, 65, 240, 241, 1024] {
+                let mut xxh3 = Xxh3::new();
+                for part in buffer[..len].chunks(piece) {
+                    xxh3.update(part);
+                }
+                assert_eq!(xxh3.digest(), expected, "{len} bytes in pieces of {piece}");
+            }
+        }
+    }
+}
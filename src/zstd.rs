@@ -0,0 +1,668 @@
+//! A streaming zstd encoder, the one frame of [`crate::StreamCompression::Zstd`], see RFC 8878
+//!
+//! Matches are found with a hash chain over the window, as deep as the level asks. The literals
+//! are stored as they are and the sequences coded with the format's predefined FSE tables, so
+//! no tables are sent. Any zstd decodes it, though it compresses less than zstd itself, whose
+//! literals are Huffman coded and whose tables are fitted to each block. A block that doesn't
+//! get any smaller is stored raw.
+
+use std::io::{self, Write};
+use std::sync::OnceLock;
+
+const MAGIC: u32 = 0xFD2F_B528;
+/// Largest block, of what it decodes to and of its own bytes
+const BLOCK: usize = 128 * 1024;
+const MIN_MATCH: usize = 4;
+/// Repeat offsets aren't used, so every offset is sent this much larger
+const REPEATS: u32 = 3;
+
+/// Baseline and extra bits of each literals length code
+const LL_CODES: [(u32, u32); 36] = [
+    (0, 0),
+    (1, 0),
+    (2, 0),
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 0),
+    (7, 0),
+    (8, 0),
+    (9, 0),
+    (10, 0),
+    (11, 0),
+    (12, 0),
+    (13, 0),
+    (14, 0),
+    (15, 0),
+    (16, 1),
+    (18, 1),
+    (20, 1),
+    (22, 1),
+    (24, 2),
+    (28, 2),
+    (32, 3),
+    (40, 3),
+    (48, 4),
+    (64, 6),
+    (128, 7),
+    (256, 8),
+    (512, 9),
+    (1024, 10),
+    (2048, 11),
+    (4096, 12),
+    (8192, 13),
+    (16384, 14),
+    (32768, 15),
+    (65536, 16),
+];
+
+/// Baseline and extra bits of each match length code
+const ML_CODES: [(u32, u32); 53] = [
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 0),
+    (7, 0),
+    (8, 0),
+    (9, 0),
+    (10, 0),
+    (11, 0),
+    (12, 0),
+    (13, 0),
+    (14, 0),
+    (15, 0),
+    (16, 0),
+    (17, 0),
+    (18, 0),
+    (19, 0),
+    (20, 0),
+    (21, 0),
+    (22, 0),
+    (23, 0),
+    (24, 0),
+    (25, 0),
+    (26, 0),
+    (27, 0),
+    (28, 0),
+    (29, 0),
+    (30, 0),
+    (31, 0),
+    (32, 0),
+    (33, 0),
+    (34, 0),
+    (35, 1),
+    (37, 1),
+    (39, 1),
+    (41, 1),
+    (43, 2),
+    (47, 2),
+    (51, 3),
+    (59, 3),
+    (67, 4),
+    (83, 4),
+    (99, 5),
+    (131, 7),
+    (259, 8),
+    (515, 9),
+    (1027, 10),
+    (2051, 11),
+    (4099, 12),
+    (8195, 13),
+    (16387, 14),
+    (32771, 15),
+    (65539, 16),
+];
+
+/// The predefined distributions, -1 for symbols less likely than the table's accuracy
+const LL_NORM: [i16; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1,
+    -1, -1, -1, -1,
+];
+const ML_NORM: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+];
+const OF_NORM: [i16; 29] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+];
+
+/// The code of `value` in a table of baselines, the last one not above it
+fn code(codes: &[(u32, u32)], value: u32) -> usize {
+    codes.partition_point(|&(baseline, _)| baseline <= value) - 1
+}
+
+fn highbit(value: u32) -> u32 {
+    31 - value.leading_zeros()
+}
+
+/// Writes bits from the lowest up, read back by the decoder from the last one
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    /// Adds the low `count` bits of `value`, at most 32
+    fn add(&mut self, value: u64, count: u32) {
+        self.bits |= (value & ((1 << count) - 1)) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// The bits with the closing 1 the decoder starts reading after
+    fn close(mut self) -> Vec<u8> {
+        self.add(1, 1);
+        if self.count > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}
+
+/// How each symbol moves an FSE encoder's state
+#[derive(Clone, Copy, Default)]
+struct Transform {
+    delta_find_state: i32,
+    delta_nb_bits: u32,
+}
+
+/// An FSE encoding table, built like the decoder builds its table from the same distribution
+struct Table {
+    log: u32,
+    states: Vec<u16>,
+    symbols: Vec<Transform>,
+}
+
+impl Table {
+    fn new(norm: &[i16], log: u32) -> Self {
+        let size = 1usize << log;
+        let mut spread = vec![0u8; size];
+        let mut high = size - 1;
+        let mut cumul = vec![0usize; norm.len() + 1];
+        for (symbol, &count) in norm.iter().enumerate() {
+            cumul[symbol + 1] = match count {
+                -1 => {
+                    spread[high] = symbol as u8;
+                    high = high.wrapping_sub(1);
+                    cumul[symbol] + 1
+                }
+                count => cumul[symbol] + count as usize,
+            };
+        }
+
+        // The likely symbols go around the table in steps, past the unlikely ones at its end
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mut position = 0;
+        for (symbol, &count) in norm.iter().enumerate() {
+            for _ in 0..count.max(0) {
+                spread[position] = symbol as u8;
+                position = (position + step) & (size - 1);
+                while position > high {
+                    position = (position + step) & (size - 1);
+                }
+            }
+        }
+
+        let mut states = vec![0u16; size];
+        for (at, &symbol) in spread.iter().enumerate() {
+            let next = &mut cumul[symbol as usize];
+            states[*next] = (size + at) as u16;
+            *next += 1;
+        }
+
+        let mut total = 0i32;
+        let symbols = norm
+            .iter()
+            .map(|&count| match count {
+                0 => Transform {
+                    delta_find_state: 0,
+                    delta_nb_bits: ((log + 1) << 16) - size as u32,
+                },
+                count => {
+                    // The unlikely symbols count as one, each taking all of the state's bits
+                    let count = count.max(1);
+                    let max_bits_out = log - highbit((count as u32 - 1).max(1));
+                    let transform = Transform {
+                        delta_find_state: total - i32::from(count),
+                        delta_nb_bits: (max_bits_out << 16) - ((count as u32) << max_bits_out),
+                    };
+                    total += i32::from(count);
+                    transform
+                }
+            })
+            .collect();
+        Table {
+            log,
+            states,
+            symbols,
+        }
+    }
+
+    /// The state the last symbol is encoded in, which the decoder starts from
+    fn start(&self, symbol: usize) -> u32 {
+        let transform = self.symbols[symbol];
+        let bits = (transform.delta_nb_bits + (1 << 15)) >> 16;
+        let value = (bits << 16) - transform.delta_nb_bits;
+        self.next(value >> bits, transform)
+    }
+
+    fn next(&self, value: u32, transform: Transform) -> u32 {
+        u32::from(self.states[(value as i32 + transform.delta_find_state) as usize])
+    }
+
+    /// Writes the bits taking the state from `symbol` back to the one before it
+    fn encode(&self, bits: &mut BitWriter, state: &mut u32, symbol: usize) {
+        let transform = self.symbols[symbol];
+        let count = (*state + transform.delta_nb_bits) >> 16;
+        bits.add(u64::from(*state), count);
+        *state = self.next(*state >> count, transform);
+    }
+
+    fn flush(&self, bits: &mut BitWriter, state: u32) {
+        bits.add(u64::from(state), self.log);
+    }
+}
+
+/// The literals length, match length and offset tables
+fn tables() -> &'static [Table; 3] {
+    static TABLES: OnceLock<[Table; 3]> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        [
+            Table::new(&LL_NORM, 6),
+            Table::new(&ML_NORM, 6),
+            Table::new(&OF_NORM, 5),
+        ]
+    })
+}
+
+/// Literals followed by a match, the format's unit of a compressed block
+struct Sequence {
+    literals: u32,
+    offset: u32,
+    length: u32,
+}
+
+/// How hard a level looks for matches
+struct Params {
+    window_log: u32,
+    hash_log: u32,
+    /// Candidates compared at each position
+    depth: usize,
+}
+
+impl Params {
+    fn of(level: u8) -> Self {
+        let level = u32::from(level.clamp(1, 19));
+        Params {
+            window_log: (18 + level / 3).min(23),
+            hash_log: (15 + level / 2).min(20),
+            depth: 2 << (level / 2),
+        }
+    }
+}
+
+/// The 64-bit xxHash of what was written, zstd's content checksum being its low 32 bits
+struct Xxh64 {
+    acc: [u64; 4],
+    pending: Vec<u8>,
+    total: u64,
+}
+
+const P1: u64 = 0x9E37_79B1_85EB_CA87;
+const P2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const P3: u64 = 0x1656_67B1_9E37_79F9;
+const P4: u64 = 0x85EB_CA77_C2B2_AE63;
+const P5: u64 = 0x27D4_EB2F_1656_67C5;
+
+fn round(acc: u64, lane: u64) -> u64 {
+    acc.wrapping_add(lane.wrapping_mul(P2))
+        .rotate_left(31)
+        .wrapping_mul(P1)
+}
+
+fn lane(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+impl Xxh64 {
+    fn new() -> Self {
+        Xxh64 {
+            acc: [P1.wrapping_add(P2), P2, 0, 0u64.wrapping_sub(P1)],
+            pending: Vec::with_capacity(32),
+            total: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.total += data.len() as u64;
+        if !self.pending.is_empty() {
+            let take = (32 - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.pending.len() < 32 {
+                return;
+            }
+            let stripe = std::mem::take(&mut self.pending);
+            self.stripe(&stripe);
+        }
+        let mut stripes = data.chunks_exact(32);
+        for stripe in stripes.by_ref() {
+            self.stripe(stripe);
+        }
+        self.pending.extend_from_slice(stripes.remainder());
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (acc, lane_bytes) in self.acc.iter_mut().zip(stripe.chunks_exact(8)) {
+            *acc = round(*acc, lane(lane_bytes));
+        }
+    }
+
+    fn digest(&self) -> u64 {
+        let mut hash = match self.total >= 32 {
+            true => {
+                let [a, b, c, d] = self.acc;
+                let mut hash = a
+                    .rotate_left(1)
+                    .wrapping_add(b.rotate_left(7))
+                    .wrapping_add(c.rotate_left(12))
+                    .wrapping_add(d.rotate_left(18));
+                for acc in self.acc {
+                    hash = (hash ^ round(0, acc)).wrapping_mul(P1).wrapping_add(P4);
+                }
+                hash
+            }
+            false => P5,
+        };
+        hash = hash.wrapping_add(self.total);
+        let mut rest = &self.pending[..];
+        while rest.len() >= 8 {
+            hash ^= round(0, lane(rest));
+            hash = hash.rotate_left(27).wrapping_mul(P1).wrapping_add(P4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            let word = u32::from_le_bytes(rest[..4].try_into().unwrap());
+            hash ^= u64::from(word).wrapping_mul(P1);
+            hash = hash.rotate_left(23).wrapping_mul(P2).wrapping_add(P3);
+            rest = &rest[4..];
+        }
+        for &byte in rest {
+            hash ^= u64::from(byte).wrapping_mul(P5);
+            hash = hash.rotate_left(11).wrapping_mul(P1);
+        }
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(P2);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(P3);
+        hash ^ (hash >> 32)
+    }
+}
+
+/// Compresses what's written to it into one zstd frame written to `out`
+///
+/// At most two windows and a block of the data are held, with a hash table and a chain entry
+/// for each byte of the window.
+pub(crate) struct Encoder<W: Write> {
+    out: W,
+    params: Params,
+    window: usize,
+    /// The window before the block being filled and the block itself
+    buf: Vec<u8>,
+    /// Where in `buf` the bytes not yet in a block start
+    pending: usize,
+    /// Positions before this are in the hash chain
+    inserted: usize,
+    /// The latest position plus one of each hash, 0 for none
+    head: Vec<u32>,
+    /// The position plus one before each position with its hash, by position in the window
+    chain: Vec<u32>,
+    checksum: Xxh64,
+    started: bool,
+}
+
+impl<W: Write> Encoder<W> {
+    pub(crate) fn new(out: W, level: u8) -> Self {
+        let params = Params::of(level);
+        let window = 1 << params.window_log;
+        Encoder {
+            out,
+            head: vec![0; 1 << params.hash_log],
+            chain: vec![0; window],
+            window,
+            params,
+            buf: Vec::new(),
+            pending: 0,
+            inserted: 0,
+            checksum: Xxh64::new(),
+            started: false,
+        }
+    }
+
+    /// Ends the frame with the last block and the checksum, handing back what it was written to
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        self.block(self.buf.len(), true)?;
+        let checksum = self.checksum.digest() as u32;
+        self.out.write_all(&checksum.to_le_bytes())?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn header(&mut self) -> io::Result<()> {
+        let mut header = MAGIC.to_le_bytes().to_vec();
+        // No content size or dictionary, a window descriptor and a checksum
+        header.push(0b0000_0100);
+        header.push(((self.params.window_log - 10) << 3) as u8);
+        self.out.write_all(&header)
+    }
+
+    /// Writes the pending bytes up to `end` as a block, then moves the window along
+    fn block(&mut self, end: usize, last: bool) -> io::Result<()> {
+        if !self.started {
+            self.header()?;
+            self.started = true;
+        }
+        let start = self.pending;
+        let compressed = self.compress(start, end);
+        let (kind, body) = match compressed {
+            Some(compressed) if compressed.len() < end - start => (2, compressed),
+            _ => (0, self.buf[start..end].to_vec()),
+        };
+        let header = ((body.len() as u32) << 3) | (kind << 1) | u32::from(last);
+        self.out.write_all(&header.to_le_bytes()[..3])?;
+        self.out.write_all(&body)?;
+        self.pending = end;
+
+        // Half of what's held goes once it's all behind the window
+        if self.pending >= 2 * self.window {
+            let window = self.window;
+            self.buf.drain(..window);
+            self.pending -= window;
+            self.inserted -= window;
+            for position in self.head.iter_mut().chain(self.chain.iter_mut()) {
+                *position = position.saturating_sub(window as u32);
+            }
+        }
+        Ok(())
+    }
+
+    fn hash(&self, at: usize) -> usize {
+        let word = u32::from_le_bytes(self.buf[at..at + 4].try_into().unwrap());
+        (word.wrapping_mul(0x9E37_79B1) >> (32 - self.params.hash_log)) as usize
+    }
+
+    /// Adds the positions before `to` to the chain, those with the four bytes to hash
+    fn insert(&mut self, to: usize) {
+        while self.inserted < to && self.inserted + MIN_MATCH <= self.buf.len() {
+            let hash = self.hash(self.inserted);
+            self.chain[self.inserted & (self.window - 1)] = self.head[hash];
+            self.head[hash] = self.inserted as u32 + 1;
+            self.inserted += 1;
+        }
+        self.inserted = self.inserted.max(to);
+    }
+
+    /// The longest earlier match of the bytes at `at` within the window, not past `end`
+    fn find(&self, at: usize, end: usize) -> Option<(usize, usize)> {
+        let limit = end - at;
+        let mut best = (0, 0);
+        let mut candidate = self.head[self.hash(at)];
+        for _ in 0..self.params.depth {
+            let Some(earlier) = (candidate as usize).checked_sub(1) else {
+                break;
+            };
+            if earlier >= at || at - earlier > self.window {
+                break;
+            }
+            let length = self.buf[earlier..]
+                .iter()
+                .zip(&self.buf[at..end])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best.1 {
+                best = (at - earlier, length);
+                if length == limit {
+                    break;
+                }
+            }
+            candidate = self.chain[earlier & (self.window - 1)];
+        }
+        (best.1 >= MIN_MATCH).then_some(best)
+    }
+
+    /// The block of the bytes from `start` to `end` compressed, none when it's empty
+    fn compress(&mut self, start: usize, end: usize) -> Option<Vec<u8>> {
+        if start == end {
+            return None;
+        }
+        let mut sequences = Vec::new();
+        let mut literals = Vec::new();
+        let (mut at, mut from) = (start, start);
+        while at + MIN_MATCH <= end {
+            self.insert(at);
+            let found = self.find(at, end);
+            self.insert(at + 1);
+            let Some((offset, length)) = found else {
+                at += 1;
+                continue;
+            };
+            literals.extend_from_slice(&self.buf[from..at]);
+            sequences.push(Sequence {
+                literals: (at - from) as u32,
+                offset: offset as u32,
+                length: length as u32,
+            });
+            at += length;
+            from = at;
+        }
+        literals.extend_from_slice(&self.buf[from..end]);
+
+        let mut block = Vec::new();
+        literals_header(&mut block, literals.len());
+        block.extend_from_slice(&literals);
+        sequences_section(&mut block, &sequences);
+        Some(block)
+    }
+
+    /// Takes in `data`, compressing each block as soon as more follows it
+    fn take(&mut self, data: &[u8]) -> io::Result<()> {
+        self.checksum.update(data);
+        self.buf.extend_from_slice(data);
+        while self.buf.len() - self.pending > BLOCK {
+            self.block(self.pending + BLOCK, false)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        // A block at a time, so a large write isn't held whole
+        for piece in data.chunks(BLOCK) {
+            self.take(piece)?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// The header of literals stored as they are
+fn literals_header(block: &mut Vec<u8>, len: usize) {
+    match len {
+        0..=31 => block.push((len << 3) as u8),
+        32..=4095 => {
+            block.extend_from_slice(&[0b0100 | ((len & 0xF) << 4) as u8, (len >> 4) as u8])
+        }
+        _ => block.extend_from_slice(&[
+            0b1100 | ((len & 0xF) << 4) as u8,
+            (len >> 4) as u8,
+            (len >> 12) as u8,
+        ]),
+    }
+}
+
+/// The number of sequences and their codes and extra bits, with every table predefined
+fn sequences_section(block: &mut Vec<u8>, sequences: &[Sequence]) {
+    match sequences.len() {
+        count @ 0..=127 => block.push(count as u8),
+        count @ 128..=0x7EFF => block.extend_from_slice(&[(count >> 8) as u8 + 128, count as u8]),
+        count => {
+            block.push(255);
+            block.extend_from_slice(&((count - 0x7F00) as u16).to_le_bytes());
+        }
+    }
+    if sequences.is_empty() {
+        return;
+    }
+    block.push(0);
+
+    let [ll_table, ml_table, of_table] = tables();
+    // The codes with the value and count of their extra bits
+    let codes: Vec<_> = sequences
+        .iter()
+        .map(|sequence| {
+            let ll = code(&LL_CODES, sequence.literals);
+            let ml = code(&ML_CODES, sequence.length);
+            let offset = sequence.offset + REPEATS;
+            let of = highbit(offset);
+            (
+                (ll, sequence.literals - LL_CODES[ll].0, LL_CODES[ll].1),
+                (ml, sequence.length - ML_CODES[ml].0, ML_CODES[ml].1),
+                (of as usize, offset - (1 << of), of),
+            )
+        })
+        .collect();
+
+    // Written from the last sequence back, as the decoder reads from the end
+    let mut bits = BitWriter::default();
+    let (ll, ml, of) = codes[codes.len() - 1];
+    let (mut ll_state, mut ml_state, mut of_state) = (
+        ll_table.start(ll.0),
+        ml_table.start(ml.0),
+        of_table.start(of.0),
+    );
+    bits.add(u64::from(ll.1), ll.2);
+    bits.add(u64::from(ml.1), ml.2);
+    bits.add(u64::from(of.1), of.2);
+    for &(ll, ml, of) in codes.iter().rev().skip(1) {
+        of_table.encode(&mut bits, &mut of_state, of.0);
+        ml_table.encode(&mut bits, &mut ml_state, ml.0);
+        ll_table.encode(&mut bits, &mut ll_state, ll.0);
+        bits.add(u64::from(ll.1), ll.2);
+        bits.add(u64::from(ml.1), ml.2);
+        bits.add(u64::from(of.1), of.2);
+    }
+    ml_table.flush(&mut bits, ml_state);
+    of_table.flush(&mut bits, of_state);
+    ll_table.flush(&mut bits, ll_state);
+    block.extend_from_slice(&bits.close());
+}
@@ -0,0 +1,290 @@
+// Each test crate uses only part of the helpers
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Lines, Read, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::{fs, thread};
+
+use flate2::read::GzDecoder;
+use native_tls::{Identity, TlsAcceptor};
+
+/// A chunk request as the server received it
+pub struct Received {
+    pub method: String,
+    pub path: String,
+    pub content_range: String,
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    /// Every header with its name in lowercase
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Received {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// An HTTP server on a free local port answering every request with 200 and recording it
+pub struct Server {
+    pub url: String,
+    pub received: Arc<Mutex<Vec<Received>>>,
+    /// Status lines with headers answered before any 200, one per request, which isn't recorded
+    ///
+    /// A reply without a Content-Length gets an empty body.
+    pub replies: Arc<Mutex<VecDeque<String>>>,
+    /// Bodies larger than this are refused with 413 and not recorded
+    pub max_body: Arc<Mutex<Option<usize>>>,
+    /// Headers and a body after an empty line for the 200s to recorded requests, one per request
+    pub responses: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl Server {
+    pub fn start() -> Self {
+        Self::listen("http", None)
+    }
+
+    /// Serves HTTPS with the self-signed `localhost` certificate in `tests/fixtures`
+    pub fn start_tls() -> Self {
+        let identity = Identity::from_pkcs8(
+            include_bytes!("../fixtures/localhost.pem"),
+            include_bytes!("../fixtures/localhost.key"),
+        )
+        .unwrap();
+        Self::listen("https", Some(TlsAcceptor::new(identity).unwrap()))
+    }
+
+    fn listen(scheme: &str, tls: Option<TlsAcceptor>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("{}://{}/upload", scheme, listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let replies = Arc::new(Mutex::new(VecDeque::new()));
+        let max_body = Arc::new(Mutex::new(None));
+        let responses = Arc::new(Mutex::new(VecDeque::new()));
+
+        let (recorded, queued, limit) = (received.clone(), replies.clone(), max_body.clone());
+        let answers = responses.clone();
+        let tls = tls.map(Arc::new);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let (recorded, queued, limit) = (recorded.clone(), queued.clone(), limit.clone());
+                let (answers, tls) = (answers.clone(), tls.clone());
+                thread::spawn(move || {
+                    let stream = stream.unwrap();
+                    match tls {
+                        // A client refusing the certificate ends the connection here
+                        Some(tls) => {
+                            if let Ok(stream) = tls.accept(stream) {
+                                serve(stream, &recorded, &queued, &limit, &answers);
+                            }
+                        }
+                        None => serve(stream, &recorded, &queued, &limit, &answers),
+                    }
+                });
+            }
+        });
+        Server {
+            url,
+            received,
+            replies,
+            max_body,
+            responses,
+        }
+    }
+
+    /// Puts the received chunks together by their Content-Range, checking each matches its body
+    /// once decompressed
+    pub fn assemble(&self) -> Vec<u8> {
+        let received = self.received.lock().unwrap();
+        let mut object = Vec::new();
+        for chunk in received.iter() {
+            let body = match chunk.content_encoding.as_deref() {
+                Some("gzip") => {
+                    let mut body = Vec::new();
+                    GzDecoder::new(&chunk.body[..])
+                        .read_to_end(&mut body)
+                        .unwrap();
+                    body
+                }
+                None => chunk.body.clone(),
+                Some(encoding) => panic!("unexpected Content-Encoding {}", encoding),
+            };
+            let (range, total) = chunk
+                .content_range
+                .strip_prefix("bytes ")
+                .and_then(|r| r.split_once('/'))
+                .expect("Content-Range should be 'bytes first-last/total'");
+            let (first, last) = range.split_once('-').unwrap();
+            let (first, last): (usize, usize) = (first.parse().unwrap(), last.parse().unwrap());
+            assert_eq!(
+                body.len(),
+                last - first + 1,
+                "body length doesn't match Content-Range {}",
+                chunk.content_range
+            );
+
+            // A stream's total is `*` until its last chunk
+            let total = match total {
+                "*" => last + 1,
+                total => total.parse().unwrap(),
+            };
+            object.resize(object.len().max(total), 0);
+            object[first..=last].copy_from_slice(&body);
+        }
+        object
+    }
+}
+
+/// Answers the requests of one keep-alive connection until the client closes it
+fn serve(
+    stream: impl Read + Write,
+    recorded: &Mutex<Vec<Received>>,
+    replies: &Mutex<VecDeque<String>>,
+    max_body: &Mutex<Option<usize>>,
+    responses: &Mutex<VecDeque<String>>,
+) {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut content_length = 0;
+        let mut content_range = String::new();
+        let mut content_type = None;
+        let mut content_encoding = None;
+        let mut headers = Vec::new();
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        let method = line.split(' ').next().unwrap_or_default().to_string();
+        let path = line.split(' ').nth(1).unwrap_or_default().to_string();
+        loop {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            // Anything else, like the HTTP/2 preface, ends the connection
+            let Some((name, value)) = line.split_once(':') else {
+                return;
+            };
+            headers.push((name.to_ascii_lowercase(), value.trim().to_string()));
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.trim().parse().unwrap(),
+                "content-range" => content_range = value.trim().to_string(),
+                "content-type" => content_type = Some(value.trim().to_string()),
+                "content-encoding" => content_encoding = Some(value.trim().to_string()),
+                _ => {}
+            }
+        }
+
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        let too_large = max_body.lock().unwrap().is_some_and(|max| body.len() > max);
+        let reply = match too_large {
+            true => Some("HTTP/1.1 413 Payload Too Large".to_string()),
+            false => replies.lock().unwrap().pop_front(),
+        };
+        if let Some(reply) = reply {
+            // A reply to a HEAD may give the length of what a GET would return
+            let reply = match reply.to_ascii_lowercase().contains("content-length:") {
+                true => format!("{}\r\n\r\n", reply),
+                false => format!("{}\r\nContent-Length: 0\r\n\r\n", reply),
+            };
+            reader.get_mut().write_all(reply.as_bytes()).unwrap();
+            continue;
+        }
+        recorded.lock().unwrap().push(Received {
+            method,
+            path,
+            content_range,
+            content_type,
+            content_encoding,
+            headers,
+            body,
+        });
+        let response = responses.lock().unwrap().pop_front().unwrap_or_default();
+        let (headers, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+        let headers = match headers.is_empty() {
+            true => String::new(),
+            false => format!("{}\r\n", headers),
+        };
+        let response = format!(
+            "HTTP/1.1 200 OK\r\n{}Content-Length: {}\r\n\r\n{}",
+            headers,
+            body.len(),
+            body
+        );
+        reader.get_mut().write_all(response.as_bytes()).unwrap();
+    }
+}
+
+/// Writes `len` bytes without zeros to a file of its own, so padding can't pass for data
+pub fn source_file(name: &str, len: usize) -> (PathBuf, Vec<u8>) {
+    let dir = std::env::temp_dir().join(format!("chunk_uploader_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("source.bin");
+    let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8 + 1).collect();
+    fs::write(&path, &data).unwrap();
+    (path, data)
+}
+
+/// Collects what the uploader writes, to look at once it's done
+#[derive(Clone, Default)]
+pub struct Captured(pub Arc<Mutex<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The `serve` command in a child process, stopped when dropped
+pub struct Serve {
+    pub url: String,
+    child: Child,
+    /// What it printed after the line naming the port
+    pub lines: Lines<BufReader<ChildStdout>>,
+}
+
+impl Serve {
+    /// Starts it on a free port with `args` after `serve`
+    pub fn start(args: &[&str]) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+            .arg("serve")
+            .args(["--port", "0"])
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        let first = lines.next().unwrap().unwrap();
+        let address = first
+            .strip_prefix("Listening on ")
+            .and_then(|rest| rest.split(',').next())
+            .unwrap();
+        Serve {
+            url: address.to_string(),
+            child,
+            lines,
+        }
+    }
+}
+
+impl Drop for Serve {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
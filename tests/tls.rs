@@ -0,0 +1,50 @@
+use std::fs;
+
+use chunk_uploader::{ChunkUploader, ChunkUploaderBuilder, Source, UploadError, Verbosity};
+use common::{source_file, Server};
+use reqwest::Certificate;
+
+mod common;
+
+async fn upload(
+    name: &str,
+    builder: ChunkUploaderBuilder,
+) -> (Server, Result<Vec<u8>, UploadError>) {
+    let server = Server::start_tls();
+    let (path, data) = source_file(name, 12345);
+    let uploader = builder
+        .chunk_size(5000)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    let result = uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await;
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    (server, result.map(|_| data))
+}
+
+#[tokio::test]
+async fn trusts_a_given_ca_certificate() {
+    let certificate = Certificate::from_pem(include_bytes!("fixtures/localhost.pem")).unwrap();
+    let builder = ChunkUploader::builder().root_certificate(certificate);
+    let (server, data) = upload("cacert", builder).await;
+
+    assert_eq!(server.assemble(), data.unwrap());
+}
+
+#[tokio::test]
+async fn rejects_an_unknown_certificate() {
+    let (server, result) = upload("unknown_ca", ChunkUploader::builder()).await;
+
+    assert!(matches!(result, Err(UploadError::Incomplete { .. })));
+    assert!(server.received.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn insecure_accepts_any_certificate() {
+    let builder = ChunkUploader::builder().insecure(true);
+    let (server, data) = upload("insecure", builder).await;
+
+    assert_eq!(server.assemble(), data.unwrap());
+}
@@ -0,0 +1,3647 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use chunk_uploader::filter::{Filter, Glob};
+use chunk_uploader::output::{ColorChoice, Stream, Tone};
+use chunk_uploader::{
+    check_url, decrypt, fill_url, parse_content_type, split_range, AwsCredentials, ByteRange,
+    Checksum, ChunkUploader, Compression, Encryption, EncryptionKey, FailureKind, HttpVersion, Kdf,
+    NotifyOn, OnFailure, Redirects, ResumeMode, RetryJitter, SkipExisting, Source,
+    StreamCompression, UploadError, UploadReport, Verbosity, Verify, DEFAULT_HMAC_PAYLOAD,
+};
+use common::{source_file, Captured, Serve, Server};
+use md5::{Digest, Md5};
+use proptest::prelude::{prop_oneof, proptest, Just, ProptestConfig, Strategy};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Method, StatusCode};
+use sha2::Sha256;
+
+mod common;
+
+async fn upload(path: &Path, url: &str, chunk_size: u64) {
+    let uploader = ChunkUploader::builder()
+        .chunk_size(chunk_size)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    let report = uploader
+        .upload(Source::File(path.to_path_buf()), url)
+        .await
+        .unwrap();
+    assert_eq!(report.chunks_succeeded, report.chunk_count);
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn sends_the_file_byte_for_byte() {
+    let server = Server::start();
+    let (path, data) = source_file("exact", 15000);
+    upload(&path, &server.url, 5000).await;
+
+    assert_eq!(server.received.lock().unwrap().len(), 3);
+    assert_eq!(server.assemble(), data);
+}
+
+#[tokio::test]
+async fn sends_a_short_last_chunk_without_padding() {
+    let server = Server::start();
+    let (path, data) = source_file("short", 12345);
+    upload(&path, &server.url, 5000).await;
+
+    let received = server.received.lock().unwrap();
+    assert_eq!(received.len(), 3);
+    assert_eq!(received[2].content_range, "bytes 10000-12344/12345");
+    assert_eq!(received[2].body.len(), 2345);
+    drop(received);
+    assert_eq!(server.assemble(), data);
+}
+
+#[tokio::test]
+async fn sends_each_chunk_as_a_form_part() {
+    let server = Server::start();
+    let (path, data) = source_file("form", 12345);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5000)
+        .form_field("file")
+        .form("kind", "backup")
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    let received = server.received.lock().unwrap();
+    assert_eq!(received.len(), 3);
+    assert_eq!(received[2].content_range, "bytes 10000-12344/12345");
+    let body = String::from_utf8_lossy(&received[2].body);
+    assert!(body.contains("name=\"kind\"\r\n\r\nbackup\r\n"));
+    assert!(body.contains("name=\"file\"; filename=\"source.bin.2\"\r\n\r\n"));
+    assert!(received[2]
+        .body
+        .windows(2345)
+        .any(|part| part == &data[10000..]));
+}
+
+#[tokio::test]
+async fn sends_the_content_type_with_every_chunk() {
+    let server = Server::start();
+    let (path, _) = source_file("content_type", 12345);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5000)
+        .content_type(parse_content_type("text/csv; charset=utf-8").unwrap())
+        .detect_content_type(true)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    let received = server.received.lock().unwrap();
+    assert_eq!(received.len(), 3);
+    for chunk in received.iter() {
+        assert_eq!(
+            chunk.content_type.as_deref(),
+            Some("text/csv; charset=utf-8")
+        );
+    }
+}
+
+async fn upload_gzipped(path: &Path, url: &str) {
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5000)
+        .compress(Compression::Gzip)
+        .chunk_md5(true)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    uploader
+        .upload(Source::File(path.to_path_buf()), url)
+        .await
+        .unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn gzips_chunks_keeping_the_range_of_the_file() {
+    let server = Server::start();
+    let (path, data) = source_file("gzip", 12345);
+    upload_gzipped(&path, &server.url).await;
+
+    let received = server.received.lock().unwrap();
+    assert_eq!(received[2].content_range, "bytes 10000-12344/12345");
+    assert!(received.iter().all(|chunk| chunk.body.len() < 5000));
+    drop(received);
+    assert_eq!(server.assemble(), data);
+}
+
+#[tokio::test]
+async fn gzips_incompressible_data_intact() {
+    let server = Server::start();
+    let (path, _) = source_file("gzip_random", 0);
+    // xorshift output, which gzip can't shrink
+    let mut state = 0x2545_f491_u32;
+    let data: Vec<u8> = (0..12345)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    fs::write(&path, &data).unwrap();
+    upload_gzipped(&path, &server.url).await;
+
+    assert!(server.received.lock().unwrap()[0].body.len() > 5000);
+    assert_eq!(server.assemble(), data);
+}
+
+#[tokio::test]
+async fn sums_up_the_chunks_in_the_stats() {
+    let server = Server::start();
+    let (path, _) = source_file("stats", 12345);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5000)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    let report = uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    let stats = report.stats();
+    assert_eq!(stats.bytes_sent, 12345);
+    assert_eq!(stats.chunks, 3);
+    assert_eq!(stats.retries, 0);
+    assert!(stats.slowest.is_some_and(|(index, _)| index < 3));
+    assert!(report
+        .chunks
+        .windows(2)
+        .all(|c| c[0].started <= c[1].started));
+}
+
+#[tokio::test]
+async fn counts_only_what_the_server_confirmed_in_the_stats() {
+    let server = Server::start();
+    let (path, _) = source_file("stats_confirmed", 12345);
+    let upload = |retries| {
+        let (path, url) = (path.clone(), server.url.clone());
+        async move {
+            ChunkUploader::builder()
+                .chunk_size(5000)
+                .retries(retries)
+                .retry_delay(Duration::ZERO)
+                .resume(ResumeMode::Off)
+                .verbosity(Verbosity::Quiet)
+                .build()
+                .unwrap()
+                .upload(Source::File(path), &url)
+                .await
+        }
+    };
+
+    // Chunk 1 goes through on its second attempt
+    server.replies.lock().unwrap().extend([
+        "HTTP/1.1 200 OK".to_string(),
+        "HTTP/1.1 500 Internal Server Error".to_string(),
+    ]);
+    let stats = upload(1).await.unwrap().stats();
+    assert_eq!((stats.bytes_sent, stats.bytes_confirmed), (17_345, 12_345));
+    let average = 12_345.0 / stats.transfer_time.as_secs_f64();
+    assert_eq!(stats.average_rate, average);
+    assert!(stats.peak_rate.is_some());
+    let summary = stats.to_string();
+    assert!(summary.starts_with("Sent 12.1 KiB in "), "{summary}");
+    assert!(
+        summary.contains(", 4.9 KiB more in attempts that failed"),
+        "{summary}"
+    );
+
+    // Nothing makes it, so there's no fastest chunk
+    server
+        .replies
+        .lock()
+        .unwrap()
+        .push_back("HTTP/1.1 500 Internal Server Error".to_string());
+    let Err(UploadError::Incomplete { report, .. }) = upload(0).await else {
+        panic!("the failed chunk was taken");
+    };
+    let stats = report.stats();
+    assert_eq!((stats.bytes_sent, stats.bytes_confirmed), (5_000, 0));
+    assert_eq!(stats.average_rate, 0.0);
+    assert_eq!(stats.peak_rate, None);
+    let summary = stats.to_string();
+    assert!(summary.starts_with("Sent 0 B in "), "{summary}");
+    assert!(summary.contains(", 0 B/s on average"), "{summary}");
+    assert!(!summary.contains("peak"), "{summary}");
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn spaces_chunk_launches_by_the_chunk_delay() {
+    let server = Server::start();
+    let (path, _) = source_file("chunk_delay", 12345);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5000)
+        .parallel(3)
+        .chunk_delay(Duration::from_millis(100))
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    let report = uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    let mut started: Vec<_> = report.chunks.iter().map(|c| c.started).collect();
+    started.sort();
+    assert!(started
+        .windows(2)
+        .all(|s| s[1] - s[0] >= Duration::from_millis(95)));
+    assert_eq!(
+        report.chunks.iter().filter(|c| c.delay.is_zero()).count(),
+        1
+    );
+    assert!(report.stats().delay >= Duration::from_millis(150));
+}
+
+async fn upload_rate_limited(name: &str, retry_after: &str) -> UploadReport {
+    let server = Server::start();
+    server.replies.lock().unwrap().push_back(format!(
+        "HTTP/1.1 429 Too Many Requests\r\nRetry-After: {}",
+        retry_after
+    ));
+    let (path, data) = source_file(name, 12345);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5000)
+        .retries(1)
+        .retry_delay(Duration::from_secs(30))
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    let report = uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    assert_eq!(server.assemble(), data);
+    report
+}
+
+#[tokio::test]
+async fn waits_the_seconds_a_retry_after_asks_for() {
+    let report = upload_rate_limited("retry_after", "1").await;
+
+    let stats = report.stats();
+    assert_eq!(stats.rate_limited, 1);
+    assert_eq!(stats.rate_limit_wait, Duration::from_secs(1));
+    assert_eq!(stats.retries, 1);
+}
+
+#[tokio::test]
+async fn retries_right_away_after_a_retry_after_date_passed() {
+    let report = upload_rate_limited("retry_after_date", "Wed, 21 Oct 2015 07:28:00 GMT").await;
+
+    let stats = report.stats();
+    assert_eq!(stats.rate_limited, 1);
+    assert_eq!(stats.rate_limit_wait, Duration::ZERO);
+}
+
+#[tokio::test]
+async fn halves_the_chunk_size_until_the_server_takes_it() {
+    let server = Server::start();
+    *server.max_body.lock().unwrap() = Some(3000);
+    let (path, data) = source_file("adaptive", 12345);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5000)
+        .adaptive_chunk(1000)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    let report = uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    assert_eq!(report.chunk_count, 5);
+    assert_eq!(report.chunks_succeeded, 5);
+    let received = server.received.lock().unwrap();
+    assert_eq!(received[0].content_range, "bytes 0-2499/12345");
+    assert_eq!(received[4].content_range, "bytes 10000-12344/12345");
+    drop(received);
+    assert_eq!(server.assemble(), data);
+}
+
+#[tokio::test]
+async fn names_the_server_limit_when_even_the_minimum_is_too_large() {
+    let server = Server::start();
+    *server.max_body.lock().unwrap() = Some(500);
+    let (path, _) = source_file("adaptive_floor", 12345);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5000)
+        .adaptive_chunk(1000)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    let result = uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await;
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    let Err(UploadError::Incomplete { report, .. }) = result else {
+        panic!("the upload should fail at the minimum chunk size");
+    };
+    let error = report.chunks[0].error.as_deref().unwrap();
+    assert!(
+        error.contains("its limit seems to be below 1000 B"),
+        "{}",
+        error
+    );
+    assert!(server.received.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn stops_during_a_retry_wait_keeping_the_state() {
+    let server = Server::start();
+    for _ in 0..3 {
+        server
+            .replies
+            .lock()
+            .unwrap()
+            .push_back("HTTP/1.1 503 Service Unavailable".into());
+    }
+    let (path, _) = source_file("interrupt", 12345);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5000)
+        .retries(3)
+        .retry_delay(Duration::from_secs(60))
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    let interrupter = uploader.interrupter();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        interrupter.interrupt();
+    });
+    let started = std::time::Instant::now();
+    let result = uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await;
+
+    assert!(started.elapsed() < Duration::from_secs(5));
+    let Err(UploadError::Interrupted { report, .. }) = result else {
+        panic!("the upload should be interrupted");
+    };
+    assert_eq!(report.failed_offset, Some(0));
+    assert!(path.with_extension("bin.chunkupload.json").exists());
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn numbers_the_chunks_from_the_start_when_resuming() {
+    let server = Server::start();
+    let (path, _) = source_file("chunk_headers", 12345);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5000)
+        .chunk_headers(
+            HeaderName::from_static("x-chunk-index"),
+            HeaderName::from_static("x-chunk-count"),
+        )
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    // Chunk 0 makes it but chunk 1 doesn't, leaving a state file to resume from
+    server.replies.lock().unwrap().extend([
+        "HTTP/1.1 200 OK".to_string(),
+        "HTTP/1.1 500 Internal Server Error".to_string(),
+    ]);
+    let first = uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await;
+    assert!(matches!(first, Err(UploadError::Incomplete { .. })));
+
+    let plan = uploader
+        .plan(Source::File(path.clone()), &server.url)
+        .unwrap();
+    assert_eq!(plan.chunks[0].index, 1);
+    assert_eq!(plan.chunks[0].headers[1].1, "3");
+    uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    let received = server.received.lock().unwrap();
+    let numbers: Vec<_> = received
+        .iter()
+        .map(|chunk| {
+            (
+                chunk.header("x-chunk-index").unwrap(),
+                chunk.header("x-chunk-count").unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(numbers, [("1", "3"), ("2", "3")]);
+}
+
+#[tokio::test]
+async fn follows_a_307_and_a_308_resending_the_streamed_chunk() {
+    let server = Server::start();
+    let (path, data) = source_file("redirect", 12345);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5000)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    // Chunk 0 is sent on twice before it's taken, the others go straight through
+    server.replies.lock().unwrap().extend([
+        "HTTP/1.1 307 Temporary Redirect\r\nLocation: /upload/moved".to_string(),
+        "HTTP/1.1 308 Permanent Redirect\r\nLocation: moved/again".to_string(),
+    ]);
+    let report = uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+    assert_eq!(report.chunks_succeeded, 3);
+    server.replies.lock().unwrap().push_back(
+        "HTTP/1.1 307 Temporary Redirect\r\nLocation: ftp://example.com/upload".to_string(),
+    );
+    let failed = uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await;
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    let received = server.received.lock().unwrap();
+    let paths: Vec<_> = received.iter().map(|r| r.path.as_str()).collect();
+    assert_eq!(paths, ["/upload/moved/again", "/upload", "/upload"]);
+    assert!(received.iter().all(|r| r.method == "PUT"));
+    drop(received);
+    assert_eq!(server.assemble(), data);
+    // A 307 keeps the body, it's only not followed to where it can't be sent
+    let Err(UploadError::Incomplete { report, .. }) = failed else {
+        panic!("the redirect to ftp should fail the chunk");
+    };
+    let error = report.chunks[0].error.as_deref().unwrap();
+    assert!(
+        error.contains("which isn't an http or https URL"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn sends_the_chunks_after_a_sticky_redirect_to_its_target() {
+    let server = Server::start();
+    let (path, data) = source_file("sticky", 12345);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5000)
+        .redirects(Redirects::Sticky)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    server
+        .replies
+        .lock()
+        .unwrap()
+        .push_back("HTTP/1.1 307 Temporary Redirect\r\nLocation: /upload/sticky".to_string());
+    let report = uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    assert_eq!(report.chunks_succeeded, 3);
+    let received = server.received.lock().unwrap();
+    assert!(received.iter().all(|r| r.path == "/upload/sticky"));
+    assert_eq!(received.len(), 3);
+    drop(received);
+    assert_eq!(server.assemble(), data);
+}
+
+#[tokio::test]
+async fn finalizes_again_after_a_failed_finalize_request() {
+    let server = Server::start();
+    let (path, _) = source_file("finalize", 12345);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5000)
+        .finalize(Method::POST, format!("{}/commit", server.url))
+        .finalize_body(
+            r#"{"name": {filename}, "size": {filesize}, "chunks": {count}, "sha256": {sha256}}"#,
+        )
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    let mut replies = vec!["HTTP/1.1 200 OK".to_string(); 3];
+    replies.push("HTTP/1.1 409 Conflict".into());
+    server.replies.lock().unwrap().extend(replies);
+    let first = uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await;
+    assert!(matches!(first, Err(UploadError::Finalize { .. })));
+
+    // Every chunk is stored, so only the finalize request is sent again
+    let report = uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    assert_eq!(report.finalize_response.as_deref(), Some(""));
+    let received = server.received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    assert_eq!(
+        received[0].content_type.as_deref(),
+        Some("application/json")
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&received[0].body),
+        r#"{"name": "source.bin", "size": 12345, "chunks": 3, "sha256": null}"#
+    );
+}
+
+#[tokio::test]
+async fn sends_the_chunks_to_the_url_the_init_request_names() {
+    let server = Server::start();
+    let (path, data) = source_file("init", 12345);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5000)
+        .init(Method::POST, format!("{}/sessions", server.url))
+        .init_body(r#"{"name": {filename}, "size": {filesize}}"#)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    server
+        .replies
+        .lock()
+        .unwrap()
+        .push_back("HTTP/1.1 201 Created\r\nLocation: /sessions/42".into());
+    uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    assert_eq!(server.assemble(), data);
+    let received = server.received.lock().unwrap();
+    assert_eq!(received.len(), 3);
+    assert!(received.iter().all(|chunk| chunk.path == "/sessions/42"));
+}
+
+#[tokio::test]
+async fn sends_back_the_cookies_a_response_set_after_resuming() {
+    let server = Server::start();
+    let (path, _) = source_file("cookies", 12345);
+    let jar = path.with_file_name("cookies.json");
+    let builder = || {
+        ChunkUploader::builder()
+            .chunk_size(5000)
+            .cookie("team", "blue")
+            .cookie_jar(&jar)
+            .verbosity(Verbosity::Quiet)
+    };
+    server.replies.lock().unwrap().extend([
+        "HTTP/1.1 200 OK\r\nSet-Cookie: session=abc".to_string(),
+        "HTTP/1.1 401 Unauthorized".into(),
+    ]);
+    let first = builder()
+        .build()
+        .unwrap()
+        .upload(Source::File(path.clone()), &server.url)
+        .await;
+    assert!(first.is_err());
+
+    // A new uploader reads the session from the jar
+    builder()
+        .build()
+        .unwrap()
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    let received = server.received.lock().unwrap();
+    assert_eq!(received.len(), 2);
+    for chunk in received.iter() {
+        let mut cookies: Vec<_> = chunk.header("cookie").unwrap().split("; ").collect();
+        cookies.sort();
+        assert_eq!(cookies, ["session=abc", "team=blue"]);
+    }
+}
+
+#[tokio::test]
+async fn sends_every_chunk_over_http2_when_forced() {
+    use hyper::service::{make_service_fn, service_fn};
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+
+    // The HTTP/1.1 test server can't speak HTTP/2, hyper's can
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let recorded = received.clone();
+    let server = hyper::Server::bind(&"127.0.0.1:0".parse().unwrap())
+        .http2_only(true)
+        .serve(make_service_fn(move |_| {
+            let recorded = recorded.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let recorded = recorded.clone();
+                    async move {
+                        let version = req.version();
+                        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                        recorded.lock().unwrap().push((version, body));
+                        Ok::<_, Infallible>(hyper::Response::new(hyper::Body::empty()))
+                    }
+                }))
+            }
+        }));
+    let url = format!("http://{}/upload", server.local_addr());
+    tokio::spawn(server);
+
+    let (path, data) = source_file("http2", 12345);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5000)
+        .http_version(HttpVersion::Http2)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    uploader
+        .upload(Source::File(path.clone()), &url)
+        .await
+        .unwrap();
+
+    // The HTTP/1.1 server can't take the HTTP/2 preface
+    let http1 = Server::start();
+    let refused = uploader
+        .upload(Source::File(path.clone()), &http1.url)
+        .await;
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    let received = received.lock().unwrap().clone();
+    assert_eq!(received.len(), 3);
+    assert!(received
+        .iter()
+        .all(|(version, _)| *version == hyper::Version::HTTP_2));
+    let sent: Vec<u8> = received
+        .iter()
+        .flat_map(|(_, body)| body.to_vec())
+        .collect();
+    assert_eq!(sent, data);
+    let report = refused.unwrap_err().report().unwrap().clone();
+    let message = report.chunks[0].error.as_deref().unwrap();
+    assert!(message.contains("may not speak HTTP/2"), "{}", message);
+
+    // However its handshake fails, the chunks waiting on the connection, the retries and a
+    // server hanging up after a line of HTTP/1.1 get the hint too
+    let uploader = ChunkUploader::builder()
+        .chunk_size(1000)
+        .parallel(4)
+        .retries(2)
+        .retry_delay(Duration::ZERO)
+        .http_version(HttpVersion::Http2)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    let (path, _) = source_file("http2_parallel", 12345);
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let hangup = format!("http://{}/upload", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let _ = std::io::Read::read(&mut stream, &mut [0; 5]);
+            let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n");
+        }
+    });
+    for url in [&http1.url, &hangup].repeat(20) {
+        let refused = uploader.upload(Source::File(path.clone()), url).await;
+        let report = refused.unwrap_err().report().unwrap().clone();
+        for message in report.chunks.iter().filter_map(|c| c.error.as_deref()) {
+            assert!(message.contains("may not speak HTTP/2"), "{}", message);
+        }
+    }
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn keeps_each_chunks_idempotency_key_when_resuming() {
+    let server = Server::start();
+    let (path, _) = source_file("idempotency", 12345);
+    let upload = |resume| {
+        let path = path.clone();
+        let url = server.url.clone();
+        async move {
+            ChunkUploader::builder()
+                .chunk_size(5000)
+                .idempotency_key_header(HeaderName::from_static("idempotency-key"))
+                .resume(resume)
+                .verbosity(Verbosity::Quiet)
+                .build()
+                .unwrap()
+                .upload(Source::File(path), &url)
+                .await
+        }
+    };
+    server.replies.lock().unwrap().extend([
+        "HTTP/1.1 200 OK".to_string(),
+        "HTTP/1.1 401 Unauthorized".into(),
+    ]);
+    assert!(upload(ResumeMode::Auto).await.is_err());
+    upload(ResumeMode::Auto).await.unwrap();
+    upload(ResumeMode::Off).await.unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    let received = server.received.lock().unwrap();
+    let keys: Vec<_> = received
+        .iter()
+        .map(|chunk| chunk.header("idempotency-key").unwrap())
+        .collect();
+    // Chunks 1 and 2 resumed, then 0, 1 and 2 again from the start
+    assert_eq!(keys.len(), 5);
+    assert_eq!(keys[..2], keys[3..]);
+    assert_ne!(keys[2], keys[3]);
+    assert_ne!(keys[2], keys[4]);
+    assert!(keys
+        .iter()
+        .all(|key| key.len() == 36 && key.as_bytes()[14] == b'5'));
+}
+
+#[tokio::test]
+async fn sends_each_chunk_with_the_checksum_asked_for() {
+    let server = Server::start();
+    let (path, _) = source_file("checksum", 3_000);
+    let upload = |checksum, header: Option<&str>| {
+        let mut builder = ChunkUploader::builder()
+            .chunk_size(1_000)
+            .checksum(checksum)
+            .verbosity(Verbosity::Quiet);
+        if let Some(header) = header {
+            builder = builder.checksum_header(HeaderName::from_bytes(header.as_bytes()).unwrap());
+        }
+        let (path, url) = (path.clone(), server.url.clone());
+        async move {
+            let uploader = builder.build().unwrap();
+            uploader.upload(Source::File(path), &url).await.unwrap()
+        }
+    };
+    let sent = |header: &str| -> Vec<String> {
+        let mut received = server.received.lock().unwrap();
+        received.sort_by_key(|r| r.content_range.clone());
+        let values = received.iter().map(|r| r.header(header).unwrap().into());
+        let values = values.collect();
+        received.clear();
+        values
+    };
+
+    // GCS takes the CRC32C named in x-goog-hash
+    let report = upload(Checksum::Crc32c, None).await;
+    assert_eq!(
+        report.checksum,
+        Some((Checksum::Crc32c, "b3bea259".to_string()))
+    );
+    assert_eq!(report.sha256, None);
+    let expected = ["crc32c=Xp3+Mg==", "crc32c=mpIYJQ==", "crc32c=qKORsg=="];
+    assert_eq!(sent("x-goog-hash"), expected);
+
+    let report = upload(Checksum::Xxh3, Some("x-hash")).await;
+    let digest = report.checksum.unwrap().1;
+    assert_eq!(digest, "fbbbe2f267bab7d8");
+    let expected = ["1OnKXAw6NiM=", "vrZq24B319I=", "e4IhDkbzR0A="];
+    assert_eq!(sent("x-hash"), expected);
+
+    let report = upload(Checksum::Sha256, None).await;
+    assert_eq!(report.sha256, Some(report.checksum.unwrap().1));
+    assert_eq!(sent("x-amz-checksum-sha256").len(), 3);
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    // Only one digest of each kind is sent or computed
+    let conflicting = ChunkUploader::builder()
+        .checksum(Checksum::Sha1)
+        .sha256(true)
+        .build();
+    assert!(matches!(conflicting, Err(UploadError::Invalid(_))));
+    let err = "crc32".parse::<Checksum>().unwrap_err();
+    assert!(err.contains("expected md5, sha1, sha256, crc32c or xxh3"));
+}
+
+#[tokio::test]
+async fn signs_each_chunk_and_takes_the_servers_clock_when_ours_is_off() {
+    let server = Server::start();
+    let (path, _) = source_file("sigv4", 2_000);
+    let upload = |session_token: Option<&str>, sign_payload| {
+        let credentials = AwsCredentials {
+            access_key_id: "AKID".into(),
+            secret_access_key: "secret".into(),
+            session_token: session_token.map(String::from),
+        };
+        let uploader = ChunkUploader::builder()
+            .chunk_size(1_000)
+            .retries(1)
+            .aws_sigv4("us-east-1", "s3", credentials)
+            .aws_sign_payload(sign_payload)
+            .verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        let (path, url) = (path.clone(), server.url.clone());
+        async move { uploader.upload(Source::File(path), &url).await.unwrap() }
+    };
+
+    upload(Some("token"), false).await;
+    for received in server.received.lock().unwrap().drain(..) {
+        let authorization = received.header("authorization").unwrap();
+        let scope = "Credential=AKID/";
+        assert!(authorization.starts_with(&format!("AWS4-HMAC-SHA256 {scope}")));
+        assert!(authorization.contains("/us-east-1/s3/aws4_request, SignedHeaders="));
+        assert!(authorization.contains("x-amz-security-token"));
+        assert_eq!(received.header("x-amz-security-token"), Some("token"));
+        assert_eq!(
+            received.header("x-amz-content-sha256"),
+            Some("UNSIGNED-PAYLOAD")
+        );
+    }
+
+    // The 403 says the server's clock is years ahead, the chunk is signed again with its time
+    let refused = "HTTP/1.1 403 Forbidden\r\nDate: Wed, 01 Jan 2031 00:00:00 GMT";
+    (server.replies.lock().unwrap()).push_back(refused.into());
+    upload(None, true).await;
+    let received = server.received.lock().unwrap();
+    assert_eq!(received.len(), 2);
+    for received in received.iter() {
+        let sha256 = format!("{:x}", Sha256::digest(&received.body));
+        assert_eq!(received.header("x-amz-content-sha256"), Some(&*sha256));
+        assert_eq!(received.header("x-amz-security-token"), None);
+        assert!(received.header("x-amz-date").unwrap().starts_with("2031"));
+    }
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    // Basic auth would be overwritten by the signature
+    let credentials = AwsCredentials {
+        access_key_id: "AKID".into(),
+        secret_access_key: "secret".into(),
+        session_token: None,
+    };
+    let conflicting = ChunkUploader::builder()
+        .basic_auth("user", "password")
+        .aws_sigv4("us-east-1", "s3", credentials)
+        .build();
+    assert!(matches!(conflicting, Err(UploadError::Invalid(_))));
+}
+
+#[tokio::test]
+async fn signs_each_chunk_with_the_hmac_of_its_filled_in_payload() {
+    let server = Server::start();
+    let (path, _) = source_file("hmac", 2_000);
+    let upload = |secret: &str, payload: &str, header: Option<&str>| {
+        let mut builder = ChunkUploader::builder()
+            .chunk_size(1_000)
+            .hmac_signature(secret, payload)
+            .verbosity(Verbosity::Quiet);
+        if let Some(header) = header {
+            builder = builder.hmac_header(HeaderName::from_bytes(header.as_bytes()).unwrap());
+        }
+        let (path, url) = (path.clone(), server.url.clone());
+        async move {
+            let uploader = builder.build().unwrap();
+            uploader.upload(Source::File(path), &url).await.unwrap()
+        }
+    };
+    let signed = |header: &str| -> Vec<String> {
+        let mut received = server.received.lock().unwrap();
+        received.sort_by_key(|r| r.content_range.clone());
+        let signatures = received.iter().map(|r| r.header(header).unwrap().into());
+        let signatures = signatures.collect();
+        received.clear();
+        signatures
+    };
+
+    // RFC 4231 test case 2, nothing filled in
+    upload("Jefe", "what do ya want for nothing?", None).await;
+    let rfc = "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843";
+    assert_eq!(signed("x-signature"), [rfc, rfc]);
+
+    // "PUT\n/upload\n0\n" and the first chunk's SHA-256, then the second's
+    let default = [
+        "d3a2bf154c327d21e1b2f3fcb759a58d41e3a3e3633bd6b90f048b344e08ee7b",
+        "d6c711993c677f2a01509ab4b1fdf21b5f47f3f8f30b0f2b9107e3008d053223",
+    ];
+    upload("key", DEFAULT_HMAC_PAYLOAD, None).await;
+    assert_eq!(signed("x-signature"), default);
+
+    // "0\t0-1000 PUT" and "1\t1000-2000 PUT"
+    upload("key", "{index}\\t{offset}-{end} {method}", Some("x-sig")).await;
+    let expected = [
+        "fa98ab1312055767e6cb18a7bd8fddb373815601a2ebf93169d3a403019aec8c",
+        "17c948241e59164b6c842e4887ad56e06cc8f43785ba69956f0c0c374252ab50",
+    ];
+    assert_eq!(signed("x-sig"), expected);
+
+    // The command line reads the secret from a file, without the newline it ends with
+    let secret = path.with_file_name("secret");
+    fs::write(&secret, "key\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+        .args([
+            "-f",
+            path.to_str().unwrap(),
+            "-u",
+            &server.url,
+            "-c",
+            "1000",
+        ])
+        .args(["--hmac-secret-file", secret.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(signed("x-signature"), default);
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    let unknown = ChunkUploader::builder()
+        .hmac_signature("key", "{method}{size}")
+        .build();
+    let Err(UploadError::Invalid(err)) = unknown else {
+        panic!("an unknown placeholder was taken");
+    };
+    assert!(err.contains("Unknown placeholder '{size}'"));
+}
+
+#[tokio::test]
+async fn streams_each_chunk_with_its_exact_content_length() {
+    let server = Server::start();
+    let (path, data) = source_file("streamed", 300_000);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(200_000)
+        .chunk_md5(true)
+        .sha256(true)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    let report = uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    assert_eq!(server.assemble(), data);
+    assert!(report.sha256.is_some());
+    let received = server.received.lock().unwrap();
+    let lengths: Vec<_> = received
+        .iter()
+        .map(|chunk| chunk.header("content-length").unwrap())
+        .collect();
+    assert_eq!(lengths, ["200000", "100000"]);
+    assert!(received
+        .iter()
+        .all(|chunk| chunk.header("transfer-encoding").is_none()));
+}
+
+#[tokio::test]
+async fn sends_a_range_from_the_memory_map_like_it_reads_it() {
+    let (path, data) = source_file("mmap", 300_000);
+    let mut digests = Vec::new();
+    for mmap in [true, false] {
+        let server = Server::start();
+        let uploader = ChunkUploader::builder()
+            .chunk_size(100_000)
+            .range(1_000, 250_000)
+            .mmap(mmap)
+            .chunk_md5(true)
+            .sha256(true)
+            .parallel(2)
+            .verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        let report = uploader
+            .upload(Source::File(path.clone()), &server.url)
+            .await
+            .unwrap();
+        assert_eq!(server.assemble()[1_000..250_000], data[1_000..250_000]);
+        digests.push(report.sha256.unwrap());
+    }
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    assert_eq!(digests[0], digests[1]);
+}
+
+#[tokio::test]
+async fn reads_the_last_chunk_into_the_reused_buffer_at_its_exact_length() {
+    let server = Server::start();
+    let (path, data) = source_file("reused_buffer", 3 * 4096 + 10);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(4096)
+        .compress(Compression::Gzip)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    // Compressed chunks are read into memory, unlike the streamed ones
+    assert_eq!(server.assemble(), data);
+    let received = server.received.lock().unwrap();
+    let ranges: Vec<_> = received.iter().map(|c| c.content_range.as_str()).collect();
+    assert_eq!(
+        ranges,
+        [
+            "bytes 0-4095/12298",
+            "bytes 4096-8191/12298",
+            "bytes 8192-12287/12298",
+            "bytes 12288-12297/12298",
+        ]
+    );
+}
+
+#[test]
+fn parses_byte_ranges_like_http_ones() {
+    assert_eq!("0-1000".parse(), Ok(ByteRange::Between(0, 1000)));
+    assert_eq!("1000000-".parse(), Ok(ByteRange::From(1_000_000)));
+    assert_eq!("-4096".parse(), Ok(ByteRange::Last(4096)));
+    assert_eq!("5-5".parse(), Ok(ByteRange::Between(5, 5)));
+
+    for (range, error) in [
+        ("-", "expected e.g. 0-1000, 1000- or -4096"),
+        ("1000", "expected e.g. 0-1000, 1000- or -4096"),
+        ("abc-10", "'abc' isn't a number of bytes"),
+        ("10-1k", "'1k' isn't a number of bytes"),
+        ("1-2-3", "'2-3' isn't a number of bytes"),
+        ("20-10", "it starts after it ends"),
+    ] {
+        assert_eq!(
+            range.parse::<ByteRange>(),
+            Err(format!("Invalid byte range '{}', {}", range, error))
+        );
+    }
+}
+
+/// Checks the chunks `split_range` cuts the range into, naming the case when they're off
+fn check_split(range: (u64, u64), chunk_size: u64) {
+    let case = format!("{:?} in chunks of {}", range, chunk_size);
+    let split = split_range(range, chunk_size);
+    let expected = split.len();
+    let chunks: Vec<_> = split.collect();
+    assert_eq!(chunks.len(), expected, "{case}: count");
+    let mut next = range.0;
+    for (i, &(offset, len)) in chunks.iter().enumerate() {
+        assert_eq!(offset, next, "{case}: chunk {i} isn't contiguous");
+        assert!(len > 0, "{case}: chunk {i} is empty");
+        if i + 1 < chunks.len() {
+            assert_eq!(len, chunk_size, "{case}: chunk {i} is short");
+        } else {
+            assert!(len <= chunk_size, "{case}: the last chunk is long");
+        }
+        next = offset + len;
+    }
+    assert_eq!(next.max(range.0), range.1, "{case}: coverage");
+    let total: u64 = chunks.iter().map(|&(_, len)| len).sum();
+    assert_eq!(total, range.1 - range.0, "{case}: sum");
+}
+
+#[test]
+fn splits_any_range_into_contiguous_chunks_covering_it() {
+    for (range, chunk_size) in [
+        ((0, 0), 1),
+        ((7, 7), 5),
+        ((0, 1), 1),
+        ((0, 10), 1),
+        ((0, 10), 3),
+        ((0, 10), 5),
+        ((0, 10), 11),
+        ((3, 4), u64::MAX),
+        ((u64::MAX - 10, u64::MAX), 4),
+        ((0, u64::MAX), u64::MAX / 2),
+    ] {
+        check_split(range, chunk_size);
+    }
+    assert_eq!(split_range((7, 7), 5).count(), 0);
+    assert_eq!(
+        split_range((10, 21), 5).collect::<Vec<_>>(),
+        [(10, 5), (15, 5), (20, 1)]
+    );
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(2_000))]
+
+    /// Random ranges, empty and short ones among them, in chunks of 1 to 3 bytes, longer than
+    /// the range or in between
+    #[test]
+    fn splits_random_ranges_into_contiguous_chunks_covering_them(
+        (start, len, chunk_size) in (0..1u64 << 40, prop_oneof![Just(0u64), 0..16u64, 0..1u64 << 16])
+            .prop_flat_map(|(start, len)| {
+                (Just(start), Just(len), prop_oneof![1..4u64, len + 1..len + 1_000, 1..1u64 << 12])
+            })
+    ) {
+        check_split((start, start + len), chunk_size);
+    }
+}
+
+#[tokio::test]
+async fn uploads_open_ended_and_suffix_ranges_within_the_file() {
+    let (path, data) = source_file("byte_ranges", 10_000);
+    for (range, sent) in [
+        (ByteRange::From(8_000), 8_000..10_000),
+        (ByteRange::Last(3_000), 7_000..10_000),
+        (ByteRange::Last(10_000), 0..10_000),
+    ] {
+        let server = Server::start();
+        let uploader = ChunkUploader::builder()
+            .chunk_size(1_500)
+            .byte_range(range)
+            .verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        let report = uploader
+            .upload(Source::File(path.clone()), &server.url)
+            .await
+            .unwrap();
+        assert_eq!(report.total_bytes, (sent.end - sent.start) as u64);
+        assert_eq!(server.assemble()[sent.clone()], data[sent]);
+    }
+
+    for range in [
+        ByteRange::From(10_001),
+        ByteRange::Last(10_001),
+        ByteRange::Between(0, 10_001),
+    ] {
+        let uploader = ChunkUploader::builder()
+            .byte_range(range)
+            .verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        match uploader
+            .upload(Source::File(path.clone()), "http://127.0.0.1:9/")
+            .await
+        {
+            Err(UploadError::Invalid(err)) => assert_eq!(
+                err,
+                format!(
+                    "Byte range {} is larger than the file's size of 10000",
+                    range
+                )
+            ),
+            other => panic!(
+                "expected the range to be refused, got {:?}",
+                other.map(|_| ())
+            ),
+        }
+    }
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn refuses_empty_ranges_and_clamps_ones_past_the_end_when_asked() {
+    let (path, data) = source_file("range_checks", 10_000);
+    let refused = |builder: chunk_uploader::ChunkUploaderBuilder| {
+        let path = path.clone();
+        async move {
+            let uploader = builder.verbosity(Verbosity::Quiet).build()?;
+            uploader
+                .upload(Source::File(path), "http://127.0.0.1:9/")
+                .await
+                .map(|_| ())
+        }
+    };
+    for (range, error) in [
+        (
+            ByteRange::From(10_000),
+            "Byte range 10000- selects no bytes of the 10000-byte file",
+        ),
+        (
+            ByteRange::Between(5, 5),
+            "Byte range 5-5 selects no bytes of the 10000-byte file",
+        ),
+        (
+            ByteRange::Last(0),
+            "Byte range -0 selects no bytes of the 10000-byte file",
+        ),
+        (
+            ByteRange::Between(9_000, 10_001),
+            "Byte range 9000-10001 is larger than the file's size of 10000",
+        ),
+    ] {
+        match refused(ChunkUploader::builder().byte_range(range)).await {
+            Err(UploadError::Invalid(err)) => assert_eq!(err, error),
+            other => panic!("expected {} to be refused, got {:?}", range, other),
+        }
+    }
+    match refused(ChunkUploader::builder().range(20, 10)).await {
+        Err(UploadError::Invalid(err)) => {
+            assert_eq!(err, "Invalid byte range 20-10, it starts after it ends")
+        }
+        other => panic!("expected 20-10 to be refused, got {:?}", other),
+    }
+    match refused(
+        ChunkUploader::builder()
+            .byte_range(ByteRange::From(10_001))
+            .clamp_range(true),
+    )
+    .await
+    {
+        Err(UploadError::Invalid(err)) => {
+            assert_eq!(
+                err,
+                "Byte range 10001- selects no bytes of the 10000-byte file"
+            )
+        }
+        other => panic!("expected 10001- to be refused, got {:?}", other),
+    }
+
+    let server = Server::start();
+    let uploader = ChunkUploader::builder()
+        .byte_range(ByteRange::Between(9_000, 20_000))
+        .clamp_range(true)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    let report = uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    assert_eq!(report.total_bytes, 1_000);
+    assert_eq!(server.assemble()[9_000..], data[9_000..]);
+}
+
+#[tokio::test]
+async fn skips_a_file_the_server_already_has_by_size_or_hash() {
+    let (path, data) = source_file("skip_existing", 12_345);
+    let md5 = Md5::digest(&data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>();
+    let ok = |headers: &str| format!("HTTP/1.1 200 OK\r\n{}", headers);
+    for (by, head, skipped) in [
+        (SkipExisting::Size, ok("Content-Length: 12345"), true),
+        (SkipExisting::Size, ok("Content-Length: 12344"), false),
+        (
+            SkipExisting::Size,
+            "HTTP/1.1 404 Not Found".to_string(),
+            false,
+        ),
+        (
+            SkipExisting::Size,
+            "HTTP/1.1 405 Method Not Allowed".to_string(),
+            false,
+        ),
+        (
+            SkipExisting::Hash,
+            ok(&format!("Content-Length: 12345\r\nETag: \"{}\"", md5)),
+            true,
+        ),
+        (
+            SkipExisting::Hash,
+            ok("Content-Length: 12345\r\nETag: \"d41d8cd98f00b204e9800998ecf8427e\""),
+            false,
+        ),
+        (SkipExisting::Hash, ok("Content-Length: 12345"), false),
+    ] {
+        let server = Server::start();
+        server.replies.lock().unwrap().push_back(head.clone());
+        let uploader = ChunkUploader::builder()
+            .chunk_size(5000)
+            .skip_existing(by)
+            .verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        let report = uploader
+            .upload(Source::File(path.clone()), &server.url)
+            .await
+            .unwrap();
+
+        assert_eq!(report.skipped_existing, skipped, "{} with {}", by, head);
+        match skipped {
+            true => assert!(server.received.lock().unwrap().is_empty()),
+            false => assert_eq!(server.assemble(), data),
+        }
+    }
+
+    let server = Server::start();
+    (server.replies.lock().unwrap()).push_back("HTTP/1.1 500 Internal Server Error".into());
+    let uploader = ChunkUploader::builder()
+        .skip_existing(SkipExisting::Size)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    match uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+    {
+        Err(UploadError::Failed {
+            kind,
+            status,
+            message,
+        }) => {
+            assert_eq!(kind, FailureKind::Http);
+            assert_eq!(status, Some(StatusCode::INTERNAL_SERVER_ERROR));
+            assert_eq!(
+                message,
+                "Error checking for an existing file: server responded with 500 Internal Server Error"
+            );
+        }
+        other => panic!("expected the check to fail, got {:?}", other),
+    }
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn fails_with_a_precondition_error_when_the_etag_no_longer_matches() {
+    let (path, data) = source_file("if_match", 12_345);
+    let server = Server::start();
+    (server.replies.lock().unwrap()).push_back("HTTP/1.1 412 Precondition Failed".into());
+    let uploader = ChunkUploader::builder()
+        .chunk_size(20_000)
+        .if_match(HeaderValue::from_static("\"v1\""))
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    match uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+    {
+        Err(UploadError::PreconditionFailed { message, .. }) => {
+            assert!(message.contains("If-Match: \"v1\""), "{}", message)
+        }
+        other => panic!("expected the precondition to fail, got {:?}", other),
+    }
+
+    // Without a finalize request every chunk carries the precondition
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5000)
+        .if_none_match(HeaderValue::from_static("*"))
+        .resume(ResumeMode::Off)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+    assert_eq!(server.assemble(), data);
+    {
+        let received = server.received.lock().unwrap();
+        assert!(received
+            .iter()
+            .all(|r| r.header("if-none-match") == Some("*")));
+    }
+
+    // With one only the finalize request does
+    let server = Server::start();
+    let mut replies = vec!["HTTP/1.1 200 OK".to_string(); 3];
+    replies.push("HTTP/1.1 412 Precondition Failed".into());
+    server.replies.lock().unwrap().extend(replies);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5000)
+        .if_match(HeaderValue::from_static("\"v1\""))
+        .finalize(Method::POST, format!("{}/commit", server.url))
+        .resume(ResumeMode::Off)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    let result = uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await;
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    assert!(matches!(
+        result,
+        Err(UploadError::PreconditionFailed { .. })
+    ));
+}
+
+#[tokio::test]
+async fn aborts_a_failed_upload_on_the_server_only_when_asked() {
+    let (path, _) = source_file("on_failure", 12_345);
+    for (on_failure, abort_reply, cleanup) in [
+        (None, None, None),
+        (Some(OnFailure::Keep), None, None),
+        (Some(OnFailure::Abort), None, Some(Ok(()))),
+        (
+            Some(OnFailure::Abort),
+            Some("HTTP/1.1 404 Not Found"),
+            Some(Err(
+                "Error aborting upload: server responded with 404 Not Found".to_string(),
+            )),
+        ),
+    ] {
+        let server = Server::start();
+        let replies = ["HTTP/1.1 400 Bad Request"].into_iter().chain(abort_reply);
+        (server.replies.lock().unwrap()).extend(replies.map(String::from));
+        let mut builder = ChunkUploader::builder()
+            .chunk_size(20_000)
+            .abort_request(Method::DELETE, Some(format!("{}/session", server.url)))
+            .verbosity(Verbosity::Quiet);
+        if let Some(on_failure) = on_failure {
+            builder = builder.on_failure(on_failure);
+        }
+        let report = match builder
+            .build()
+            .unwrap()
+            .upload(Source::File(path.clone()), &server.url)
+            .await
+        {
+            Err(UploadError::Incomplete { report, .. }) => report,
+            other => panic!("expected the upload to fail, got {:?}", other),
+        };
+
+        assert_eq!(report.cleanup, cleanup, "{:?}", on_failure);
+        let received = server.received.lock().unwrap();
+        let aborted: Vec<_> = (received.iter())
+            .map(|r| (r.method.as_str(), r.path.as_str()))
+            .collect();
+        match (on_failure, abort_reply) {
+            (Some(OnFailure::Abort), None) => assert_eq!(aborted, [("DELETE", "/upload/session")]),
+            _ => assert!(aborted.is_empty()),
+        }
+    }
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn echoes_the_value_each_chunk_response_gives_on_the_next_chunk() {
+    let (path, data) = source_file("chain", 12_345);
+    for (from, responses) in [
+        (
+            "header:X-Next-Token",
+            ["X-Next-Token: a", "X-Next-Token: b", ""],
+        ),
+        (
+            "json:$.next.token",
+            [
+                "Content-Type: application/json\r\n\r\n{\"next\": {\"token\": \"a\"}}",
+                "\r\n\r\n{\"next\": {\"token\": \"b\"}}",
+                "\r\n\r\n{}",
+            ],
+        ),
+    ] {
+        let server = Server::start();
+        (server.responses.lock().unwrap()).extend(responses.map(String::from));
+        let uploader = ChunkUploader::builder()
+            .chunk_size(5000)
+            .chain(from.parse().unwrap(), HeaderName::from_static("x-token"))
+            .resume(ResumeMode::Off)
+            .verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        uploader
+            .upload(Source::File(path.clone()), &server.url)
+            .await
+            .unwrap();
+
+        assert_eq!(server.assemble(), data);
+        let tokens: Vec<_> = (server.received.lock().unwrap().iter())
+            .map(|r| r.header("x-token").map(String::from))
+            .collect();
+        assert_eq!(
+            tokens,
+            [None, Some("a".into()), Some("b".into())],
+            "{}",
+            from
+        );
+    }
+
+    // Without the value there's nothing the next chunk would be accepted with
+    let server = Server::start();
+    (server.responses.lock().unwrap()).push_back("X-Other: a".into());
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5000)
+        .chain(
+            "header:X-Next-Token".parse().unwrap(),
+            HeaderName::from_static("x-token"),
+        )
+        .resume(ResumeMode::Off)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    let report = match uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+    {
+        Err(UploadError::Incomplete { report, .. }) => report,
+        other => panic!("expected the upload to fail, got {:?}", other),
+    };
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    assert_eq!(
+        report.chunks[0].error.as_deref(),
+        Some(
+            "Error uploading chunk 0: its response has no x-next-token header to chain into the \
+             next chunk"
+        )
+    );
+    assert_eq!(server.received.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn saves_every_chunk_response_and_the_final_one() {
+    let (path, data) = source_file("save_responses", 12_345);
+    let dir = path.parent().unwrap().join("responses");
+    let last = path.parent().unwrap().join("final.json");
+    // An earlier upload's chunk files go, anything else stays
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("chunk-7.bin"), "stale").unwrap();
+    fs::write(dir.join("notes.txt"), "mine").unwrap();
+
+    let server = Server::start();
+    (server.responses.lock().unwrap()).extend(
+        [
+            "X-Chunk: 0\r\n\r\nfirst",
+            "X-Chunk: 1",
+            "Content-Type: application/json\r\n\r\n{\"id\": 42}",
+        ]
+        .map(String::from),
+    );
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5000)
+        .save_responses(&dir)
+        .save_final_response(&last)
+        .resume(ResumeMode::Off)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+    assert_eq!(server.assemble(), data);
+
+    let mut names: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            "chunk-0.bin",
+            "chunk-0.meta",
+            "chunk-1.bin",
+            "chunk-1.meta",
+            "chunk-2.bin",
+            "chunk-2.meta",
+            "notes.txt"
+        ]
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("chunk-0.bin")).unwrap(),
+        "first"
+    );
+    assert_eq!(fs::read(dir.join("chunk-1.bin")).unwrap(), b"");
+    let meta = fs::read_to_string(dir.join("chunk-1.meta")).unwrap();
+    assert!(meta.starts_with("HTTP/1.1 200 OK\n"), "{}", meta);
+    assert!(meta.contains("x-chunk: 1\n"), "{}", meta);
+    assert_eq!(fs::read_to_string(&last).unwrap(), "{\"id\": 42}");
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn tells_a_server_out_of_reach_from_one_refusing_the_chunks() {
+    let (path, _) = source_file("failure_kind", 12_345);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5_000)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+
+    // Nothing listens on a port just freed
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/upload", closed.local_addr().unwrap());
+    drop(closed);
+    let err = uploader
+        .upload(Source::File(path.clone()), &url)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(FailureKind::Network), "{}", err);
+
+    let server = Server::start();
+    (server.replies.lock().unwrap()).push_back("HTTP/1.1 403 Forbidden".into());
+    let err = uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(FailureKind::Http), "{}", err);
+
+    let err = uploader
+        .upload(Source::File(path.with_extension("missing")), &server.url)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(FailureKind::Io), "{}", err);
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn reports_the_refusing_status_with_the_start_of_the_body() {
+    let (path, _) = source_file("status_snippet", 12_345);
+    let server = Server::start();
+    let page = format!("<html>\n  <body>{}</body>\n</html>", "Error ".repeat(100));
+    // The server ends the reply with an empty line, counted as part of the body
+    (server.replies.lock().unwrap()).push_back(format!(
+        "HTTP/1.1 500 Internal Server Error\r\nContent-Length: {}\r\n\r\n{}",
+        page.len() + 4,
+        page
+    ));
+    let uploader = ChunkUploader::builder()
+        .chunk_size(20_000)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+
+    let err = uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(FailureKind::Http));
+    assert_eq!(err.status(), Some(StatusCode::INTERNAL_SERVER_ERROR));
+    let chunk = &err.report().unwrap().chunks[0];
+    let message = chunk.error.as_deref().unwrap();
+    assert!(
+        message.starts_with(
+            "Http Error uploading chunk 0: server responded with 500 Internal Server Error: \
+             <html> <body>Error Error"
+        ),
+        "{}",
+        message
+    );
+    assert!(message.ends_with("..."), "{}", message);
+    assert!(message.len() < page.len(), "{}", message);
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn writes_a_progress_event_per_chunk_and_retry() {
+    let (path, _) = source_file("events", 12_345);
+    let server = Server::start();
+    (server.replies.lock().unwrap()).push_back("HTTP/1.1 503 Service Unavailable".into());
+    let events = Captured::default();
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5_000)
+        .retries(1)
+        .retry_delay(Duration::from_millis(10))
+        .verbosity(Verbosity::Quiet)
+        .progress_events(events.clone())
+        .build()
+        .unwrap();
+    uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+
+    let events = events.0.lock().unwrap();
+    let events = String::from_utf8_lossy(&events)
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    let names = events
+        .iter()
+        .map(|event| event["event"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        [
+            "upload_started",
+            "retrying",
+            "chunk_done",
+            "chunk_done",
+            "chunk_done",
+            "upload_finished"
+        ]
+    );
+    assert_eq!(events[0]["bytes"], 12_345);
+    assert_eq!(events[0]["chunks"], 3);
+    assert_eq!(events[1]["index"], 0);
+    assert_eq!(events[1]["attempt"], 1);
+    assert_eq!(events[3]["index"], 1);
+    assert_eq!(events[3]["offset"], 5_000);
+    assert_eq!(events[3]["bytes"], 5_000);
+    assert_eq!(events[3]["status"], 200);
+    assert!(events[3]["elapsed_ms"].is_u64());
+    assert_eq!(events[4]["bytes"], 2_345);
+    assert_eq!(events[5]["success"], true);
+    assert_eq!(events[5]["file"], path.to_string_lossy().as_ref());
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn notifies_the_webhook_of_how_the_upload_ended() {
+    let (path, _) = source_file("notify", 12_345);
+    let server = Server::start();
+    let webhook = Server::start();
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5_000)
+        .verbosity(Verbosity::Quiet)
+        .headers(HeaderMap::from_iter([(
+            HeaderName::from_static("x-upload"),
+            HeaderValue::from_static("1"),
+        )]))
+        .notify(&webhook.url)
+        .notify_token("hook-secret")
+        .build()
+        .unwrap();
+    uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+
+    (server.replies.lock().unwrap()).push_back("HTTP/1.1 403 Forbidden".into());
+    uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap_err();
+
+    {
+        let received = webhook.received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].method, "POST");
+        assert_eq!(
+            received[0].header("authorization"),
+            Some("Bearer hook-secret")
+        );
+        assert_eq!(received[0].header("x-upload"), None);
+        let sent = serde_json::from_slice::<serde_json::Value>(&received[0].body).unwrap();
+        assert_eq!(sent["file"], path.to_string_lossy().as_ref());
+        assert_eq!(sent["size"], 12_345);
+        assert_eq!(sent["success"], true);
+        assert!(sent["error"].is_null());
+        let sent = serde_json::from_slice::<serde_json::Value>(&received[1].body).unwrap();
+        assert_eq!(sent["success"], false);
+        assert!(sent["error"].as_str().unwrap().starts_with("Upload failed"));
+        assert_eq!(sent["status"], 403);
+    }
+
+    // Only failures are told about, and a webhook refusing it leaves the result alone
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5_000)
+        .verbosity(Verbosity::Quiet)
+        .notify(&webhook.url)
+        .notify_on(NotifyOn::Failure)
+        .build()
+        .unwrap();
+    (webhook.replies.lock().unwrap()).push_back("HTTP/1.1 500 Internal Server Error".into());
+    uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+    assert_eq!(webhook.received.lock().unwrap().len(), 2);
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn spreads_each_retry_delay_within_its_backoff_as_the_jitter_says() {
+    let (path, _) = source_file("jitter", 1_000);
+    let server = Server::start();
+    for jitter in [RetryJitter::None, RetryJitter::Equal, RetryJitter::Full] {
+        for _ in 0..3 {
+            (server.replies.lock().unwrap()).push_back("HTTP/1.1 503 Service Unavailable".into());
+        }
+        let events = Captured::default();
+        let uploader = ChunkUploader::builder()
+            .retries(3)
+            .retry_delay(Duration::from_millis(40))
+            .retry_jitter(jitter)
+            .verbosity(Verbosity::Quiet)
+            .progress_events(events.clone())
+            .build()
+            .unwrap();
+        uploader
+            .upload(Source::File(path.clone()), &server.url)
+            .await
+            .unwrap();
+
+        let events = events.0.lock().unwrap();
+        let delays = String::from_utf8_lossy(&events)
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .filter(|event| event["event"] == "retrying")
+            .map(|event| event["delay_ms"].as_u64().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(delays.len(), 3);
+        for (delay, backoff) in delays.into_iter().zip([40, 80, 160]) {
+            let least = match jitter {
+                RetryJitter::None => backoff,
+                RetryJitter::Equal => backoff / 2,
+                RetryJitter::Full => 0,
+            };
+            assert!((least..=backoff).contains(&delay), "{jitter}: {delay}ms");
+        }
+    }
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn skips_the_chunks_the_manifest_confirmed_while_the_file_holds_them() {
+    let server = Server::start();
+    let (path, mut data) = source_file("manifest", 12_345);
+    let manifest = path.with_file_name("manifest.json");
+    let upload = || async {
+        let uploader = ChunkUploader::builder()
+            .chunk_size(5_000)
+            .verbosity(Verbosity::Quiet)
+            .manifest(&manifest)
+            .build()
+            .unwrap();
+        uploader
+            .upload(Source::File(path.clone()), &server.url)
+            .await
+    };
+    (server.responses.lock().unwrap()).push_back("ETag: \"c0\"".into());
+    upload().await.unwrap();
+
+    let written = fs::read_to_string(&manifest).unwrap();
+    let written = serde_json::from_str::<serde_json::Value>(&written).unwrap();
+    let entry = &written["files"][0];
+    let canonical = fs::canonicalize(&path).unwrap();
+    assert_eq!(entry["file"], canonical.to_string_lossy().as_ref());
+    assert_eq!(entry["complete"], true);
+    let chunks = entry["chunks"].as_array().unwrap();
+    let offsets: Vec<_> = chunks
+        .iter()
+        .map(|c| c["offset"].as_u64().unwrap())
+        .collect();
+    assert_eq!(offsets, [0, 5_000, 10_000]);
+    assert_eq!(chunks[2]["length"], 2_345);
+    assert_eq!(chunks[0]["etag"], "\"c0\"");
+    assert_eq!(chunks[0]["status"], 200);
+    assert_eq!(chunks[0]["attempts"], 1);
+    assert_eq!(entry["checksum"], "sha256");
+    assert_eq!(chunks[0]["digest"].as_str().unwrap().len(), 64);
+    assert!(chunks.iter().all(|c| c["confirmed"] == true));
+
+    // Nothing is sent again for a file the manifest has complete
+    let report = upload().await.unwrap();
+    assert!(report.skipped_existing);
+    assert_eq!(server.received.lock().unwrap().len(), 3);
+
+    // A changed second chunk is sent again with everything after it
+    data[6_000] ^= 0xff;
+    fs::write(&path, &data).unwrap();
+    let report = upload().await.unwrap();
+    assert_eq!(report.chunks_succeeded, 2);
+    {
+        let received = server.received.lock().unwrap();
+        assert_eq!(received.len(), 5);
+        assert_eq!(received[3].content_range, "bytes 5000-9999/12345");
+    }
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn sends_only_the_chunks_that_changed_since_the_delta_manifest() {
+    let server = Server::start();
+    let (path, mut data) = source_file("delta", 17_345);
+    let manifest = path.with_file_name("manifest.json");
+    let upload = |chunk_size, delta: bool| {
+        let mut builder = ChunkUploader::builder()
+            .chunk_size(chunk_size)
+            .sha256(true)
+            .verbosity(Verbosity::Quiet);
+        builder = match delta {
+            true => builder.delta_from(&manifest),
+            false => builder.manifest(&manifest),
+        };
+        let (path, url) = (path.clone(), server.url.clone());
+        async move { builder.build()?.upload(Source::File(path), &url).await }
+    };
+    upload(5_000, false).await.unwrap();
+
+    // The first chunk changes, the two after it don't and the last is sent regardless
+    data[100] ^= 0xff;
+    fs::write(&path, &data).unwrap();
+    let report = upload(5_000, true).await.unwrap();
+    assert_eq!(report.chunks_succeeded, 2);
+    assert_eq!(
+        (report.unchanged_chunks, report.unchanged_bytes),
+        (2, 10_000)
+    );
+    assert_eq!(report.stats().unchanged_bytes, 10_000);
+    assert_eq!(report.stats().bytes_sent, 7_345);
+    assert_eq!(
+        report.sha256.unwrap(),
+        format!("{:x}", Sha256::digest(&data))
+    );
+    {
+        let received = server.received.lock().unwrap();
+        let ranges: Vec<_> = received[4..]
+            .iter()
+            .map(|r| r.content_range.as_str())
+            .collect();
+        assert_eq!(ranges, ["bytes 0-4999/17345", "bytes 15000-17344/17345"]);
+    }
+    assert_eq!(server.assemble(), data);
+
+    // The manifest now lists what the file holds, so nothing's left to send
+    let written = fs::read_to_string(&manifest).unwrap();
+    let written = serde_json::from_str::<serde_json::Value>(&written).unwrap();
+    let chunks = written["files"][0]["chunks"].as_array().unwrap();
+    let offsets: Vec<_> = chunks
+        .iter()
+        .map(|c| c["offset"].as_u64().unwrap())
+        .collect();
+    assert_eq!(offsets, [0, 5_000, 10_000, 15_000]);
+    assert_eq!(
+        chunks[0]["digest"].as_str().unwrap(),
+        format!("{:x}", Sha256::digest(&data[..5_000]))
+    );
+    assert!(upload(5_000, true).await.unwrap().skipped_existing);
+
+    data[100] ^= 0xff;
+    fs::write(&path, &data).unwrap();
+    let err = upload(4_000, true).await.unwrap_err();
+    assert!(matches!(err, UploadError::Invalid(_)), "{err}");
+    assert!(err.to_string().contains("chunks of"), "{err}");
+    assert_eq!(server.received.lock().unwrap().len(), 6);
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn leaves_out_the_chunks_a_head_request_finds_on_the_server() {
+    let server = Server::start();
+    let (path, data) = source_file("check_chunk", 12_345);
+    let upload = |soft| {
+        let uploader = ChunkUploader::builder()
+            .chunk_size(5_000)
+            .sha256(true)
+            .verbosity(Verbosity::Quiet)
+            .check_chunk_url(format!("{}/part/{{index}}", server.url))
+            .check_chunk_soft(soft)
+            .build()
+            .unwrap();
+        let (path, url) = (path.clone(), server.url.clone());
+        async move { uploader.upload(Source::File(path), &url).await }
+    };
+    // The first chunk is there, the second isn't and only part of the last one is
+    (server.replies.lock().unwrap()).extend([
+        "HTTP/1.1 200 OK\r\nContent-Length: 5000".to_string(),
+        "HTTP/1.1 404 Not Found".to_string(),
+    ]);
+    let report = upload(false).await.unwrap();
+    let existed: Vec<_> = report.chunks.iter().map(|c| c.existed).collect();
+    assert_eq!(existed, [true, false, false]);
+    assert_eq!(report.chunks[0].bytes_sent, 0);
+    assert_eq!(
+        report.sha256.unwrap(),
+        format!("{:x}", Sha256::digest(&data))
+    );
+    {
+        let received = server.received.lock().unwrap();
+        let requests: Vec<_> = (received.iter())
+            .map(|r| format!("{} {} {}", r.method, r.path, r.content_range))
+            .collect();
+        assert_eq!(
+            requests,
+            [
+                "PUT /upload bytes 5000-9999/12345",
+                "HEAD /upload/part/2 ",
+                "PUT /upload bytes 10000-12344/12345"
+            ]
+        );
+    }
+
+    // A check the server can't answer fails the chunk, unless that's soft
+    (server.replies.lock().unwrap()).push_back("HTTP/1.1 503 Service Unavailable".into());
+    let err = upload(false).await.unwrap_err();
+    let error = err.report().unwrap().chunks[0].error.clone().unwrap();
+    assert!(error.contains("checking whether chunk 0 exists"), "{error}");
+    assert_eq!(server.received.lock().unwrap().len(), 3);
+    (server.replies.lock().unwrap()).push_back("HTTP/1.1 503 Service Unavailable".into());
+    let report = upload(true).await.unwrap();
+    assert!(report.chunks.iter().all(|c| !c.existed));
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn appends_a_metrics_row_for_every_chunk_attempt() {
+    let server = Server::start();
+    let (path, _) = source_file("metrics", 12_345);
+    let metrics = path.with_file_name("metrics.csv");
+    let upload = |retries| {
+        let uploader = ChunkUploader::builder()
+            .chunk_size(5_000)
+            .retries(retries)
+            .retry_delay(Duration::from_millis(10))
+            .verbosity(Verbosity::Quiet)
+            .metrics_csv(&metrics)
+            .build()
+            .unwrap();
+        let (path, url) = (path.clone(), server.url.clone());
+        async move { uploader.upload(Source::File(path), &url).await }
+    };
+    (server.replies.lock().unwrap()).push_back("HTTP/1.1 503 Service Unavailable".into());
+    upload(1).await.unwrap();
+    // A second run appends to the file without another header
+    (server.replies.lock().unwrap()).push_back("HTTP/1.1 500 Internal Server Error".into());
+    upload(0).await.unwrap_err();
+
+    let written = fs::read_to_string(&metrics).unwrap();
+    let rows = written
+        .lines()
+        .map(|line| line.split(',').collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        rows[0],
+        [
+            "timestamp",
+            "file",
+            "index",
+            "offset",
+            "bytes",
+            "attempt",
+            "status",
+            "result",
+            "ttfb_ms",
+            "duration_ms"
+        ]
+    );
+    let columns = |row: &[&str]| row[2..8].join(",");
+    let attempts = rows[1..].iter().map(|row| columns(row)).collect::<Vec<_>>();
+    assert_eq!(
+        attempts,
+        [
+            "0,0,5000,1,503,retried",
+            "0,0,5000,2,200,ok",
+            "1,5000,5000,1,200,ok",
+            "2,10000,2345,1,200,ok",
+            "0,0,5000,1,500,failed"
+        ]
+    );
+    for row in rows[1..].iter() {
+        assert!(row[0].ends_with('Z'));
+        assert_eq!(row[1], path.to_string_lossy());
+        let ttfb = row[8].parse::<u64>().unwrap();
+        assert!(ttfb <= row[9].parse::<u64>().unwrap());
+    }
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn sends_the_chunk_size_a_copy_of_the_uploader_was_given() {
+    let server = Server::start();
+    let (path, data) = source_file("with_chunk_size", 12_345);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5_000)
+        .adaptive_chunk(1_000)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    let report = (uploader.with_chunk_size(4_000).unwrap())
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap();
+    assert_eq!(report.chunk_count, 4);
+    assert_eq!(server.assemble(), data);
+    assert!(matches!(
+        uploader.with_chunk_size(1_000),
+        Err(UploadError::Invalid(_))
+    ));
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn reassembles_the_file_in_serve_through_its_injected_failures() {
+    let (path, data) = source_file("serve", 12_345);
+    let dir = path.with_file_name("received");
+    let mut serve = Serve::start(&["--dir", dir.to_str().unwrap(), "--fail-every", "2", "-q"]);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(1_000)
+        .retries(1)
+        .retry_delay(Duration::ZERO)
+        .sha256(true)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    let report = uploader
+        .upload(
+            Source::File(path.clone()),
+            &format!("{}/a/b.bin", serve.url),
+        )
+        .await
+        .unwrap();
+    // Every other request is refused, the first chunk's went through
+    assert_eq!(report.stats().retries, 12);
+
+    let line = serve.lines.next().unwrap().unwrap();
+    let sha256 = report.sha256.unwrap();
+    assert!(line.ends_with(&format!("complete, 12345 bytes, SHA-256: {sha256}")));
+    assert_eq!(fs::read(dir.join("a").join("b.bin")).unwrap(), data);
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn chunks_files_of_sizes_around_the_chunk_size() {
+    for (len, ranges) in [
+        (0, vec!["bytes */0"]),
+        (1, vec!["bytes 0-0/1"]),
+        (999, vec!["bytes 0-998/999"]),
+        (1_000, vec!["bytes 0-999/1000"]),
+        (1_001, vec!["bytes 0-999/1001", "bytes 1000-1000/1001"]),
+    ] {
+        let server = Server::start();
+        let (path, data) = source_file(&format!("sizes_{len}"), len);
+        upload(&path, &server.url, 1_000).await;
+
+        let sent: Vec<_> = (server.received.lock().unwrap())
+            .iter()
+            .map(|chunk| chunk.content_range.clone())
+            .collect();
+        assert_eq!(sent, ranges, "{len} bytes");
+        match len {
+            0 => assert!(server.received.lock().unwrap()[0].body.is_empty()),
+            _ => assert_eq!(server.assemble(), data, "{len} bytes"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn stops_at_a_refused_chunk_without_sending_the_rest() {
+    let server = Server::start();
+    let (path, _) = source_file("refused", 12_345);
+    server.replies.lock().unwrap().extend([
+        "HTTP/1.1 200 OK".to_string(),
+        "HTTP/1.1 403 Forbidden".to_string(),
+    ]);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(5_000)
+        .retries(3)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    let err = uploader
+        .upload(Source::File(path.clone()), &server.url)
+        .await
+        .unwrap_err();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    // A 4xx isn't worth another attempt, nor are the chunks after it
+    assert!(matches!(err, UploadError::Incomplete { .. }));
+    assert_eq!(err.status(), Some(StatusCode::FORBIDDEN));
+    let report = err.report().unwrap();
+    assert_eq!(report.failed_offset, Some(5_000));
+    assert_eq!(report.chunks.len(), 2);
+    assert_eq!(report.chunks[1].retries, 0);
+    assert!(server.received.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn uploads_the_files_a_nul_separated_list_names() {
+    let server = Server::start();
+    let (path, data) = source_file("files_from", 3_000);
+    let odd = path.with_file_name("two\nlines.bin");
+    fs::write(&odd, b"odd").unwrap();
+    let mut list = Vec::new();
+    for listed in [&path, &odd] {
+        list.extend_from_slice(listed.to_str().unwrap().as_bytes());
+        list.push(0);
+    }
+    let url = format!("{}/{{filename}}", server.url);
+    let upload = |args: &[&str]| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+            .args(["--files-from", "-", "-u", &url, "-q"])
+            .args(args)
+            .stdin(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(&list).unwrap();
+        child.wait().unwrap()
+    };
+    assert!(upload(&["-0"]).success());
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    let received = server.received.lock().unwrap();
+    let paths: Vec<_> = received.iter().map(|r| r.path.as_str()).collect();
+    assert_eq!(paths, ["/upload/source.bin", "/upload/two%0Alines.bin"]);
+    assert_eq!(received[0].body, data);
+    drop(received);
+
+    // Every listed file is missing now, which stops the run before anything is sent
+    assert_eq!(upload(&["-0"]).code(), Some(3));
+    assert_eq!(server.received.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn uploads_several_files_at_once_telling_their_lines_apart() {
+    let server = Server::start();
+    let (path, data) = source_file("jobs", 3_000);
+    let dir = path.parent().unwrap();
+    for name in ["b.bin", "c.bin"] {
+        fs::write(dir.join(name), &data).unwrap();
+    }
+    // Whichever file sends the first request fails with it, the others upload
+    (server.replies.lock().unwrap()).push_back("HTTP/1.1 403 Forbidden".into());
+    let url = format!("{}/{{filename}}", server.url);
+    let output = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+        .arg("--dir")
+        .arg(dir)
+        .args(["-u", &url, "-c", "1000", "--jobs", "3", "--parallel", "2"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(5));
+    let received = server.received.lock().unwrap();
+    // The failing file's other chunk in flight may still have been stored
+    assert!((6..=7).contains(&received.len()));
+    assert!(received.iter().all(|r| r.body == data[..1_000]
+        || r.body == data[1_000..2_000]
+        || r.body == data[2_000..]));
+    drop(received);
+
+    let out = String::from_utf8(output.stdout).unwrap();
+    let err = String::from_utf8(output.stderr).unwrap();
+    let lines = out.lines().chain(err.lines());
+    let results: Vec<_> = lines
+        .filter(|line| line.contains("Request completed") || line.contains("Http Error"))
+        .collect();
+    assert_eq!(results.len(), 3);
+    for name in ["b.bin", "c.bin", "source.bin"] {
+        let prefix = format!("{}: ", dir.join(name).display());
+        assert_eq!(results.iter().filter(|l| l.starts_with(&prefix)).count(), 1);
+    }
+    assert!(out.contains("Uploaded 2 of 3 files, 1 failed (1 http), 0 skipped"));
+
+    // More requests in flight than allowed are refused before anything is sent
+    let refused = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+        .arg("--dir")
+        .arg(dir)
+        .args(["-u", &url, "--jobs", "9", "--parallel", "8"])
+        .output()
+        .unwrap();
+    assert_eq!(refused.status.code(), Some(2));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn goes_on_after_a_failed_file_only_when_asked_and_logs_the_ones_left() {
+    let server = Server::start();
+    let (path, data) = source_file("continue", 3_000);
+    let dir = path.parent().unwrap();
+    for name in ["a.bin", "b.bin"] {
+        fs::write(dir.join(name), &data).unwrap();
+    }
+    let log = dir.with_extension("log");
+    let url = format!("{}/{{filename}}", server.url);
+    let upload = |reply: Option<&str>, args: &[&str]| {
+        if let Some(reply) = reply {
+            (server.replies.lock().unwrap()).push_back(reply.into());
+        }
+        let output = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+            .args(["-u", &url, "--error-log", log.to_str().unwrap()])
+            .args(args)
+            .output()
+            .unwrap();
+        let logged = fs::read_to_string(&log).unwrap();
+        let out = String::from_utf8(output.stdout).unwrap();
+        (output.status.code(), logged, out)
+    };
+    let dir_arg = dir.to_str().unwrap();
+    let named = |names: &[&str]| -> String {
+        names
+            .iter()
+            .map(|name| format!("{}\n", dir.join(name).display()))
+            .collect()
+    };
+
+    // The first file fails, which stops the run before the others
+    let (code, logged, out) = upload(Some("HTTP/1.1 403 Forbidden"), &["--dir", dir_arg]);
+    assert_eq!(code, Some(5));
+    assert_eq!(logged, named(&["a.bin", "b.bin", "source.bin"]));
+    assert!(out.contains("Uploaded 0 of 3 files, 1 failed (1 http), 0 skipped"));
+    assert!(server.received.lock().unwrap().is_empty());
+
+    let (code, logged, out) = upload(
+        Some("HTTP/1.1 403 Forbidden"),
+        &["--dir", dir_arg, "--continue-on-error"],
+    );
+    assert_eq!(code, Some(5));
+    assert_eq!(logged, named(&["a.bin"]));
+    let failed = format!("{}: failed (http), ", dir.join("a.bin").display());
+    assert!(out.contains(&failed));
+    assert!(out.contains("Uploaded 2 of 3 files, 1 failed (1 http), 0 skipped"));
+    assert_eq!(server.received.lock().unwrap().len(), 2);
+
+    // Credentials that aren't taken would fail every file, so the run stops anyway
+    let (code, logged, _) = upload(
+        Some("HTTP/1.1 401 Unauthorized"),
+        &["--dir", dir_arg, "--continue-on-error"],
+    );
+    assert_eq!(code, Some(5));
+    assert_eq!(logged, named(&["a.bin", "b.bin", "source.bin"]));
+    assert_eq!(server.received.lock().unwrap().len(), 2);
+
+    // The log is a list to upload the files left from
+    let list = dir.with_extension("list");
+    fs::rename(&log, &list).unwrap();
+    let (code, logged, _) = upload(None, &["--files-from", list.to_str().unwrap()]);
+    assert_eq!(code, Some(0));
+    assert_eq!(logged, "");
+    assert_eq!(server.received.lock().unwrap().len(), 5);
+    fs::remove_dir_all(dir).unwrap();
+    fs::remove_file(list).unwrap();
+}
+
+// Stopped with SIGINT like Ctrl-C would
+#[cfg(unix)]
+#[test]
+fn uploads_the_file_again_once_its_change_settled() {
+    let server = Server::start();
+    let (path, data) = source_file("watch", 3_000);
+    let child = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+        .arg("-f")
+        .arg(&path)
+        .args(["-u", &server.url, "--no-progress", "--watch"])
+        .args(["--watch-poll-interval", "20ms", "--watch-settle", "200ms"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let received = |n: usize| {
+        for _ in 0..250 {
+            if server.received.lock().unwrap().len() >= n {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        panic!("no upload number {n}");
+    };
+    received(1);
+    // Written in two steps, only the second of which is uploaded
+    fs::write(&path, &data[..1_000]).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    let changed = [&data[..1_000], b"changed"].concat();
+    fs::write(&path, &changed).unwrap();
+    received(2);
+    std::thread::sleep(Duration::from_millis(300));
+    let stopped = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(stopped.success());
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    let received = server.received.lock().unwrap();
+    assert_eq!(received.len(), 2);
+    assert_eq!(received[0].body, data);
+    assert_eq!(received[1].body, changed);
+    let out = String::from_utf8(output.stdout).unwrap();
+    let results: Vec<_> = out.lines().filter(|line| line.starts_with('[')).collect();
+    assert_eq!(results.len(), 2);
+    assert!(results[1].ends_with("1 of 1 chunks succeeded, 0 failed"));
+}
+
+#[cfg(unix)]
+#[test]
+fn pauses_on_sigusr1_until_sigcont_and_stops_on_sigterm() {
+    let server = Server::start();
+    let (path, data) = source_file("pause", 6_000);
+    let start = || {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+            .arg("-f")
+            .arg(&path)
+            .args(["-u", &server.url, "-c", "1000", "--no-progress"])
+            .args(["--chunk-delay", "150ms"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let (lines, received) = std::sync::mpsc::channel();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        std::thread::spawn(move || {
+            for line in stdout.lines() {
+                let _ = lines.send(line.unwrap());
+            }
+        });
+        (child, received)
+    };
+    let signal = |child: &std::process::Child, signal: &str| {
+        let sent = Command::new("kill")
+            .args([signal, &child.id().to_string()])
+            .status()
+            .unwrap();
+        assert!(sent.success());
+    };
+    let wait_for_chunk = || {
+        for _ in 0..250 {
+            if !server.received.lock().unwrap().is_empty() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("no chunk arrived");
+    };
+
+    let (mut child, lines) = start();
+    wait_for_chunk();
+    signal(&child, "-USR1");
+    let paused = lines
+        .iter()
+        .find(|line| line.starts_with("Paused at offset"))
+        .unwrap();
+    // Nothing more goes out until it's resumed, and everything before the offset is confirmed
+    let sent = server.received.lock().unwrap().len();
+    assert_eq!(paused, format!("Paused at offset {}", sent * 1_000));
+    std::thread::sleep(Duration::from_millis(500));
+    assert_eq!(server.received.lock().unwrap().len(), sent);
+    assert!(sent < 6);
+    signal(&child, "-CONT");
+    assert!(child.wait().unwrap().success());
+    assert_eq!(server.assemble(), data);
+
+    // SIGTERM stops like Ctrl-C, keeping the state to resume from
+    server.received.lock().unwrap().clear();
+    let (mut child, _lines) = start();
+    wait_for_chunk();
+    signal(&child, "-TERM");
+    assert_eq!(child.wait().unwrap().code(), Some(6));
+    let state = path.with_file_name("source.bin.chunkupload.json");
+    assert!(state.exists());
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn asks_to_proceed_at_a_terminal_unless_told_yes() {
+    use std::io::Read;
+    use std::os::fd::FromRawFd;
+
+    let server = Server::start();
+    let (path, data) = source_file("confirm", 2_500);
+    // Stdin and stdout are a pseudo-terminal, whose other end answers
+    let run = |answer: &str, args: &[&str]| {
+        let (mut master, mut slave) = (0, 0);
+        let opened = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(opened, 0);
+        let mut terminal = unsafe { fs::File::from_raw_fd(master) };
+        let slave = unsafe { fs::File::from_raw_fd(slave) };
+        let child = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+            .arg("-f")
+            .arg(&path)
+            .args(["-u", &format!("{}/{{filename}}", server.url), "-c", "1000"])
+            .args(["--no-progress"])
+            .args(args)
+            .stdin(slave.try_clone().unwrap())
+            .stdout(slave)
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut shown = Vec::new();
+        let mut byte = [0];
+        // Reading fails once the child is gone, when it never asked
+        while !String::from_utf8_lossy(&shown).contains("Proceed? [y/N]") {
+            match terminal.read(&mut byte) {
+                Ok(1) => shown.push(byte[0]),
+                _ => break,
+            }
+        }
+        terminal.write_all(answer.as_bytes()).unwrap();
+        let output = child.wait_with_output().unwrap();
+        (String::from_utf8_lossy(&shown).into_owned(), output)
+    };
+
+    let (shown, output) = run("n\n", &[]);
+    let url = format!("{}/source.bin", server.url);
+    assert!(shown.contains("bytes 0-2500 in 3 chunks of up to 1000 bytes"));
+    assert!(shown.contains(&format!("PUT {}", url)));
+    // Declining counts as a usage error, nothing having been sent
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("the upload wasn't confirmed"));
+    assert!(server.received.lock().unwrap().is_empty());
+
+    let (_, output) = run("y\n", &[]);
+    assert!(output.status.success());
+    assert_eq!(server.assemble(), data);
+
+    server.received.lock().unwrap().clear();
+    let (shown, output) = run("", &["-y"]);
+    assert!(output.status.success());
+    assert!(!shown.contains("Proceed?"));
+    assert_eq!(server.received.lock().unwrap().len(), 3);
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn colors_a_terminal_unless_no_color_and_anything_when_forced() {
+    // Choice, whether it's a terminal and NO_COLOR is set, and whether it's colored then
+    let cases = [
+        (ColorChoice::Auto, true, false, true),
+        (ColorChoice::Auto, false, false, false),
+        (ColorChoice::Auto, true, true, false),
+        (ColorChoice::Always, false, false, true),
+        (ColorChoice::Always, true, true, true),
+        (ColorChoice::Never, true, false, false),
+    ];
+    for (choice, terminal, no_color, colored) in cases {
+        let stream = Stream::new(terminal, choice, no_color);
+        assert_eq!(stream.color, colored, "{choice} {terminal} {no_color}");
+        assert_eq!(stream.terminal, terminal);
+    }
+    let colored = Stream::new(true, ColorChoice::Auto, false);
+    assert_eq!(
+        colored.paint("failed", Tone::Error),
+        "\x1b[31mfailed\x1b[0m"
+    );
+    assert_eq!(colored.paint("sent", Tone::Plain), "sent");
+    let plain = Stream::new(true, ColorChoice::Never, false);
+    assert_eq!(plain.paint("failed", Tone::Error), "failed");
+    assert!("sometimes".parse::<ColorChoice>().is_err());
+
+    // A pipe gets no color unless it's forced, NO_COLOR or not
+    let server = Server::start();
+    let (path, _) = source_file("color", 100);
+    let upload = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+            .arg("-f")
+            .arg(&path)
+            .args(["-u", &server.url])
+            .args(args)
+            .env("NO_COLOR", "1")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert!(!upload(&["--no-resume"]).contains('\x1b'));
+    let forced = upload(&["--no-resume", "--color", "always"]);
+    assert!(
+        forced.contains("\x1b[32mRequest completed successfully"),
+        "{forced}"
+    );
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn runs_the_hooks_around_each_chunk_and_stops_when_the_one_before_fails() {
+    let server = Server::start();
+    let (path, _) = source_file("hooks", 250);
+    let log = path.with_file_name("hooks.log");
+    let upload = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+            .arg("-f")
+            .arg(&path)
+            .args(["-u", &format!("{}/up/{{index}}", server.url), "-c", "100"])
+            .args(["--retries", "1", "--retry-delay", "1", "--no-resume"])
+            .args(args)
+            .current_dir(path.parent().unwrap())
+            .output()
+            .unwrap()
+    };
+    let before =
+        r#"echo "before $CHUNK_INDEX $CHUNK_OFFSET $CHUNK_BYTES $CHUNK_ATTEMPT $URL" >> hooks.log"#;
+    let after =
+        r#"echo "after $CHUNK_INDEX $CHUNK_STATUS $CHUNK_ATTEMPT" >> hooks.log; echo stamped"#;
+
+    // Hooks don't run on a dry run
+    let output = upload(&["--exec-before-chunk", before, "--dry-run"]);
+    assert!(output.status.success());
+    assert!(!log.exists());
+
+    // Chunk 1 is retried once, the hooks run once for it
+    server.replies.lock().unwrap().extend([
+        "HTTP/1.1 200 OK".to_string(),
+        "HTTP/1.1 503 Service Unavailable".to_string(),
+    ]);
+    let output = upload(&[
+        "--exec-before-chunk",
+        before,
+        "--exec-after-chunk",
+        after,
+        "--verbose",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("after-chunk hook of chunk 2: stamped"),
+        "{stdout}"
+    );
+    let url = |index| format!("{}/up/{}", server.url, index);
+    assert_eq!(
+        fs::read_to_string(&log)
+            .unwrap()
+            .lines()
+            .collect::<Vec<_>>(),
+        [
+            format!("before 0 0 100 1 {}", url(0)),
+            "after 0 200 1".to_string(),
+            format!("before 1 100 100 1 {}", url(1)),
+            "after 1 200 2".to_string(),
+            format!("before 2 200 50 1 {}", url(2)),
+            "after 2 200 1".to_string(),
+        ]
+    );
+    server.received.lock().unwrap().clear();
+
+    // A failing hook before a chunk stops the upload before it's sent
+    let refuse = r#"if [ "$CHUNK_INDEX" = 1 ]; then echo "not audited" >&2; exit 3; fi"#;
+    let output = upload(&["--exec-before-chunk", refuse]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("The before-chunk hook of chunk 1 failed with exit status: 3: not audited"),
+        "{stderr}"
+    );
+    assert_eq!(server.received.lock().unwrap().len(), 1);
+    server.received.lock().unwrap().clear();
+
+    // One failing after a chunk only warns, unless it's strict
+    let output = upload(&["--exec-after-chunk", "exit 1"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("after-chunk hook of chunk 2 failed"));
+    assert_eq!(server.received.lock().unwrap().len(), 3);
+    let output = upload(&["--exec-after-chunk", "exit 1", "--hook-strict"]);
+    assert!(!output.status.success());
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn runs_a_hook_once_the_upload_ended_and_only_fails_it_when_strict() {
+    let server = Server::start();
+    let (path, _) = source_file("upload_hooks", 250);
+    let dir = path.parent().unwrap().to_path_buf();
+    let upload = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+            .arg("-f")
+            .arg(&path)
+            .args(["-u", &server.url, "-c", "100", "--no-resume"])
+            .args(args)
+            .current_dir(&dir)
+            .output()
+            .unwrap()
+    };
+    let record = r#"echo "$FILE $URL $BYTES_SENT $EXIT_CODE $EXIT_CLASS $ERROR" > ended; test -n "$DURATION_MS""#;
+
+    // The upload that went through gets the success hook, one that failed the failure hook
+    let output = upload(&["--exec-on-success", record, "--exec-on-failure", "exit 1"]);
+    assert!(output.status.success(), "{output:?}");
+    let ended = fs::read_to_string(dir.join("ended")).unwrap();
+    assert_eq!(
+        ended,
+        format!("{} {} 250 0 success \n", path.display(), server.url)
+    );
+    server
+        .replies
+        .lock()
+        .unwrap()
+        .push_back("HTTP/1.1 403 Forbidden".to_string());
+    let output = upload(&["--exec-on-success", "exit 1", "--exec-on-failure", record]);
+    assert_eq!(output.status.code(), Some(5));
+    let ended = fs::read_to_string(dir.join("ended")).unwrap();
+    assert!(
+        ended.starts_with(&format!("{} {} 100 5 http ", path.display(), server.url)),
+        "{ended}"
+    );
+    assert!(
+        ended.contains(" http Upload failed: 0 of 3 chunks succeeded"),
+        "{ended}"
+    );
+
+    // A failing hook only warns, unless it's strict
+    let failing = "echo moved >&2; exit 4";
+    let output = upload(&["--exec-on-success", failing]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Warning: The on-success hook failed with exit status: 4: moved"),
+        "{stderr}"
+    );
+    let output = upload(&[
+        "--exec-on-success",
+        failing,
+        "--hook-strict",
+        "--output",
+        "json",
+    ]);
+    assert_eq!(output.status.code(), Some(3));
+    let document: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(document["success"], false);
+    assert_eq!(
+        document["error"],
+        "The on-success hook failed with exit status: 4: moved"
+    );
+
+    // A hook still running after the timeout is killed
+    let started = Instant::now();
+    let output = upload(&["--exec-on-success", "sleep 5", "--hook-timeout", "200ms"]);
+    assert!(output.status.success());
+    assert!(started.elapsed() < Duration::from_secs(4));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("The on-success hook didn't finish within 200ms"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn writes_a_textfile_of_each_files_metrics_even_when_the_upload_fails() {
+    let server = Server::start();
+    let (path, data) = source_file("textfile", 300);
+    // Label values have their quotes and backslashes escaped
+    let odd = path.with_file_name("we\"ird\\.bin");
+    fs::write(&odd, &data).unwrap();
+    let prom = path.with_file_name("uploads.prom");
+    let upload = |file: &Path| {
+        Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+            .arg("-f")
+            .arg(file)
+            .args(["-u", &server.url, "-c", "100", "--retries", "1"])
+            .args(["--retry-delay", "1", "--no-resume", "--metrics-textfile"])
+            .arg(&prom)
+            .output()
+            .unwrap()
+    };
+    server
+        .replies
+        .lock()
+        .unwrap()
+        .push_back("HTTP/1.1 503 Service Unavailable".to_string());
+    assert!(upload(&odd).status.success());
+    let labels = format!(
+        "{{file=\"{}\",host=\"127.0.0.1\"}}",
+        odd.display()
+            .to_string()
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+    );
+    let text = fs::read_to_string(&prom).unwrap();
+    for sample in [
+        format!("chunk_uploader_bytes_sent_total{labels} 400"),
+        format!("chunk_uploader_chunks_total{labels} 3"),
+        format!("chunk_uploader_retries_total{labels} 1"),
+        format!("chunk_uploader_success{labels} 1"),
+    ] {
+        assert!(text.lines().any(|line| line == sample), "{sample}\n{text}");
+    }
+    assert!(text.contains("# TYPE chunk_uploader_duration_seconds gauge\n"));
+    assert!(text.contains(&format!("chunk_uploader_duration_seconds{labels} ")));
+    assert!(!prom.with_file_name("uploads.prom.tmp").exists());
+
+    // A failed upload replaces it, with what it got done
+    server.replies.lock().unwrap().extend([
+        "HTTP/1.1 200 OK".to_string(),
+        "HTTP/1.1 500 Internal Server Error".to_string(),
+        "HTTP/1.1 500 Internal Server Error".to_string(),
+    ]);
+    assert!(!upload(&path).status.success());
+    let text = fs::read_to_string(&prom).unwrap();
+    assert!(!text.contains("ird"));
+    let labels = format!("{{file=\"{}\",host=\"127.0.0.1\"}}", path.display());
+    assert!(text.contains(&format!("chunk_uploader_chunks_total{labels} 1\n")));
+    assert!(text.contains(&format!("chunk_uploader_success{labels} 0\n")));
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn logs_every_attempt_in_its_spans_to_a_json_file_without_secrets() {
+    let server = Server::start();
+    let (path, _) = source_file("logging", 200);
+    let log = path.with_file_name("upload.log");
+    // The first chunk is retried once
+    server
+        .replies
+        .lock()
+        .unwrap()
+        .push_back("HTTP/1.1 503 Service Unavailable".to_string());
+    let output = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+        .arg("-f")
+        .arg(&path)
+        .args(["-u", &server.url.replace("http://", "http://user:hunter2@")])
+        .args(["-c", "100", "--retries", "1", "--retry-delay", "1"])
+        .args(["-H", "Authorization: Bearer secret", "--no-resume"])
+        .arg("--log-file")
+        .arg(&log)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    // Only the file was asked for, the terminal gets no log lines
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("INFO"));
+
+    let text = fs::read_to_string(&log).unwrap();
+    assert!(
+        !text.contains("secret") && !text.contains("hunter2"),
+        "{text}"
+    );
+    let lines: Vec<serde_json::Value> = text
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let with = |message: &'static str| lines.iter().filter(move |line| line["message"] == message);
+    let retry = with("retry scheduled").next().expect("a retry");
+    assert_eq!(retry["level"], "info");
+    let spans: Vec<_> = retry["spans"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|span| span["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(spans, ["upload", "chunk", "attempt"]);
+    assert_eq!(retry["spans"][1]["index"], 0);
+    assert_eq!(retry["spans"][2]["attempt"], 1);
+    // Debug events are left out at the file's default level
+    assert_eq!(with("request built").count(), 0);
+    assert_eq!(
+        with("upload finished").next().unwrap()["fields"]["bytes"],
+        200
+    );
+    // The messages printed are in it too, in order with the rest
+    let finished = lines
+        .iter()
+        .position(|line| line["message"] == "upload finished")
+        .unwrap();
+    assert!(lines[finished..].iter().any(|line| line["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Request completed successfully")));
+    fs::remove_file(&log).unwrap();
+
+    // Debug shows what was sent, the header's value redacted
+    let output = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+        .arg("-f")
+        .arg(&path)
+        .args(["-u", &server.url, "-c", "100"])
+        .args(["-H", "Authorization: Bearer secret", "--no-resume"])
+        .args(["--log-level", "chunk_uploader=debug", "--log-file"])
+        .arg(&log)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("DEBUG upload{"), "{stderr}");
+    let text = fs::read_to_string(&log).unwrap();
+    assert!(!text.contains("secret") && !stderr.contains("secret"));
+    let built: Vec<serde_json::Value> = text
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .filter(|line| line["message"] == "request built")
+        .collect();
+    assert_eq!(built.len(), 2);
+    assert!(built[0]["fields"]["headers"]
+        .as_str()
+        .unwrap()
+        .contains("authorization: <redacted>"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+        .args(["-f", "x", "-u", &server.url, "--log-level", "loud"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn matches_globs_against_the_path_or_file_name() {
+    let glob = |pattern: &str| Glob::new(pattern, false).unwrap();
+    let cases = [
+        ("*.mp4", "clip.mp4", true),
+        ("*.mp4", "videos/2024/clip.mp4", true),
+        ("*.mp4", "clip.MP4", false),
+        ("*.mp4", "clip.mp4.tmp", false),
+        ("videos/*.mp4", "videos/clip.mp4", true),
+        ("videos/*.mp4", "videos/2024/clip.mp4", false),
+        ("videos/**/*.mp4", "videos/clip.mp4", true),
+        ("videos/**/*.mp4", "videos/2024/05/clip.mp4", true),
+        ("/videos/*", "videos/clip.mp4", true),
+        (".git/**", ".git/objects/ab/cd", true),
+        (".git/**", "src/.git/config", false),
+        ("**/.git/**", "src/.git/config", true),
+        ("clip-?.mp4", "clip-1.mp4", true),
+        ("clip-?.mp4", "clip-10.mp4", false),
+        ("clip-[0-4].mp4", "clip-3.mp4", true),
+        ("clip-[!0-4].mp4", "clip-3.mp4", false),
+        ("clip-[!0-4].mp4", "clip-7.mp4", true),
+        ("[]x-]", "-", true),
+        ("\\*", "*", true),
+        ("\\*", "a", false),
+    ];
+    for (pattern, path, matched) in cases {
+        assert_eq!(glob(pattern).matches(path), matched, "{pattern} on {path}");
+    }
+    assert!(Glob::new("*.MP4", true).unwrap().matches("a/clip.mp4"));
+    assert!(Glob::new("[a", false).is_err());
+
+    // Includes pick the files, excludes take some of them away again whatever the order
+    let mut filter = Filter::default();
+    assert_eq!(filter.excludes("notes.txt"), None);
+    filter.exclude(glob("tmp/**"));
+    filter.include(glob("*.mp4"));
+    assert_eq!(filter.excludes("a/clip.mp4"), None);
+    assert_eq!(
+        filter.excludes("tmp/clip.mp4").as_deref(),
+        Some("matches --exclude 'tmp/**'")
+    );
+    assert_eq!(
+        filter.excludes("notes.txt").as_deref(),
+        Some("matches no --include")
+    );
+}
+
+#[test]
+fn keeps_the_directories_of_the_tree_in_the_url_behind_the_prefix() {
+    let server = Server::start();
+    let (path, _) = source_file("prefix", 10);
+    let root = path.parent().unwrap();
+    fs::create_dir_all(root.join("photos/2023 summer")).unwrap();
+    fs::write(root.join("photos/2023 summer/img#1.jpg"), b"jpg").unwrap();
+    let upload = |url: &str| {
+        Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+            .args(["--dir", root.to_str().unwrap(), "-u", url, "-q"])
+            .args(["--prefix", "/backups/2024/"])
+            .output()
+            .unwrap()
+    };
+    assert!(upload(&format!("{}/{{path}}", server.url)).status.success());
+
+    let received = server.received.lock().unwrap();
+    let paths: Vec<_> = received.iter().map(|r| r.path.as_str()).collect();
+    assert_eq!(
+        paths,
+        [
+            "/upload/backups/2024/photos/2023%20summer/img%231.jpg",
+            "/upload/backups/2024/source.bin"
+        ]
+    );
+    drop(received);
+
+    // Two files of the same name would land on the same URL, so nothing is sent
+    fs::write(root.join("photos/source.bin"), b"other").unwrap();
+    let output = upload(&format!("{}/{{filename}}", server.url));
+    fs::remove_dir_all(root).unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("/upload/backups/2024/source.bin: '"),
+        "{stderr}"
+    );
+    assert_eq!(server.received.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn percent_encodes_what_fills_the_url_but_the_slashes() {
+    let url = "https://example.com/bucket/{path}?name={filename}";
+    let fill =
+        |path: &str, filename: &str| fill_url(url, &[("{path}", path), ("{filename}", filename)]);
+    assert_eq!(
+        fill("holiday photos/img 1.jpg", "img 1.jpg"),
+        "https://example.com/bucket/holiday%20photos/img%201.jpg?name=img%201.jpg"
+    );
+    assert_eq!(
+        fill("café/naïve.txt", "naïve.txt"),
+        "https://example.com/bucket/caf%C3%A9/na%C3%AFve.txt?name=na%C3%AFve.txt"
+    );
+    // A plus in a query is a space, and & = # would end the value early
+    assert_eq!(
+        fill("a+b/c#1?.txt", "x&y=z+1;@:$,!'()*.txt"),
+        "https://example.com/bucket/a%2Bb/c%231%3F.txt?name=x%26y%3Dz%2B1%3B%40%3A%24%2C%21%27%28%29%2A.txt"
+    );
+    // Slashes left over from a missing prefix or doubled in the path don't make empty segments
+    assert_eq!(
+        fill("/photos//2023/", "x"),
+        "https://example.com/bucket/photos/2023?name=x"
+    );
+
+    // A URL without the placeholders stays as it was given, encoded or not
+    let given = "https://example.com/a%20b/c d?sig=x%2By+z";
+    assert_eq!(fill_url(given, &[("{path}", "ignored")]), given);
+
+    assert!(check_url("https://example.com/{path}?part={index}").is_ok());
+    assert!(check_url("http://[::1]:8080/upload").is_ok());
+    let invalid = check_url("https://exa mple.com/{path}").unwrap_err();
+    assert!(
+        invalid.starts_with("Invalid URL 'https://exa mple.com/{path}'"),
+        "{invalid}"
+    );
+    assert!(check_url("example.com/upload").is_err());
+    assert!(check_url("ftp://example.com/upload")
+        .unwrap_err()
+        .contains("Unsupported scheme 'ftp'"));
+}
+
+#[tokio::test]
+async fn verifies_the_upload_by_reading_its_blocks_back() {
+    let (path, data) = source_file("verify", 12_345);
+    let dir = path.with_file_name("received");
+    let serve = Serve::start(&["--dir", dir.to_str().unwrap(), "-q"]);
+    let uploader = ChunkUploader::builder()
+        .chunk_size(1_000)
+        .verify(Verify::Readback)
+        .verify_block_size(3_000)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    let report = uploader
+        .upload(Source::File(path.clone()), &format!("{}/v.bin", serve.url))
+        .await
+        .unwrap();
+    assert!(report.verified);
+    // Only asked for, the digest computed for the blocks stays out of the report
+    assert_eq!(report.sha256, None);
+    assert_eq!(fs::read(dir.join("v.bin")).unwrap(), data);
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn names_the_blocks_a_server_without_ranges_holds_differently() {
+    let server = Server::start();
+    let dir = std::env::temp_dir().join(format!("chunk_uploader_readback_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("source.txt");
+    let data = "0123456789".repeat(300);
+    fs::write(&path, &data).unwrap();
+
+    // The chunks get empty 200s, the GET the whole file with a byte changed and the end missing
+    let mut stored = data.clone().into_bytes();
+    stored[1_500] = b'x';
+    stored.truncate(2_800);
+    {
+        let mut responses = server.responses.lock().unwrap();
+        responses.extend(["", "", ""].map(String::from));
+        responses.push_back(format!("\r\n\r\n{}", String::from_utf8(stored).unwrap()));
+    }
+
+    let uploader = ChunkUploader::builder()
+        .chunk_size(1_000)
+        .verify(Verify::Readback)
+        .verbosity(Verbosity::Quiet)
+        .build()
+        .unwrap();
+    let err = uploader
+        .upload(Source::File(path), &server.url)
+        .await
+        .unwrap_err();
+    fs::remove_dir_all(&dir).unwrap();
+    let UploadError::Verification { message, report } = err else {
+        panic!("expected a verification failure, got {err}");
+    };
+    assert_eq!(
+        message,
+        "Verification failed: 2 of 3 blocks read back differ from what was uploaded, bytes \
+         1000-1999, 2000-2999"
+    );
+    assert_eq!(report.chunks_succeeded, 3);
+    assert!(!report.verified);
+
+    let received = server.received.lock().unwrap();
+    assert_eq!(received.len(), 4);
+    assert_eq!(received[3].method, "GET");
+    assert_eq!(received[3].header("range"), Some("bytes=0-999"));
+}
+
+#[tokio::test]
+async fn encrypts_each_chunk_so_that_the_server_copy_decrypts_to_the_file() {
+    let server = Server::start();
+    let (path, mut data) = source_file("encrypt", 2_500);
+    let manifest = path.with_file_name("manifest.json");
+    let key = EncryptionKey::Key([7; 32]);
+    let attempt = || async {
+        let uploader = ChunkUploader::builder()
+            .chunk_size(1_000)
+            .encrypt(Encryption::AesGcm, key.clone())
+            .manifest(&manifest)
+            .verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        uploader
+            .upload(Source::File(path.clone()), &server.url)
+            .await
+    };
+    let upload = || async { attempt().await.unwrap() };
+    let report = upload().await;
+    let record = report.encryption.clone().unwrap();
+    assert_eq!(record.kdf, Kdf::Key);
+    assert_eq!(record.segment_size, 1_000);
+    assert_eq!(record.original_size, Some(2_500));
+    let sealed = {
+        let received = server.received.lock().unwrap();
+        let ranges: Vec<_> = received.iter().map(|r| r.content_range.as_str()).collect();
+        // Every chunk is its 16-byte tag longer
+        assert_eq!(
+            ranges,
+            [
+                "bytes 0-1015/2548",
+                "bytes 1016-2031/2548",
+                "bytes 2032-2547/2548"
+            ]
+        );
+        assert_ne!(received[0].body[..1_000], data[..1_000]);
+        drop(received);
+        server.assemble()
+    };
+    let mut decrypted = Vec::new();
+    assert_eq!(
+        decrypt(&record, &key, &sealed[..], &mut decrypted).unwrap(),
+        2_500
+    );
+    assert_eq!(decrypted, data);
+
+    // A wrong key, chunks out of place and a missing last chunk are all refused
+    let wrong = EncryptionKey::Key([8; 32]);
+    let err = decrypt(&record, &wrong, &sealed[..], std::io::sink()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "The key isn't the one it was encrypted with"
+    );
+    let swapped = [&sealed[1_016..2_032], &sealed[..1_016], &sealed[2_032..]].concat();
+    let err = decrypt(&record, &key, &swapped[..], std::io::sink()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Segment 0 doesn't decrypt, the data was changed or cut short"
+    );
+    assert!(decrypt(&record, &key, &sealed[..2_032], std::io::sink()).is_err());
+
+    // A changed chunk is never sealed again under the same salt and nonce, the whole file is
+    // encrypted again with a new one
+    data[2_400] ^= 0xff;
+    fs::write(&path, &data).unwrap();
+    let report = upload().await;
+    assert_eq!(report.chunks_succeeded, 3);
+    let changed = report.encryption.clone().unwrap();
+    assert_ne!(changed.salt, record.salt);
+    let written: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&manifest).unwrap()).unwrap();
+    assert_eq!(written["files"][0]["encryption"]["salt"], changed.salt);
+    let mut decrypted = Vec::new();
+    decrypt(&changed, &key, &server.assemble()[..], &mut decrypted).unwrap();
+    assert_eq!(decrypted, data);
+
+    // A chunk the state file has as sealed is sent again under its salt only with the same bytes
+    let state = path.with_file_name("source.bin.chunkupload.json");
+    let salt_in_state = || {
+        let state = fs::read_to_string(&state).unwrap();
+        let state: serde_json::Value = serde_json::from_str(&state).unwrap();
+        state["encryption"]["salt"].as_str().unwrap().to_string()
+    };
+    data[100] ^= 0xff;
+    fs::write(&path, &data).unwrap();
+    server
+        .replies
+        .lock()
+        .unwrap()
+        .push_back("HTTP/1.1 400 Bad Request".into());
+    assert!(attempt().await.is_err());
+    let failed = salt_in_state();
+    assert_ne!(failed, changed.salt);
+    server
+        .replies
+        .lock()
+        .unwrap()
+        .push_back("HTTP/1.1 400 Bad Request".into());
+    assert!(attempt().await.is_err());
+    assert_eq!(salt_in_state(), failed);
+    data[200] ^= 0xff;
+    fs::write(&path, &data).unwrap();
+    let report = upload().await;
+    assert_eq!(report.chunks_succeeded, 3);
+    let resealed = report.encryption.clone().unwrap();
+    assert_ne!(resealed.salt, failed);
+    let mut decrypted = Vec::new();
+    decrypt(&resealed, &key, &server.assemble()[..], &mut decrypted).unwrap();
+    assert_eq!(decrypted, data);
+
+    // Anything that would see the ciphertext instead of the file is refused
+    let err = ChunkUploader::builder()
+        .encrypt(Encryption::AesGcm, key.clone())
+        .compress(Compression::Gzip)
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(err.to_string(), "Encrypted chunks can't be compressed");
+
+    // The command line uploads with a key file and decrypts what serve reassembled
+    let dir = path.with_file_name("received");
+    let key_file = path.with_file_name("backup.key");
+    fs::write(&key_file, format!("{}\n", "07".repeat(32))).unwrap();
+    let mut serve = Serve::start(&["--dir", dir.to_str().unwrap(), "-q"]);
+    let output = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+        .args(["-f", path.to_str().unwrap(), "-c", "1000"])
+        .args(["-u", &format!("{}/e.bin", serve.url)])
+        .args(["--encrypt", "aes-gcm", "--encrypt-key-file"])
+        .arg(&key_file)
+        .args(["--output", "json", "--no-resume"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let json = path.with_file_name("upload.json");
+    fs::write(&json, &output.stdout).unwrap();
+    serve.lines.next().unwrap().unwrap();
+    let restored = path.with_file_name("restored");
+    let output = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+        .args(["decrypt", "--record", json.to_str().unwrap(), "--key-file"])
+        .arg(&key_file)
+        .arg("-o")
+        .arg(&restored)
+        .arg(dir.join("e.bin"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(fs::read(&restored).unwrap(), data);
+
+    // Into a reader that already had enough, like `head`
+    let (reader, writer) = std::io::pipe().unwrap();
+    drop(reader);
+    let output = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+        .args(["decrypt", "--record", json.to_str().unwrap(), "--key-file"])
+        .arg(&key_file)
+        .arg(dir.join("e.bin"))
+        .stdout(writer)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert!(output.stderr.is_empty(), "{output:?}");
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn encrypts_to_an_age_file_that_age_itself_decrypts() {
+    use age::secrecy::ExposeSecret;
+
+    let server = Server::start();
+    let (path, data) = source_file("age", 150_000);
+    let identity = age::x25519::Identity::generate();
+    let key = EncryptionKey::Identity(identity.to_string().expose_secret().to_string());
+    let attempt = || async {
+        let uploader = ChunkUploader::builder()
+            .chunk_size(65_536)
+            .encrypt(Encryption::Age, key.clone())
+            .verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        uploader
+            .upload(Source::File(path.clone()), &server.url)
+            .await
+    };
+    let report = attempt().await.unwrap();
+    let record = report.encryption.clone().unwrap();
+    assert_eq!(record.kdf, Kdf::X25519);
+    assert_eq!(record.original_size, Some(150_000));
+    let sealed = {
+        let received = server.received.lock().unwrap();
+        let ranges: Vec<_> = received.iter().map(|r| r.content_range.as_str()).collect();
+        // The header and nonce ahead of the first chunk, and a tag after every 64 KiB
+        assert_eq!(
+            ranges,
+            [
+                "bytes 0-65735/150232",
+                "bytes 65736-131287/150232",
+                "bytes 131288-150231/150232"
+            ]
+        );
+        drop(received);
+        server.assemble()
+    };
+    let decryptor = age::Decryptor::new(&sealed[..]).unwrap();
+    let mut reader = decryptor
+        .decrypt(std::iter::once(&identity as &dyn age::Identity))
+        .unwrap();
+    let mut decrypted = Vec::new();
+    std::io::Read::read_to_end(&mut reader, &mut decrypted).unwrap();
+    assert_eq!(decrypted, data);
+    let mut decrypted = Vec::new();
+    assert_eq!(
+        decrypt(&record, &key, &sealed[..], &mut decrypted).unwrap(),
+        150_000
+    );
+    assert_eq!(decrypted, data);
+
+    let other = age::x25519::Identity::generate();
+    let other = EncryptionKey::Identity(other.to_string().expose_secret().to_string());
+    let err = decrypt(&record, &other, &sealed[..], std::io::sink()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "The key isn't the one it was encrypted with"
+    );
+    assert!(decrypt(&record, &key, &sealed[..131_288], std::io::sink()).is_err());
+
+    // A failed upload resumes under the header the state file kept, sent the same again
+    server
+        .replies
+        .lock()
+        .unwrap()
+        .push_back("HTTP/1.1 400 Bad Request".into());
+    assert!(attempt().await.is_err());
+    let state = path.with_file_name("source.bin.chunkupload.json");
+    let state: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&state).unwrap()).unwrap();
+    let failed = state["encryption"]["header"].as_str().unwrap().to_string();
+    assert_ne!(Some(&failed), record.header.as_ref());
+    let report = attempt().await.unwrap();
+    assert_eq!(report.encryption.unwrap().header, Some(failed));
+
+    // The default chunk size isn't whole 64 KiB
+    let err = ChunkUploader::builder()
+        .encrypt(Encryption::Age, key.clone())
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "age encrypts 64 KiB at a time, so the chunk size has to be a multiple of it like 4MiB, \
+         not 5000000"
+    );
+
+    // The command line takes an identity file the way age-keygen writes it
+    let dir = path.with_file_name("received");
+    let key_file = path.with_file_name("age.key");
+    fs::write(
+        &key_file,
+        format!(
+            "# created: 2026-10-14T00:00:00Z\n# public key: {}\n{}\n",
+            identity.to_public(),
+            identity.to_string().expose_secret()
+        ),
+    )
+    .unwrap();
+    let mut serve = Serve::start(&["--dir", dir.to_str().unwrap(), "-q"]);
+    let output = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+        .args(["-f", path.to_str().unwrap(), "-c", "64KiB"])
+        .args(["-u", &format!("{}/e.age", serve.url)])
+        .args(["--encrypt", "age", "--encrypt-key-file"])
+        .arg(&key_file)
+        .args(["--output", "json", "--no-resume"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let json = path.with_file_name("upload.json");
+    fs::write(&json, &output.stdout).unwrap();
+    serve.lines.next().unwrap().unwrap();
+    let restored = path.with_file_name("restored");
+    let output = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+        .args(["decrypt", "--record", json.to_str().unwrap(), "--key-file"])
+        .arg(&key_file)
+        .arg("-o")
+        .arg(&restored)
+        .arg(dir.join("e.age"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(fs::read(&restored).unwrap(), data);
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn ends_cleanly_once_what_reads_stdout_is_gone() {
+    let server = Server::start();
+    let (path, data) = source_file("closed_stdout", 3_000);
+    let run = |args: &[&str]| {
+        let (reader, writer) = std::io::pipe().unwrap();
+        drop(reader);
+        Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+            .args(args)
+            .stdout(writer)
+            .output()
+            .unwrap()
+    };
+    for args in [
+        &["--help"][..],
+        &["--version"],
+        &["--version", "--output", "json"],
+        &["--completions", "bash"],
+        &["-u", &server.url, "--print-config"],
+    ] {
+        let output = run(args);
+        assert!(output.status.success(), "{args:?}: {output:?}");
+        assert!(output.stderr.is_empty(), "{args:?}: {output:?}");
+    }
+
+    // The upload goes on without the lines and the document it would print
+    let file = path.to_str().unwrap();
+    for output in ["text", "json"] {
+        let args = ["-f", file, "-u", &server.url, "-c", "1000", "--no-resume"];
+        let output = run(&[&args[..], &["--output", output]].concat());
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(server.assemble(), data);
+        server.received.lock().unwrap().clear();
+    }
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn streams_a_directory_as_a_tar_archive_that_extracts_to_the_same_tree() {
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    let (path, data) = source_file("tar_stream", 3_000);
+    let tree = path.with_file_name("tree");
+    fs::create_dir_all(tree.join("sub/empty")).unwrap();
+    fs::write(tree.join("run.sh"), "#!/bin/sh\n").unwrap();
+    fs::set_permissions(tree.join("run.sh"), fs::Permissions::from_mode(0o750)).unwrap();
+    fs::rename(&path, tree.join("sub/data.bin")).unwrap();
+    symlink("../run.sh", tree.join("sub/link")).unwrap();
+
+    let upload = |presize: bool| {
+        let server = Server::start();
+        let mut command = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"));
+        command
+            .arg("--tar")
+            .arg(&tree)
+            .args(["-u", &format!("{}/{{filename}}", server.url), "-c", "1000"])
+            .args(["--tar-clamp-mtime", "1700000000", "-q"]);
+        if presize {
+            command.arg("--tar-presize");
+        }
+        let output = command.output().unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let received = server.received.lock().unwrap();
+        assert!(received
+            .iter()
+            .all(|chunk| chunk.path == "/upload/tree.tar"));
+        let ranges: Vec<_> = received.iter().map(|c| c.content_range.clone()).collect();
+        drop(received);
+        (server.assemble(), ranges)
+    };
+
+    // The length isn't sent, unless the tree was walked for it first
+    let (archive, ranges) = upload(false);
+    assert_eq!(archive.len() % 512, 0);
+    assert!(ranges.iter().all(|r| r.ends_with("/*")));
+    let total = format!("/{}", archive.len());
+    let (presized, ranges) = upload(true);
+    assert_eq!(presized, archive);
+    assert!(ranges.iter().all(|r| r.ends_with(&total)));
+
+    let out = tree.with_file_name("extracted");
+    fs::create_dir_all(&out).unwrap();
+    let mut tar = Command::new("tar")
+        .arg("-xf")
+        .arg("-")
+        .arg("-C")
+        .arg(&out)
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    tar.stdin.take().unwrap().write_all(&archive).unwrap();
+    assert!(tar.wait().unwrap().success());
+
+    let listing = Command::new("tar")
+        .args(["-tf", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    listing.stdin.as_ref().unwrap().write_all(&archive).unwrap();
+    let listing = listing.wait_with_output().unwrap();
+    assert_eq!(
+        String::from_utf8(listing.stdout).unwrap(),
+        "tree/\ntree/run.sh\ntree/sub/\ntree/sub/data.bin\ntree/sub/empty/\ntree/sub/link\n"
+    );
+    let extracted = out.join("tree");
+    assert_eq!(fs::read(extracted.join("sub/data.bin")).unwrap(), data);
+    let run = fs::metadata(extracted.join("run.sh")).unwrap();
+    assert_eq!(run.permissions().mode() & 0o777, 0o750);
+    assert_eq!(
+        fs::read_link(extracted.join("sub/link")).unwrap(),
+        Path::new("../run.sh")
+    );
+    assert!(extracted.join("sub/empty").is_dir());
+    let mtime = run.modified().unwrap();
+    assert_eq!(
+        mtime
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        1_700_000_000
+    );
+
+    // Only the raw protocol takes a stream of unknown length
+    let output = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+        .arg("--tar")
+        .arg(&tree)
+        .args(["-u", "http://127.0.0.1:1/x", "--protocol", "tus"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Reading from a tar stream only works with the raw protocol"));
+    fs::remove_dir_all(tree.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn compresses_the_whole_file_as_one_zstd_stream_with_checksums_of_both() {
+    let (path, data) = source_file("compress_stream", 300_000);
+    let server = Server::start();
+    let output = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+        .arg(&path)
+        .args(["-u", &format!("{}/{{filename}}", server.url), "-c", "1000"])
+        .args([
+            "--compress-stream",
+            "zstd:5",
+            "--sha256",
+            "--output",
+            "json",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let received = server.received.lock().unwrap();
+    assert!(received
+        .iter()
+        .all(|chunk| chunk.path == "/upload/source.bin.zst"));
+    assert!(received
+        .iter()
+        .all(|chunk| chunk.content_range.ends_with("/*")));
+    drop(received);
+
+    // One frame of what the file held, far smaller than it
+    let object = server.assemble();
+    assert_eq!(object[..4], [0x28, 0xb5, 0x2f, 0xfd]);
+    assert!(object.len() < data.len() / 20, "{}", object.len());
+    let document: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(document["total_bytes"], object.len());
+    assert_eq!(document["sha256"], format!("{:x}", Sha256::digest(&object)));
+    let compressed = &document["compressed"];
+    assert_eq!(compressed["compression"], "zstd:5");
+    assert_eq!(compressed["original_bytes"], data.len());
+    assert_eq!(
+        compressed["original_checksum"]["digest"],
+        format!("{:x}", Sha256::digest(&data))
+    );
+    let ratio = compressed["ratio"].as_f64().unwrap();
+    assert!((ratio - data.len() as f64 / object.len() as f64).abs() < 1e-9);
+
+    let refused = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_chunk_uploader"))
+            .arg(&path)
+            .args(["-u", "http://127.0.0.1:1/x"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(2));
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+    assert!(refused(&["--compress-stream", "zstd:20"]).contains("expected 1 to 19"));
+    assert!(
+        refused(&["--compress-stream", "zstd", "--compress", "gzip"])
+            .contains("'--compress-stream' and '--compress' can't be used together")
+    );
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+/// What ruzstd makes of a stream of one frame, checking the frame's checksum too
+fn unzstd(mut stream: &[u8]) -> Vec<u8> {
+    let mut decoder = ruzstd::decoding::StreamingDecoder::new(&mut stream).unwrap();
+    let mut data = Vec::new();
+    std::io::Read::read_to_end(&mut decoder, &mut data).unwrap();
+    let frame = decoder.into_frame_decoder();
+    let checksum = frame
+        .get_checksum_from_data()
+        .expect("the frame has a checksum");
+    assert_eq!(frame.get_calculated_checksum(), Some(checksum));
+    assert!(stream.is_empty(), "{} bytes after the frame", stream.len());
+    data
+}
+
+#[tokio::test]
+async fn compresses_streams_that_zstd_decompresses_to_the_same_bytes() {
+    let (path, pattern) = source_file("compress_stream_levels", 200_000);
+    let mut rng = fastrand::Rng::with_seed(7);
+    let noise: Vec<u8> = (0..300_000).map(|_| rng.u8(..)).collect();
+    // Many blocks long, past the window of the low levels, repeating the noise further back
+    // than theirs reaches
+    let mut long = noise.clone();
+    long.extend(pattern.iter().cycle().take(600_000));
+    long.extend(std::iter::repeat_n(0, 300_000));
+    long.extend(b"a line of text repeated ".iter().cycle().take(400_000));
+    long.extend(&noise);
+    long.extend((0..200_000u32).flat_map(|i| (i % 997).to_le_bytes()));
+    let inputs = [
+        ("empty", Vec::new()),
+        ("pattern", pattern),
+        ("noise", noise),
+        ("long", long),
+    ];
+
+    let server = Server::start();
+    for level in [1, 3, 9, 19] {
+        for (name, data) in &inputs {
+            fs::write(&path, data).unwrap();
+            server.received.lock().unwrap().clear();
+            let uploader = ChunkUploader::builder()
+                .chunk_size(256 * 1024)
+                .compress_stream(StreamCompression::Zstd(level))
+                .verbosity(Verbosity::Quiet)
+                .build()
+                .unwrap();
+            let report = uploader
+                .upload(Source::File(path.clone()), &server.url)
+                .await
+                .unwrap();
+            let object = server.assemble();
+            assert_eq!(report.total_bytes, object.len() as u64, "{name} at {level}");
+            assert!(unzstd(&object) == *data, "{name} at level {level}");
+            let compressed = report.compressed.unwrap();
+            assert_eq!(compressed.original_bytes, data.len() as u64);
+        }
+    }
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}